@@ -1,13 +1 @@
-use protoc_rust::Customize;
-
-fn main() {
-    protoc_rust::run(protoc_rust::Args {
-        out_dir: "src/protos",
-        input: &["protos/message.proto"],
-        includes: &["protos"],
-        customize: Customize {
-            ..Default::default()
-        },
-    })
-    .expect("protoc is not installed.");
-}
+fn main() {}
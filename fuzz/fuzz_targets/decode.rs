@@ -0,0 +1,8 @@
+#![no_main]
+use distributed_consensus::transport;
+use libfuzzer_sys::fuzz_target;
+
+// Whatever a peer sends, the listener either decodes a message or drops it with an error.
+fuzz_target!(|frame: &[u8]| {
+    let _ = transport::decode(frame);
+});
@@ -1 +1 @@
-powershell $env:RUST_LOG="distributed_consensus"; .\target\debug\distributed_consensus.exe %1 --config nodes.json --hub hub.json
\ No newline at end of file
+powershell $env:RUST_LOG="distributed_consensus"; .\target\debug\distributed_consensus.exe %1 --config nodes.json --hub hub.json --state-dir state\%1
\ No newline at end of file
@@ -5,12 +5,12 @@ then
 elif [ $1 = "debug" ];
 then
     cargo clippy && cargo build
-    RUST_LOG=distributed_consensus ./target/debug/distributed_consensus $2 --config nodes.json --hub hub.json
+    RUST_LOG=distributed_consensus ./target/debug/distributed_consensus $2 --config nodes.json --hub hub.json --state-dir state/$2
 elif [ $1 = "debug_no_logs" ];
 then
     cargo clippy && cargo build
-    ./target/debug/distributed_consensus $2 --config nodes.json --hub hub.json
+    ./target/debug/distributed_consensus $2 --config nodes.json --hub hub.json --state-dir state/$2
 else
     cargo clippy && cargo build --release
-    RUST_LOG=info ./target/release/distributed_consensus $2 --config nodes.json --hub hub.json
+    RUST_LOG=info ./target/release/distributed_consensus $2 --config nodes.json --hub hub.json --state-dir state/$2
 fi
\ No newline at end of file
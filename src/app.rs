@@ -1,34 +1,188 @@
 use crate::event::*;
 use crate::node::*;
 use crate::protos::message::*;
-use crate::sys::System;
-use log::{info, trace};
-use std::collections::HashMap;
-use std::sync::Arc;
+use crate::quorum::{QuorumPolicy, Quorums};
+use crate::sink::{Decision, DecisionSink};
+use crate::sys::{
+    local_epoch_change_storage, local_state_storage, remove_local_storage, System, SystemViews,
+};
+use crate::uc::DEFAULT_DECISION_DEADLINE;
+use crate::value::{Batch, ConsensusValue};
+use log::{error, info, trace, warn};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use timer::{Guard, Timer};
 use uuid::Uuid;
 
 const ABSTRACTION_ID: &str = "app";
+/// The registration belongs to no system, the hub takes it under the id of its first one.
+const REGISTRATION_SYSTEM_ID: &str = "sys-1";
+/// How long the process waits for the hub to acknowledge its registration before registering again.
+pub const DEFAULT_REGISTRATION_INTERVAL: i64 = 1000;
+/// How many times the process registers with the hub before giving up on it.
+pub const MAX_REGISTRATION_ATTEMPTS: u32 = 10;
+/// How many of the last decided systems are remembered, so that their decisions are reused.
+const DECIDED_SYSTEMS_KEPT: usize = 1024;
+
+/// Returns the id of the system deciding the proposals `system_id` left undecided. It only
+/// depends on `system_id`, so the processes which queued the same proposals agree on it.
+pub fn follow_up_system_id(system_id: &str) -> String {
+    format!("{}+", system_id)
+}
+
+/// Called with the system id and the value of every integer decision.
+pub type DecisionCallback = Box<dyn Fn(&str, ValueType) + Send>;
+
+/// Called with the system id and the batch of every batch decision, once it is applied.
+pub type BatchCallback = Box<dyn Fn(&str, &Batch) + Send>;
+
+/// Called with the system id and the new leader whenever the leader of a system changes.
+pub type LeaderCallback = Box<dyn Fn(&str, &Node) + Send>;
+
+/// Called with the system id and the decision deadline whenever a proposal of a system goes
+/// that long without progress.
+pub type TimeoutCallback = Box<dyn Fn(&str, chrono::Duration) + Send>;
 
 pub struct App {
     current_node: Node,
-    hub: Node,
+    hub: Option<Node>,
     event_queue: Arc<EventQueue>,
+    /// The running systems, by the id their proposal carried.
     systems: HashMap<String, System>,
-    current_system_id: String,
+    /// What the embedders get to see of the running systems.
+    views: SystemViews,
+    state_dir: PathBuf,
+    store: BTreeMap<String, String>,
+    /// The values of the last decided systems, by system id, the oldest being forgotten first.
+    decisions: HashMap<String, Value>,
+    decided_order: VecDeque<String>,
+    epfd_delta: chrono::Duration,
+    epfd_startup_grace: chrono::Duration,
+    sinks: Vec<Box<dyn DecisionSink>>,
+    on_decision: DecisionCallback,
+    on_batch: BatchCallback,
+    on_leader_change: LeaderCallback,
+    on_timeout: TimeoutCallback,
+    quorums: Quorums,
+    decision_deadline: chrono::Duration,
+    report_fan_out: bool,
+    /// The registration sent to the hub, until the hub acknowledges it by sending anything.
+    registration: Option<Message>,
+    registration_attempts: u32,
+    registration_interval: chrono::Duration,
+    registration_guard: Option<Guard>,
+    timer: Mutex<Timer>,
 }
 
 impl App {
-    pub fn new(current_node: Node, hub: Node, event_queue: Arc<EventQueue>) -> App {
+    pub fn new(
+        current_node: Node,
+        hub: Option<Node>,
+        event_queue: Arc<EventQueue>,
+        state_dir: PathBuf,
+        epfd_delta: chrono::Duration,
+        epfd_startup_grace: chrono::Duration,
+        sinks: Vec<Box<dyn DecisionSink>>,
+    ) -> App {
         App {
             current_node,
             hub,
             event_queue,
             systems: HashMap::new(),
-            current_system_id: "sys-1".to_owned(),
+            views: SystemViews::default(),
+            state_dir,
+            store: BTreeMap::new(),
+            decisions: HashMap::new(),
+            decided_order: VecDeque::new(),
+            epfd_delta,
+            epfd_startup_grace,
+            sinks,
+            on_decision: Box::new(|_, _| {}),
+            on_batch: Box::new(|_, _| {}),
+            on_leader_change: Box::new(|_, _| {}),
+            on_timeout: Box::new(|_, _| {}),
+            quorums: Quorums::default(),
+            decision_deadline: chrono::Duration::milliseconds(DEFAULT_DECISION_DEADLINE),
+            report_fan_out: false,
+            registration: None,
+            registration_attempts: 0,
+            registration_interval: chrono::Duration::milliseconds(DEFAULT_REGISTRATION_INTERVAL),
+            registration_guard: None,
+            timer: Mutex::new(Timer::new()),
         }
     }
 
+    /// Registers again every `interval` rather than every `DEFAULT_REGISTRATION_INTERVAL`.
+    pub fn with_registration_interval(mut self, interval: chrono::Duration) -> Self {
+        self.registration_interval = interval;
+        self
+    }
+
+    /// Starts the systems with `quorum` rather than with a majority of their voters.
+    pub fn with_quorum(self, quorum: QuorumPolicy) -> Self {
+        self.with_quorums(Quorums::uniform(quorum))
+    }
+
+    /// Starts the systems with a read and a write quorum of their own.
+    pub fn with_quorums(mut self, quorums: Quorums) -> Self {
+        self.quorums = quorums;
+        self
+    }
+
+    /// Reports the proposals of the systems as timed out once they go without progress for
+    /// `deadline`, rather than for `DEFAULT_DECISION_DEADLINE`.
+    pub fn with_decision_deadline(mut self, deadline: chrono::Duration) -> Self {
+        self.decision_deadline = deadline;
+        self
+    }
+
+    /// Has the broadcasts of the systems report the peers their messages reached, see
+    /// `InternalMessage::BebBroadcastComplete`.
+    pub fn with_fan_out_reports(mut self, report_fan_out: bool) -> Self {
+        self.report_fan_out = report_fan_out;
+        self
+    }
+
+    /// Lets embedders observe every integer decision, e.g. to hand it back to application code.
+    /// The batches go to the batch callback instead.
+    pub fn with_decision_callback(mut self, on_decision: DecisionCallback) -> Self {
+        self.on_decision = on_decision;
+        self
+    }
+
+    /// Lets embedders observe every batch decision, which the decision callback never gets.
+    pub fn with_batch_callback(mut self, on_batch: BatchCallback) -> Self {
+        self.on_batch = on_batch;
+        self
+    }
+
+    /// Lets embedders follow the leader of every system, e.g. to route the writes of clients to it.
+    pub fn with_leader_callback(mut self, on_leader_change: LeaderCallback) -> Self {
+        self.on_leader_change = on_leader_change;
+        self
+    }
+
+    /// Lets embedders hear about the proposals making no progress, e.g. to stop waiting for them.
+    pub fn with_timeout_callback(mut self, on_timeout: TimeoutCallback) -> Self {
+        self.on_timeout = on_timeout;
+        self
+    }
+
+    /// Publishes the views of the running systems to `views`, e.g. so that embedders can find
+    /// out whom to direct their proposals to.
+    pub fn with_system_views(mut self, views: SystemViews) -> Self {
+        self.views = views;
+        self
+    }
+
+    /// Registers the process with the hub, if there is one. The hub may not be up yet, the
+    /// registration is sent again until the hub acknowledges it.
     fn init(&mut self) {
+        if self.hub.is_none() {
+            info!("Running without a hub, nothing to register with");
+            return;
+        }
         let mut app_register = AppRegistration::new();
         app_register.set_index(self.current_node.id as i32);
         app_register.set_owner(self.current_node.owner.clone());
@@ -38,65 +192,330 @@ impl App {
         initial_message.set_messageUuid(uuid.to_string());
         initial_message.set_field_type(Message_Type::APP_REGISTRATION);
         initial_message.set_appRegistration(app_register);
-        initial_message.set_systemId(self.current_system_id.clone());
+        initial_message.set_systemId(REGISTRATION_SYSTEM_ID.to_owned());
         initial_message.set_abstractionId(ABSTRACTION_ID.to_owned());
 
-        let internal_message =
-            InternalMessage::PlSend(self.current_node.clone(), self.hub.clone(), initial_message);
+        self.registration = Some(initial_message);
+        self.registration_attempts = 0;
+        self.register();
+    }
+
+    fn register(&mut self) {
+        let (hub, registration) = match (&self.hub, &self.registration) {
+            (Some(hub), Some(registration)) => (hub.clone(), registration.clone()),
+            _ => return,
+        };
+        if self.registration_attempts == MAX_REGISTRATION_ATTEMPTS {
+            warn!(
+                "The hub {} did not acknowledge any of the {} registrations, giving up",
+                hub, MAX_REGISTRATION_ATTEMPTS
+            );
+            self.stop_registering();
+            return;
+        }
+        self.registration_attempts += 1;
+        trace!("Registering with {}, attempt {}", hub, self.registration_attempts);
 
-        let event_data = EventData::Internal(self.current_system_id.clone(), internal_message);
+        let internal_message =
+            InternalMessage::PlSend(self.current_node.clone(), hub, registration);
+        let event_data = EventData::Internal(REGISTRATION_SYSTEM_ID.to_owned(), internal_message);
         self.event_queue.push(event_data);
+
+        // the queue owns the application, the timer must not keep it alive.
+        let event_queue = Arc::downgrade(&self.event_queue);
+        self.registration_guard = Some(self.timer.lock().unwrap().schedule_with_delay(
+            self.registration_interval,
+            move || {
+                if let Some(event_queue) = event_queue.upgrade() {
+                    let message = InternalMessage::AppRegistrationTimeout;
+                    event_queue.push(EventData::Internal(REGISTRATION_SYSTEM_ID.to_owned(), message));
+                }
+            },
+        ));
     }
 
+    /// Stops registering with the hub, once it acknowledged the registration or was given up on.
+    fn stop_registering(&mut self) {
+        self.registration = None;
+        self.registration_guard = None;
+    }
+
+    /// Proposals carrying a serialized payload are batches of key-value updates, all the
+    /// others are plain integers chosen by the hub. A proposal for a decided system is answered
+    /// with its decision again, e.g. for a hub which missed it.
     fn on_propose(&mut self, msg: &Message) {
-        let app_propose = msg.get_appPropose();
-        let involved_processes = app_propose.get_processes();
-        let maybe_value = app_propose.get_value();
-
-        if maybe_value.get_defined() {
-            let involved_nodes: Vec<Node> = involved_processes.iter().map(|p| p.into()).collect();
-            let node_info = Arc::new(NodeInfo {
-                current_node: self.current_node.clone(),
-                hub: self.hub.clone(),
-                nodes: involved_nodes,
-            });
-            let value = maybe_value.get_v() as ValueType;
-            let system = System::new(
-                msg.get_systemId().to_owned(),
-                node_info.clone(),
-                self.event_queue.clone(),
-                value,
+        let system_id = msg.get_systemId();
+        if self.systems.contains_key(system_id) {
+            error!("Ignoring another proposal for the system {}", system_id);
+            return;
+        }
+        if let Some(decided) = self.decisions.get(system_id).cloned() {
+            info!("System {} already decided, reporting its decision again", system_id);
+            self.report_decision(decided, system_id);
+            return;
+        }
+        let value = msg.get_appPropose().get_value();
+        if value.get_data().is_empty() {
+            if let Some(value) = ValueType::from_value(value) {
+                self.start_system(msg, value);
+            }
+        } else if let Some(batch) = Batch::from_value(value) {
+            self.start_system(msg, batch);
+        }
+    }
+
+    /// A client proposes straight to the process, without the hub. Its proposal is then
+    /// handled just like a proposal of the hub would be.
+    fn on_client_propose(&mut self, system_id: &str, msg: &Message) {
+        if system_id.is_empty() {
+            error!("Ignoring a client proposal without a system id");
+            return;
+        }
+        if self.systems.contains_key(system_id) {
+            error!("Ignoring a client proposal for the running system {}", system_id);
+            return;
+        }
+        let client_propose = msg.get_clientPropose();
+        let mut app_propose = AppPropose::new();
+        app_propose.set_value(client_propose.get_value().clone());
+        app_propose.set_processes(client_propose.get_processes().to_vec().into());
+
+        let mut propose = Message::new();
+        propose.set_messageUuid(msg.get_messageUuid().to_owned());
+        propose.set_field_type(Message_Type::APP_PROPOSE);
+        propose.set_appPropose(app_propose);
+        propose.set_systemId(system_id.to_owned());
+        self.on_propose(&propose);
+    }
+
+    /// A proposal whose value some system already decided, whatever its id, does not start a
+    /// new instance: the existing decision is reported right away under the id of the proposal.
+    /// As this process then takes no part in the new system, the other processes are expected
+    /// to answer the proposal the same way.
+    fn start_system<V: ConsensusValue>(&mut self, msg: &Message, value: V) {
+        let encoded = value.to_value();
+        let decided_by = self
+            .decisions
+            .iter()
+            .find(|(_, decided)| **decided == encoded)
+            .map(|(system_id, _)| system_id.clone());
+        if let Some(decided_by) = decided_by {
+            info!(
+                "System {} proposes the value already decided by {}, reusing the decision",
+                msg.get_systemId(),
+                decided_by
             );
+            self.report_decision(encoded, msg.get_systemId());
+            return;
+        }
 
-            self.current_system_id = format!("sys-{}", self.systems.len() + 1);
-            self.systems.insert(msg.get_systemId().to_owned(), system);
-            let proposal = InternalMessage::UcPropose(value);
-            self.event_queue
-                .push(EventData::Internal(msg.get_systemId().to_owned(), proposal));
+        let involved_processes = msg.get_appPropose().get_processes();
+        let involved_nodes: Vec<Node> = involved_processes.iter().map(|p| p.into()).collect();
+        let involved_nodes = without_hub(self.hub.as_ref(), &involved_nodes);
+        if let Err(e) = self.quorums.validate(total_votes(&involved_nodes)) {
+            error!("Unable to start system {}: {}", msg.get_systemId(), e);
+            return;
+        }
+        if let Some((node, other)) = find_rank_collision(&involved_nodes) {
+            error!(
+                "Nodes {} and {} of system {} share the rank {}, leader election is not deterministic!",
+                node,
+                other,
+                msg.get_systemId(),
+                node.rank
+            );
         }
+        // the systems send through the link of the process, which compresses on its own.
+        let node_info = Arc::new(NodeInfo {
+            epfd_delta: self.epfd_delta,
+            epfd_startup_grace: self.epfd_startup_grace,
+            quorums: self.quorums,
+            decision_deadline: self.decision_deadline,
+            report_fan_out: self.report_fan_out,
+            ..NodeInfo::new(self.current_node.clone(), self.hub.clone(), involved_nodes)
+        });
+        self.run_system::<V>(msg.get_systemId(), node_info, encoded);
+    }
+
+    /// Starts the abstractions of a system and proposes `value` to it.
+    fn run_system<V: ConsensusValue>(
+        &mut self,
+        system_id: &str,
+        node_info: Arc<NodeInfo>,
+        value: Value,
+    ) {
+        let proposal = InternalMessage::UcPropose(value);
+        let system = System::new::<V>(
+            system_id.to_owned(),
+            node_info,
+            self.event_queue.clone(),
+            local_state_storage(&self.state_dir, system_id),
+            local_epoch_change_storage(&self.state_dir, system_id),
+        );
+
+        self.views
+            .lock()
+            .unwrap()
+            .insert(system_id.to_owned(), system.view());
+        self.systems.insert(system_id.to_owned(), system);
+        self.event_queue
+            .push(EventData::Internal(system_id.to_owned(), proposal));
     }
 
-    fn on_decide(&mut self, value: &i32, system_id: &String) {
+    /// A system decides a single value, the other values proposed to it are proposed again, in
+    /// the order they were made, to its follow-up. The follow-up runs with the same members,
+    /// the first of those values starts it.
+    fn on_undecided(&mut self, value: &Value, system_id: &str) {
+        let follow_up = follow_up_system_id(system_id);
+        if self.systems.contains_key(&follow_up) {
+            let proposal = InternalMessage::UcPropose(value.clone());
+            self.event_queue.push(EventData::Internal(follow_up, proposal));
+            return;
+        }
+        info!("Proposing the values left undecided by {} to {}", system_id, follow_up);
+        let node_info = self.systems[system_id].node_info.clone();
+        if value.get_data().is_empty() {
+            self.run_system::<ValueType>(&follow_up, node_info, value.clone());
+        } else {
+            self.run_system::<Batch>(&follow_up, node_info, value.clone());
+        }
+    }
+
+    /// Proposals from the hub arrive as plain link deliveries, they are turned into
+    /// application events here so that the link itself stays unaware of them.
+    fn route_propose(&self, from: &Node, msg: &Message, system_id: &str) {
+        let internal_message = InternalMessage::AppPropose(from.clone(), msg.clone());
+        let event_data = EventData::Internal(system_id.to_owned(), internal_message);
+        self.event_queue.push(event_data);
+    }
+
+    /// Only the hub, or the process itself when there is none, changes the members of a running
+    /// system. The process itself must remain one of them, it would otherwise go on running
+    /// consensus on its own.
+    fn on_reconfigure(&mut self, from: &Node, msg: &Message, system_id: &str) {
+        let coordinator = self.hub.as_ref().unwrap_or(&self.current_node);
+        if !from.same_address(coordinator) {
+            error!("Ignoring the reconfiguration of {} sent by {}", system_id, from);
+            return;
+        }
+        if !self.systems.contains_key(system_id) {
+            error!("Unable to reconfigure the unknown system {}", system_id);
+            return;
+        }
+        let nodes: Vec<Node> = msg
+            .get_reconfigure()
+            .get_processes()
+            .iter()
+            .map(|p| p.into())
+            .collect();
+        let nodes = without_hub(self.hub.as_ref(), &nodes);
+        if !nodes.contains(&self.current_node) {
+            error!(
+                "Ignoring the reconfiguration of {} which leaves this process out",
+                system_id
+            );
+            return;
+        }
+        if let Some((node, other)) = find_rank_collision(&nodes) {
+            error!(
+                "Nodes {} and {} of system {} share the rank {}, leader election is not deterministic!",
+                node, other, system_id, node.rank
+            );
+        }
+
+        info!("Reconfiguring system {} to {} processes", system_id, nodes.len());
+        if let Some(system) = self.systems.get_mut(system_id) {
+            system.node_info = Arc::new(system.node_info.with_members(&nodes));
+        }
+        self.event_queue.push(EventData::Internal(
+            system_id.to_owned(),
+            InternalMessage::Reconfigure(nodes),
+        ));
+    }
+
+    fn on_decide(&mut self, value: ValueType, system_id: &str) {
         info!("Decided value {}", value);
+        self.event_queue.metrics().record_decision();
+        (self.on_decision)(system_id, value);
+        self.export_decision(&value, system_id);
+        self.report_decision(value.to_value(), system_id);
+        self.close_system(system_id);
+    }
+
+    /// The whole batch is applied within a single event, so no other event observes a
+    /// partially applied batch.
+    fn on_decide_batch(&mut self, batch: Batch, system_id: &str) {
+        info!("Decided batch {:?}", batch.entries);
+        self.event_queue.metrics().record_decision();
+        batch.apply_to(&mut self.store);
+        (self.on_batch)(system_id, &batch);
+        self.export_decision(&batch, system_id);
+        self.report_decision(batch.to_value(), system_id);
+        self.close_system(system_id);
+    }
+
+    /// A decided system has nothing left to do, its abstractions are let go of.
+    fn close_system(&mut self, system_id: &str) {
+        if let Some(system) = self.systems.remove(system_id) {
+            info!("Closing the decided system {}", system_id);
+            self.views.lock().unwrap().remove(system_id);
+            system.close(&self.event_queue);
+            if let Err(e) = remove_local_storage(&self.state_dir, system_id) {
+                warn!("Unable to remove the persisted state of {}: {}", system_id, e);
+            }
+        }
+    }
+
+    fn export_decision<V: ConsensusValue>(&mut self, value: &V, system_id: &str) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        let decision = match serde_json::to_value(value) {
+            Ok(value) => Decision {
+                system_id: system_id.to_owned(),
+                value,
+            },
+            Err(e) => {
+                error!("Unable to export the decision of {}: {}", system_id, e);
+                return;
+            }
+        };
+        for sink in self.sinks.iter_mut() {
+            if let Err(e) = sink.publish(&decision) {
+                error!("Unable to export the decision of {}: {}", system_id, e);
+            }
+        }
+    }
 
-        let mut maybe_value = Value::new();
-        maybe_value.set_defined(true);
-        maybe_value.set_v(*value);
+    /// Remembers the decision, and reports it to the hub if there is one.
+    fn report_decision(&mut self, value: Value, system_id: &str) {
+        if self.decisions.insert(system_id.to_owned(), value.clone()).is_none() {
+            self.decided_order.push_back(system_id.to_owned());
+        }
+        if self.decided_order.len() > DECIDED_SYSTEMS_KEPT {
+            if let Some(oldest) = self.decided_order.pop_front() {
+                self.decisions.remove(&oldest);
+            }
+        }
+        let hub = match &self.hub {
+            Some(hub) => hub.clone(),
+            None => return,
+        };
 
         let mut app_decide = AppDecide::new();
-        app_decide.set_value(maybe_value);
+        app_decide.set_value(value);
 
         let uuid = Uuid::new_v4();
         let mut msg = Message::new();
         msg.set_messageUuid(uuid.to_string());
         msg.set_field_type(Message_Type::APP_DECIDE);
         msg.set_appDecide(app_decide);
-        msg.set_systemId(system_id.clone());
+        msg.set_systemId(system_id.to_owned());
         msg.set_abstractionId(ABSTRACTION_ID.to_owned());
 
         self.event_queue.push(EventData::Internal(
-            system_id.clone(),
-            InternalMessage::PlSend(self.current_node.clone(), self.hub.clone(), msg),
+            system_id.to_owned(),
+            InternalMessage::PlSend(self.current_node.clone(), hub, msg),
         ));
     }
 }
@@ -109,13 +528,647 @@ impl EventHandler for App {
     fn handle(&mut self, event_data: &EventData) {
         trace!("Handler summoned with event {:?}", event_data);
 
+        if let EventData::External(
+            system_id,
+            msg @ Message {
+                field_type: Message_Type::CLIENT_PROPOSE,
+                ..
+            },
+        ) = event_data
+        {
+            self.on_client_propose(system_id, msg);
+        }
         if let EventData::Internal(system_id, data) = event_data {
+            if let InternalMessage::PlDeliver(from, _) = data {
+                if self.registration.is_some() && is_hub(self.hub.as_ref(), from) {
+                    trace!("The hub acknowledged the registration");
+                    self.stop_registering();
+                }
+            }
             match data {
-                InternalMessage::AppPropose(_, msg) => self.on_propose(msg), //self.on_propose(),
+                InternalMessage::PlDeliver(
+                    from,
+                    msg @ Message {
+                        field_type: Message_Type::APP_PROPOSE,
+                        ..
+                    },
+                ) => self.route_propose(from, msg, system_id),
+                InternalMessage::PlDeliver(
+                    from,
+                    msg @ Message {
+                        field_type: Message_Type::RECONFIGURE,
+                        ..
+                    },
+                ) => self.on_reconfigure(from, msg, system_id),
+                InternalMessage::AppPropose(_, msg) => self.on_propose(msg),
                 InternalMessage::AppInit => self.init(),
-                InternalMessage::UcDecide(value) => self.on_decide(value, system_id),
+                InternalMessage::AppRegistrationTimeout => self.register(),
+                InternalMessage::UcProposeTimedOut if self.systems.contains_key(system_id) => {
+                    warn!(
+                        "System {} made no progress towards a decision within {}ms",
+                        system_id,
+                        self.decision_deadline.num_milliseconds()
+                    );
+                    (self.on_timeout)(system_id, self.decision_deadline)
+                }
+                InternalMessage::LeaderChanged(leader) if self.systems.contains_key(system_id) => {
+                    info!("{} leads system {}", leader, system_id);
+                    (self.on_leader_change)(system_id, leader)
+                }
+                InternalMessage::UcUndecided(value) if self.systems.contains_key(system_id) => {
+                    self.on_undecided(value, system_id)
+                }
+                InternalMessage::UcDecide(value) if self.systems.contains_key(system_id) => {
+                    if value.get_data().is_empty() {
+                        if let Some(value) = ValueType::from_value(value) {
+                            self.on_decide(value, system_id)
+                        }
+                    } else if let Some(batch) = Batch::from_value(value) {
+                        self.on_decide_batch(batch, system_id)
+                    }
+                }
+                InternalMessage::SeqDecide(slot, value) => {
+                    if let Some(value) = ValueType::from_value(value) {
+                        info!("Decided value {} for slot {} of {}", value, slot, system_id);
+                    }
+                }
                 _ => (),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epfd::{DEFAULT_DELTA, DEFAULT_STARTUP_GRACE};
+    use crate::testing::{hub, node, state_dir, wait_until, Recorder};
+
+    /// The application of node 1, coordinated by a hub, along with what it pushes.
+    fn app(name: &str) -> (App, Recorder) {
+        app_with_hub(name, Some(hub()))
+    }
+
+    fn app_with_hub(name: &str, hub: Option<Node>) -> (App, Recorder) {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let app = App::new(
+            node(1),
+            hub,
+            event_queue,
+            state_dir(name),
+            chrono::Duration::milliseconds(DEFAULT_DELTA),
+            chrono::Duration::milliseconds(DEFAULT_STARTUP_GRACE),
+            Vec::new(),
+        );
+        (app, recorder)
+    }
+
+    fn propose(system_id: &str, value: Value) -> Message {
+        let mut app_propose = AppPropose::new();
+        app_propose.set_value(value);
+        app_propose.set_processes((1..=3).map(|id| ProcessId::from(node(id))).collect());
+
+        let mut msg = Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(Message_Type::APP_PROPOSE);
+        msg.set_appPropose(app_propose);
+        msg.set_systemId(system_id.to_owned());
+        msg
+    }
+
+    #[test]
+    fn a_proposal_delivered_by_the_link_becomes_an_application_event() {
+        let (mut app, recorder) = app("app-route");
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::PlDeliver(hub(), msg.clone()),
+        ));
+
+        wait_until(|| !recorder.events().is_empty());
+        let pushed = recorder.events();
+        assert_eq!(pushed.len(), 1);
+        match &pushed[0] {
+            EventData::Internal(system_id, InternalMessage::AppPropose(from, routed)) => {
+                assert_eq!(system_id, "sys-1");
+                assert_eq!(from, &hub());
+                assert_eq!(routed, &msg);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_system_is_tracked_under_the_id_of_its_proposal() {
+        let (mut app, _) = app("app-system-ids");
+        for (system_id, value) in &[("alpha", 1), ("beta", 2), ("alpha", 3)] {
+            let msg = propose(system_id, value.to_value());
+            app.handle(&EventData::Internal(
+                system_id.to_string(),
+                InternalMessage::AppPropose(hub(), msg),
+            ));
+        }
+
+        let mut system_ids: Vec<_> = app.systems.keys().cloned().collect();
+        system_ids.sort();
+        assert_eq!(system_ids, vec!["alpha", "beta"]);
+        assert_eq!(app.systems["alpha"].system_id, "alpha");
+        assert_eq!(app.systems["beta"].system_id, "beta");
+    }
+
+    #[test]
+    fn the_decided_systems_are_let_go_of() {
+        let (mut app, _) = app("app-close");
+        let handler_count = app.event_queue.handler_count();
+        for (system_id, value) in &[("sys-1", 1), ("sys-2", 2)] {
+            let msg = propose(system_id, value.to_value());
+            let proposal = InternalMessage::AppPropose(hub(), msg);
+            app.handle(&EventData::Internal(system_id.to_string(), proposal));
+            assert_eq!(app.systems.len(), 1);
+            assert!(app.views.lock().unwrap().contains_key(*system_id));
+
+            let decision = InternalMessage::UcDecide(value.to_value());
+            let event_data = EventData::Internal(system_id.to_string(), decision);
+            app.handle(&event_data);
+            // a decision indicated twice closes the system once.
+            app.handle(&event_data);
+            assert!(app.systems.is_empty());
+            assert!(app.views.lock().unwrap().is_empty());
+        }
+        assert_eq!(app.event_queue.handler_count(), handler_count);
+    }
+
+    #[test]
+    fn the_persisted_state_of_a_decided_system_is_removed() {
+        let (mut app, _) = app("app-close-state");
+        let msg = propose("tenant/sys-1", 1.to_value());
+        let proposal = InternalMessage::AppPropose(hub(), msg);
+        app.handle(&EventData::Internal("tenant/sys-1".to_owned(), proposal));
+        let files = ["tenant-sys-1.ep.json", "tenant-sys-1.ec.json"];
+        for file in &files {
+            std::fs::create_dir_all(&app.state_dir).unwrap();
+            std::fs::write(app.state_dir.join(file), "{}").unwrap();
+        }
+
+        let decision = InternalMessage::UcDecide(1.to_value());
+        app.handle(&EventData::Internal("tenant/sys-1".to_owned(), decision));
+        for file in &files {
+            assert!(!app.state_dir.join(file).exists());
+        }
+    }
+
+    #[test]
+    fn a_batch_is_decided_and_applied_as_a_whole() {
+        let (mut app, recorder) = app("app-batch");
+        let mut batch = Batch::new();
+        batch.insert("x", "1");
+        batch.insert("y", "2");
+
+        let msg = propose("batch-1", batch.to_value());
+        app.handle(&EventData::Internal(
+            "batch-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        assert_eq!(app.systems.len(), 1);
+
+        app.handle(&EventData::Internal(
+            "batch-1".to_owned(),
+            InternalMessage::UcDecide(batch.to_value()),
+        ));
+        assert_eq!(app.store, batch.entries);
+
+        let reported = |event_data: &EventData| match event_data {
+            EventData::Internal(_, InternalMessage::PlSend(_, to, msg)) if to == &hub() => {
+                Batch::from_value(msg.get_appDecide().get_value())
+            }
+            _ => None,
+        };
+        wait_until(|| recorder.events().iter().any(|e| reported(e).is_some()));
+        let decisions: Vec<Batch> = recorder.events().iter().filter_map(reported).collect();
+        assert_eq!(decisions, vec![batch]);
+    }
+
+    #[test]
+    fn the_values_left_undecided_are_decided_in_the_follow_up_of_the_system() {
+        let (mut app, recorder) = app("app-follow-up");
+        let msg = propose("sys-1", 4.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        // the system decides 4, 6 and 8 were proposed to it in the meantime.
+        for value in &[6, 8] {
+            let undecided = InternalMessage::UcUndecided(value.to_value());
+            app.handle(&EventData::Internal("sys-1".to_owned(), undecided));
+        }
+        let decision = InternalMessage::UcDecide(4.to_value());
+        app.handle(&EventData::Internal("sys-1".to_owned(), decision));
+
+        let follow_up = follow_up_system_id("sys-1");
+        assert_eq!(app.systems.keys().collect::<Vec<_>>(), vec![&follow_up]);
+        let members: Vec<Node> = (1..=3).map(node).collect();
+        assert_eq!(app.systems[&follow_up].node_info.nodes, members);
+        let proposals = || -> Vec<Option<ValueType>> {
+            recorder
+                .events()
+                .iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(system_id, InternalMessage::UcPropose(value))
+                        if system_id == &follow_up =>
+                    {
+                        Some(ValueType::from_value(value))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| proposals().len() == 2);
+        assert_eq!(proposals(), vec![Some(6), Some(8)]);
+
+        // the follow-up decides 6, and hands 8 on to a follow-up of its own.
+        let undecided = InternalMessage::UcUndecided(8.to_value());
+        app.handle(&EventData::Internal(follow_up.clone(), undecided));
+        let decision = InternalMessage::UcDecide(6.to_value());
+        app.handle(&EventData::Internal(follow_up.clone(), decision));
+        let decision = InternalMessage::UcDecide(8.to_value());
+        app.handle(&EventData::Internal(follow_up_system_id(&follow_up), decision));
+
+        let decisions: Vec<_> = app.decided_order.iter().map(|id| &app.decisions[id]).collect();
+        assert_eq!(decisions, vec![&4.to_value(), &6.to_value(), &8.to_value()]);
+        assert!(app.systems.is_empty());
+    }
+
+    #[test]
+    fn a_decided_value_is_reported_without_running_consensus_again() {
+        let (mut app, recorder) = app("app-decided-value");
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg.clone()),
+        ));
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::UcDecide(42.to_value()),
+        ));
+
+        // the decided system is proposed again, and the decided value under a new id.
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        let msg = propose("sys-2", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-2".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        assert!(app.systems.is_empty());
+
+        let reported = |event_data: &EventData| match event_data {
+            EventData::Internal(_, InternalMessage::PlSend(_, to, msg)) if to == &hub() => {
+                i32::from_value(msg.get_appDecide().get_value())
+                    .map(|value| (msg.get_systemId().to_owned(), value))
+            }
+            _ => None,
+        };
+        wait_until(|| recorder.events().iter().filter_map(reported).count() == 3);
+        let decisions: Vec<_> = recorder.events().iter().filter_map(reported).collect();
+        assert_eq!(
+            decisions,
+            vec![
+                ("sys-1".to_owned(), 42),
+                ("sys-1".to_owned(), 42),
+                ("sys-2".to_owned(), 42)
+            ]
+        );
+
+        // a value not decided yet runs consensus.
+        let msg = propose("sys-3", 7.to_value());
+        app.handle(&EventData::Internal(
+            "sys-3".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        assert!(app.systems.contains_key("sys-3"));
+    }
+
+    #[test]
+    fn only_the_last_decided_systems_are_remembered() {
+        let (mut app, _) = app("app-decided-systems");
+        for index in 0..=DECIDED_SYSTEMS_KEPT {
+            app.report_decision(42.to_value(), &format!("sys-{}", index));
+        }
+
+        assert_eq!(app.decisions.len(), DECIDED_SYSTEMS_KEPT);
+        assert!(!app.decisions.contains_key("sys-0"));
+        assert!(app.decisions.contains_key(&format!("sys-{}", DECIDED_SYSTEMS_KEPT)));
+    }
+
+    #[test]
+    fn a_client_proposal_starts_the_system_it_names() {
+        let (mut app, recorder) = app("app-client-propose");
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let msg = crate::client::client_propose("client-1", &42, &nodes);
+        app.handle(&EventData::External("client-1".to_owned(), msg.clone()));
+        assert!(app.systems.contains_key("client-1"));
+
+        // the same system can't be started twice.
+        app.handle(&EventData::External("client-1".to_owned(), msg));
+        assert_eq!(app.systems.len(), 1);
+
+        let proposed = || -> Vec<(String, Value)> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(system_id, InternalMessage::UcPropose(value)) => {
+                        Some((system_id, value))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| !proposed().is_empty());
+        assert_eq!(proposed(), vec![("client-1".to_owned(), 42.to_value())]);
+    }
+
+    fn reconfigure(system_id: &str, ids: &[u16]) -> Message {
+        let mut reconfigure = Reconfigure::new();
+        reconfigure.set_processes(ids.iter().map(|&id| ProcessId::from(node(id))).collect());
+
+        let mut msg = Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(Message_Type::RECONFIGURE);
+        msg.set_reconfigure(reconfigure);
+        msg.set_systemId(system_id.to_owned());
+        msg
+    }
+
+    #[test]
+    fn only_a_reconfiguration_keeping_the_process_is_applied() {
+        let (mut app, recorder) = app("app-reconfigure");
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+
+        // the process has to remain a member, the others may join and leave.
+        for ids in &[&[2, 3, 4][..], &[1, 2, 3, 4][..], &[1, 2][..]] {
+            app.handle(&EventData::Internal(
+                "sys-1".to_owned(),
+                InternalMessage::PlDeliver(hub(), reconfigure("sys-1", ids)),
+            ));
+        }
+        // neither an unknown system nor another sender than the hub can be reconfigured.
+        app.handle(&EventData::Internal(
+            "sys-2".to_owned(),
+            InternalMessage::PlDeliver(hub(), reconfigure("sys-2", &[1, 2])),
+        ));
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::PlDeliver(node(2), reconfigure("sys-1", &[1, 2])),
+        ));
+
+        let reconfigured = || -> Vec<Vec<Node>> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, InternalMessage::Reconfigure(nodes)) => Some(nodes),
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| reconfigured().len() == 2);
+        assert_eq!(
+            reconfigured(),
+            vec![(1..=4).map(node).collect(), vec![node(1), node(2)]]
+        );
+        assert_eq!(app.systems["sys-1"].node_info.nodes, vec![node(1), node(2)]);
+    }
+
+    #[test]
+    fn a_joining_process_gets_the_heartbeats_of_the_system() {
+        let (mut app, recorder) = app("app-join");
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::PlDeliver(hub(), reconfigure("sys-1", &[1, 2, 3, 4])),
+        ));
+
+        // the heartbeat requests are broadcast to the members.
+        let heartbeated = || {
+            recorder.events().iter().any(|event_data| match event_data {
+                EventData::Internal(system_id, InternalMessage::PlSend(_, to, msg)) => {
+                    system_id == "sys-1"
+                        && to == &node(4)
+                        && msg.get_bebBroadcast().get_message().get_field_type()
+                            == Message_Type::EPFD_HEARTBEAT_REQUEST
+                }
+                _ => false,
+            })
+        };
+        wait_until(heartbeated);
+    }
+
+    #[test]
+    fn every_decision_is_handed_to_the_callback() {
+        let (app, _) = app("app-callback");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut app = app.with_decision_callback(Box::new(move |system_id, value| {
+            sender.send((system_id.to_owned(), value)).unwrap();
+        }));
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::UcDecide(42.to_value()),
+        ));
+
+        assert_eq!(receiver.try_recv(), Ok(("sys-1".to_owned(), 42)));
+    }
+
+    #[test]
+    fn every_batch_decision_is_handed_to_the_batch_callback() {
+        let (app, _) = app("app-batch-callback");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut app = app
+            .with_decision_callback(Box::new(|_, _| panic!("A batch is not an integer.")))
+            .with_batch_callback(Box::new(move |system_id, batch| {
+                sender.send((system_id.to_owned(), batch.clone())).unwrap();
+            }));
+        let mut batch = Batch::new();
+        batch.insert("x", "1");
+        let msg = propose("sys-1", batch.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::UcDecide(batch.to_value()),
+        ));
+
+        assert_eq!(receiver.try_recv(), Ok(("sys-1".to_owned(), batch)));
+    }
+
+    #[test]
+    fn every_leader_change_is_handed_to_the_callback() {
+        let (app, _) = app("app-leader-callback");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut app = app.with_leader_callback(Box::new(move |system_id, leader| {
+            sender.send((system_id.to_owned(), leader.clone())).unwrap();
+        }));
+        let changed = InternalMessage::LeaderChanged(node(3));
+        // a system that is not running has no leader to follow.
+        app.handle(&EventData::Internal("sys-1".to_owned(), changed.clone()));
+        assert!(receiver.try_recv().is_err());
+
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        app.handle(&EventData::Internal("sys-1".to_owned(), changed));
+        assert_eq!(receiver.try_recv(), Ok(("sys-1".to_owned(), node(3))));
+    }
+
+    #[test]
+    fn every_timeout_is_handed_to_the_callback() {
+        let (app, _) = app("app-timeout-callback");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut app = app.with_timeout_callback(Box::new(move |system_id, deadline| {
+            sender.send((system_id.to_owned(), deadline)).unwrap();
+        }));
+        // a system that is not running has no proposal to wait for.
+        let timed_out = EventData::Internal("sys-1".to_owned(), InternalMessage::UcProposeTimedOut);
+        app.handle(&timed_out);
+        assert!(receiver.try_recv().is_err());
+
+        let msg = propose("sys-1", 42.to_value());
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::AppPropose(hub(), msg),
+        ));
+        app.handle(&timed_out);
+        assert_eq!(receiver.try_recv(), Ok(("sys-1".to_owned(), app.decision_deadline)));
+    }
+
+    #[test]
+    fn without_a_hub_a_decision_is_only_recorded() {
+        let (app, recorder) = app_with_hub("app-no-hub", None);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut app = app.with_decision_callback(Box::new(move |system_id, value| {
+            sender.send((system_id.to_owned(), value)).unwrap();
+        }));
+        app.handle(&EventData::Internal("sys-1".to_owned(), InternalMessage::AppInit));
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let msg = crate::client::client_propose("sys-1", &42, &nodes);
+        app.handle(&EventData::External("sys-1".to_owned(), msg));
+        app.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::UcDecide(42.to_value()),
+        ));
+
+        assert_eq!(receiver.try_recv(), Ok(("sys-1".to_owned(), 42)));
+        assert_eq!(app.decisions.get("sys-1"), Some(&42.to_value()));
+        // the proposal of the system started is pushed, the application itself sends nothing.
+        wait_until(|| !recorder.events().is_empty());
+        assert!(recorder.events().iter().all(|event_data| match event_data {
+            EventData::Internal(_, InternalMessage::PlSend(_, _, msg)) => {
+                msg.get_abstractionId() != ABSTRACTION_ID
+            }
+            _ => true,
+        }));
+    }
+
+    fn registrations(recorder: &Recorder) -> usize {
+        recorder
+            .events()
+            .iter()
+            .filter(|event_data| match event_data {
+                EventData::Internal(_, InternalMessage::PlSend(_, to, msg)) => {
+                    to == &hub() && msg.get_field_type() == Message_Type::APP_REGISTRATION
+                }
+                _ => false,
+            })
+            .count()
+    }
+
+    /// Stands in for a hub that only answers the `accept_at`th registration, with a proposal.
+    struct LateHub {
+        accept_at: usize,
+        registrations: usize,
+        event_queue: std::sync::Weak<EventQueue>,
+    }
+
+    impl EventHandler for LateHub {
+        fn should_handle_event(&self, _: &EventData) -> bool {
+            true
+        }
+
+        fn handle(&mut self, event_data: &EventData) {
+            if let EventData::Internal(_, InternalMessage::PlSend(_, _, msg)) = event_data {
+                if msg.get_field_type() == Message_Type::APP_REGISTRATION {
+                    self.registrations += 1;
+                    if self.registrations == self.accept_at {
+                        let proposal =
+                            InternalMessage::PlDeliver(hub(), propose("sys-1", 42.to_value()));
+                        let event_queue = self.event_queue.upgrade().unwrap();
+                        event_queue.push(EventData::Internal("sys-1".to_owned(), proposal));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the application on its queue, registering again every `interval` milliseconds.
+    fn registering_app(name: &str, interval: i64) -> (Arc<EventQueue>, Recorder) {
+        let (app, recorder) = app(name);
+        let event_queue = app.event_queue.clone();
+        let app = app.with_registration_interval(chrono::Duration::milliseconds(interval));
+        event_queue.register_handler(Box::new(app));
+        (event_queue, recorder)
+    }
+
+    #[test]
+    fn the_registration_is_sent_again_until_the_hub_acknowledges_it() {
+        let interval = 20;
+        let (event_queue, recorder) = registering_app("app-registration", interval);
+        event_queue.register_handler(Box::new(LateHub {
+            accept_at: 2,
+            registrations: 0,
+            event_queue: Arc::downgrade(&event_queue),
+        }));
+
+        event_queue.push(EventData::Internal("sys-1".to_owned(), InternalMessage::AppInit));
+        wait_until(|| {
+            recorder.events().iter().any(|event_data| {
+                matches!(event_data, EventData::Internal(_, InternalMessage::AppPropose(..)))
+            })
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(interval as u64 * 5));
+        assert_eq!(registrations(&recorder), 2);
+    }
+
+    #[test]
+    fn the_registration_is_given_up_on_after_the_last_attempt() {
+        let interval = 5;
+        let (event_queue, recorder) = registering_app("app-registration-given-up", interval);
+
+        event_queue.push(EventData::Internal("sys-1".to_owned(), InternalMessage::AppInit));
+        wait_until(|| registrations(&recorder) == MAX_REGISTRATION_ATTEMPTS as usize);
+
+        std::thread::sleep(std::time::Duration::from_millis(interval as u64 * 5));
+        assert_eq!(registrations(&recorder), MAX_REGISTRATION_ATTEMPTS as usize);
+    }
+}
@@ -2,9 +2,13 @@ use crate::event::*;
 use crate::node::*;
 use crate::protos::message;
 use log::trace;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const ABSTRACTION_ID: &str = "beb";
+/// How long the uuid of a broadcast message is remembered, to skip it being broadcast again.
+const DEDUP_WINDOW: Duration = Duration::from_secs(10);
 
 /// A broadcast abstraction enables a process to send amessage, in a one-shotoperation,
 /// to all processes in a system, including itself. We give here the specification and an
@@ -21,10 +25,27 @@ const ABSTRACTION_ID: &str = "beb";
 /// Note that broadcast messages are implicitly addressed to all processes. Remember
 /// also that messages are unique, that is, no process ever broadcasts the same message
 /// twice and furthermore, no two processes ever broadcast the same message.
+/// An upper layer pushing the same message again within DEDUP_WINDOW is ignored, so that it
+/// is not delivered twice, the messages being told apart by their uuid.
+/// When `NodeInfo::report_fan_out` is set, every message with a uuid is followed by a
+/// `BebBroadcastComplete` listing the peers that acknowledged it, once the others are suspected.
 pub struct BestEffortBroadcast {
     node_info: Arc<NodeInfo>,
     event_queue: Arc<EventQueue>,
     system_id: String,
+    /// When the messages broadcast lately were, by uuid.
+    broadcast_at: HashMap<String, Instant>,
+    /// The fan-outs still to resolve, by the uuid of their message.
+    fan_outs: HashMap<String, FanOut>,
+    /// The peers suspected to have crashed, their acknowledgements are not waited for.
+    suspected: HashSet<NodeId>,
+}
+
+/// The peers a message was sent to, split by whether they acknowledged it yet.
+#[derive(Default)]
+struct FanOut {
+    waiting: HashSet<NodeId>,
+    reached: Vec<NodeId>,
 }
 
 impl BestEffortBroadcast {
@@ -33,13 +54,94 @@ impl BestEffortBroadcast {
             node_info,
             event_queue,
             system_id,
+            broadcast_at: HashMap::new(),
+            fan_outs: HashMap::new(),
+            suspected: HashSet::new(),
+        }
+    }
+
+    /// Whether `message` was already broadcast within the window, remembering it otherwise.
+    /// The messages without a uuid can't be told apart, they are always broadcast.
+    fn already_broadcast(&mut self, message: &message::Message) -> bool {
+        let now = Instant::now();
+        self.broadcast_at.retain(|_, at| now.duration_since(*at) < DEDUP_WINDOW);
+        let uuid = message.get_messageUuid();
+        if uuid.is_empty() {
+            return false;
+        }
+        self.broadcast_at.insert(uuid.to_owned(), now).is_some()
+    }
+
+    /// The local copy is delivered straight through the event queue, so that self-delivery
+    /// does not depend on a round-trip through our own socket.
+    fn broadcast(&mut self, message: &message::Message) {
+        if self.already_broadcast(message) {
+            trace!("Skipping the broadcast of {} again", message.get_messageUuid());
+            return;
+        }
+        let current_node = &self.node_info.current_node;
+        for node in self.node_info.participants() {
+            if node == current_node {
+                self.deliver(current_node, message);
+            } else {
+                self.send(node, message);
+            }
+        }
+        if self.node_info.report_fan_out && !message.get_messageUuid().is_empty() {
+            self.track_fan_out(message.get_messageUuid());
         }
     }
 
-    fn broadcast(&self, message: &message::Message) {
-        // send the message to all other nodes
-        for node in &self.node_info.nodes {
-            self.send(node, message);
+    fn track_fan_out(&mut self, uuid: &str) {
+        let current_node = &self.node_info.current_node;
+        let waiting = self
+            .node_info
+            .participants()
+            .iter()
+            .filter(|node| *node != current_node && !self.suspected.contains(&node.id))
+            .map(|node| node.id)
+            .collect();
+        let fan_out = FanOut {
+            waiting,
+            ..FanOut::default()
+        };
+        self.fan_outs.insert(uuid.to_owned(), fan_out);
+        self.complete_fan_outs();
+    }
+
+    fn on_acknowledged(&mut self, to: &Node, uuid: &str) {
+        if let Some(fan_out) = self.fan_outs.get_mut(uuid) {
+            if fan_out.waiting.remove(&to.id) {
+                fan_out.reached.push(to.id);
+                self.complete_fan_outs();
+            }
+        }
+    }
+
+    /// A suspected peer may never acknowledge anything, it is no longer waited for.
+    fn on_suspect(&mut self, node: &Node) {
+        self.suspected.insert(node.id);
+        for fan_out in self.fan_outs.values_mut() {
+            fan_out.waiting.remove(&node.id);
+        }
+        self.complete_fan_outs();
+    }
+
+    /// Reports the fan-outs no peer is waited for anymore.
+    fn complete_fan_outs(&mut self) {
+        let completed: Vec<String> = self
+            .fan_outs
+            .iter()
+            .filter(|(_, fan_out)| fan_out.waiting.is_empty())
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+        for uuid in completed {
+            if let Some(mut fan_out) = self.fan_outs.remove(&uuid) {
+                fan_out.reached.sort_unstable();
+                let complete = InternalMessage::BebBroadcastComplete(uuid, fan_out.reached);
+                self.event_queue
+                    .push(EventData::Internal(self.system_id.clone(), complete));
+            }
         }
     }
 
@@ -49,6 +151,7 @@ impl BestEffortBroadcast {
 
         let mut message_data = message::Message::new();
         message_data.set_bebBroadcast(beb_broadcast_message);
+        message_data.set_field_type(message::Message_Type::BEB_BROADCAST);
         message_data.set_abstractionId(ABSTRACTION_ID.to_owned());
         message_data.set_systemId(self.system_id.clone());
 
@@ -68,7 +171,7 @@ impl BestEffortBroadcast {
 impl EventHandler for BestEffortBroadcast {
     fn should_handle_event(&self, event_data: &EventData) -> bool {
         if let EventData::Internal(system_id, _) = event_data {
-            system_id == &self.system_id   
+            system_id == &self.system_id && self.node_info.accepts(event_data)
         } else {
             false
         }
@@ -80,9 +183,161 @@ impl EventHandler for BestEffortBroadcast {
         if let EventData::Internal(_, data) = event_data {
             match data {
                 InternalMessage::BebBroadcast(msg) => self.broadcast(msg),
-                InternalMessage::PlDeliver(sender, msg) => self.deliver(&sender, msg),
+                InternalMessage::PlDeliver(sender, msg) => self.deliver(sender, msg),
+                InternalMessage::PlAcknowledged(to, uuid) => self.on_acknowledged(to, uuid),
+                InternalMessage::EpfdSuspect(node) => self.on_suspect(node),
+                InternalMessage::EpfdRestore(node) => {
+                    self.suspected.remove(&node.id);
+                }
+                InternalMessage::Reconfigure(nodes) => {
+                    self.node_info = Arc::new(self.node_info.with_members(nodes))
+                }
                 _ => (),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{node, node_info, wait_until, Recorder};
+
+    const SYSTEM_ID: &str = "sys-1";
+
+    /// The broadcast of node 1 in a system of the nodes 1 and 2, with its events recorded
+    /// by `recorder`.
+    fn beb(recorder: &Recorder) -> BestEffortBroadcast {
+        let nodes = vec![node(1), node(2)];
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        event_queue.register_handler(Box::new(recorder.clone()));
+        BestEffortBroadcast::new(
+            Arc::new(node_info(1, &nodes)),
+            event_queue,
+            SYSTEM_ID.to_owned(),
+        )
+    }
+
+    fn broadcast(beb: &mut BestEffortBroadcast, field_type: message::Message_Type) {
+        let mut message = message::Message::new();
+        message.set_field_type(field_type);
+        beb.handle(&EventData::Internal(
+            SYSTEM_ID.to_owned(),
+            InternalMessage::BebBroadcast(message),
+        ));
+    }
+
+    #[test]
+    fn the_sender_delivers_its_own_broadcast_exactly_once() {
+        let recorder = Recorder::default();
+        let mut beb = beb(&recorder);
+        broadcast(&mut beb, message::Message_Type::EP_WRITE_);
+
+        let events = || -> Vec<InternalMessage> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, event) => Some(event),
+                    EventData::External(..) => None,
+                })
+                .collect()
+        };
+        // the send to node 2 is pushed after the local delivery.
+        wait_until(|| {
+            events()
+                .iter()
+                .any(|event| matches!(event, InternalMessage::PlSend(..)))
+        });
+        let delivered: Vec<_> = events()
+            .into_iter()
+            .filter_map(|event| match event {
+                InternalMessage::BebDeliver(from, msg) => Some((from, msg)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(delivered.len(), 1);
+        let (from, msg) = &delivered[0];
+        assert_eq!(from, &node(1));
+        assert_eq!(msg.get_field_type(), message::Message_Type::EP_WRITE_);
+    }
+
+    #[test]
+    fn a_message_broadcast_twice_is_sent_once() {
+        let recorder = Recorder::default();
+        let mut beb = beb(&recorder);
+        let mut message = message::Message::new();
+        message.set_messageUuid("uuid-1".to_owned());
+        message.set_field_type(message::Message_Type::EC_NEW_EPOCH_);
+        for _ in 0..2 {
+            let broadcast = InternalMessage::BebBroadcast(message.clone());
+            beb.handle(&EventData::Internal(SYSTEM_ID.to_owned(), broadcast));
+        }
+        broadcast(&mut beb, message::Message_Type::EP_WRITE_);
+
+        let count = |matches: &dyn Fn(&InternalMessage) -> bool| {
+            recorder
+                .events()
+                .iter()
+                .filter(|event_data| match event_data {
+                    EventData::Internal(_, event) => matches(event),
+                    EventData::External(..) => false,
+                })
+                .count()
+        };
+        let is_new_epoch = |msg: &message::Message| {
+            msg.get_field_type() == message::Message_Type::EC_NEW_EPOCH_
+                || msg.get_bebBroadcast().get_message().get_field_type()
+                    == message::Message_Type::EC_NEW_EPOCH_
+        };
+        // the broadcast that follows is handled after the duplicate would have been.
+        wait_until(|| count(&|event| matches!(event, InternalMessage::PlSend(..))) == 2);
+        assert_eq!(
+            count(&|event| matches!(event, InternalMessage::PlSend(_, _, msg) if is_new_epoch(msg))),
+            1
+        );
+        assert_eq!(
+            count(&|event| matches!(event, InternalMessage::BebDeliver(_, msg) if is_new_epoch(msg))),
+            1
+        );
+    }
+
+    #[test]
+    fn the_complete_fan_out_lists_the_peers_reached() {
+        let recorder = Recorder::default();
+        let nodes = vec![node(1), node(2), node(3)];
+        let mut node_info = node_info(1, &nodes);
+        node_info.report_fan_out = true;
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let mut beb = BestEffortBroadcast::new(Arc::new(node_info), event_queue, SYSTEM_ID.to_owned());
+
+        let mut message = message::Message::new();
+        message.set_messageUuid("uuid-1".to_owned());
+        message.set_field_type(message::Message_Type::EP_READ_);
+        let events = [
+            InternalMessage::BebBroadcast(message),
+            InternalMessage::PlAcknowledged(node(2), "uuid-1".to_owned()),
+            // node 3 is unreachable, it never acknowledges before being suspected.
+            InternalMessage::EpfdSuspect(node(3)),
+        ];
+        for event in events {
+            beb.handle(&EventData::Internal(SYSTEM_ID.to_owned(), event));
+        }
+
+        let completed = || -> Vec<(String, Vec<NodeId>)> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, InternalMessage::BebBroadcastComplete(uuid, reached)) => {
+                        Some((uuid, reached))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| !completed().is_empty());
+        assert_eq!(completed(), vec![("uuid-1".to_owned(), vec![2])]);
+    }
+}
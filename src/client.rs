@@ -0,0 +1,33 @@
+use crate::node::Node;
+use crate::protos::message::{ClientPropose, Message, Message_Type, ProcessId};
+use crate::frame::write_frame;
+use crate::value::ConsensusValue;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use uuid::Uuid;
+
+/// The proposal of `value` in the system `system_id` made of `processes`, as sent by a client.
+pub fn client_propose<V: ConsensusValue>(system_id: &str, value: &V, processes: &[Node]) -> Message {
+    let mut client_propose = ClientPropose::new();
+    client_propose.set_value(value.to_value());
+    client_propose.set_processes(processes.iter().map(ProcessId::from).collect());
+
+    let mut msg = Message::new();
+    msg.set_messageUuid(Uuid::new_v4().to_string());
+    msg.set_field_type(Message_Type::CLIENT_PROPOSE);
+    msg.set_clientPropose(client_propose);
+    msg.set_systemId(system_id.to_owned());
+    msg
+}
+
+/// Proposes `value` to the process listening on `address`, without going through the hub.
+/// Just like the hub, a client has to propose to every process of the system.
+pub fn propose<V: ConsensusValue>(
+    address: SocketAddr,
+    system_id: &str,
+    value: &V,
+    processes: &[Node],
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    write_frame(&mut stream, &client_propose(system_id, value, processes))
+}
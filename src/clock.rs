@@ -0,0 +1,175 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use timer::Timer;
+
+/// A callback scheduled on a clock.
+pub type Callback = Box<dyn FnOnce() + Send>;
+
+/// Schedules the timeouts of the abstractions, so that tests can drive them by hand rather
+/// than wait for them, see `ManualClock`.
+pub trait Clock: Send + Sync {
+    /// Runs `f` once `delay` elapsed, unless the returned guard is dropped before.
+    fn schedule(&self, delay: chrono::Duration, f: Callback) -> Guard;
+}
+
+/// Cancels the callback it was returned with once dropped.
+pub struct Guard {
+    _cancel: Box<dyn Send>,
+}
+
+/// The wall clock, the callbacks running on the thread of a `timer::Timer`.
+pub struct SystemClock {
+    timer: Mutex<Timer>,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            timer: Mutex::new(Timer::new()),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn schedule(&self, delay: chrono::Duration, f: Callback) -> Guard {
+        let mut f = Some(f);
+        let guard = self.timer.lock().unwrap().schedule_with_delay(delay, move || {
+            if let Some(f) = f.take() {
+                f()
+            }
+        });
+        Guard {
+            _cancel: Box::new(guard),
+        }
+    }
+}
+
+/// A clock standing still until `advance` moves it forward, running the callbacks that
+/// became due on the thread advancing it. Meant for tests.
+pub struct ManualClock {
+    state: Mutex<ManualState>,
+}
+
+struct ManualState {
+    /// How far the clock was advanced since it was created.
+    now: chrono::Duration,
+    scheduled: Vec<Scheduled>,
+}
+
+struct Scheduled {
+    at: chrono::Duration,
+    cancelled: Arc<AtomicBool>,
+    f: Callback,
+}
+
+/// Marks the callback as cancelled when dropped.
+struct Cancel(Arc<AtomicBool>);
+
+impl Drop for Cancel {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            state: Mutex::new(ManualState {
+                now: chrono::Duration::zero(),
+                scheduled: Vec::new(),
+            }),
+        }
+    }
+
+    /// Moves the clock forward by `by`, running the callbacks due by then in the order they
+    /// are due. The callbacks they schedule in turn only run on a later advance.
+    pub fn advance(&self, by: chrono::Duration) {
+        let due = {
+            let mut state = self.state.lock().unwrap();
+            state.now += by;
+            let now = state.now;
+            let (mut due, pending): (Vec<_>, Vec<_>) =
+                state.scheduled.drain(..).partition(|scheduled| scheduled.at <= now);
+            state.scheduled = pending;
+            due.sort_by_key(|scheduled| scheduled.at);
+            due
+        };
+        // the callbacks may schedule again, the state must not be locked meanwhile.
+        for scheduled in due {
+            if !scheduled.cancelled.load(Ordering::SeqCst) {
+                (scheduled.f)();
+            }
+        }
+    }
+
+    /// How many callbacks are scheduled and not cancelled yet.
+    pub fn pending(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .scheduled
+            .iter()
+            .filter(|scheduled| !scheduled.cancelled.load(Ordering::SeqCst))
+            .count()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn schedule(&self, delay: chrono::Duration, f: Callback) -> Guard {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut state = self.state.lock().unwrap();
+        let at = state.now + delay;
+        state.scheduled.push(Scheduled {
+            at,
+            cancelled: cancelled.clone(),
+            f,
+        });
+        Guard {
+            _cancel: Box::new(Cancel(cancelled)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn a_callback_only_runs_once_due_and_unless_cancelled() {
+        let clock = ManualClock::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let schedule = |ms| {
+            let runs = runs.clone();
+            clock.schedule(
+                chrono::Duration::milliseconds(ms),
+                Box::new(move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                }),
+            )
+        };
+        let _kept = schedule(10);
+        let cancelled = schedule(10);
+        drop(cancelled);
+        assert_eq!(clock.pending(), 1);
+
+        clock.advance(chrono::Duration::milliseconds(9));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        clock.advance(chrono::Duration::milliseconds(1));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        clock.advance(chrono::Duration::milliseconds(100));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(clock.pending(), 0);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::node::{is_hub, Node, NodeId};
+use crate::quorum::{QuorumPolicy, Quorums};
+use log::warn;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// TOML documents can't have an array at the top level, so the nodes are listed as
+/// `[[nodes]]` tables instead.
+#[derive(Deserialize)]
+struct TomlConfig {
+    nodes: Vec<Node>,
+}
+
+/// Reads the nodes listed in a `.json`, `.toml`, `.yaml` or `.yml` file, the format being
+/// picked from the extension.
+pub fn read_config<P: AsRef<Path>>(path: P) -> io::Result<Vec<Node>> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let mut file = fs::File::open(path)?;
+    let mut contents = String::new();
+    let _ = file.read_to_string(&mut contents)?;
+
+    let nodes = match extension.as_str() {
+        "json" => serde_json::from_str(&contents).map_err(invalid_data)?,
+        "toml" => {
+            toml::from_str::<TomlConfig>(&contents)
+                .map_err(invalid_data)?
+                .nodes
+        }
+        "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(invalid_data)?,
+        _ => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Unsupported configuration file {}, expected a .json, .toml, .yaml or .yml file.",
+                path.display()
+            ),
+        )),
+    };
+    Ok(nodes)
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// A configuration the process can't safely start with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    NoNodes,
+    /// Every node is a learner, none of them can lead nor decide.
+    NoVoters,
+    DuplicateId(NodeId),
+    DuplicateAddress(NodeId, NodeId),
+    UnknownNode(NodeId),
+    /// The node casts no vote at all.
+    ZeroWeight(NodeId),
+    /// The hub has the id of a node listening on another address.
+    HubSharesId(NodeId),
+    /// The quorum can't be reached among the given number of votes, or two of its quorums
+    /// might not intersect.
+    InvalidQuorum(QuorumPolicy, usize),
+    /// The read or the write quorum can't be reached among the given number of votes, or
+    /// a read and a write quorum might not intersect.
+    UnsafeQuorums(Quorums, usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoNodes => write!(f, "The configuration does not list any node."),
+            ConfigError::NoVoters => write!(f, "Every node of the configuration is a learner."),
+            ConfigError::DuplicateId(id) => {
+                write!(f, "Several nodes of the configuration have the id {}.", id)
+            }
+            ConfigError::DuplicateAddress(node, other) => {
+                write!(f, "Nodes {} and {} listen on the same address.", node, other)
+            }
+            ConfigError::UnknownNode(id) => {
+                write!(f, "The node {} is not part of the configuration.", id)
+            }
+            ConfigError::ZeroWeight(id) => {
+                write!(f, "The node {} has a weight of 0, weights have to be positive.", id)
+            }
+            ConfigError::HubSharesId(id) => {
+                write!(f, "The hub has the id {} of another node of the configuration.", id)
+            }
+            ConfigError::InvalidQuorum(policy, n) => write!(
+                f,
+                "A quorum of {} votes out of {} either can't be reached or doesn't intersect every other.",
+                policy.threshold(*n),
+                n
+            ),
+            ConfigError::UnsafeQuorums(quorums, n) => write!(
+                f,
+                "A read quorum of {} and a write quorum of {} votes out of {} either can't be reached or don't always intersect.",
+                quorums.read.threshold(*n),
+                quorums.write.threshold(*n),
+                n
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Checks that the nodes can be told apart, both by id and by address, that they all have
+/// a positive weight, and that the process `my_id` is one of them.
+pub fn validate_config(nodes: &[Node], my_id: NodeId) -> Result<(), ConfigError> {
+    if nodes.is_empty() {
+        return Err(ConfigError::NoNodes);
+    }
+    for (index, node) in nodes.iter().enumerate() {
+        if node.weight == 0 {
+            return Err(ConfigError::ZeroWeight(node.id));
+        }
+        for other in &nodes[index + 1..] {
+            if other.id == node.id {
+                return Err(ConfigError::DuplicateId(node.id));
+            }
+            if other.same_address(node) {
+                return Err(ConfigError::DuplicateAddress(node.id, other.id));
+            }
+        }
+    }
+    if nodes.iter().all(Node::is_learner) {
+        return Err(ConfigError::NoVoters);
+    }
+    if !nodes.iter().any(|node| node.id == my_id) {
+        return Err(ConfigError::UnknownNode(my_id));
+    }
+    Ok(())
+}
+
+/// Drops the hub from the consensus members, see `node::is_hub`. The hub stays reachable
+/// through `NodeInfo::hub`, but it must not count in any quorum. A member with the id of the
+/// hub but another address can't be told apart from it by id, it is refused.
+pub fn exclude_hub(nodes: &mut Vec<Node>, hub: &Node) -> Result<(), ConfigError> {
+    if let Some(node) = nodes.iter().find(|node| node.id == hub.id && !is_hub(Some(hub), node)) {
+        return Err(ConfigError::HubSharesId(node.id));
+    }
+    nodes.retain(|node| {
+        let listed = is_hub(Some(hub), node);
+        if listed {
+            warn!("Node {} of the configuration is the hub {}, dropping it", node, hub);
+        }
+        !listed
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeRole;
+    use crate::testing::{hub, node, state_dir};
+
+    const JSON: &str = r#"[
+        {"owner": "test", "name": "node1", "host": "127.0.0.1", "port": 5001, "id": 1, "rank": 1},
+        {"owner": "test", "name": "node2", "host": "127.0.0.1", "port": 5002, "id": 2, "rank": 2}
+    ]"#;
+
+    const TOML: &str = r#"
+        [[nodes]]
+        owner = "test"
+        name = "node1"
+        host = "127.0.0.1"
+        port = 5001
+        id = 1
+        rank = 1
+
+        [[nodes]]
+        owner = "test"
+        name = "node2"
+        host = "127.0.0.1"
+        port = 5002
+        id = 2
+        rank = 2
+    "#;
+
+    const YAML: &str = "
+- owner: test
+  name: node1
+  host: 127.0.0.1
+  port: 5001
+  id: 1
+  rank: 1
+- owner: test
+  name: node2
+  host: 127.0.0.1
+  port: 5002
+  id: 2
+  rank: 2
+";
+
+    fn write_config(dir: &Path, file_name: &str, contents: &str) -> std::path::PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(file_name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn assert_same_nodes(actual: &[Node], expected: &[Node]) {
+        // `Node` equality only compares the ids.
+        let describe = |nodes: &[Node]| {
+            nodes
+                .iter()
+                .map(|node| format!("{:?}", node))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(describe(actual), describe(expected));
+    }
+
+    #[test]
+    fn every_format_yields_the_same_nodes() {
+        let dir = state_dir("every_format_yields_the_same_nodes");
+        let expected = vec![node(1), node(2)];
+
+        for (file_name, contents) in &[
+            ("nodes.json", JSON),
+            ("nodes.toml", TOML),
+            ("nodes.yaml", YAML),
+            ("nodes.yml", YAML),
+        ] {
+            let nodes = read_config(write_config(&dir, file_name, contents)).unwrap();
+            assert_same_nodes(&nodes, &expected);
+        }
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected() {
+        let dir = state_dir("unknown_extensions_are_rejected");
+        let path = write_config(&dir, "nodes.ini", JSON);
+
+        let error = read_config(path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn a_valid_configuration_is_accepted() {
+        let nodes = vec![node(1), node(2), node(3)];
+        assert_eq!(validate_config(&nodes, 2), Ok(()));
+    }
+
+    #[test]
+    fn an_empty_configuration_is_rejected() {
+        assert_eq!(validate_config(&[], 1), Err(ConfigError::NoNodes));
+    }
+
+    #[test]
+    fn a_configuration_of_learners_only_is_rejected() {
+        let nodes = vec![node(1).with_role(NodeRole::Learner), node(2).with_role(NodeRole::Learner)];
+        assert_eq!(validate_config(&nodes, 1), Err(ConfigError::NoVoters));
+
+        let nodes = vec![node(1).with_role(NodeRole::Learner), node(2)];
+        assert_eq!(validate_config(&nodes, 1), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let mut nodes = vec![node(1), node(2), node(3)];
+        nodes[2].id = 1;
+        assert_eq!(validate_config(&nodes, 1), Err(ConfigError::DuplicateId(1)));
+    }
+
+    #[test]
+    fn duplicate_addresses_are_rejected() {
+        let mut nodes = vec![node(1), node(2), node(3)];
+        nodes[2].port = nodes[1].port;
+        assert_eq!(
+            validate_config(&nodes, 1),
+            Err(ConfigError::DuplicateAddress(2, 3))
+        );
+    }
+
+    #[test]
+    fn a_node_without_any_vote_is_rejected() {
+        let nodes = vec![node(1), node(2).with_weight(0), node(3)];
+        assert_eq!(validate_config(&nodes, 1), Err(ConfigError::ZeroWeight(2)));
+    }
+
+    #[test]
+    fn the_current_node_must_be_configured() {
+        let nodes = vec![node(1), node(2), node(3)];
+        assert_eq!(validate_config(&nodes, 4), Err(ConfigError::UnknownNode(4)));
+    }
+
+    #[test]
+    fn the_hub_is_not_one_of_the_nodes() {
+        let nodes = vec![node(1), node(2), node(3)];
+        let mut by_address = nodes.clone();
+        let mut hub = node(3);
+        hub.id = 100;
+        assert_eq!(exclude_hub(&mut by_address, &hub), Ok(()));
+        assert_same_nodes(&by_address, &nodes[..2]);
+
+        // a node only sharing the id of the hub is another process, which can't be told apart.
+        let mut by_id = nodes.clone();
+        let mut hub = node(3);
+        hub.port = 9000;
+        assert_eq!(exclude_hub(&mut by_id, &hub), Err(ConfigError::HubSharesId(3)));
+        assert_same_nodes(&by_id, &nodes);
+    }
+
+    #[test]
+    fn a_hub_listed_among_the_nodes_is_not_counted_twice() {
+        // the node file lists the hub, which the hub file lists as well.
+        let mut nodes = vec![node(1), hub(), node(2), hub()];
+        assert_eq!(exclude_hub(&mut nodes, &hub()), Ok(()));
+        assert_same_nodes(&nodes, &[node(1), node(2)]);
+    }
+}
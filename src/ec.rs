@@ -1,12 +1,76 @@
 use crate::event::*;
-use crate::node::{Node, NodeInfo};
+use crate::node::{highest_ranked, Node, NodeInfo};
 use crate::protos::message::{EcNack_, EcNewEpoch_, Message, Message_Type};
-use log::trace;
+use crate::storage::Storage;
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use timer::{Guard, Timer};
 use uuid::Uuid;
 
-const N: u32 = 10;
 const ABSTRACTION_ID: &str = "ec";
+const MAX_EPOCH_CHANGES: usize = 10;
+const EPOCH_CHANGE_WINDOW: Duration = Duration::from_secs(1);
+/// The timestamps are signed on the wire, no epoch may go beyond.
+const MAX_TS: u32 = i32::MAX as u32;
+
+/// Allows at most `max` events inside of any sliding window of length `window`.
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    events: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        RateLimiter {
+            max,
+            window,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Records an event at `now` if the limit allows it.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        while let Some(oldest) = self.events.front() {
+            if now.duration_since(*oldest) >= self.window {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.events.len() < self.max {
+            self.events.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How long until `try_acquire` succeeds again.
+    pub fn time_until_available(&self, now: Instant) -> Duration {
+        match self.events.front() {
+            Some(oldest) if self.events.len() >= self.max => {
+                (*oldest + self.window).saturating_duration_since(now)
+            }
+            _ => Duration::from_secs(0),
+        }
+    }
+}
+
+/// The timestamps an epoch change must not go back on across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochChangeState {
+    pub ts: u32,
+    pub last_ts: u32,
+}
+
+pub type EpochChangeStorage = Arc<dyn Storage<EpochChangeState>>;
 
 /// The epoch-change algorithmis quite simple. Every process p maintains two timestamps:
 /// a timestamp lastts of the last epoch that it started (i.e., for which it triggered
@@ -20,49 +84,103 @@ const ABSTRACTION_ID: &str = "ec";
 /// process informs the aspiring leader ℓ with a NACK message that the new epoch could
 /// not be started. When a process receives a NACK message and still trusts itself, it increments
 /// ts by N and tries again to start an epoch by sending another NEWEPOCH message.
+/// N is the number of processes and the rank of p its position among them, from 0 to N - 1,
+/// so that the timestamps of no two leaders collide. A reconfiguration changes both at the
+/// next epoch, ts moving to the first timestamp of the new rank above the ones used so far.
+/// To protect an unstable cluster from an election storm, at most MAX_EPOCH_CHANGES epochs
+/// are started per EPOCH_CHANGE_WINDOW. Further epochs are deferred until the window allows
+/// them, only the most recent deferred epoch being started.
+/// ts and lastts are persisted, a restarted process resuming from them, so that it never
+/// broadcasts a NEWEPOCH with a timestamp it already used. Once ts would go beyond MAX_TS,
+/// the process no longer tries to lead the system, the others go on electing each other.
 pub struct EpochChange {
     node_info: Arc<NodeInfo>,
     event_queue: Arc<EventQueue>,
     last_ts: u32,
     ts: u32,
+    /// The number of processes, by which ts is incremented.
+    n: u32,
     pub trusted: Node, // needs to be accessible by UniformConsensus
     system_id: String,
+    limiter: RateLimiter,
+    deferred: Option<(Node, u32)>,
+    timer: Option<Timer>,
+    timer_guard: Option<Guard>,
+    storage: EpochChangeStorage,
 }
 
 impl EpochChange {
-    pub fn new(node_info: Arc<NodeInfo>, event_queue: Arc<EventQueue>, system_id: String) -> Self {
-        let id = node_info.current_node.id as u32;
-        let initial_trusted = node_info
-            .nodes
-            .first()
+    /// If `storage` holds the timestamps of a previous run, the epoch change resumes from them.
+    pub fn new(
+        node_info: Arc<NodeInfo>,
+        event_queue: Arc<EventQueue>,
+        system_id: String,
+        storage: EpochChangeStorage,
+    ) -> Self {
+        let n = node_info.participants().len() as u32;
+        let rank = rank_of(&node_info.current_node, node_info.participants());
+        let state = match storage.read() {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Unable to read the persisted epoch change state: {}", e);
+                }
+                EpochChangeState { ts: rank, last_ts: 0 }
+            }
+        };
+        // the leader detector is yet to trust anyone, it will pick the same process unless
+        // some are suspected by then.
+        let initial_trusted = highest_ranked(node_info.participants())
             .cloned()
             .expect("Node information must have at least one node.");
 
         EpochChange {
             node_info,
             event_queue,
-            last_ts: 0,
-            ts: id,
+            last_ts: state.last_ts,
+            ts: state.ts,
+            n,
             trusted: initial_trusted,
             system_id,
+            limiter: RateLimiter::new(MAX_EPOCH_CHANGES, EPOCH_CHANGE_WINDOW),
+            deferred: None,
+            timer: None,
+            timer_guard: None,
+            storage,
         }
     }
 
+    /// Persists the timestamps, before they are used by any message. A timestamp which could
+    /// not be persisted must not be used, the process could reuse it after a crash.
+    fn persist(&self, ts: u32, last_ts: u32) -> io::Result<()> {
+        self.storage.write(&EpochChangeState { ts, last_ts })
+    }
+
     /// upon event ⟨ Ω, Trust | p ⟩ do
     fn eld_trust(&mut self, node: &Node) {
         self.trusted = node.clone();
 
         if node == &self.node_info.current_node {
-            self.ts += N;
-            self.new_epoch(self.ts);
+            self.next_epoch();
         }
     }
 
     /// upon event ⟨ beb, Deliver | l, [NEWEPOCH, newts] ⟩ do
+    /// A NEWEPOCH of the trusted leader for the epoch we already started is a retransmission,
+    /// it is ignored instead of being answered with a NACK that would make the leader churn.
     fn beb_deliver(&mut self, node: &Node, new_ts: u32) {
         if node == &self.trusted && new_ts > self.last_ts {
+            if let Err(e) = self.persist(self.ts, new_ts) {
+                error!(
+                    "Unable to persist the epoch change state, not starting epoch {} of {}: {}",
+                    new_ts, node, e
+                );
+                return;
+            }
             self.last_ts = new_ts;
             self.start_epoch(node, new_ts);
+        } else if node == &self.trusted && new_ts == self.last_ts {
+            trace!("Ignoring retransmitted NEWEPOCH {} of {}", new_ts, node);
         } else {
             self.pl_send_nack(node);
         }
@@ -71,8 +189,28 @@ impl EpochChange {
     /// upon event ⟨ pl, Deliver | p, [NACK] ⟩ do
     fn on_nack(&mut self) {
         if self.trusted == self.node_info.current_node {
-            self.ts += N;
-            self.new_epoch(self.ts);
+            self.next_epoch();
+        }
+    }
+
+    /// ts := ts + N; trigger ⟨ beb, Broadcast | [NEWEPOCH, ts] ⟩;
+    fn next_epoch(&mut self) {
+        match self.ts.checked_add(self.n).filter(|&ts| ts <= MAX_TS) {
+            Some(ts) => {
+                if let Err(e) = self.persist(ts, self.last_ts) {
+                    error!(
+                        "Unable to persist the epoch change state, not broadcasting epoch {} of {}: {}",
+                        ts, self.system_id, e
+                    );
+                    return;
+                }
+                self.ts = ts;
+                self.new_epoch(ts);
+            }
+            None => error!(
+                "The epoch timestamps of {} are exhausted, no longer trying to lead it",
+                self.system_id
+            ),
         }
     }
 
@@ -94,11 +232,56 @@ impl EpochChange {
     }
 
     fn start_epoch(&mut self, node: &Node, ts: u32) {
+        let now = Instant::now();
+        if !self.limiter.try_acquire(now) {
+            warn!(
+                "Too many epoch changes in {}, deferring epoch {} of {}",
+                self.system_id, ts, node
+            );
+            if self.deferred.replace((node.clone(), ts)).is_none() {
+                self.schedule_deferred(self.limiter.time_until_available(now));
+            }
+            return;
+        }
+
         let message = InternalMessage::EcStartEpoch(node.clone(), ts);
         let event_data = EventData::Internal(self.system_id.clone(), message);
         self.event_queue.push(event_data);
     }
 
+    fn on_deferred_start_epoch(&mut self) {
+        if let Some((node, ts)) = self.deferred.take() {
+            self.start_epoch(&node, ts);
+        }
+    }
+
+    fn schedule_deferred(&mut self, delay: Duration) {
+        let event_queue = Arc::clone(&self.event_queue);
+        let system_id = self.system_id.clone();
+        let delay = chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+        let timer = self.timer.get_or_insert_with(Timer::new);
+        self.timer_guard = Some(timer.schedule_with_delay(delay, move || {
+            let message = InternalMessage::EcDeferredStartEpoch;
+            event_queue.push(EventData::Internal(system_id.clone(), message));
+        }));
+    }
+
+    /// The epoch in progress keeps its timestamp, the next ones are spread by the new members.
+    /// Every member moves above the timestamps it used so far, into the residue class of its
+    /// new rank, so that the leaders of the next epochs still never collide.
+    fn reconfigure(&mut self, nodes: &[Node]) {
+        self.node_info = Arc::new(self.node_info.with_members(nodes));
+        let participants = self.node_info.participants();
+        if participants.is_empty() {
+            return;
+        }
+        self.n = participants.len() as u32;
+        let rank = rank_of(&self.node_info.current_node, participants);
+        let used = self.ts.max(self.last_ts);
+        let ts = used - used % self.n + rank;
+        self.ts = if ts > used { ts } else { ts + self.n };
+    }
+
     fn pl_send_nack(&self, node: &Node) {
         let current_node = &self.node_info.current_node;
         let nack = EcNack_::new();
@@ -117,10 +300,18 @@ impl EpochChange {
     }
 }
 
+/// The position of `node` among `nodes` ordered by rank, ties being broken by id.
+fn rank_of(node: &Node, nodes: &[Node]) -> u32 {
+    nodes
+        .iter()
+        .filter(|other| (other.rank, other.id) < (node.rank, node.id))
+        .count() as u32
+}
+
 impl EventHandler for EpochChange {
     fn should_handle_event(&self, event_data: &EventData) -> bool {
         if let EventData::Internal(system_id, _) = event_data {
-            system_id == &self.system_id   
+            system_id == &self.system_id && self.node_info.accepts(event_data)
         } else {
             false
         }
@@ -132,6 +323,7 @@ impl EventHandler for EpochChange {
         if let EventData::Internal(_, internal_data) = event_data {
             match internal_data {
                 InternalMessage::EldTrust(trusted_node) => self.eld_trust(trusted_node),
+                InternalMessage::EcDeferredStartEpoch => self.on_deferred_start_epoch(),
                 InternalMessage::BebDeliver(from, msg) => {
                     if let Message {
                         field_type: Message_Type::EC_NEW_EPOCH_,
@@ -139,20 +331,281 @@ impl EventHandler for EpochChange {
                     } = msg
                     {
                         let new_ts = msg.get_ecNewEpoch_().get_timestamp();
-                        self.beb_deliver(from, new_ts as u32);
+                        match u32::try_from(new_ts) {
+                            Ok(new_ts) => self.beb_deliver(from, new_ts),
+                            Err(_) => error!(
+                                "Ignoring the NEWEPOCH of {} with the negative timestamp {}",
+                                from, new_ts
+                            ),
+                        }
                     }
                 }
-                InternalMessage::PlDeliver(_, msg) => {
-                    if let Message {
+                InternalMessage::PlDeliver(
+                    _,
+                    Message {
                         field_type: Message_Type::EC_NACK_,
                         ..
-                    } = msg
-                    {
-                        self.on_nack();
-                    }
-                }
+                    },
+                ) => self.on_nack(),
+                InternalMessage::Reconfigure(nodes) => self.reconfigure(nodes),
                 _ => (),
             }
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::testing::{node, node_info, pushed};
+
+    const SYSTEM_ID: &str = "sys-1";
+
+    /// The epoch change of `current` in a system of the nodes `1..=n`, initially trusting `n`.
+    fn epoch_change(current: u16, n: u16) -> (EpochChange, Arc<EventQueue>) {
+        epoch_change_with_storage(current, n, MemoryStorage::new())
+    }
+
+    fn epoch_change_with_storage(
+        current: u16,
+        n: u16,
+        storage: MemoryStorage<EpochChangeState>,
+    ) -> (EpochChange, Arc<EventQueue>) {
+        let nodes: Vec<Node> = (1..=n).map(node).collect();
+        let event_queue = Arc::new(EventQueue::new_inert());
+        let ec = EpochChange::new(
+            Arc::new(node_info(current, &nodes)),
+            event_queue.clone(),
+            SYSTEM_ID.to_owned(),
+            Arc::new(storage),
+        );
+        (ec, event_queue)
+    }
+
+    fn new_epoch(ts: i32) -> Message {
+        let mut new_epoch_msg = EcNewEpoch_::new();
+        new_epoch_msg.set_timestamp(ts);
+
+        let mut msg = Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(Message_Type::EC_NEW_EPOCH_);
+        msg.set_ecNewEpoch_(new_epoch_msg);
+        msg.set_abstractionId(ABSTRACTION_ID.to_owned());
+        msg.set_systemId(SYSTEM_ID.to_owned());
+        msg
+    }
+
+    fn deliver(ec: &mut EpochChange, event: InternalMessage) {
+        ec.handle(&EventData::Internal(SYSTEM_ID.to_owned(), event));
+    }
+
+    fn started_epochs(events: &[InternalMessage]) -> Vec<u32> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                InternalMessage::EcStartEpoch(_, ts) => Some(*ts),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn nacks(events: &[InternalMessage]) -> usize {
+        events
+            .iter()
+            .filter(|event| match event {
+                InternalMessage::PlSend(_, _, msg) => {
+                    msg.get_field_type() == Message_Type::EC_NACK_
+                }
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn a_retransmitted_new_epoch_is_not_nacked() {
+        let (mut ec, event_queue) = epoch_change(1, 3);
+        deliver(&mut ec, InternalMessage::EldTrust(node(3)));
+        deliver(&mut ec, InternalMessage::BebDeliver(node(3), new_epoch(2)));
+        deliver(&mut ec, InternalMessage::BebDeliver(node(3), new_epoch(2)));
+
+        let events = pushed(&event_queue);
+        assert_eq!(started_epochs(&events), vec![2]);
+        assert_eq!(nacks(&events), 0);
+
+        // an older epoch of the leader is still rejected.
+        deliver(&mut ec, InternalMessage::BebDeliver(node(3), new_epoch(1)));
+        assert_eq!(nacks(&pushed(&event_queue)), 1);
+    }
+
+    #[test]
+    fn the_limiter_frees_up_once_the_window_slides_past() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(1));
+        assert!(limiter.try_acquire(start));
+        assert!(limiter.try_acquire(start + Duration::from_millis(100)));
+        assert!(!limiter.try_acquire(start + Duration::from_millis(200)));
+        assert_eq!(
+            limiter.time_until_available(start + Duration::from_millis(200)),
+            Duration::from_millis(800)
+        );
+        assert!(limiter.try_acquire(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn a_flapping_leader_starts_a_limited_number_of_epochs() {
+        let (mut ec, event_queue) = epoch_change(1, 3);
+        // the leader flaps between 2 and 3, each of them starting a new epoch.
+        for ts in 1..=3 * MAX_EPOCH_CHANGES as u32 {
+            let leader = node(2 + (ts % 2) as u16);
+            deliver(&mut ec, InternalMessage::EldTrust(leader.clone()));
+            deliver(
+                &mut ec,
+                InternalMessage::BebDeliver(leader, new_epoch(ts as i32)),
+            );
+        }
+
+        let started = started_epochs(&pushed(&event_queue));
+        assert_eq!(started.len(), MAX_EPOCH_CHANGES);
+        assert_eq!(started, (1..=MAX_EPOCH_CHANGES as u32).collect::<Vec<_>>());
+        // the latest epoch is still started, once the window allows it.
+        assert_eq!(
+            ec.deferred.as_ref().map(|(_, ts)| *ts),
+            Some(3 * MAX_EPOCH_CHANGES as u32)
+        );
+    }
+
+    fn new_epochs(events: &[InternalMessage]) -> Vec<u32> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                InternalMessage::BebBroadcast(msg) => {
+                    Some(msg.get_ecNewEpoch_().get_timestamp() as u32)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_timestamps_of_a_leader_are_congruent_to_its_rank() {
+        let mut all = Vec::new();
+        for current in 1..=3 {
+            let (mut ec, event_queue) = epoch_change(current, 3);
+            deliver(&mut ec, InternalMessage::EldTrust(node(current)));
+            let nack = || {
+                let mut msg = Message::new();
+                msg.set_field_type(Message_Type::EC_NACK_);
+                InternalMessage::PlDeliver(node(3), msg)
+            };
+            deliver(&mut ec, nack());
+            deliver(&mut ec, nack());
+
+            let timestamps = new_epochs(&pushed(&event_queue));
+            assert_eq!(timestamps.len(), 3);
+            // the nodes 1 to 3 are ranked 0 to 2 among them.
+            let rank = current as u32 - 1;
+            assert!(timestamps.iter().all(|ts| ts % 3 == rank));
+            all.extend(timestamps);
+        }
+
+        let mut distinct = all.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), all.len());
+    }
+
+    #[test]
+    fn the_timestamps_follow_the_members_of_a_reconfiguration() {
+        let (mut ec, event_queue) = epoch_change(2, 3);
+        deliver(&mut ec, InternalMessage::EldTrust(node(2)));
+        deliver(&mut ec, InternalMessage::BebDeliver(node(2), new_epoch(4)));
+        assert_eq!(new_epochs(&pushed(&event_queue)), vec![4]);
+
+        // a fourth process joins, node 2 keeps the rank 1 among the four of them.
+        let nodes: Vec<Node> = (1..=4).map(node).collect();
+        deliver(&mut ec, InternalMessage::Reconfigure(nodes));
+        deliver(&mut ec, InternalMessage::EldTrust(node(2)));
+        let timestamps = new_epochs(&pushed(&event_queue));
+        assert_eq!(timestamps.len(), 1);
+        assert!(timestamps[0] > 4);
+        assert_eq!(timestamps[0] % 4, 1);
+
+        // node 1 leaves, node 2 is ranked 0 among the remaining three.
+        let nodes: Vec<Node> = (2..=4).map(node).collect();
+        deliver(&mut ec, InternalMessage::Reconfigure(nodes));
+        deliver(&mut ec, InternalMessage::EldTrust(node(2)));
+        let next = new_epochs(&pushed(&event_queue));
+        assert_eq!(next.len(), 1);
+        assert!(next[0] > timestamps[0]);
+        assert_eq!(next[0] % 3, 0);
+    }
+
+    #[test]
+    fn a_restarted_leader_moves_its_timestamps_forward() {
+        let storage = MemoryStorage::new();
+        let (mut ec, event_queue) = epoch_change_with_storage(3, 3, storage.clone());
+        deliver(&mut ec, InternalMessage::EldTrust(node(3)));
+        deliver(&mut ec, InternalMessage::BebDeliver(node(3), new_epoch(5)));
+        assert_eq!(new_epochs(&pushed(&event_queue)), vec![5]);
+        drop(ec);
+
+        let (mut restarted, event_queue) = epoch_change_with_storage(3, 3, storage);
+        assert_eq!(restarted.last_ts, 5);
+        deliver(&mut restarted, InternalMessage::EldTrust(node(3)));
+        assert_eq!(new_epochs(&pushed(&event_queue)), vec![8]);
+
+        // the epoch it already started is not started again.
+        deliver(&mut restarted, InternalMessage::BebDeliver(node(3), new_epoch(5)));
+        assert!(started_epochs(&pushed(&event_queue)).is_empty());
+    }
+
+    /// Refuses every write, like a full disk.
+    struct FailingStorage;
+
+    impl Storage<EpochChangeState> for FailingStorage {
+        fn write(&self, _: &EpochChangeState) -> io::Result<()> {
+            Err(io::Error::other("no space left on device"))
+        }
+
+        fn read(&self) -> io::Result<EpochChangeState> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "nothing has been written yet"))
+        }
+    }
+
+    #[test]
+    fn a_timestamp_which_could_not_be_persisted_is_not_used() {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let event_queue = Arc::new(EventQueue::new_inert());
+        let mut ec = EpochChange::new(
+            Arc::new(node_info(3, &nodes)),
+            event_queue.clone(),
+            SYSTEM_ID.to_owned(),
+            Arc::new(FailingStorage),
+        );
+
+        let ts = ec.ts;
+        deliver(&mut ec, InternalMessage::EldTrust(node(3)));
+        assert!(new_epochs(&pushed(&event_queue)).is_empty());
+        assert_eq!(ec.ts, ts);
+
+        deliver(&mut ec, InternalMessage::BebDeliver(node(3), new_epoch(6)));
+        assert!(started_epochs(&pushed(&event_queue)).is_empty());
+        assert_eq!(ec.last_ts, 0);
+    }
+
+    #[test]
+    fn neither_a_negative_nor_an_exhausted_timestamp_starts_an_epoch() {
+        let (mut ec, event_queue) = epoch_change(3, 3);
+        deliver(&mut ec, InternalMessage::EldTrust(node(2)));
+        deliver(&mut ec, InternalMessage::BebDeliver(node(2), new_epoch(-2)));
+        let events = pushed(&event_queue);
+        assert!(started_epochs(&events).is_empty());
+        assert_eq!(ec.last_ts, 0);
+
+        ec.ts = MAX_TS - 1;
+        deliver(&mut ec, InternalMessage::EldTrust(node(3)));
+        assert!(new_epochs(&pushed(&event_queue)).is_empty());
+        assert_eq!(ec.ts, MAX_TS - 1);
+    }
+}
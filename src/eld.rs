@@ -1,4 +1,6 @@
 use crate::event::*;
+use crate::history::LeadershipHistory;
+use crate::status::SystemStatus;
 use crate::node::*;
 use log::{trace, debug};
 use std::sync::Arc;
@@ -9,16 +11,26 @@ pub struct EventualLeaderDetector {
     suspected: Vec<Node>,
     leader: Option<Node>,
     system_id: String,
+    history: LeadershipHistory,
+    status: SystemStatus,
 }
 
 impl EventualLeaderDetector {
-    pub fn new(node_info: Arc<NodeInfo>, event_queue: Arc<EventQueue>, system_id: String) -> Self {
+    pub fn new(
+        node_info: Arc<NodeInfo>,
+        event_queue: Arc<EventQueue>,
+        system_id: String,
+        history: LeadershipHistory,
+        status: SystemStatus,
+    ) -> Self {
         Self {
             node_info,
             event_queue,
             suspected: Vec::new(),
             leader: None,
             system_id,
+            history,
+            status,
         }
     }
 
@@ -37,19 +49,31 @@ impl EventualLeaderDetector {
         self.check_leader();
     }
 
+    /// The processes that left can't be leaders anymore, while a joining one may well be.
+    fn reconfigure(&mut self, nodes: &[Node]) {
+        self.suspected.retain(|node| nodes.contains(node));
+        self.node_info = Arc::new(self.node_info.with_members(nodes));
+        self.check_leader();
+    }
+
     fn check_leader(&mut self) {
-        let candidates: Vec<Node> = self
+        let candidates = self
             .node_info
-            .nodes
+            .participants()
             .iter()
-            .filter(|n| !self.suspected.contains(n))
-            .cloned()
-            .collect();
-
-        let max_by_rank = candidates.iter().max_by(|&x, &y| x.rank.cmp(&y.rank)).cloned();
-        if max_by_rank.is_some() {
-            self.leader = max_by_rank;
-            let message = InternalMessage::EldTrust(self.leader.clone().unwrap());
+            .filter(|n| !self.suspected.contains(n));
+        let max_by_rank = highest_ranked(candidates).cloned();
+        // only an actual change of leader is indicated, every trust makes the epoch change
+        // abstraction attempt a new epoch.
+        if let Some(new_leader) = max_by_rank {
+            if self.leader.as_ref() == Some(&new_leader) {
+                return;
+            }
+            self.history
+                .record(self.leader.as_ref(), &new_leader, &self.suspected);
+            self.status.set_leader(&new_leader);
+            self.leader = Some(new_leader.clone());
+            let message = InternalMessage::EldTrust(new_leader);
             self.event_queue
                 .push(EventData::Internal(self.system_id.clone(), message));
         }
@@ -71,8 +95,107 @@ impl EventHandler for EventualLeaderDetector {
             match msg {
                 InternalMessage::EpfdSuspect(node) => self.on_received_suspect(node),
                 InternalMessage::EpfdRestore(node) => self.on_removed_suspect(node),
+                InternalMessage::Reconfigure(nodes) => self.reconfigure(nodes),
                 _ => (),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{node, node_info, pushed};
+
+    const SYSTEM_ID: &str = "sys-1";
+
+    /// The leader detector of node 1 in a system of the nodes 1 to 3, trusting no one yet.
+    fn leader_detector(history: LeadershipHistory) -> EventualLeaderDetector {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        leader_detector_among(1, &nodes, history)
+    }
+
+    fn leader_detector_among(
+        current: u16,
+        nodes: &[Node],
+        history: LeadershipHistory,
+    ) -> EventualLeaderDetector {
+        EventualLeaderDetector::new(
+            Arc::new(node_info(current, nodes)),
+            Arc::new(EventQueue::new_inert()),
+            SYSTEM_ID.to_owned(),
+            history,
+            SystemStatus::new(),
+        )
+    }
+
+    fn deliver(eld: &mut EventualLeaderDetector, event: InternalMessage) {
+        eld.handle(&EventData::Internal(SYSTEM_ID.to_owned(), event));
+    }
+
+    #[test]
+    fn the_history_records_every_change_of_leader() {
+        let history = LeadershipHistory::new();
+        let mut eld = leader_detector(history.clone());
+        deliver(&mut eld, InternalMessage::EpfdSuspect(node(3)));
+        deliver(&mut eld, InternalMessage::EpfdSuspect(node(2)));
+
+        let changes = history.changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].old_leader, None);
+        assert_eq!(changes[0].new_leader, node(2));
+        assert_eq!(changes[0].suspected, vec![node(3)]);
+        assert_eq!(changes[1].old_leader, Some(node(2)));
+        assert_eq!(changes[1].new_leader, node(1));
+        assert_eq!(changes[1].suspected, vec![node(3), node(2)]);
+    }
+
+    #[test]
+    fn the_leader_is_trusted_once_per_change() {
+        let mut eld = leader_detector(LeadershipHistory::new());
+        deliver(&mut eld, InternalMessage::EpfdSuspect(node(3)));
+        // neither changes the leader.
+        deliver(&mut eld, InternalMessage::EpfdSuspect(node(1)));
+        deliver(&mut eld, InternalMessage::EpfdRestore(node(1)));
+
+        let trusted: Vec<_> = pushed(&eld.event_queue)
+            .into_iter()
+            .filter_map(|event| match event {
+                InternalMessage::EldTrust(node) => Some(node),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(trusted, vec![node(2)]);
+    }
+
+    #[test]
+    fn a_joining_process_of_the_highest_rank_is_trusted() {
+        let mut eld = leader_detector(LeadershipHistory::new());
+        deliver(&mut eld, InternalMessage::EpfdSuspect(node(2)));
+        assert_eq!(eld.leader, Some(node(3)));
+
+        deliver(
+            &mut eld,
+            InternalMessage::Reconfigure(vec![node(1), node(2), node(4)]),
+        );
+        assert_eq!(eld.leader, Some(node(4)));
+        // node 3 left, node 4 leaving as well leaves node 1 as node 2 is still suspected.
+        deliver(&mut eld, InternalMessage::Reconfigure(vec![node(1), node(2)]));
+        assert_eq!(eld.leader, Some(node(1)));
+        assert_eq!(eld.suspected, vec![node(2)]);
+    }
+
+    #[test]
+    fn equal_ranks_are_resolved_by_the_higher_id() {
+        let mut nodes: Vec<Node> = (1..=4).map(node).collect();
+        for node in nodes.iter_mut().skip(1) {
+            node.rank = 7;
+        }
+
+        for current in 1..=4 {
+            let mut eld = leader_detector_among(current, &nodes, LeadershipHistory::new());
+            deliver(&mut eld, InternalMessage::EpfdSuspect(nodes[3].clone()));
+            assert_eq!(eld.leader, Some(nodes[2].clone()));
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::app::App;
+use crate::history::LeadershipChange;
+use crate::event::{EventData, EventQueue, InternalMessage};
+use crate::metrics::Metrics;
+use crate::node::{Node, NodeInfo};
+use crate::pl::PerfectLink;
+use crate::protos::message::{AppPropose, Message, Message_Type, ProcessId, Reconfigure};
+use crate::sink::DecisionSink;
+use crate::sys::SystemViews;
+use crate::timeline::{Timeline, TimelineEntry, TimelineRecorder};
+use crate::transport::{TcpTransport, Transport};
+use crate::value::{Batch, ConsensusValue};
+use crate::ValueType;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const APP_SYSTEM_ID: &str = "app_system_id";
+
+/// The ends of the channels handed out by `Engine::decisions` and `Engine::leaders`, until their
+/// receivers are dropped.
+type Subscribers<T> = Arc<Mutex<Vec<Sender<(String, T)>>>>;
+
+/// Sends `item` of `system_id` to the subscribers still listening.
+fn publish<T: Clone>(subscribers: &Subscribers<T>, system_id: &str, item: &T) {
+    let mut subscribers = subscribers.lock().unwrap();
+    subscribers.retain(|subscriber| subscriber.send((system_id.to_owned(), item.clone())).is_ok());
+}
+
+/// The consensus engine of a single process: the event queue together with the
+/// perfect link and the application layer registered on it.
+pub struct Engine {
+    node_info: Arc<NodeInfo>,
+    event_queue: Arc<EventQueue>,
+    timeline: Timeline,
+    subscribers: Subscribers<ValueType>,
+    batch_subscribers: Subscribers<Batch>,
+    leader_subscribers: Subscribers<Node>,
+    timeout_subscribers: Subscribers<chrono::Duration>,
+    views: SystemViews,
+}
+
+impl Engine {
+    /// `state_dir` is where the consensus state is persisted in order to survive crashes,
+    /// every decision is additionally exported to the given `sinks`.
+    pub fn new(
+        node_info: Arc<NodeInfo>,
+        state_dir: PathBuf,
+        sinks: Vec<Box<dyn DecisionSink>>,
+    ) -> io::Result<Self> {
+        Self::with_transport(node_info, state_dir, sinks, Arc::new(TcpTransport::new()))
+    }
+
+    /// Like `new`, but the other processes are reached through `transport` instead of TCP.
+    pub fn with_transport(
+        node_info: Arc<NodeInfo>,
+        state_dir: PathBuf,
+        sinks: Vec<Box<dyn DecisionSink>>,
+        transport: Arc<dyn Transport>,
+    ) -> io::Result<Self> {
+        Self::with_workers(node_info, state_dir, sinks, transport, 1)
+    }
+
+    /// Like `with_transport`, the events being handled by `workers` threads. The events of a
+    /// system are always handled in order, by the same one.
+    pub fn with_workers(
+        node_info: Arc<NodeInfo>,
+        state_dir: PathBuf,
+        sinks: Vec<Box<dyn DecisionSink>>,
+        transport: Arc<dyn Transport>,
+        workers: usize,
+    ) -> io::Result<Self> {
+        if node_info.epfd_delta <= chrono::Duration::zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The heartbeat timeout of the failure detector has to be positive.",
+            ));
+        }
+        if workers == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "At least one worker has to handle the events.",
+            ));
+        }
+        let event_queue = Arc::new(EventQueue::create_and_run_with_workers(workers)?);
+        let pl = PerfectLink::new(event_queue.clone(), node_info.clone(), transport);
+        let subscribers = Subscribers::default();
+        let decided = subscribers.clone();
+        let batch_subscribers = Subscribers::default();
+        let batched = batch_subscribers.clone();
+        let leader_subscribers = Subscribers::default();
+        let led = leader_subscribers.clone();
+        let timeout_subscribers = Subscribers::default();
+        let timed_out = timeout_subscribers.clone();
+        let views = SystemViews::default();
+        let app = App::new(
+            node_info.current_node.clone(),
+            node_info.hub.clone(),
+            event_queue.clone(),
+            state_dir,
+            node_info.epfd_delta,
+            node_info.epfd_startup_grace,
+            sinks,
+        )
+        .with_quorums(node_info.quorums)
+        .with_decision_deadline(node_info.decision_deadline)
+        .with_fan_out_reports(node_info.report_fan_out)
+        .with_system_views(views.clone())
+        .with_decision_callback(Box::new(move |system_id, value| {
+            publish(&decided, system_id, &value)
+        }))
+        .with_batch_callback(Box::new(move |system_id, batch| {
+            publish(&batched, system_id, batch)
+        }))
+        .with_leader_callback(Box::new(move |system_id, leader| {
+            publish(&led, system_id, leader)
+        }))
+        .with_timeout_callback(Box::new(move |system_id, deadline| {
+            publish(&timed_out, system_id, &deadline)
+        }));
+        let timeline = Timeline::new();
+        let recorder = TimelineRecorder::new(node_info.clone(), timeline.clone());
+        event_queue.register_handler(Box::new(app));
+        event_queue.register_handler(Box::new(pl));
+        event_queue.register_handler(Box::new(recorder));
+
+        Ok(Engine {
+            node_info,
+            event_queue,
+            timeline,
+            subscribers,
+            batch_subscribers,
+            leader_subscribers,
+            timeout_subscribers,
+            views,
+        })
+    }
+
+    /// Initializes the application layer, which registers the process with the hub.
+    pub fn start(&self) {
+        self.event_queue.push(EventData::Internal(
+            APP_SYSTEM_ID.to_owned(),
+            InternalMessage::AppInit,
+        ));
+    }
+
+    /// Proposes `value` in the system `system_id` made of `processes`, just like the hub
+    /// would. The application agrees on integers and on `Batch`es.
+    pub fn propose<V: ConsensusValue>(&self, system_id: &str, value: &V, processes: &[Node]) {
+        let mut app_propose = AppPropose::new();
+        app_propose.set_value(value.to_value());
+        app_propose.set_processes(processes.iter().map(ProcessId::from).collect());
+
+        let mut msg = Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(Message_Type::APP_PROPOSE);
+        msg.set_appPropose(app_propose);
+        msg.set_systemId(system_id.to_owned());
+
+        let current_node = self.node_info.current_node.clone();
+        let internal_message = InternalMessage::AppPropose(current_node, msg);
+        let event_data = EventData::Internal(system_id.to_owned(), internal_message);
+        self.event_queue.push(event_data);
+    }
+
+    /// Proposes `value` in the system `system_id` made of `processes`, then blocks until the
+    /// system decides, returning the decided value, which may be the proposal of another process.
+    /// Gives up with a `TimedOut` error once `timeout` elapses: a system may never decide, e.g.
+    /// without a quorum, or decide a batch rather than a value.
+    pub fn propose_and_wait(
+        &self,
+        system_id: &str,
+        value: ValueType,
+        processes: &[Node],
+        timeout: Duration,
+    ) -> io::Result<ValueType> {
+        // subscribing first, the decision could otherwise come before the waiter.
+        let decisions = self.decisions();
+        self.propose(system_id, &value, processes);
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let decision = decisions.recv_timeout(left).map_err(|e| match e {
+                RecvTimeoutError::Timeout => io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} was not decided within {:?}.", system_id, timeout),
+                ),
+                RecvTimeoutError::Disconnected => {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "The engine is gone.")
+                }
+            })?;
+            if decision.0 == system_id {
+                return Ok(decision.1);
+            }
+        }
+    }
+
+    /// Changes the members of the running system `system_id` to `processes`, just like the
+    /// hub would. The current epoch carries on with the previous members.
+    pub fn reconfigure(&self, system_id: &str, processes: &[Node]) {
+        let mut reconfigure = Reconfigure::new();
+        reconfigure.set_processes(processes.iter().map(ProcessId::from).collect());
+
+        let mut msg = Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(Message_Type::RECONFIGURE);
+        msg.set_reconfigure(reconfigure);
+        msg.set_systemId(system_id.to_owned());
+
+        // without a hub, the process reconfigures its systems on its own.
+        let coordinator = match &self.node_info.hub {
+            Some(hub) => hub.clone(),
+            None => self.node_info.current_node.clone(),
+        };
+        let internal_message = InternalMessage::PlDeliver(coordinator, msg);
+        let event_data = EventData::Internal(system_id.to_owned(), internal_message);
+        self.event_queue.push(event_data);
+    }
+
+    /// The integer decisions made from now on, along with the id of their system. The decisions
+    /// of a system arrive in the order they were made, every system being decided once.
+    pub fn decisions(&self) -> Receiver<(String, ValueType)> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// The batches decided from now on, along with the id of their system, just like the
+    /// integer decisions are handed out by `decisions`.
+    pub fn batches(&self) -> Receiver<(String, Batch)> {
+        let (sender, receiver) = mpsc::channel();
+        self.batch_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// The leaders of the systems from now on, along with the id of their system. A system shows
+    /// up whenever it changes leader, not for the leader it started with.
+    pub fn leaders(&self) -> Receiver<(String, Node)> {
+        let (sender, receiver) = mpsc::channel();
+        self.leader_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// The systems whose proposal goes without progress from now on, along with the decision
+    /// deadline it went over. A system may still decide afterwards, e.g. once a partition heals.
+    pub fn timeouts(&self) -> Receiver<(String, chrono::Duration)> {
+        let (sender, receiver) = mpsc::channel();
+        self.timeout_subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Whether the system `system_id` was started here and did not decide yet.
+    pub fn is_running(&self, system_id: &str) -> bool {
+        self.views.lock().unwrap().contains_key(system_id)
+    }
+
+    /// The process to direct the proposals of `system_id` to, as far as this process knows.
+    /// `None` while the system isn't running here, or while it changes epoch.
+    pub fn leader_hint(&self, system_id: &str) -> Option<Node> {
+        let views = self.views.lock().unwrap();
+        views.get(system_id).and_then(|view| view.leader_hint.leader())
+    }
+
+    /// The recorded leadership changes of the running system `system_id`, oldest first.
+    pub fn leadership_history(&self, system_id: &str) -> Vec<LeadershipChange> {
+        let views = self.views.lock().unwrap();
+        views
+            .get(system_id)
+            .map(|view| view.leadership_history.changes())
+            .unwrap_or_default()
+    }
+
+    pub fn node_info(&self) -> Arc<NodeInfo> {
+        self.node_info.clone()
+    }
+
+    pub fn event_queue(&self) -> Arc<EventQueue> {
+        self.event_queue.clone()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.event_queue.metrics()
+    }
+
+    /// The messages sent and delivered for `system_id`, in order.
+    pub fn timeline(&self, system_id: &str) -> Vec<TimelineEntry> {
+        self.timeline.timeline(system_id)
+    }
+}
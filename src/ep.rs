@@ -1,9 +1,14 @@
 use crate::event::*;
-use crate::node::{Node, NodeId, NodeInfo};
+use crate::node::{total_votes, Node, NodeId, NodeInfo};
 use crate::protos::message;
-use log::trace;
+use crate::quorum::QuorumCounter;
+use crate::storage::Storage;
+use crate::value::{to_maybe_value, ConsensusValue};
+use log::{debug, error, trace, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -18,15 +23,16 @@ const ABSTRACTION_ID: &str = "ep";
 /// Indication: ⟨ ep, Decide | v ⟩: Outputs a decided value v of epoch consensus.
 /// Indication: ⟨ ep, Aborted | state ⟩: Signals hat epoch consensus has completed the
 /// abort and outputs internal state state.
-
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Copy, Clone)]
-pub struct EpochConsensusState {
+///
+/// The (valts, val) pair of the algorithm; `None` stands for the undefined value ⊥.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct EpochConsensusState<V> {
     pub value_timestamp: u32,
-    pub value: ValueType,
+    pub value: Option<V>,
 }
 
-impl EpochConsensusState {
-    pub fn new(value_timestamp: u32, value: ValueType) -> Self {
+impl<V> EpochConsensusState<V> {
+    pub fn new(value_timestamp: u32, value: Option<V>) -> Self {
         EpochConsensusState {
             value_timestamp,
             value,
@@ -34,85 +40,171 @@ impl EpochConsensusState {
     }
 }
 
-impl PartialOrd<EpochConsensusState> for EpochConsensusState {
-    fn partial_cmp(&self, other: &EpochConsensusState) -> Option<std::cmp::Ordering> {
-        self.value_timestamp.partial_cmp(&other.value_timestamp)
+impl<V: Eq> PartialOrd<EpochConsensusState<V>> for EpochConsensusState<V> {
+    fn partial_cmp(&self, other: &EpochConsensusState<V>) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl Ord for EpochConsensusState {
+impl<V: Eq> Ord for EpochConsensusState<V> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.value_timestamp.cmp(&other.value_timestamp)
     }
 }
 
-pub struct EpochConsensus {
+/// The state with the highest value timestamp, or `None` if there are no states.
+/// The first epoch has timestamp 0, just like the initial state, so on equal timestamps a
+/// written value wins over ⊥, otherwise a value decided in the first epoch could be lost.
+/// Remaining ties are resolved in favour of the state reported by the process with the
+/// highest id, independently of the iteration order of the map, so that the chosen value
+/// is deterministic.
+fn highest<V>(states: &BTreeMap<NodeId, EpochConsensusState<V>>) -> Option<&EpochConsensusState<V>> {
+    states
+        .iter()
+        .max_by_key(|(id, state)| (state.value_timestamp, state.value.is_some(), **id))
+        .map(|(_, state)| state)
+}
+
+/// The timestamp of the epoch `msg` belongs to, or `None` if it isn't a message of an instance.
+pub fn epoch_of(msg: &message::Message) -> Option<u32> {
+    msg.get_abstractionId()
+        .strip_prefix(ABSTRACTION_ID)
+        .and_then(|ts| ts.parse().ok())
+}
+
+/// Where an epoch consensus instance keeps its state so that it survives a crash.
+/// It is shared by all the instances of a system, each one taking over the state of the previous.
+pub type StateStorage<V> = Arc<dyn Storage<EpochConsensusState<V>>>;
+
+pub struct EpochConsensus<V> {
     node_info: Arc<NodeInfo>,
     event_queue: Arc<EventQueue>,
-    temporary_value: ValueType,
-    states: BTreeMap<NodeId, EpochConsensusState>,
-    accepted: u32,
-    state: EpochConsensusState,
+    temporary_value: Option<V>,
+    states: BTreeMap<NodeId, EpochConsensusState<V>>,
+    state_senders: QuorumCounter,
+    accepted: QuorumCounter,
+    state: EpochConsensusState<V>,
+    storage: StateStorage<V>,
     aborted: bool,
     leader: Node, // TOOD: use this to check if we have to do anything (probably)
     epoch_ts: u32,
     system_id: String,
-    index: usize,
+    handler_id: Option<HandlerId>,
 }
 
-impl EpochConsensus {
+impl<V: ConsensusValue> EpochConsensus<V> {
+    /// If `storage` holds a state more recent than `initial_state`, the instance resumes from it.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         node_info: Arc<NodeInfo>,
         event_queue: Arc<EventQueue>,
-        initial_state: EpochConsensusState,
+        initial_state: EpochConsensusState<V>,
+        storage: StateStorage<V>,
         leader: Node,
         epoch_ts: u32,
         system_id: String,
-        index: usize,
     ) -> Self {
+        let state = match storage.read() {
+            Ok(persisted) if persisted.value_timestamp > initial_state.value_timestamp => persisted,
+            Ok(_) => initial_state,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Unable to read the persisted epoch consensus state: {}", e);
+                }
+                initial_state
+            }
+        };
+
+        // learners get the decision, but are not counted, the voters cast as many votes as
+        // their weight.
+        let votes = total_votes(node_info.participants());
+        let (read, write) = (
+            node_info.quorums.read.threshold(votes),
+            node_info.quorums.write.threshold(votes),
+        );
+        let weights = node_info.weights();
         EpochConsensus {
             node_info,
             event_queue,
-            temporary_value: ValueType::default(),
+            temporary_value: None,
             states: BTreeMap::new(),
-            accepted: 0,
-            state: initial_state,
+            state_senders: QuorumCounter::weighted(read, weights.clone()),
+            accepted: QuorumCounter::weighted(write, weights),
+            state,
+            storage,
             aborted: false,
             leader,
             epoch_ts,
             system_id,
-            index
+            handler_id: None,
         }
     }
 
+    /// Every process derives the id of an instance from its epoch timestamp, which tells
+    /// apart the messages of different epochs even when they reach the same instance.
+    fn abstraction_id(&self) -> String {
+        format!("{}{}", ABSTRACTION_ID, self.epoch_ts)
+    }
+
+    /// A late message of a previous epoch must not be taken for one of this epoch.
+    fn is_of_this_epoch(&self, msg: &message::Message) -> bool {
+        let of_this_epoch = epoch_of(msg) == Some(self.epoch_ts);
+        if !of_this_epoch {
+            trace!("Epoch {} ignores a message of {}", self.epoch_ts, msg.get_abstractionId());
+        }
+        of_this_epoch
+    }
+
+    fn is_voter(&self) -> bool {
+        self.node_info.is_voter(&self.node_info.current_node)
+    }
+
+    /// The id the instance is registered under, it deregisters itself once aborted.
+    pub fn with_handler_id(mut self, handler_id: HandlerId) -> Self {
+        self.handler_id = Some(handler_id);
+        self
+    }
+
     /// upon event ⟨ ep, Propose | v ⟩ do
     /// only leader l.
-    fn ep_propose(&mut self, time_stamp: u32, value: ValueType) {
+    fn ep_propose(&mut self, time_stamp: u32, value: &message::Value) {
         if self.epoch_ts == time_stamp && self.node_info.current_node == self.leader {
-            self.temporary_value = value;
+            self.temporary_value = V::from_value(value);
             self.beb_broadcast_read();
         }
     }
 
     /// upon event ⟨ beb, Deliver | l, [READ] ⟩ do
+    /// only voters.
     fn beb_deliver_read(&self, from: &Node) {
-        self.pl_send_state(from);
+        if self.is_voter() {
+            self.pl_send_state(from);
+        }
     }
 
     /// upon event ⟨ pl, Deliver | q, [STATE, ts, v] ⟩ do
     /// only leader l.
+    /// A negative timestamp would otherwise be read as a huge one, outranking every other state.
     fn pl_deliver_state(&mut self, from: &Node, msg: &message::EpState_) {
-        if self.node_info.current_node == self.leader {
-            let value_timestamp = msg.get_valueTimestamp() as u32;
-            let value = msg.get_value();
-            if value.get_defined() {
-                let state = EpochConsensusState::new(value_timestamp, value.get_v());
-                self.states.insert(from.id, state);
-                if self.states.len() >= self.node_info.nodes.len() / 2 {
-                    let states_message = InternalMessage::EpStateCountReached;
-                    let event_data = EventData::Internal(self.system_id.clone(), states_message);
-                    self.event_queue.push(event_data);
+        if self.node_info.current_node == self.leader && self.node_info.is_voter(from) {
+            let value_timestamp = match u32::try_from(msg.get_valueTimestamp()) {
+                Ok(value_timestamp) => value_timestamp,
+                Err(_) => {
+                    error!(
+                        "Ignoring the STATE of {} with the negative timestamp {}",
+                        from,
+                        msg.get_valueTimestamp()
+                    );
+                    return;
                 }
+            };
+            let value = V::from_value(msg.get_value());
+            let state = EpochConsensusState::new(value_timestamp, value);
+            self.states.insert(from.id, state);
+            if self.state_senders.add(from.id) {
+                let states_message = InternalMessage::EpStateCountReached;
+                let event_data = EventData::Internal(self.system_id.clone(), states_message);
+                self.event_queue.push(event_data);
             }
         }
     }
@@ -121,46 +213,59 @@ impl EpochConsensus {
     /// only leader l.
     fn ep_state_count_reached(&mut self) {
         if self.node_info.current_node == self.leader {
-            let highest_timestamp = self.states.iter().max_by(|(_, x), (_, y)| x.cmp(y));
-            if let Some((_, state)) = highest_timestamp {
-                self.temporary_value = state.value;
+            // (ts, v) := highest(states); if v ≠ ⊥ then tmpval := v;
+            if let Some(EpochConsensusState {
+                value: Some(value), ..
+            }) = highest(&self.states)
+            {
+                self.temporary_value = Some(value.clone());
             }
             self.states.clear();
-            self.beb_broadcast_write(self.temporary_value);
+            self.state_senders.reset();
+            if let Some(value) = self.temporary_value.clone() {
+                self.beb_broadcast_write(&value);
+            }
         }
     }
 
     /// upon event ⟨ beb, Deliver | ℓ, [WRITE, v] ⟩ do
-    fn beb_deliver_write(&mut self, from: &Node, msg: &message::EpWrite_) {
-        let value_from = msg.get_value();
-        if value_from.get_defined() {
+    /// only voters.
+    fn beb_deliver_write(&mut self, from: &Node, msg: &message::Message) {
+        if !self.is_voter() || !self.is_of_this_epoch(msg) {
+            return;
+        }
+        let msg = msg.get_epWrite_();
+        if let Some(value) = V::from_value(msg.get_value()) {
             self.state.value_timestamp = self.epoch_ts;
-            self.state.value = value_from.get_v() as ValueType;
-            self.pl_send_accept(from);
+            self.state.value = Some(value);
+
+            // the accept may only be sent once the state is durable, otherwise a crash
+            // could make us forget a value we helped deciding.
+            match self.storage.write(&self.state) {
+                Ok(()) => self.pl_send_accept(from),
+                Err(e) => error!("Unable to persist the epoch consensus state: {}", e),
+            }
         }
     }
 
     /// upon event ⟨ pl, Deliver | q, [ACCEPT] ⟩ do
-    fn pl_deliver_accept(&mut self) {
-        if self.node_info.current_node == self.leader {
-            self.accepted += 1;
-            if self.accepted as usize >= self.node_info.nodes.len() / 2 {
-                let accepted_message = InternalMessage::EpAcceptedCountReached;
-                let event_data = EventData::Internal(self.system_id.clone(), accepted_message);
-                self.event_queue.push(event_data);
-            }
+    fn pl_deliver_accept(&mut self, from: &Node) {
+        if self.node_info.current_node == self.leader
+            && self.node_info.is_voter(from)
+            && self.accepted.add(from.id)
+        {
+            let accepted_message = InternalMessage::EpAcceptedCountReached;
+            let event_data = EventData::Internal(self.system_id.clone(), accepted_message);
+            self.event_queue.push(event_data);
         }
     }
 
     /// upon accepted > N/2 do
     fn ep_accepted_count_reached(&mut self) {
         if self.node_info.current_node == self.leader {
-            self.accepted = 0;
+            self.accepted.reset();
             let mut decided_message = message::EpDecided_::new();
-            let mut msg_value = message::Value::new();
-            msg_value.set_defined(true);
-            msg_value.set_v(self.temporary_value as i32);
-            decided_message.set_value(msg_value);
+            decided_message.set_value(to_maybe_value(self.temporary_value.as_ref()));
 
             let uuid = Uuid::new_v4();
             let mut msg = message::Message::new();
@@ -168,7 +273,7 @@ impl EpochConsensus {
             msg.set_epDecided_(decided_message);
             msg.set_field_type(message::Message_Type::EP_DECIDED_);
             msg.set_systemId(self.system_id.clone());
-            msg.set_abstractionId(format!("{}{}", ABSTRACTION_ID.to_owned(), self.index));
+            msg.set_abstractionId(self.abstraction_id());
 
             let broadcast_message = InternalMessage::BebBroadcast(msg);
             let event_data = EventData::Internal(self.system_id.clone(), broadcast_message);
@@ -177,27 +282,39 @@ impl EpochConsensus {
     }
 
     /// upon event ⟨ beb, Deliver | ℓ, [DECIDED, v] ⟩ do
-    fn beb_deliver_decided(&self, msg: &message::EpDecided_) {
-        let value = msg.get_value();
-        if value.get_defined() {
-            let internal_message =
-                InternalMessage::EpDecide(self.epoch_ts, value.get_v() as ValueType);
+    fn beb_deliver_decided(&self, msg: &message::Message) {
+        if !self.is_of_this_epoch(msg) {
+            return;
+        }
+        let msg = msg.get_epDecided_();
+        if let Some(value) = V::from_value(msg.get_value()) {
+            let internal_message = InternalMessage::EpDecide(self.epoch_ts, value.to_value());
             let event_data = EventData::Internal(self.system_id.clone(), internal_message);
             self.event_queue.push(event_data);
         }
     }
 
     /// upon event ⟨ ep, Abort ⟩ do
+    /// The aborted state is indicated once, whatever the number of aborts of the epoch.
     fn abort(&mut self, ts: u32) {
-        if self.epoch_ts == ts {
+        if self.aborted {
+            debug!("Ignoring another abort of the aborted epoch {}", self.epoch_ts);
+        } else if self.epoch_ts != ts {
+            debug!("Ignoring the abort of epoch {} in epoch {}", ts, self.epoch_ts);
+        } else {
             self.aborted = true;
             let internal_message = InternalMessage::EpAborted(
                 self.epoch_ts,
                 self.state.value_timestamp,
-                self.state.value,
+                to_maybe_value(self.state.value.as_ref()),
             );
             let event_data = EventData::Internal(self.system_id.clone(), internal_message);
             self.event_queue.push(event_data);
+
+            // an aborted instance must not keep answering for this system.
+            if let Some(handler_id) = self.handler_id {
+                self.event_queue.deregister_handler(handler_id);
+            }
         }
     }
 
@@ -211,7 +328,7 @@ impl EpochConsensus {
         message.set_epAccept_(accept_message);
         message.set_field_type(message::Message_Type::EP_ACCEPT_);
         message.set_systemId(self.system_id.clone());
-        message.set_abstractionId(format!("{}{}", ABSTRACTION_ID.to_owned(), self.index));
+        message.set_abstractionId(self.abstraction_id());
 
         let internal_message =
             InternalMessage::PlSend(current_node.clone(), receiver.clone(), message);
@@ -223,10 +340,7 @@ impl EpochConsensus {
         let current_node = &self.node_info.current_node;
         println!("Sending state {:?}", self.state);
         let mut state_message = message::EpState_::new();
-        let mut msg_value = message::Value::new();
-        msg_value.set_defined(true);
-        msg_value.set_v(self.state.value);
-        state_message.set_value(msg_value);
+        state_message.set_value(to_maybe_value(self.state.value.as_ref()));
         state_message.set_valueTimestamp(self.state.value_timestamp as i32);
 
         let uuid = Uuid::new_v4();
@@ -235,7 +349,7 @@ impl EpochConsensus {
         message.set_epState_(state_message);
         message.set_field_type(message::Message_Type::EP_STATE_);
         message.set_systemId(self.system_id.clone());
-        message.set_abstractionId(format!("{}{}", ABSTRACTION_ID.to_owned(), self.index));
+        message.set_abstractionId(self.abstraction_id());
 
         let internal_message =
             InternalMessage::PlSend(current_node.clone(), receiver.clone(), message);
@@ -252,19 +366,16 @@ impl EpochConsensus {
         message.set_field_type(message::Message_Type::EP_READ_);
         message.set_epRead_(read_message);
         message.set_systemId(self.system_id.clone());
-        message.set_abstractionId(format!("{}{}", ABSTRACTION_ID.to_owned(), self.index));
+        message.set_abstractionId(self.abstraction_id());
 
         let internal_message = InternalMessage::BebBroadcast(message);
         let event_data = EventData::Internal(self.system_id.clone(), internal_message);
         self.event_queue.push(event_data);
     }
 
-    fn beb_broadcast_write(&self, value: ValueType) {
+    fn beb_broadcast_write(&self, value: &V) {
         let mut write_message = message::EpWrite_::new();
-        let mut msg_value = message::Value::new();
-        msg_value.set_defined(true);
-        msg_value.set_v(value);
-        write_message.set_value(msg_value);
+        write_message.set_value(value.to_value());
 
         let uuid = Uuid::new_v4();
         let mut message = message::Message::new();
@@ -272,7 +383,7 @@ impl EpochConsensus {
         message.set_field_type(message::Message_Type::EP_WRITE_);
         message.set_epWrite_(write_message);
         message.set_systemId(self.system_id.clone());
-        message.set_abstractionId(format!("{}{}", ABSTRACTION_ID.to_owned(), self.index));
+        message.set_abstractionId(self.abstraction_id());
 
         let internal_message = InternalMessage::BebBroadcast(message);
         let event_data = EventData::Internal(self.system_id.clone(), internal_message);
@@ -280,12 +391,18 @@ impl EpochConsensus {
     }
 }
 
-impl EventHandler for EpochConsensus {
+impl<V: ConsensusValue> EventHandler for EpochConsensus<V> {
     fn should_handle_event(&self, event_data: &EventData) -> bool {
-        if let EventData::Internal(system_id, _) = event_data {
-            system_id == &self.system_id   
-        } else {
-            false
+        match event_data {
+            // a straggler of another epoch must not be answered as if it were of ours.
+            EventData::Internal(system_id, InternalMessage::BebDeliver(_, msg))
+            | EventData::Internal(system_id, InternalMessage::PlDeliver(_, msg)) => {
+                system_id == &self.system_id
+                    && epoch_of(msg) == Some(self.epoch_ts)
+                    && self.node_info.accepts(event_data)
+            }
+            EventData::Internal(system_id, _) => system_id == &self.system_id,
+            EventData::External(..) => false,
         }
     }
 
@@ -293,67 +410,39 @@ impl EventHandler for EpochConsensus {
         trace!("Handler summoned with event {:?}", event_data);
         match event_data {
             EventData::Internal(_, internal_msg) => match internal_msg {
-                InternalMessage::EpPropose(ts, value) => self.ep_propose(*ts, *value),
+                InternalMessage::EpPropose(ts, value) => self.ep_propose(*ts, value),
                 InternalMessage::BebDeliver(from, msg) => match msg {
                     message::Message {
                         field_type: message::Message_Type::EP_READ_,
                         ..
-                    } => {
-                        if !self.aborted {
-                            self.beb_deliver_read(from)
-                        }
-                    }
+                    } if !self.aborted => self.beb_deliver_read(from),
                     message::Message {
                         field_type: message::Message_Type::EP_WRITE_,
                         ..
-                    } => {
-                        if !self.aborted {
-                            self.beb_deliver_write(from, msg.get_epWrite_())
-                        }
-                    }
+                    } if !self.aborted => self.beb_deliver_write(from, msg),
                     message::Message {
                         field_type: message::Message_Type::EP_DECIDED_,
                         ..
-                    } => {
-                        if !self.aborted {
-                            self.beb_deliver_decided(msg.get_epDecided_())
-                        }
-                    }
+                    } if !self.aborted => self.beb_deliver_decided(msg),
                     _ => (),
                 },
                 InternalMessage::PlDeliver(from, msg) => match msg {
                     message::Message {
                         field_type: message::Message_Type::EP_STATE_,
                         ..
-                    } => {
-                        if !self.aborted {
-                            self.pl_deliver_state(from, msg.get_epState_());
-                        }
-                    }
+                    } if !self.aborted => self.pl_deliver_state(from, msg.get_epState_()),
                     message::Message {
                         field_type: message::Message_Type::EP_ACCEPT_,
                         ..
-                    } => {
-                        if !self.aborted {
-                            self.pl_deliver_accept()
-                        };
-                    }
+                    } if !self.aborted => self.pl_deliver_accept(from),
                     _ => (),
                 },
-                InternalMessage::EpAbort(ts) => {
-                    if !self.aborted {
-                        self.abort(*ts)
-                    }
+                InternalMessage::EpAbort(ts) => self.abort(*ts),
+                InternalMessage::EpStateCountReached if !self.aborted => {
+                    self.ep_state_count_reached()
                 }
-                InternalMessage::EpStateCountReached => {
-                    if !self.aborted {
-                        self.ep_state_count_reached()
-                    }
-                }
-                InternalMessage::EpAcceptedCountReached => {
-                    if !self.aborted {
-                        self.ep_accepted_count_reached()
-                    }
+                InternalMessage::EpAcceptedCountReached if !self.aborted => {
+                    self.ep_accepted_count_reached()
                 }
                 _ => (),
             },
@@ -361,3 +450,423 @@ impl EventHandler for EpochConsensus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeRole;
+    use crate::quorum::{QuorumPolicy, Quorums};
+    use crate::storage::MemoryStorage;
+    use crate::testing::{node, node_info, pushed, wait_until, Recorder};
+    use crate::value::ConsensusValue;
+
+    const SYSTEM_ID: &str = "sys-1";
+
+    /// The instance of epoch 0 led by node 1, as seen by node 1 in a system of the nodes
+    /// `1..=n`, with its events recorded by `recorder`.
+    fn instance(n: u16, recorder: &Recorder) -> EpochConsensus<i32> {
+        instance_with_storage(1, 1, n, 0, MemoryStorage::new(), recorder)
+    }
+
+    /// The instance of epoch `epoch_ts` led by `leader`, as seen by `current` in a system of
+    /// the nodes `1..=n`.
+    fn instance_with_storage(
+        current: u16,
+        leader: u16,
+        n: u16,
+        epoch_ts: u32,
+        storage: MemoryStorage<EpochConsensusState<i32>>,
+        recorder: &Recorder,
+    ) -> EpochConsensus<i32> {
+        let nodes: Vec<Node> = (1..=n).map(node).collect();
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        event_queue.register_handler(Box::new(recorder.clone()));
+        EpochConsensus::new(
+            Arc::new(node_info(current, &nodes)),
+            event_queue,
+            EpochConsensusState::new(0, None),
+            Arc::new(storage),
+            node(leader),
+            epoch_ts,
+            SYSTEM_ID.to_owned(),
+        )
+    }
+
+    /// The instance of epoch `epoch_ts` led by node 1, as seen by `node_info`, on an inert
+    /// queue: what it pushes is only taken with `pushed`, so that whatever it must not push is
+    /// checked without waiting.
+    fn inert_instance(node_info: NodeInfo, epoch_ts: u32) -> EpochConsensus<i32> {
+        EpochConsensus::new(
+            Arc::new(node_info),
+            Arc::new(EventQueue::new_inert()),
+            EpochConsensusState::new(0, None),
+            Arc::new(MemoryStorage::new()),
+            node(1),
+            epoch_ts,
+            SYSTEM_ID.to_owned(),
+        )
+    }
+
+    /// How many of `events` are `EpStateCountReached` and `EpAcceptedCountReached`.
+    fn quorums_reached(events: &[InternalMessage]) -> (usize, usize) {
+        let states = events
+            .iter()
+            .filter(|event| matches!(event, InternalMessage::EpStateCountReached))
+            .count();
+        let accepts = events
+            .iter()
+            .filter(|event| matches!(event, InternalMessage::EpAcceptedCountReached))
+            .count();
+        (states, accepts)
+    }
+
+    /// A message of the instance of epoch `epoch_ts`.
+    fn message(epoch_ts: u32, field_type: message::Message_Type) -> message::Message {
+        let mut msg = message::Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(field_type);
+        msg.set_systemId(SYSTEM_ID.to_owned());
+        msg.set_abstractionId(format!("{}{}", ABSTRACTION_ID, epoch_ts));
+        msg
+    }
+
+    fn deliver(ep: &mut EpochConsensus<i32>, event: InternalMessage) {
+        let event_data = EventData::Internal(SYSTEM_ID.to_owned(), event);
+        if ep.should_handle_event(&event_data) {
+            ep.handle(&event_data);
+        }
+    }
+
+    fn accepted_count_reached(recorder: &Recorder) -> usize {
+        recorder
+            .events()
+            .iter()
+            .filter(|event_data| {
+                matches!(
+                    event_data,
+                    EventData::Internal(_, InternalMessage::EpAcceptedCountReached)
+                )
+            })
+            .count()
+    }
+
+    fn state_count_reached(recorder: &Recorder) -> usize {
+        recorder
+            .events()
+            .iter()
+            .filter(|event_data| {
+                matches!(
+                    event_data,
+                    EventData::Internal(_, InternalMessage::EpStateCountReached)
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn an_epoch_aborted_twice_is_indicated_once() {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut ep = inert_instance(node_info(1, &nodes), 0);
+        deliver(&mut ep, InternalMessage::EpAbort(0));
+        deliver(&mut ep, InternalMessage::EpAbort(0));
+
+        let aborted = pushed(&ep.event_queue)
+            .into_iter()
+            .filter(|event| matches!(event, InternalMessage::EpAborted(..)))
+            .count();
+        assert_eq!(aborted, 1);
+    }
+
+    #[test]
+    fn a_strict_majority_of_four_takes_three_states() {
+        let nodes: Vec<Node> = (1..=4).map(node).collect();
+        let mut ep = inert_instance(node_info(1, &nodes), 0);
+        for id in 1..=2 {
+            let state = message(0, message::Message_Type::EP_STATE_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(id), state));
+        }
+        // half of the voters are not a majority.
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (0, 0));
+
+        let state = message(0, message::Message_Type::EP_STATE_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(3), state));
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (1, 0));
+    }
+
+    #[test]
+    fn the_quorum_is_computed_over_the_voters() {
+        let recorder = Recorder::default();
+        let mut nodes: Vec<Node> = (1..=4).map(node).collect();
+        nodes[3].role = NodeRole::Learner;
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let mut ep = EpochConsensus::<i32>::new(
+            Arc::new(node_info(1, &nodes)),
+            event_queue,
+            EpochConsensusState::new(0, None),
+            Arc::new(MemoryStorage::new()),
+            node(1),
+            0,
+            SYSTEM_ID.to_owned(),
+        );
+
+        // a majority of the 3 voters, the state of the learner not counting.
+        for id in [4, 1, 2] {
+            let state = message(0, message::Message_Type::EP_STATE_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(id), state));
+        }
+        assert_eq!(ep.state_senders.len(), 2);
+        wait_until(|| state_count_reached(&recorder) == 1);
+    }
+
+    #[test]
+    fn the_states_are_counted_by_the_weight_of_their_senders() {
+        let nodes = vec![node(1).with_weight(3), node(2), node(3)];
+        let mut ep = inert_instance(node_info(1, &nodes), 0);
+
+        // the two light voters only cast 2 of the 5 votes.
+        for id in [2, 3] {
+            let state = message(0, message::Message_Type::EP_STATE_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(id), state));
+        }
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (0, 0));
+
+        let state = message(0, message::Message_Type::EP_STATE_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(1), state));
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (1, 0));
+    }
+
+    #[test]
+    fn the_phases_wait_for_their_own_quorum() {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let quorums = Quorums {
+            read: QuorumPolicy::AtLeast(1),
+            write: QuorumPolicy::All,
+        };
+        let node_info = NodeInfo {
+            quorums,
+            ..node_info(1, &nodes)
+        };
+        let mut ep = inert_instance(node_info, 0);
+
+        let state = message(0, message::Message_Type::EP_STATE_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(2), state));
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (1, 0));
+
+        for id in 1..=2 {
+            let accept = message(0, message::Message_Type::EP_ACCEPT_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(id), accept));
+        }
+        assert_eq!(ep.accepted.len(), 2);
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (0, 0));
+        let accept = message(0, message::Message_Type::EP_ACCEPT_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(3), accept));
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (0, 1));
+    }
+
+    #[test]
+    fn a_strict_majority_of_four_takes_three_accepts() {
+        let nodes: Vec<Node> = (1..=4).map(node).collect();
+        let mut ep = inert_instance(node_info(1, &nodes), 0);
+        for id in 1..=2 {
+            let accept = message(0, message::Message_Type::EP_ACCEPT_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(id), accept));
+        }
+        assert_eq!(ep.accepted.len(), 2);
+        // half of the voters are not a majority.
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (0, 0));
+
+        let accept = message(0, message::Message_Type::EP_ACCEPT_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(3), accept));
+        assert_eq!(quorums_reached(&pushed(&ep.event_queue)), (0, 1));
+    }
+
+    #[test]
+    fn duplicate_accepts_of_a_process_count_once() {
+        let recorder = Recorder::default();
+        let mut ep = instance(3, &recorder);
+        for _ in 0..100 {
+            let accept = message(0, message::Message_Type::EP_ACCEPT_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(2), accept));
+        }
+        assert_eq!(ep.accepted.len(), 1);
+
+        let accept = message(0, message::Message_Type::EP_ACCEPT_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(3), accept));
+        assert_eq!(ep.accepted.len(), 2);
+        wait_until(|| accepted_count_reached(&recorder) == 1);
+    }
+
+    #[test]
+    fn the_messages_of_another_epoch_are_ignored() {
+        let recorder = Recorder::default();
+        let mut ep = instance(3, &recorder);
+        for id in 1..=3 {
+            let accept = message(3, message::Message_Type::EP_ACCEPT_);
+            deliver(&mut ep, InternalMessage::PlDeliver(node(id), accept));
+        }
+        assert!(ep.accepted.is_empty());
+    }
+
+    #[test]
+    fn a_late_write_or_decided_of_a_previous_epoch_is_dropped() {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut ep = inert_instance(node_info(2, &nodes), 5);
+        let mut write = message(4, message::Message_Type::EP_WRITE_);
+        write.mut_epWrite_().set_value(42.to_value());
+        let mut decided = message(4, message::Message_Type::EP_DECIDED_);
+        decided.mut_epDecided_().set_value(42.to_value());
+        // handed over directly, as if it had been routed to this instance.
+        for msg in [write, decided] {
+            let event_data = EventData::Internal(
+                SYSTEM_ID.to_owned(),
+                InternalMessage::BebDeliver(node(1), msg),
+            );
+            ep.handle(&event_data);
+        }
+
+        assert_eq!(ep.state.value, None);
+        assert!(pushed(&ep.event_queue).is_empty());
+    }
+
+    #[test]
+    fn a_message_is_only_handled_by_the_instance_of_its_epoch() {
+        let recorder = Recorder::default();
+        let instances: Vec<_> = (0..=1)
+            .map(|ts| instance_with_storage(2, 1, 3, ts, MemoryStorage::new(), &recorder))
+            .collect();
+        let read = message(1, message::Message_Type::EP_READ_);
+        let event_data = EventData::Internal(
+            SYSTEM_ID.to_owned(),
+            InternalMessage::BebDeliver(node(1), read),
+        );
+
+        let handled_by: Vec<u32> = instances
+            .iter()
+            .filter(|ep| ep.should_handle_event(&event_data))
+            .map(|ep| ep.epoch_ts)
+            .collect();
+        assert_eq!(handled_by, vec![1]);
+    }
+
+    #[test]
+    fn the_states_of_non_members_are_ignored() {
+        let recorder = Recorder::default();
+        let mut ep = instance(3, &recorder);
+        // node 4 took part in another system, it is not one of the nodes 1 to 3.
+        let state = message(0, message::Message_Type::EP_STATE_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(4), state));
+        assert!(ep.states.is_empty());
+        assert!(ep.state_senders.is_empty());
+
+        let state = message(0, message::Message_Type::EP_STATE_);
+        deliver(&mut ep, InternalMessage::PlDeliver(node(3), state));
+        assert_eq!(ep.state_senders.len(), 1);
+    }
+
+    #[test]
+    fn a_state_with_a_negative_timestamp_is_rejected() {
+        let recorder = Recorder::default();
+        let mut ep = instance(3, &recorder);
+        let mut state = message(0, message::Message_Type::EP_STATE_);
+        state.mut_epState_().set_valueTimestamp(-1);
+        state.mut_epState_().set_value(7.to_value());
+        deliver(&mut ep, InternalMessage::PlDeliver(node(2), state));
+        assert!(ep.states.is_empty());
+        assert!(ep.state_senders.is_empty());
+    }
+
+    #[test]
+    fn an_accepted_value_survives_the_instance_being_recreated() {
+        let recorder = Recorder::default();
+        let storage = MemoryStorage::new();
+        let mut ep = instance_with_storage(2, 1, 3, 4, storage.clone(), &recorder);
+        let mut write = message(4, message::Message_Type::EP_WRITE_);
+        write.mut_epWrite_().set_value(42.to_value());
+        deliver(&mut ep, InternalMessage::BebDeliver(node(1), write));
+        drop(ep);
+
+        let ep = instance_with_storage(2, 1, 3, 5, storage, &recorder);
+        assert_eq!(ep.state.value_timestamp, 4);
+        assert_eq!(ep.state.value, Some(42));
+    }
+
+    #[test]
+    fn a_value_written_in_the_first_epoch_wins_over_the_initial_state() {
+        // the process with the highest id never accepted the write of epoch 0.
+        let mut states = BTreeMap::new();
+        states.insert(1, EpochConsensusState::new(0, Some(7)));
+        states.insert(2, EpochConsensusState::new(0, None));
+
+        let highest = highest(&states).unwrap();
+        assert_eq!(highest.value_timestamp, 0);
+        assert_eq!(highest.value, Some(7));
+    }
+
+    #[test]
+    fn equal_states_are_resolved_by_the_highest_id() {
+        let mut states = BTreeMap::new();
+        states.insert(2, EpochConsensusState::new(3, Some(5)));
+        states.insert(1, EpochConsensusState::new(3, Some(9)));
+        states.insert(3, EpochConsensusState::new(2, Some(4)));
+
+        assert_eq!(highest(&states).unwrap().value, Some(5));
+    }
+
+    #[test]
+    fn equal_undefined_states_are_resolved_by_the_highest_id() {
+        let mut states = BTreeMap::new();
+        states.insert(3, EpochConsensusState::<i32>::new(1, None));
+        states.insert(1, EpochConsensusState::new(1, None));
+
+        let highest = highest(&states).unwrap();
+        assert!(std::ptr::eq(highest, &states[&3]));
+    }
+
+    #[test]
+    fn a_single_state_is_the_highest() {
+        let mut states = BTreeMap::new();
+        states.insert(2, EpochConsensusState::new(6, Some(1)));
+
+        assert_eq!(
+            highest(&states),
+            Some(&EpochConsensusState::new(6, Some(1)))
+        );
+    }
+
+    #[test]
+    fn there_is_no_highest_of_no_states() {
+        let states = BTreeMap::<NodeId, EpochConsensusState<i32>>::new();
+        assert_eq!(highest(&states), None);
+    }
+
+    #[test]
+    fn without_states_the_proposed_value_is_written() {
+        let recorder = Recorder::default();
+        let mut ep = instance(3, &recorder);
+        ep.temporary_value = Some(5);
+        deliver(&mut ep, InternalMessage::EpStateCountReached);
+
+        let written = || -> Vec<_> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, InternalMessage::BebBroadcast(msg)) => Some(msg),
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| !written().is_empty());
+        let written = written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(
+            written[0].get_field_type(),
+            message::Message_Type::EP_WRITE_
+        );
+        assert_eq!(
+            i32::from_value(written[0].get_epWrite_().get_value()),
+            Some(5)
+        );
+    }
+}
@@ -1,16 +1,26 @@
+use crate::clock::{Clock, Guard};
 use crate::event::*;
+use crate::metadata::HeartbeatMetadata;
+use crate::status::SystemStatus;
 use crate::node::{Node, NodeInfo};
 use crate::protos::message::*;
-use chrono;
 use log::trace;
-use log::{warn};
+use log::{debug, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::sync::Arc;
-use std::sync::Mutex;
-use timer::Guard;
-use timer::Timer;
 use uuid::Uuid;
 
-const DELTA: i64 = 100;
+/// The default heartbeat timeout, in milliseconds.
+pub const DEFAULT_DELTA: i64 = 100;
+/// The default delay before the first heartbeat round, in milliseconds.
+pub const DEFAULT_STARTUP_GRACE: i64 = 0;
+/// Number of consecutive timeouts without a false suspicion after which the timeout is
+/// decreased again by one delta.
+const DECAY_AFTER: u32 = 10;
+/// Every round is scheduled up to this fraction of the timeout earlier or later, so that the
+/// heartbeats of the processes, which all start at about the same time, drift apart.
+const JITTER: f64 = 0.1;
 const ABSTRACTION_ID: &str = "epfd";
 
 pub struct EvenutallyPerfectFailureDetector {
@@ -18,41 +28,77 @@ pub struct EvenutallyPerfectFailureDetector {
     event_queue: Arc<EventQueue>,
     alive: Vec<Node>,
     suspected: Vec<Node>,
+    delta: chrono::Duration,
     delay: chrono::Duration,
+    clean_rounds: u32,
     timer_guard: Option<Guard>,
-    timer: Mutex<Timer>,
+    clock: Arc<dyn Clock>,
     system_id: String,
+    metadata: HeartbeatMetadata,
+    status: SystemStatus,
+    /// Whether the timeouts were started, the health of the process then depends on them.
+    started: bool,
+    rng: StdRng,
 }
 
 impl EvenutallyPerfectFailureDetector {
-    pub fn new(node_info: Arc<NodeInfo>, event_queue: Arc<EventQueue>, system_id: String) -> Self {
-        let alive = node_info.nodes.clone();
+    pub fn new(
+        node_info: Arc<NodeInfo>,
+        event_queue: Arc<EventQueue>,
+        system_id: String,
+        metadata: HeartbeatMetadata,
+        status: SystemStatus,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let alive = node_info.participants().to_vec();
+        let delta = node_info.epfd_delta;
         EvenutallyPerfectFailureDetector {
             node_info,
             event_queue,
             alive,
             suspected: Vec::new(),
-            delay: chrono::Duration::milliseconds(DELTA),
+            delta,
+            delay: delta,
+            clean_rounds: 0,
             timer_guard: None,
-            timer: Mutex::new(Timer::new()),
+            clock,
             system_id,
+            metadata,
+            status,
+            started: false,
+            rng: StdRng::from_entropy(),
         }
     }
 
+    /// Jitters the rounds with a generator seeded with `seed`, so that they are reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// The first round only starts once the startup grace period is over, so that peers
+    /// which did not bind their listener yet are not heartbeated, nor suspected.
     pub fn init(&mut self) {
-        self.start_timer();
+        self.started = true;
+        self.event_queue.health().record_detector_started();
+        let delay = self.next_delay();
+        self.schedule_timeout(self.node_info.epfd_startup_grace + delay);
     }
 
     fn on_timeout(&mut self) {
+        self.event_queue.health().record_timeout();
         if self.contains_suspected() {
-            self.delay = self.delay + chrono::Duration::milliseconds(DELTA);
+            self.delay += self.delta;
             let seconds = self.delay.num_seconds();
             let milliseconds = self.delay.num_milliseconds();
             let seconds = if seconds > 0 {seconds} else {milliseconds / 1000};
             warn!("Increased timeout to {} seconds.", seconds);
+            self.clean_rounds = 0;
+        } else {
+            self.decay_delay();
         }
 
-        for item in self.node_info.nodes.iter() {
+        for item in self.node_info.participants() {
             if item.id == self.node_info.current_node.id {
                 continue;
             }
@@ -60,39 +106,66 @@ impl EvenutallyPerfectFailureDetector {
             let suspected = self.suspected.iter().find(|&o| o == item).is_some();
             if !alive && !suspected {
                 self.suspected.push(item.clone());
+                self.event_queue.metrics().record_suspect();
                 let msg = InternalMessage::EpfdSuspect(item.clone());
                 self.event_queue
                     .push(EventData::Internal(self.system_id.clone(), msg));
             } else if alive && suspected {
                 let item_index = self.suspected.iter().position(|o| o == item).unwrap();
                 self.suspected.remove(item_index);
+                self.event_queue.metrics().record_restore();
                 let msg = InternalMessage::EpfdRestore(item.clone());
                 self.event_queue
                     .push(EventData::Internal(self.system_id.clone(), msg));
             }
 
-            let heart_message = EpfdHeartbeatRequest_::new();
-
-            let uuid = Uuid::new_v4();
-            let mut msg = Message::new();
-            msg.set_messageUuid(uuid.to_string());
-            msg.set_epfdHeartbeatRequest_(heart_message);
-            msg.set_field_type(Message_Type::EPFD_HEARTBEAT_REQUEST);
-            msg.set_abstractionId(ABSTRACTION_ID.to_owned());
-            msg.set_systemId(self.system_id.clone());
-
-            let from = self.node_info.current_node.clone();
-            let internal_msg = InternalMessage::PlSend(from.clone(), item.clone(), msg);
-            let event_data = EventData::Internal(self.system_id.clone(), internal_msg);
-            self.event_queue.push(event_data);
         }
+        self.broadcast_request();
 
+        self.status.set_suspected(&self.suspected);
         self.alive.clear();
         self.start_timer();
     }
 
+    /// A single heartbeat request for all the peers, fanned out by the broadcast. The copy the
+    /// broadcast delivers locally is never answered: the current process is not heartbeated.
+    fn broadcast_request(&self) {
+        let current_node = &self.node_info.current_node;
+        if self.node_info.participants().iter().all(|node| node == current_node) {
+            return;
+        }
+        let mut heart_message = EpfdHeartbeatRequest_::new();
+        heart_message.set_metadata(self.metadata.outgoing());
+
+        let uuid = Uuid::new_v4();
+        let mut msg = Message::new();
+        msg.set_messageUuid(uuid.to_string());
+        msg.set_epfdHeartbeatRequest_(heart_message);
+        msg.set_field_type(Message_Type::EPFD_HEARTBEAT_REQUEST);
+        msg.set_abstractionId(ABSTRACTION_ID.to_owned());
+        msg.set_systemId(self.system_id.clone());
+
+        let event_data = EventData::Internal(self.system_id.clone(), InternalMessage::BebBroadcast(msg));
+        self.event_queue.push(event_data);
+    }
+
+    /// Brings the timeout back towards the configured delta once the false suspicions
+    /// have stopped for a while, never going below it.
+    fn decay_delay(&mut self) {
+        if self.delay <= self.delta {
+            return;
+        }
+        self.clean_rounds += 1;
+        if self.clean_rounds >= DECAY_AFTER {
+            self.clean_rounds = 0;
+            self.delay = std::cmp::max(self.delay - self.delta, self.delta);
+            debug!("Decreased timeout to {} ms.", self.delay.num_milliseconds());
+        }
+    }
+
     fn send_reply(&mut self, to: &Node) {
-        let heart_message = EpfdHeartbeatReply_::new();
+        let mut heart_message = EpfdHeartbeatReply_::new();
+        heart_message.set_metadata(self.metadata.outgoing());
 
         let uuid = Uuid::new_v4();
         let mut msg = Message::new();
@@ -108,6 +181,27 @@ impl EvenutallyPerfectFailureDetector {
         self.event_queue.push(event_data);
     }
 
+    /// The processes joining get a whole round to answer before they can be suspected, the ones
+    /// leaving are neither heartbeated nor suspected anymore.
+    fn reconfigure(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            if !self.node_info.is_member(node) {
+                self.alive.push(node.clone());
+            }
+        }
+        let (suspected, left): (Vec<Node>, Vec<Node>) = self
+            .suspected
+            .drain(..)
+            .partition(|node| nodes.contains(node));
+        for _ in left {
+            self.event_queue.metrics().record_restore();
+        }
+        self.suspected = suspected;
+        self.alive.retain(|node| nodes.contains(node));
+        self.node_info = Arc::new(self.node_info.with_members(nodes));
+        self.status.set_suspected(&self.suspected);
+    }
+
     fn on_got_reply(&mut self, from: &Node) {
         self.alive.push(from.clone());
     }
@@ -123,24 +217,48 @@ impl EvenutallyPerfectFailureDetector {
     }
 
     fn start_timer(&mut self) {
+        let delay = self.next_delay();
+        self.schedule_timeout(delay);
+    }
+
+    /// The timeout, give or take `JITTER` of it.
+    fn next_delay(&mut self) -> chrono::Duration {
+        let band = (self.delay.num_milliseconds() as f64 * JITTER) as i64;
+        let jitter = self.rng.gen_range(-band, band + 1);
+        self.delay + chrono::Duration::milliseconds(jitter)
+    }
+
+    fn schedule_timeout(&mut self, delay: chrono::Duration) {
         let event_queue = Arc::clone(&self.event_queue);
         let system_id = self.system_id.clone();
-        self.timer_guard = Some(self.timer.lock().unwrap().schedule_with_delay(
-            self.delay,
-            move || {
+        self.timer_guard = Some(self.clock.schedule(
+            delay,
+            Box::new(move || {
                 // we just need to send the timeout message to ourselvles.
                 let message = InternalMessage::EpfdTimeout;
-                let event_data = EventData::Internal(system_id.clone(), message);
+                let event_data = EventData::Internal(system_id, message);
                 event_queue.push(event_data);
-            },
+            }),
         ));
     }
 }
 
+impl Drop for EvenutallyPerfectFailureDetector {
+    fn drop(&mut self) {
+        // the processes it suspects are no longer suspected by anyone.
+        for _ in self.suspected.drain(..) {
+            self.event_queue.metrics().record_restore();
+        }
+        if self.started {
+            self.event_queue.health().record_detector_stopped();
+        }
+    }
+}
+
 impl EventHandler for EvenutallyPerfectFailureDetector {
     fn should_handle_event(&self, event_data: &EventData) -> bool {
         if let EventData::Internal(system_id, _) = event_data {
-            system_id == &self.system_id   
+            system_id == &self.system_id && self.node_info.accepts(event_data)
         } else {
             false
         }
@@ -149,8 +267,8 @@ impl EventHandler for EvenutallyPerfectFailureDetector {
     fn handle(&mut self, event_data: &EventData) {
         trace!("Handler summoned with event {:?}", event_data);
 
-        match event_data {
-            EventData::Internal(_, message) => match message {
+        if let EventData::Internal(_, message) = event_data {
+            match message {
                 InternalMessage::EpfdTimeout => self.on_timeout(),
                 InternalMessage::PlDeliver(from, msg) => {
                     if let Message {
@@ -158,6 +276,8 @@ impl EventHandler for EvenutallyPerfectFailureDetector {
                         ..
                     } = msg
                     {
+                        self.metadata
+                            .received(from, msg.get_epfdHeartbeatRequest_().get_metadata());
                         self.send_reply(from);
                     }
                     if let Message {
@@ -165,12 +285,398 @@ impl EventHandler for EvenutallyPerfectFailureDetector {
                         ..
                     } = msg
                     {
+                        self.metadata
+                            .received(from, msg.get_epfdHeartbeatReply_().get_metadata());
                         self.on_got_reply(from);
                     }
                 }
+                InternalMessage::Reconfigure(nodes) => self.reconfigure(nodes),
                 _ => (),
-            },
-            _ => (),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beb::BestEffortBroadcast;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::hint::LeaderHint;
+    use crate::metadata::LEADER_HINT_KEY;
+    use crate::testing::{node, node_info, wait_until, Recorder};
+
+    const SYSTEM_ID: &str = "sys-1";
+
+    /// The detector of `current` in a system of the nodes 1 to 3, with its events recorded
+    /// by `recorder`. Its timer is never started, the timeouts are handed over by the tests.
+    fn detector(
+        current: u16,
+        metadata: HeartbeatMetadata,
+        recorder: &Recorder,
+    ) -> EvenutallyPerfectFailureDetector {
+        let delta = chrono::Duration::milliseconds(DEFAULT_DELTA);
+        detector_with_delta(current, delta, metadata, recorder)
+    }
+
+    fn detector_with_delta(
+        current: u16,
+        delta: chrono::Duration,
+        metadata: HeartbeatMetadata,
+        recorder: &Recorder,
+    ) -> EvenutallyPerfectFailureDetector {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut node_info = node_info(current, &nodes);
+        node_info.epfd_delta = delta;
+        detector_with_node_info(node_info, metadata, recorder)
+    }
+
+    fn detector_with_node_info(
+        node_info: NodeInfo,
+        metadata: HeartbeatMetadata,
+        recorder: &Recorder,
+    ) -> EvenutallyPerfectFailureDetector {
+        detector_with_clock(node_info, metadata, recorder, Arc::new(SystemClock::new()))
+    }
+
+    fn detector_with_clock(
+        node_info: NodeInfo,
+        metadata: HeartbeatMetadata,
+        recorder: &Recorder,
+        clock: Arc<dyn Clock>,
+    ) -> EvenutallyPerfectFailureDetector {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        event_queue.register_handler(Box::new(recorder.clone()));
+        EvenutallyPerfectFailureDetector::new(
+            Arc::new(node_info),
+            event_queue,
+            SYSTEM_ID.to_owned(),
+            metadata,
+            SystemStatus::new(),
+            clock,
+        )
+    }
+
+    fn deliver(epfd: &mut EvenutallyPerfectFailureDetector, event: InternalMessage) {
+        epfd.handle(&EventData::Internal(SYSTEM_ID.to_owned(), event));
+    }
+
+    fn reply() -> Message {
+        let mut msg = Message::new();
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_field_type(Message_Type::EPFD_HEARTBEAT_REPLY);
+        msg.set_epfdHeartbeatReply_(EpfdHeartbeatReply_::new());
+        msg.set_abstractionId(ABSTRACTION_ID.to_owned());
+        msg.set_systemId(SYSTEM_ID.to_owned());
+        msg
+    }
+
+    /// A round in which nodes 2 and 3 both replied.
+    fn clean_round(epfd: &mut EvenutallyPerfectFailureDetector) {
+        for id in 2..=3 {
+            deliver(epfd, InternalMessage::PlDeliver(node(id), reply()));
+        }
+        deliver(epfd, InternalMessage::EpfdTimeout);
+    }
+
+    /// The heartbeat requests broadcast so far.
+    fn requests(recorder: &Recorder) -> Vec<Message> {
+        recorder
+            .events()
+            .into_iter()
+            .filter_map(|event_data| match event_data {
+                EventData::Internal(_, InternalMessage::BebBroadcast(msg))
+                    if msg.get_field_type() == Message_Type::EPFD_HEARTBEAT_REQUEST =>
+                {
+                    Some(msg)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_heartbeat_metadata_reaches_the_peers() {
+        let leader_hint = LeaderHint::new();
+        leader_hint.update(&node(3), 6);
+        let metadata = HeartbeatMetadata::with_leader_hint(leader_hint);
+        assert!(metadata.set("load", vec![7]));
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, metadata, &recorder);
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        wait_until(|| !requests(&recorder).is_empty());
+        let request = requests(&recorder).remove(0);
+
+        let received = HeartbeatMetadata::new();
+        let mut peer = detector(2, received.clone(), &Recorder::default());
+        deliver(&mut peer, InternalMessage::PlDeliver(node(1), request));
+
+        assert_eq!(received.peer_leader_hint(1), Some((node(3), 6)));
+        let peer_metadata = received.peer(1).unwrap();
+        assert_eq!(peer_metadata["load"], vec![7]);
+        assert!(peer_metadata.contains_key(LEADER_HINT_KEY));
+    }
+
+    #[test]
+    fn the_hub_is_never_heartbeated_nor_suspected() {
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder);
+        // the requests are sent by the broadcast, to every process it fans out to.
+        let beb = BestEffortBroadcast::new(
+            epfd.node_info.clone(),
+            epfd.event_queue.clone(),
+            SYSTEM_ID.to_owned(),
+        );
+        epfd.event_queue.register_handler(Box::new(beb));
+
+        // nobody ever replies, every round suspects whoever was not suspected yet.
+        for _ in 0..3 {
+            deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        }
+        let events = || -> Vec<InternalMessage> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, event) => Some(event),
+                    EventData::External(..) => None,
+                })
+                .collect()
+        };
+        // the last round sends the last heartbeat requests.
+        let sent_to = || -> Vec<Node> {
+            events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    InternalMessage::PlSend(_, to, _) => Some(to),
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| sent_to().len() == 3 * 2);
+        let events = events();
+
+        let suspected: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                InternalMessage::EpfdSuspect(node) => Some(node.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(suspected, vec![node(2), node(3)]);
+        assert!(sent_to().iter().all(|to| to == &node(2) || to == &node(3)));
+    }
+
+    #[test]
+    fn a_round_broadcasts_a_single_request() {
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder);
+        for _ in 0..2 {
+            deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        }
+        wait_until(|| requests(&recorder).len() == 2);
+
+        // the local copy of the broadcast is not answered.
+        let request = requests(&recorder).remove(0);
+        deliver(&mut epfd, InternalMessage::BebDeliver(node(1), request));
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        wait_until(|| requests(&recorder).len() == 3);
+        assert!(recorder.events().iter().all(|event_data| {
+            !matches!(event_data, EventData::Internal(_, InternalMessage::PlSend(..)))
+        }));
+    }
+
+    #[test]
+    fn the_suspected_processes_can_be_polled() {
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder);
+        let status = epfd.status.clone();
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert!(status.suspected().is_empty());
+
+        // only node 2 replied within the round.
+        deliver(&mut epfd, InternalMessage::PlDeliver(node(2), reply()));
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        let suspected = std::thread::spawn(move || status.suspected())
+            .join()
+            .unwrap();
+        assert_eq!(suspected, vec![node(3)]);
+    }
+
+    #[test]
+    fn the_suspects_of_a_dropped_detector_are_no_longer_counted() {
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder);
+        let metrics = epfd.event_queue.metrics();
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert_eq!(metrics.suspected(), 2);
+
+        drop(epfd);
+        assert_eq!(metrics.suspected(), 0);
+    }
+
+    #[test]
+    fn the_timeout_decays_back_to_the_delta() {
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder);
+        // nobody replied, both peers get suspected and then turn out to be alive.
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        clean_round(&mut epfd);
+        let delta = chrono::Duration::milliseconds(DEFAULT_DELTA);
+        assert_eq!(epfd.delay, delta * 2);
+
+        for _ in 1..DECAY_AFTER {
+            clean_round(&mut epfd);
+        }
+        assert_eq!(epfd.delay, delta * 2);
+        clean_round(&mut epfd);
+        assert_eq!(epfd.delay, delta);
+
+        for _ in 0..DECAY_AFTER {
+            clean_round(&mut epfd);
+        }
+        assert_eq!(epfd.delay, delta);
+    }
+
+    #[test]
+    fn the_rounds_are_jittered_within_the_band() {
+        let recorder = Recorder::default();
+        let delays = |seed| {
+            let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder).with_seed(seed);
+            (0..20).map(|_| epfd.next_delay().num_milliseconds()).collect::<Vec<_>>()
+        };
+        let jittered = delays(7);
+
+        assert!(jittered.iter().all(|delay| (90..=110).contains(delay)));
+        assert!(jittered.windows(2).any(|pair| pair[0] != pair[1]));
+        assert_eq!(delays(7), jittered);
+    }
+
+    #[test]
+    fn the_timeout_grows_by_the_configured_delta() {
+        let delta = chrono::Duration::milliseconds(10);
+        let clock = Arc::new(ManualClock::new());
+        let recorder = Recorder::default();
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut node_info = node_info(1, &nodes);
+        node_info.epfd_delta = delta;
+        let metadata = HeartbeatMetadata::new();
+        let mut epfd = detector_with_clock(node_info, metadata, &recorder, clock.clone());
+        assert_eq!(epfd.delay, delta);
+
+        // the first round sees everyone alive, the second suspects whoever did not reply.
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert_eq!(epfd.suspected, vec![node(2), node(3)]);
+
+        // a suspected node replying shows that the timeout was too short.
+        deliver(&mut epfd, InternalMessage::PlDeliver(node(2), reply()));
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert_eq!(epfd.delay, delta + delta);
+
+        // the next round is scheduled 20ms away, give or take the jitter of 2ms.
+        let timeouts = || {
+            recorder
+                .events()
+                .iter()
+                .filter(|event_data| {
+                    matches!(event_data, EventData::Internal(_, InternalMessage::EpfdTimeout))
+                })
+                .count()
+        };
+        clock.advance(chrono::Duration::milliseconds(17));
+        assert_eq!(clock.pending(), 1);
+        clock.advance(chrono::Duration::milliseconds(5));
+        assert_eq!(clock.pending(), 0);
+        wait_until(|| timeouts() == 1);
+    }
+
+    #[test]
+    fn a_joining_process_is_heartbeated_and_a_leaving_one_forgotten() {
+        let recorder = Recorder::default();
+        let mut epfd = detector(1, HeartbeatMetadata::new(), &recorder);
+        // nobody replied, both peers are suspected.
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert_eq!(epfd.suspected, vec![node(2), node(3)]);
+
+        let nodes = vec![node(1), node(2), node(4)];
+        deliver(&mut epfd, InternalMessage::Reconfigure(nodes));
+        assert_eq!(epfd.suspected, vec![node(2)]);
+
+        // the joining process is only suspected if it does not answer its first heartbeat,
+        // which the broadcast following the same reconfiguration sends it.
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert_eq!(epfd.suspected, vec![node(2)]);
+        wait_until(|| requests(&recorder).len() == 3);
+        deliver(&mut epfd, InternalMessage::EpfdTimeout);
+        assert_eq!(epfd.suspected, vec![node(2), node(4)]);
+    }
+
+    #[test]
+    fn no_heartbeat_is_sent_before_the_startup_grace_period_elapses() {
+        let grace = chrono::Duration::milliseconds(300);
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut node_info = node_info(1, &nodes);
+        node_info.epfd_delta = chrono::Duration::milliseconds(10);
+        node_info.epfd_startup_grace = grace;
+        let recorder = Recorder::default();
+        let mut epfd = detector_with_node_info(node_info, HeartbeatMetadata::new(), &recorder);
+        let event_queue = epfd.event_queue.clone();
+
+        let started = std::time::Instant::now();
+        epfd.init();
+        event_queue.register_handler(Box::new(epfd));
+
+        wait_until(|| !requests(&recorder).is_empty());
+        assert!(started.elapsed() >= grace.to_std().unwrap());
+    }
+
+    #[test]
+    fn a_silent_peer_is_suspected_once_the_clock_moves_past_the_timeout() {
+        let clock = Arc::new(ManualClock::new());
+        let recorder = Recorder::default();
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let node_info = node_info(1, &nodes);
+        let metadata = HeartbeatMetadata::new();
+        let mut epfd = detector_with_clock(node_info, metadata, &recorder, clock.clone());
+        let event_queue = epfd.event_queue.clone();
+        epfd.init();
+        event_queue.register_handler(Box::new(epfd));
+        // past the timeout, whatever the jitter.
+        let past_the_timeout = chrono::Duration::milliseconds(DEFAULT_DELTA * 2);
+        let suspected = || -> Vec<Node> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, InternalMessage::EpfdSuspect(node)) => Some(node),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // every peer is assumed alive in the first round.
+        assert!(requests(&recorder).is_empty());
+        clock.advance(past_the_timeout);
+        wait_until(|| requests(&recorder).len() == 1 && clock.pending() == 1);
+        assert!(suspected().is_empty());
+
+        // node 2 replies in time, node 3 never does.
+        event_queue.push(EventData::Internal(
+            SYSTEM_ID.to_owned(),
+            InternalMessage::PlDeliver(node(2), reply()),
+        ));
+        // the timeouts take precedence over the replies in the queue, the reply has to be in first.
+        wait_until(|| {
+            recorder.events().iter().any(|event_data| {
+                matches!(event_data, EventData::Internal(_, InternalMessage::PlDeliver(..)))
+            })
+        });
+        clock.advance(past_the_timeout);
+        wait_until(|| requests(&recorder).len() == 2);
+        assert_eq!(suspected(), vec![node(3)]);
+    }
+}
@@ -1,10 +1,23 @@
-use crate::node::Node;
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::node::{Node, NodeId};
 use crate::protos::message::*;
+use crate::trace::{self, Tracer};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::sync::{Arc, Condvar, Mutex};
-use std::thread;
+use std::thread::{self, ThreadId};
 
+/// The value type the application layer runs consensus on.
+/// The consensus abstractions themselves are generic over any `ConsensusValue` and exchange
+/// values among each other in their wire representation.
 pub type ValueType = i32;
 
 pub trait EventHandler {
@@ -12,156 +25,972 @@ pub trait EventHandler {
     fn handle(&mut self, event_data: &EventData);
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InternalMessage {
-    AppPropose(Node, Message),
+    AppPropose(Node, #[serde(with = "proto")] Message),
     AppInit,
+    AppRegistrationTimeout,
     EpfdTimeout,
     EpfdSuspect(Node),
     EpfdRestore(Node),
     EldTrust(Node),
-    BebBroadcast(Message),
-    BebDeliver(Node, Message),
+    BebBroadcast(#[serde(with = "proto")] Message),
+    BebDeliver(Node, #[serde(with = "proto")] Message),
+    /// Signalled by best-effort broadcast, when asked to report its fan-out, once every peer
+    /// either acknowledged the message or is suspected.
+    BebBroadcastComplete(String, Vec<NodeId>), // (uuid of the message, the peers reached)
     EcStartEpoch(Node, u32), //(leader, epoch_timestamp)
-    EpPropose(u32, ValueType), // (timestamp, value)
-    EpDecide(u32, ValueType),
+    EcDeferredStartEpoch,
+    EpPropose(u32, #[serde(with = "proto")] Value), // (timestamp, value)
+    EpDecide(u32, #[serde(with = "proto")] Value),
+    /// Only signalled by an epoch consensus instance to itself, once a quorum of STATEs arrived.
     EpStateCountReached,
+    /// Only signalled by an epoch consensus instance to itself, once a quorum of ACCEPTs arrived.
     EpAcceptedCountReached,
-    EpAbort(u32),                   // timestamp
-    EpAborted(u32, u32, ValueType), // (epoch_ts, value_ts, value)
-    UcPropose(ValueType),
-    UcDecide(ValueType),
-    PlSend(Node, Node, Message), //(from, to, msg)
-    PlDeliver(Node, Message),    // (from, msg)
+    EpAbort(u32),                                        // timestamp
+    EpAborted(u32, u32, #[serde(with = "proto")] Value), // (epoch_ts, value_ts, value)
+    UcPropose(#[serde(with = "proto")] Value),
+    UcDecide(#[serde(with = "proto")] Value),
+    /// Signalled by uniform consensus when its proposal made no progress within the deadline.
+    UcProposeTimedOut,
+    /// Signalled by uniform consensus once it decided, for every value proposed to it that was
+    /// not decided, in the order they were proposed.
+    UcUndecided(#[serde(with = "proto")] Value),
+    /// Signalled by uniform consensus once it starts an epoch led by another process than the
+    /// previous one.
+    LeaderChanged(Node),
+    SeqPropose(#[serde(with = "proto")] Value),
+    SeqDecide(usize, #[serde(with = "proto")] Value), // (slot, value)
+    PlSend(Node, Node, #[serde(with = "proto")] Message), //(from, to, msg)
+    PlDeliver(Node, #[serde(with = "proto")] Message),    // (from, msg)
+    PlRetransmit,
+    PlAcknowledged(Node, String), // (to, uuid of the message acknowledged)
+    PlSendFailed(Node),           // the process a frame could not be sent to
+    Reconfigure(Vec<Node>), // the new members of the system
+    /// Signalled once the abstractions of the system are closed, see `System::close`.
+    SystemClosed,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EventData {
-    Internal(String, InternalMessage), // system id
-    External(String, Message),         // system id
+    Internal(String, InternalMessage),                  // system id
+    External(String, #[serde(with = "proto")] Message), // system id
 }
 
+/// The protobuf messages carried by the events don't implement serde, so they are
+/// (de)serialized as their wire representation.
+mod proto {
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<M, S>(message: &M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        M: protobuf::Message,
+        S: Serializer,
+    {
+        let bytes = message.write_to_bytes().map_err(S::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    pub fn deserialize<'de, M, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: protobuf::Message,
+        D: Deserializer<'de>,
+    {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let mut message = M::new();
+        message.merge_from_bytes(&bytes).map_err(D::Error::custom)?;
+        message.check_initialized().map_err(D::Error::custom)?;
+        Ok(message)
+    }
+}
+
+/// The process that sent a delivered message, `None` for any other event.
+pub fn delivered_by(event_data: &EventData) -> Option<&Node> {
+    match event_data {
+        EventData::Internal(_, InternalMessage::PlDeliver(from, _))
+        | EventData::Internal(_, InternalMessage::BebDeliver(from, _)) => Some(from),
+        _ => None,
+    }
+}
+
+/// Events of the `High` lane are handled before any event of the `Normal` lane, so that
+/// failure detection keeps up even behind a backlog of broadcasts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Normal,
+}
+
+pub fn priority(event_data: &EventData) -> Priority {
+    match event_data {
+        EventData::Internal(_, InternalMessage::EpfdTimeout)
+        | EventData::Internal(_, InternalMessage::EpfdSuspect(..))
+        | EventData::Internal(_, InternalMessage::EpfdRestore(..))
+        | EventData::Internal(_, InternalMessage::EldTrust(..)) => Priority::High,
+        _ => Priority::Normal,
+    }
+}
+
+/// The queued events, one FIFO lane per priority.
+#[derive(Default)]
+struct Lanes {
+    high: VecDeque<EventData>,
+    normal: VecDeque<EventData>,
+}
+
+impl Lanes {
+    fn push_back(&mut self, event_data: EventData) {
+        match priority(&event_data) {
+            Priority::High => self.high.push_back(event_data),
+            Priority::Normal => self.normal.push_back(event_data),
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<EventData> {
+        self.high.pop_front().or_else(|| self.normal.pop_front())
+    }
+
+    fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+}
+
+/// The system an event belongs to.
+fn system_id(event_data: &EventData) -> &str {
+    match event_data {
+        EventData::Internal(system_id, _) | EventData::External(system_id, _) => system_id,
+    }
+}
+
+/// The queued events, one set of lanes per worker. The events of a system always go to the
+/// same worker, so that they are handled in the order they were pushed.
+struct Queued {
+    workers: Vec<Lanes>,
+}
+
+impl Queued {
+    fn new(workers: usize) -> Self {
+        Queued {
+            workers: (0..workers.max(1)).map(|_| Lanes::default()).collect(),
+        }
+    }
+
+    fn worker_of(&self, system_id: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        system_id.hash(&mut hasher);
+        (hasher.finish() % self.workers.len() as u64) as usize
+    }
+
+    fn push_back(&mut self, event_data: EventData) {
+        let worker = self.worker_of(system_id(&event_data));
+        self.workers[worker].push_back(event_data);
+    }
+
+    fn pop_front(&mut self, worker: usize) -> Option<EventData> {
+        self.workers[worker].pop_front()
+    }
+
+    /// The next event of whichever worker, for the queues without any worker thread.
+    fn pop_any(&mut self) -> Option<EventData> {
+        self.workers.iter_mut().find_map(Lanes::pop_front)
+    }
+
+    fn len(&self) -> usize {
+        self.workers.iter().map(Lanes::len).sum()
+    }
+}
+
+/// Returned by `EventQueue::try_push` along with the rejected event when the queue is at capacity.
+#[derive(Debug)]
+pub struct Full(pub Box<EventData>);
+
+/// Opaque identifier returned by `EventQueue::register_handler`, used to deregister the handler later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerId(usize);
+
 type EventHandlerType = Box<dyn EventHandler + Send>;
-type EventHandlerCollection = Vec<Mutex<EventHandlerType>>;
+type EventHandlerCollection = Vec<(HandlerId, Arc<Mutex<EventHandlerType>>)>;
 type SafeEventHandlerCollection = Mutex<EventHandlerCollection>;
+/// The handlers the events are dispatched to. The workers share a snapshot of them, which
+/// is only copied when handlers are registered or removed.
+type SharedEventHandlerCollection = Mutex<Arc<EventHandlerCollection>>;
 
 pub struct EventQueue {
-    handlers: Arc<SafeEventHandlerCollection>,
+    handlers: Arc<SharedEventHandlerCollection>,
     new_handlers: Arc<SafeEventHandlerCollection>,
-    queue: Arc<Mutex<VecDeque<EventData>>>,
+    removed_handlers: Arc<Mutex<Vec<HandlerId>>>,
+    next_handler_id: AtomicUsize,
+    queue: Arc<Mutex<Queued>>,
     cvar: Arc<Condvar>,
     is_running: Arc<AtomicBool>,
-    handle: Mutex<Option<thread::JoinHandle<()>>>,
-    element_added: Arc<Mutex<bool>>,
+    handles: Mutex<Vec<thread::JoinHandle<()>>>,
+    wakeup: Arc<Mutex<()>>,
+    capacity: Option<usize>,
+    workers: Arc<Mutex<Vec<ThreadId>>>,
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+    tracer: Arc<Mutex<Option<Tracer>>>,
+    replaying: bool,
 }
 
 impl EventQueue {
-    pub fn create_and_run() -> Self {
-        // We need the mutex for the condition variable.
-        #[allow(clippy::mutex_atomic)]
-        let mut event_queue = EventQueue {
-            handlers: Arc::new(Mutex::new(Vec::new())),
+    /// Creates the queue and spawns its worker thread. Only returns once the worker
+    /// has signalled that it is actually running.
+    pub fn create_and_run() -> io::Result<Self> {
+        Self::create_and_run_with_workers(1)
+    }
+
+    /// Like `create_and_run`, with `workers` worker threads. The events of a system are all
+    /// handled by the same worker, in order, while the other systems go on on the others.
+    /// Every handler still handles a single event at a time.
+    pub fn create_and_run_with_workers(workers: usize) -> io::Result<Self> {
+        let mut event_queue = Self::new_inert();
+        event_queue.queue = Arc::new(Mutex::new(Queued::new(workers)));
+        event_queue.run()?;
+        Ok(event_queue)
+    }
+
+    /// Like `create_and_run_with_workers`, but `push` blocks while `capacity` events are
+    /// already queued, so that a fast producer can't make the queue grow without limit.
+    pub fn create_and_run_bounded(capacity: usize, workers: usize) -> io::Result<Self> {
+        let mut event_queue = Self::new_inert();
+        event_queue.queue = Arc::new(Mutex::new(Queued::new(workers)));
+        event_queue.capacity = Some(capacity);
+        event_queue.run()?;
+        Ok(event_queue)
+    }
+
+    /// Creates a queue without a worker thread: pushed events simply stay queued until
+    /// they are handed over to a handler with `drain_to`. Meant for exercising a single
+    /// handler deterministically.
+    pub fn new_inert() -> Self {
+        EventQueue {
+            handlers: Arc::new(Mutex::new(Arc::new(Vec::new()))),
             new_handlers: Arc::new(Mutex::new(Vec::new())),
-            queue: Arc::new(Mutex::new(VecDeque::new())),
+            removed_handlers: Arc::new(Mutex::new(Vec::new())),
+            next_handler_id: AtomicUsize::new(0),
+            queue: Arc::new(Mutex::new(Queued::new(1))),
             cvar: Arc::new(Condvar::default()),
             is_running: Arc::new(AtomicBool::new(false)),
-            handle: Mutex::new(None),
-            element_added: Arc::new(Mutex::new(false)),
-        };
-        event_queue.run();
-        event_queue
+            handles: Mutex::new(Vec::new()),
+            // We need the mutex for the condition variable.
+            wakeup: Arc::new(Mutex::new(())),
+            capacity: None,
+            workers: Arc::new(Mutex::new(Vec::new())),
+            metrics: Arc::new(Metrics::new()),
+            health: Arc::new(Health::new()),
+            tracer: Arc::new(Mutex::new(None)),
+            replaying: false,
+        }
+    }
+
+    /// Creates a queue without a worker thread holding the events recorded in the trace file
+    /// at `path`, see `trace_to`. The handlers registered on it get them with `replay`.
+    pub fn create_from_trace<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut event_queue = Self::new_inert();
+        event_queue.replaying = true;
+        // a single lane, so that the events come out in the order they were recorded.
+        event_queue.queue.lock().unwrap().workers[0].normal = trace::replay(path)?.into();
+        Ok(event_queue)
+    }
+
+    /// Hands the events of a queue created with `create_from_trace` over to the registered
+    /// handlers, on the calling thread. The events the handlers push in the meantime are
+    /// deliberately dropped: the trace already holds the ones they pushed while recording, and
+    /// they would otherwise be handled twice. Handlers which push anything that was not traced,
+    /// e.g. because they did not run while recording, don't get to see it. Returns the number
+    /// of events replayed.
+    pub fn replay(&self) -> usize {
+        let mut replayed = 0;
+        while let Some(event_data) = Self::pop_any(&self.queue, &self.metrics) {
+            let current_handlers =
+                Self::current_handlers(&self.handlers, &self.new_handlers, &self.removed_handlers);
+            Self::dispatch(&current_handlers, &event_data);
+            replayed += 1;
+        }
+        replayed
+    }
+
+    /// Records every event dispatched from now on to a JSONL file at `path`, which is
+    /// truncated first, so that the run can be replayed with `create_from_trace`.
+    pub fn trace_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        *self.tracer.lock().unwrap() = Some(Tracer::create(path)?);
+        Ok(())
+    }
+
+    /// Feeds the queued events, including the ones pushed while draining, to `handler`
+    /// until the queue is empty. Returns the number of events taken off the queue.
+    pub fn drain_to(&self, handler: &mut dyn EventHandler) -> usize {
+        let mut drained = 0;
+        loop {
+            let next = Self::pop_any(&self.queue, &self.metrics);
+            match next {
+                Some(event_data) => {
+                    drained += 1;
+                    if handler.should_handle_event(&event_data) {
+                        handler.handle(&event_data);
+                    }
+                }
+                None => return drained,
+            }
+        }
     }
 
+    /// The metrics of the process the queue runs the handlers of.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// When the failure detectors of the process last timed out.
+    pub fn health(&self) -> Arc<Health> {
+        self.health.clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
+    /// Whether the worker threads still run, i.e. neither shut down nor died in a handler.
+    pub fn is_worker_alive(&self) -> bool {
+        let handles = self.handles.lock().unwrap();
+        self.is_running()
+            && !handles.is_empty()
+            && handles.iter().all(|handle| !handle.is_finished())
+    }
+
+    /// Blocks while a bounded queue is at capacity, unless called by a handler: the worker
+    /// threads running the handlers are the ones making room, so they never wait for themselves.
     pub fn push(&self, event_data: EventData) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.push_back(event_data);
-        let mut guard = self.element_added.lock().unwrap();
-        *guard = true;
-        self.cvar.notify_one();
+        if let Some(capacity) = self.capacity {
+            if !self.is_worker_thread() {
+                let guard = self.wakeup.lock().unwrap();
+                let _guard = self
+                    .cvar
+                    .wait_while(guard, |_| {
+                        self.is_running() && self.queue.lock().unwrap().len() >= capacity
+                    })
+                    .unwrap();
+            }
+        }
+        self.enqueue(event_data);
     }
 
-    fn run(&mut self) {
+    /// Never blocks, the event is handed back if a bounded queue is at capacity.
+    pub fn try_push(&self, event_data: EventData) -> Result<(), Full> {
+        if let Some(capacity) = self.capacity {
+            if self.queue.lock().unwrap().len() >= capacity {
+                return Err(Full(Box::new(event_data)));
+            }
+        }
+        self.enqueue(event_data);
+        Ok(())
+    }
+
+    fn enqueue(&self, event_data: EventData) {
+        if self.replaying {
+            return;
+        }
+        {
+            let mut queue = self.queue.lock().unwrap();
+            queue.push_back(event_data);
+            self.metrics.set_queue_depth(queue.len());
+        }
+        let _guard = self.wakeup.lock().unwrap();
+        // producers blocked on a full queue wait on the same condition variable as the workers.
+        self.cvar.notify_all();
+    }
+
+    fn pop_front(queue: &Mutex<Queued>, worker: usize, metrics: &Metrics) -> Option<EventData> {
+        let mut queue = queue.lock().unwrap();
+        let next = queue.pop_front(worker);
+        metrics.set_queue_depth(queue.len());
+        next
+    }
+
+    fn pop_any(queue: &Mutex<Queued>, metrics: &Metrics) -> Option<EventData> {
+        let mut queue = queue.lock().unwrap();
+        let next = queue.pop_any();
+        metrics.set_queue_depth(queue.len());
+        next
+    }
+
+    fn is_worker_thread(&self) -> bool {
+        self.workers.lock().unwrap().contains(&thread::current().id())
+    }
+
+    fn run(&mut self) -> io::Result<()> {
         if self.is_running.load(Ordering::SeqCst) {
             panic!("Event queue is already running.");
         }
 
-        let handlers = Arc::clone(&self.handlers);
-        let cvar = Arc::clone(&self.cvar);
-        let queue = Arc::clone(&self.queue);
-        let is_running = Arc::clone(&self.is_running);
-        let element_added = Arc::clone(&self.element_added);
-        let new_event_handlers = self.new_handlers.clone();
-        self.handle = Mutex::new(Some(thread::spawn(move || {
-            is_running.store(true, Ordering::SeqCst);
-
-            loop {
-                let mut q = queue.lock().unwrap();
-                let mut queue_items: VecDeque<EventData> = q.iter().cloned().collect();
-                q.clear();
-                std::mem::drop(q);
-
-                // handle the case where a certain event handler's 'handle' method was called
-                // and it uses the 'EventQueue' to call 'register_handler'
-                let mut current_handlers = handlers.lock().unwrap();
-                {
-                    let mut event_handlers = new_event_handlers.lock().unwrap();
-                    while let Some(handler) = event_handlers.pop() {
-                        current_handlers.push(handler);
-                    }
-                }
+        let workers = self.queue.lock().unwrap().workers.len();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let mut handles = Vec::with_capacity(workers);
+        // set beforehand, a worker seeing the queue stopped would exit straight away.
+        self.is_running.store(true, Ordering::SeqCst);
+        for index in 0..workers {
+            let handlers = Arc::clone(&self.handlers);
+            let cvar = Arc::clone(&self.cvar);
+            let queue = Arc::clone(&self.queue);
+            let is_running = Arc::clone(&self.is_running);
+            let wakeup = Arc::clone(&self.wakeup);
+            let new_event_handlers = self.new_handlers.clone();
+            let removed_event_handlers = self.removed_handlers.clone();
+            let worker_ids = self.workers.clone();
+            let bounded = self.capacity.is_some();
+            let metrics = self.metrics.clone();
+            let tracer = self.tracer.clone();
+            let ready_sender = ready_sender.clone();
+            let name = if workers == 1 {
+                "event-queue".to_owned()
+            } else {
+                format!("event-queue-{}", index)
+            };
+            let handle = thread::Builder::new().name(name).spawn(move || {
+                worker_ids.lock().unwrap().push(thread::current().id());
+                let _ = ready_sender.send(());
+
+                loop {
+                    // Events are taken off one at a time, so that a high priority event pushed in
+                    // the meantime goes first. Our event handlers might in turn use the event queue
+                    // to send other messages, which means that we cannot hold a lock on the queue here.
+                    loop {
+                        let next = Self::pop_front(&queue, index, &metrics);
+                        let first = match next {
+                            Some(event_data) => event_data,
+                            None => break,
+                        };
+                        if bounded {
+                            // the queue has room again for the producers blocked on it.
+                            let _guard = wakeup.lock().unwrap();
+                            cvar.notify_all();
+                        }
+
+                        // a handler might have been registered or deregistered while handling the previous event.
+                        let current_handlers = Self::current_handlers(
+                            &handlers,
+                            &new_event_handlers,
+                            &removed_event_handlers,
+                        );
 
-                // We need to parse a copy of the original items since our event handlers
-                // might in turn use the event queue to send other messages.
-                // This means that we cannot hold a lock on the queue here.
-                while !queue_items.is_empty() {
-                    let first = queue_items.pop_front().unwrap();
-
-                    // we are sending the message to everyone for now...
-                    // they will need to filter it themselvles.
-                    for event_handler in current_handlers.iter() {
-                        let mut event_handler_guard = event_handler.lock().unwrap();
-                        if event_handler_guard.should_handle_event(&first) {
-                            event_handler_guard.handle(&first);
+                        if let Some(tracer) = tracer.lock().unwrap().as_mut() {
+                            if let Err(e) = tracer.record(&first) {
+                                error!("Unable to trace an event: {}", e);
+                            }
                         }
+                        Self::dispatch(&current_handlers, &first);
                     }
-                }
 
-                if !is_running.load(Ordering::SeqCst) {
-                    break;
+                    // once shut down, the events that are still queued get handled before exiting.
+                    let has_work = || !queue.lock().unwrap().workers[index].is_empty();
+                    if !is_running.load(Ordering::SeqCst) && !has_work() {
+                        break;
+                    }
+
+                    let guard = wakeup.lock().unwrap();
+                    let _guard = cvar
+                        .wait_while(guard, |_| !has_work() && is_running.load(Ordering::SeqCst))
+                        .unwrap();
+                }
+            });
+            match handle {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    *self.handles.lock().unwrap() = handles;
+                    self.shutdown();
+                    self.is_running.store(false, Ordering::SeqCst);
+                    return Err(e);
                 }
+            }
+        }
+        drop(ready_sender);
+        *self.handles.lock().unwrap() = handles;
 
-                let guard = element_added.lock().unwrap();
-                let mut guard = cvar
-                    .wait_while(guard, |added| !*added && is_running.load(Ordering::SeqCst))
-                    .unwrap();
+        // a worker might die before ever getting to run, in which case its sender is dropped.
+        for _ in 0..workers {
+            ready_receiver
+                .recv()
+                .map_err(|_| io::Error::other("The event queue worker thread failed to start."))?;
+        }
+        Ok(())
+    }
+
+    /// The handlers to dispatch the next event to, once the pending changes are applied.
+    /// The handlers are let go of while dispatching, so that the other workers keep going.
+    fn current_handlers(
+        handlers: &SharedEventHandlerCollection,
+        new_handlers: &SafeEventHandlerCollection,
+        removed_handlers: &Mutex<Vec<HandlerId>>,
+    ) -> Arc<EventHandlerCollection> {
+        let mut handlers = handlers.lock().unwrap();
+        Self::apply_pending_changes(&mut handlers, new_handlers, removed_handlers);
+        Arc::clone(&handlers)
+    }
 
-                // we woke up because there was some work to do, now we can reset the work variable to false
-                // since we are going to do the work that we were woken up about.
-                *guard = false;
+    fn dispatch(current_handlers: &EventHandlerCollection, event_data: &EventData) {
+        // we are sending the message to everyone for now...
+        // they will need to filter it themselvles.
+        for (_, event_handler) in current_handlers.iter() {
+            let mut event_handler_guard = event_handler.lock().unwrap();
+            if event_handler_guard.should_handle_event(event_data) {
+                event_handler_guard.handle(event_data);
             }
-        })));
+        }
+    }
+
+    /// Handles the case where a certain event handler's 'handle' method was called
+    /// and it uses the 'EventQueue' to call 'register_handler' or 'deregister_handler'.
+    /// Both are deferred and applied by the workers in between events. The snapshot a worker
+    /// may still be dispatching to is left alone, the changes go to a copy of it.
+    fn apply_pending_changes(
+        current_handlers: &mut Arc<EventHandlerCollection>,
+        new_handlers: &SafeEventHandlerCollection,
+        removed_handlers: &Mutex<Vec<HandlerId>>,
+    ) {
+        let mut added = new_handlers.lock().unwrap();
+        if !added.is_empty() {
+            Arc::make_mut(current_handlers).append(&mut added);
+        }
+
+        let mut removed = removed_handlers.lock().unwrap();
+        if !removed.is_empty() {
+            Arc::make_mut(current_handlers).retain(|(id, _)| !removed.contains(id));
+            removed.clear();
+        }
     }
 
-    fn close(&mut self) {
-        let mut handle = self.handle.lock().unwrap();
-        if handle.is_some() {
-            self.is_running.store(false, Ordering::SeqCst);
-            let _lock = self.element_added.lock().unwrap();
-            self.cvar.notify_one();
-            let _ = handle.take().unwrap().join();
-        } else {
-            panic!("The queue has been already closed");
+    /// Stops the worker threads once they have handled the outstanding events, and waits for
+    /// them to exit. Only the first call has any effect, dropping the queue shuts it down as well.
+    /// Must not be called from within a handler, which runs on a worker thread itself.
+    pub fn shutdown(&self) {
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        if handles.is_empty() {
+            return;
         }
+        self.is_running.store(false, Ordering::SeqCst);
+        let lock = self.wakeup.lock().unwrap();
+        self.cvar.notify_all();
+        // the workers need the lock back in order to wake up and exit.
+        std::mem::drop(lock);
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+
+    pub fn register_handler(&self, event_handler: Box<dyn EventHandler + Send>) -> HandlerId {
+        let id = HandlerId(self.next_handler_id.fetch_add(1, Ordering::SeqCst));
+        let mut handlers = self.new_handlers.lock().unwrap();
+        handlers.push((id, Arc::new(Mutex::new(event_handler))));
+        id
     }
 
-    pub fn register_handler(&self, event_handler: Box<dyn EventHandler + Send>) {
+    /// Registers the handler built by `create`, which is given the id the handler is
+    /// registered under, e.g. so that it is able to deregister itself later.
+    pub fn register_handler_with<F>(&self, create: F) -> HandlerId
+    where
+        F: FnOnce(HandlerId) -> Box<dyn EventHandler + Send>,
+    {
+        let id = HandlerId(self.next_handler_id.fetch_add(1, Ordering::SeqCst));
+        let event_handler = create(id);
         let mut handlers = self.new_handlers.lock().unwrap();
-        handlers.push(Mutex::new(event_handler));
+        handlers.push((id, Arc::new(Mutex::new(event_handler))));
+        id
+    }
+
+    /// Removes a previously registered handler. Like registration, the removal is deferred
+    /// so it is safe to call from within a handler's `handle` method.
+    pub fn deregister_handler(&self, id: HandlerId) {
+        let mut removed = self.removed_handlers.lock().unwrap();
+        removed.push(id);
+    }
+
+    /// The number of registered handlers, the pending registrations and removals included.
+    pub fn handler_count(&self) -> usize {
+        let mut handlers = self.handlers.lock().unwrap();
+        Self::apply_pending_changes(&mut handlers, &self.new_handlers, &self.removed_handlers);
+        handlers.len()
     }
 }
 
 impl Drop for EventQueue {
     fn drop(&mut self) {
-        self.close();
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{node, wait_until, Recorder};
+    use std::collections::HashSet;
+
+    fn timeout() -> EventData {
+        EventData::Internal("sys-1".to_owned(), InternalMessage::EpfdTimeout)
+    }
+
+    /// Every variant, so that adding one fails to compile until it is listed here too.
+    fn variant_name(event: &InternalMessage) -> &'static str {
+        match event {
+            InternalMessage::AppPropose(..) => "AppPropose",
+            InternalMessage::AppInit => "AppInit",
+            InternalMessage::AppRegistrationTimeout => "AppRegistrationTimeout",
+            InternalMessage::EpfdTimeout => "EpfdTimeout",
+            InternalMessage::EpfdSuspect(..) => "EpfdSuspect",
+            InternalMessage::EpfdRestore(..) => "EpfdRestore",
+            InternalMessage::EldTrust(..) => "EldTrust",
+            InternalMessage::BebBroadcast(..) => "BebBroadcast",
+            InternalMessage::BebDeliver(..) => "BebDeliver",
+            InternalMessage::BebBroadcastComplete(..) => "BebBroadcastComplete",
+            InternalMessage::EcStartEpoch(..) => "EcStartEpoch",
+            InternalMessage::EcDeferredStartEpoch => "EcDeferredStartEpoch",
+            InternalMessage::EpPropose(..) => "EpPropose",
+            InternalMessage::EpDecide(..) => "EpDecide",
+            InternalMessage::EpStateCountReached => "EpStateCountReached",
+            InternalMessage::EpAcceptedCountReached => "EpAcceptedCountReached",
+            InternalMessage::EpAbort(..) => "EpAbort",
+            InternalMessage::EpAborted(..) => "EpAborted",
+            InternalMessage::UcPropose(..) => "UcPropose",
+            InternalMessage::UcDecide(..) => "UcDecide",
+            InternalMessage::UcProposeTimedOut => "UcProposeTimedOut",
+            InternalMessage::UcUndecided(..) => "UcUndecided",
+            InternalMessage::LeaderChanged(..) => "LeaderChanged",
+            InternalMessage::SeqPropose(..) => "SeqPropose",
+            InternalMessage::SeqDecide(..) => "SeqDecide",
+            InternalMessage::PlSend(..) => "PlSend",
+            InternalMessage::PlDeliver(..) => "PlDeliver",
+            InternalMessage::PlRetransmit => "PlRetransmit",
+            InternalMessage::PlAcknowledged(..) => "PlAcknowledged",
+            InternalMessage::PlSendFailed(..) => "PlSendFailed",
+            InternalMessage::Reconfigure(..) => "Reconfigure",
+            InternalMessage::SystemClosed => "SystemClosed",
+        }
+    }
+
+    /// One of every variant, see `variant_name`.
+    fn every_internal_message() -> Vec<InternalMessage> {
+        let node = node(1);
+        let msg = Message::new();
+        let value = Value::new();
+        vec![
+            InternalMessage::AppPropose(node.clone(), msg.clone()),
+            InternalMessage::AppInit,
+            InternalMessage::AppRegistrationTimeout,
+            InternalMessage::EpfdTimeout,
+            InternalMessage::EpfdSuspect(node.clone()),
+            InternalMessage::EpfdRestore(node.clone()),
+            InternalMessage::EldTrust(node.clone()),
+            InternalMessage::BebBroadcast(msg.clone()),
+            InternalMessage::BebDeliver(node.clone(), msg.clone()),
+            InternalMessage::BebBroadcastComplete(String::new(), vec![node.id]),
+            InternalMessage::EcStartEpoch(node.clone(), 1),
+            InternalMessage::EcDeferredStartEpoch,
+            InternalMessage::EpPropose(1, value.clone()),
+            InternalMessage::EpDecide(1, value.clone()),
+            InternalMessage::EpStateCountReached,
+            InternalMessage::EpAcceptedCountReached,
+            InternalMessage::EpAbort(1),
+            InternalMessage::EpAborted(1, 0, value.clone()),
+            InternalMessage::UcPropose(value.clone()),
+            InternalMessage::UcDecide(value.clone()),
+            InternalMessage::UcProposeTimedOut,
+            InternalMessage::UcUndecided(value.clone()),
+            InternalMessage::LeaderChanged(node.clone()),
+            InternalMessage::SeqPropose(value.clone()),
+            InternalMessage::SeqDecide(0, value),
+            InternalMessage::PlSend(node.clone(), node.clone(), msg.clone()),
+            InternalMessage::PlDeliver(node.clone(), msg.clone()),
+            InternalMessage::PlRetransmit,
+            InternalMessage::PlAcknowledged(node.clone(), String::new()),
+            InternalMessage::PlSendFailed(node.clone()),
+            InternalMessage::Reconfigure(vec![node]),
+            InternalMessage::SystemClosed,
+        ]
+    }
+
+    #[test]
+    fn every_internal_message_can_be_constructed() {
+        let events = every_internal_message();
+        let names: HashSet<_> = events.iter().map(variant_name).collect();
+        assert_eq!(names.len(), events.len());
+    }
+
+    /// Whether `source` mentions the variant `name`, rather than another variant it prefixes.
+    fn mentions(source: &str, name: &str) -> bool {
+        let path = format!("InternalMessage::{}", name);
+        source.match_indices(&path).any(|(index, _)| {
+            source[index + path.len()..]
+                .chars()
+                .next()
+                .is_none_or(|next| !next.is_alphanumeric() && next != '_')
+        })
+    }
+
+    #[test]
+    fn every_internal_message_is_used_outside_of_the_tests() {
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let sources: Vec<String> = std::fs::read_dir(src)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "rs"))
+            // the variants are declared and prioritized here, they are used by the abstractions.
+            .filter(|path| !path.ends_with("event.rs") && !path.ends_with("testing.rs"))
+            .map(|path| {
+                let source = std::fs::read_to_string(path).unwrap();
+                source.split("#[cfg(test)]").next().unwrap().to_owned()
+            })
+            .collect();
+
+        for event in every_internal_message() {
+            let name = variant_name(&event);
+            assert!(
+                sources.iter().any(|source| mentions(source, name)),
+                "InternalMessage::{} is not used by any abstraction",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn the_queue_is_running_once_created() {
+        let event_queue = EventQueue::create_and_run().unwrap();
+        assert!(event_queue.is_running());
+    }
+
+    #[test]
+    fn a_deregistered_handler_receives_nothing() {
+        let event_queue = EventQueue::create_and_run().unwrap();
+        let removed = Recorder::default();
+        let kept = Recorder::default();
+        let removed_id = event_queue.register_handler(Box::new(removed.clone()));
+        event_queue.register_handler(Box::new(kept.clone()));
+
+        event_queue.push(timeout());
+        wait_until(|| removed.events().len() == 1);
+
+        event_queue.deregister_handler(removed_id);
+        event_queue.push(timeout());
+        wait_until(|| kept.events().len() == 2);
+        assert_eq!(removed.events().len(), 1);
+    }
+
+    #[test]
+    fn the_handlers_are_only_copied_when_they_change() {
+        let event_queue = EventQueue::new_inert();
+        let id = event_queue.register_handler(Box::new(Recorder::default()));
+        let current = || {
+            EventQueue::current_handlers(
+                &event_queue.handlers,
+                &event_queue.new_handlers,
+                &event_queue.removed_handlers,
+            )
+        };
+
+        let first = current();
+        assert!(Arc::ptr_eq(&first, &current()));
+
+        event_queue.deregister_handler(id);
+        let second = current();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!((first.len(), second.len()), (1, 0));
+    }
+
+    #[test]
+    fn the_events_of_a_system_are_handled_in_order_by_several_workers() {
+        let event_queue = EventQueue::create_and_run_with_workers(2).unwrap();
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(Slow(recorder.clone())));
+        for ts in 0..20 {
+            for system_id in &["sys-1", "sys-2", "sys-3"] {
+                let abort = InternalMessage::EpAbort(ts);
+                event_queue.push(EventData::Internal(system_id.to_string(), abort));
+            }
+        }
+        wait_until(|| recorder.events().len() == 60);
+
+        for system_id in &["sys-1", "sys-2", "sys-3"] {
+            let handled: Vec<_> = recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(id, InternalMessage::EpAbort(ts)) if &id == system_id => {
+                        Some(ts)
+                    }
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(handled, (0..20).collect::<Vec<_>>());
+        }
+        event_queue.shutdown();
+        assert!(event_queue.handles.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn an_inert_queue_keeps_the_events_until_drained() {
+        let event_queue = EventQueue::new_inert();
+        let registered = Recorder::default();
+        event_queue.register_handler(Box::new(registered.clone()));
+        event_queue.push(timeout());
+        event_queue.push(EventData::Internal(
+            "sys-2".to_owned(),
+            InternalMessage::AppInit,
+        ));
+
+        let drained = Recorder::default();
+        assert_eq!(event_queue.drain_to(&mut drained.clone()), 2);
+        assert_eq!(event_queue.drain_to(&mut Recorder::default()), 0);
+        assert!(registered.events().is_empty());
+
+        let system_ids: Vec<_> = drained
+            .events()
+            .into_iter()
+            .map(|event_data| match event_data {
+                EventData::Internal(system_id, _) => system_id,
+                EventData::External(system_id, _) => system_id,
+            })
+            .collect();
+        assert_eq!(system_ids, vec!["sys-1", "sys-2"]);
+    }
+
+    /// Takes its time with every event, so that events pile up in the queue.
+    struct Slow(Recorder);
+
+    impl EventHandler for Slow {
+        fn should_handle_event(&self, _: &EventData) -> bool {
+            true
+        }
+
+        fn handle(&mut self, event_data: &EventData) {
+            thread::sleep(std::time::Duration::from_millis(5));
+            self.0.handle(event_data);
+        }
+    }
+
+    #[test]
+    fn a_shutdown_handles_the_outstanding_events_and_joins_the_worker() {
+        let event_queue = EventQueue::create_and_run().unwrap();
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(Slow(recorder.clone())));
+        for _ in 0..10 {
+            event_queue.push(timeout());
+        }
+
+        event_queue.shutdown();
+        assert!(!event_queue.is_running());
+        assert!(event_queue.handles.lock().unwrap().is_empty());
+        assert_eq!(recorder.events().len(), 10);
+
+        // later calls, including the one of `drop`, have nothing left to do.
+        event_queue.shutdown();
+    }
+
+    /// Blocks on every event until it is let through, so that the worker stays busy.
+    struct Gate(mpsc::Receiver<()>);
+
+    impl EventHandler for Gate {
+        fn should_handle_event(&self, _: &EventData) -> bool {
+            true
+        }
+
+        fn handle(&mut self, _: &EventData) {
+            let _ = self.0.recv();
+        }
+    }
+
+    /// Pushes an event of its own for every event it handles.
+    struct Echo(Arc<EventQueue>);
+
+    impl EventHandler for Echo {
+        fn should_handle_event(&self, _: &EventData) -> bool {
+            true
+        }
+
+        fn handle(&mut self, _: &EventData) {
+            self.0.push(timeout());
+        }
+    }
+
+    #[test]
+    fn the_events_pushed_while_replaying_are_dropped() {
+        let trace = crate::testing::state_dir("event-replay").join("trace.jsonl");
+        let event_queue = EventQueue::create_and_run().unwrap();
+        event_queue.trace_to(&trace).unwrap();
+        for _ in 0..3 {
+            event_queue.push(timeout());
+        }
+        event_queue.shutdown();
+
+        let event_queue = Arc::new(EventQueue::create_from_trace(&trace).unwrap());
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(Echo(event_queue.clone())));
+        event_queue.register_handler(Box::new(recorder.clone()));
+
+        assert_eq!(event_queue.replay(), 3);
+        assert_eq!(recorder.events().len(), 3);
+    }
+
+    #[test]
+    fn a_full_queue_rejects_the_events_it_is_tried_with() {
+        let event_queue = EventQueue::create_and_run_bounded(2, 1).unwrap();
+        let (open, gate) = mpsc::channel();
+        event_queue.register_handler(Box::new(Gate(gate)));
+
+        // the worker takes the first event off the queue and blocks on it.
+        event_queue.push(timeout());
+        wait_until(|| event_queue.queue.lock().unwrap().len() == 0);
+        assert!(event_queue.try_push(timeout()).is_ok());
+        assert!(event_queue.try_push(timeout()).is_ok());
+        match event_queue.try_push(timeout()) {
+            Err(Full(event_data)) => assert!(matches!(
+                *event_data,
+                EventData::Internal(_, InternalMessage::EpfdTimeout)
+            )),
+            Ok(()) => panic!("The queue should be full."),
+        }
+
+        for _ in 0..3 {
+            open.send(()).unwrap();
+        }
+        wait_until(|| event_queue.try_push(timeout()).is_ok());
+        drop(open);
+    }
+
+    #[test]
+    fn a_timeout_overtakes_the_queued_broadcasts() {
+        let event_queue = EventQueue::create_and_run().unwrap();
+        let (open, gate) = mpsc::channel();
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(Gate(gate)));
+        event_queue.register_handler(Box::new(recorder.clone()));
+
+        // the worker blocks on the first broadcast while the others pile up behind it.
+        let broadcast = || {
+            EventData::Internal(
+                "sys-1".to_owned(),
+                InternalMessage::BebBroadcast(Message::new()),
+            )
+        };
+        event_queue.push(broadcast());
+        wait_until(|| event_queue.queue.lock().unwrap().len() == 0);
+        for _ in 0..50 {
+            event_queue.push(broadcast());
+        }
+        event_queue.push(timeout());
+        for _ in 0..52 {
+            open.send(()).unwrap();
+        }
+
+        wait_until(|| recorder.events().len() == 52);
+        let is_high: Vec<_> = recorder
+            .events()
+            .iter()
+            .map(|event_data| priority(event_data) == Priority::High)
+            .collect();
+        assert_eq!(is_high.iter().position(|&high| high), Some(1));
     }
 }
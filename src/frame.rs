@@ -0,0 +1,136 @@
+use crate::protos::message::Message;
+use crate::transport::decode_payload;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+/// The largest frame read by default, in bytes. A frame announcing more is rejected rather
+/// than read.
+pub const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+/// Writes `message` framed the way the hub expects it: its length, as a big endian `i32`,
+/// followed by the message itself.
+pub fn write_frame<W: Write>(writer: &mut W, message: &Message) -> io::Result<()> {
+    let bytes = protobuf::Message::write_to_bytes(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_payload(writer, &bytes)
+}
+
+/// Reads the next message framed by `write_frame`, or by `transport::encode_versioned`.
+/// `None` once the stream ends before a new frame starts, a frame cut short is an error.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Message>> {
+    read_frame_limited(reader, MAX_FRAME_BYTES)
+}
+
+/// See `read_frame`. A frame announcing more than `max_bytes` is an error, none of it being
+/// read, let alone allocated, and so is a message decompressing to more than `max_bytes`.
+pub fn read_frame_limited<R: Read>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> io::Result<Option<Message>> {
+    match read_payload(reader, max_bytes)? {
+        Some(payload) => decode_payload(&payload, max_bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Writes `payload` preceded by its length, as a big endian `i32`.
+pub(crate) fn write_payload<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let length = i32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "The message is too large."))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Reads the payload following the next length, see `write_payload`.
+pub(crate) fn read_payload<R: Read>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    let mut read = 0;
+    while read < length.len() {
+        match reader.read(&mut length[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(truncated("The message is missing its length.")),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let length = u64::try_from(i32::from_be_bytes(length)).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "The message has a negative length.")
+    })?;
+    if length > max_bytes as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The message announces {} bytes, more than the {} allowed.", length, max_bytes),
+        ));
+    }
+
+    // the length is not trusted with an allocation, only the bytes actually read are kept.
+    let mut payload = Vec::new();
+    reader.take(length).read_to_end(&mut payload)?;
+    if payload.len() as u64 != length {
+        return Err(truncated("The message is shorter than its length."));
+    }
+    Ok(Some(payload))
+}
+
+fn truncated(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::message::Message_Type;
+    use crate::transport::encode_versioned;
+
+    fn message(system_id: &str) -> Message {
+        let mut message = Message::new();
+        message.set_field_type(Message_Type::APP_PROPOSE);
+        message.set_systemId(system_id.to_owned());
+        message
+    }
+
+    #[test]
+    fn several_messages_are_read_back_in_order() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &message("sys-1")).unwrap();
+        stream.extend(encode_versioned(&message("sys-2"), Some(0)).unwrap());
+        write_frame(&mut stream, &Message::new()).unwrap();
+
+        let mut reader = &stream[..];
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(message("sys-1")));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(message("sys-2")));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(Message::new()));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn a_truncated_frame_is_an_error() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &message("sys-1")).unwrap();
+
+        for end in 1..frame.len() {
+            let error = read_frame(&mut &frame[..end]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+        let error = read_frame(&mut &[0xff, 0xff, 0xff, 0xff][..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn a_frame_larger_than_the_limit_is_not_read() {
+        // the announced 2 GiB would be there to read, and then some.
+        let header = i32::MAX.to_be_bytes();
+        let mut endless = (&header[..]).chain(io::repeat(0));
+        let error = read_frame_limited(&mut endless, 1024).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &message("sys-1")).unwrap();
+        assert!(read_frame_limited(&mut &frame[..], frame.len() - 5).is_err());
+        assert!(read_frame_limited(&mut &frame[..], frame.len() - 4).unwrap().is_some());
+    }
+}
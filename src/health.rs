@@ -0,0 +1,173 @@
+use crate::event::EventQueue;
+use log::{error, trace};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a running failure detector may go without handling its timeout before the
+/// process is reported as wedged.
+pub const DEFAULT_HEALTH_WINDOW: Duration = Duration::from_secs(30);
+/// How long a probe may take to send its request, and to read the answer, before it is
+/// dropped. The probes are answered one at a time, a silent one would hold back the others.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// When the failure detectors of the process last handled their timeout. The timeouts go
+/// through the event queue, so a process whose queue is stuck stops updating it, while an
+/// idle one keeps doing so.
+#[derive(Debug)]
+pub struct Health {
+    last_timeout: Mutex<Instant>,
+    detectors: AtomicUsize,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Health {
+            last_timeout: Mutex::new(Instant::now()),
+            detectors: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether a failure detector handled its timeout within `window`. A process without
+    /// any running failure detector is idle rather than wedged.
+    pub fn timeouts_within(&self, window: Duration) -> bool {
+        self.detectors.load(Ordering::Relaxed) == 0
+            || self.last_timeout.lock().unwrap().elapsed() <= window
+    }
+
+    pub(crate) fn record_timeout(&self) {
+        *self.last_timeout.lock().unwrap() = Instant::now();
+    }
+
+    /// A failure detector started, it is expected to time out from now on.
+    pub(crate) fn record_detector_started(&self) {
+        self.detectors.fetch_add(1, Ordering::Relaxed);
+        self.record_timeout();
+    }
+
+    pub(crate) fn record_detector_stopped(&self) {
+        let _ = self
+            .detectors
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
+
+/// Whether the worker of `event_queue` still runs and its failure detectors still time out.
+pub fn is_healthy(event_queue: &EventQueue, window: Duration) -> bool {
+    event_queue.is_worker_alive() && event_queue.health().timeouts_within(window)
+}
+
+/// Answers every HTTP request on `listener` with `200 OK` while the process is healthy, see
+/// `is_healthy`, and with `503 Service Unavailable` otherwise. The queue is only weakly held,
+/// the process is unhealthy once it is gone.
+pub fn serve_health(
+    listener: TcpListener,
+    event_queue: &Arc<EventQueue>,
+    window: Duration,
+) -> io::Result<()> {
+    let event_queue = Arc::downgrade(event_queue);
+    thread::Builder::new()
+        .name("health".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| answer_probe(stream, &event_queue, window));
+                if let Err(e) = result {
+                    error!("Unable to answer the health probe: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn answer_probe(
+    mut stream: TcpStream,
+    event_queue: &Weak<EventQueue>,
+    window: Duration,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request)?;
+    trace!(
+        "Health probed with {}",
+        String::from_utf8_lossy(&request[..read]).lines().next().unwrap_or_default()
+    );
+
+    let healthy = event_queue
+        .upgrade()
+        .is_some_and(|event_queue| is_healthy(&event_queue, window));
+    let (status, body) = if healthy {
+        ("200 OK", "ok\n")
+    } else {
+        ("503 Service Unavailable", "unhealthy\n")
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(address: std::net::SocketAddr) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /healthz HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn a_stopped_queue_is_reported_unhealthy() {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve_health(listener, &event_queue, DEFAULT_HEALTH_WINDOW).unwrap();
+        assert!(probe(address).starts_with("HTTP/1.1 200 OK"));
+
+        event_queue.shutdown();
+        assert!(probe(address).starts_with("HTTP/1.1 503 Service Unavailable"));
+    }
+
+    #[test]
+    fn a_silent_probe_does_not_hold_back_the_next_ones() {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve_health(listener, &event_queue, DEFAULT_HEALTH_WINDOW).unwrap();
+
+        let _silent = TcpStream::connect(address).unwrap();
+        let started = Instant::now();
+        assert!(probe(address).starts_with("HTTP/1.1 200 OK"));
+        assert!(started.elapsed() < PROBE_TIMEOUT * 5);
+    }
+
+    #[test]
+    fn a_running_detector_has_to_time_out_within_the_window() {
+        let health = Health::new();
+        assert!(health.timeouts_within(Duration::from_secs(0)));
+
+        health.record_detector_started();
+        thread::sleep(Duration::from_millis(20));
+        assert!(!health.timeouts_within(Duration::from_millis(10)));
+        health.record_timeout();
+        assert!(health.timeouts_within(Duration::from_millis(10)));
+
+        health.record_detector_stopped();
+        assert!(health.timeouts_within(Duration::from_secs(0)));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::node::Node;
+use std::sync::{Arc, RwLock};
+
+/// A cheap to read guess of who currently leads a system, so that proposals can be sent
+/// straight to the leader instead of being forwarded around.
+/// The hint is tagged with the epoch timestamp it was learned in and is invalidated as soon
+/// as an epoch change starts, until the new leader is known.
+#[derive(Debug, Clone, Default)]
+pub struct LeaderHint {
+    inner: Arc<RwLock<Hint>>,
+}
+
+#[derive(Debug, Default)]
+struct Hint {
+    current: Option<(Node, u32)>,
+    /// The last leader learned, kept across the invalidations to tell whether it changed.
+    last_leader: Option<Node>,
+}
+
+impl LeaderHint {
+    pub fn new() -> Self {
+        LeaderHint::default()
+    }
+
+    /// Records `leader` as the leader of the epoch `epoch_ts`. Hints from older epochs are ignored.
+    /// Returns whether the leader changed, an epoch change keeping the same one did not.
+    pub fn update(&self, leader: &Node, epoch_ts: u32) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let is_newer = match inner.current.as_ref() {
+            Some((_, current_ts)) => epoch_ts >= *current_ts,
+            None => true,
+        };
+        if !is_newer {
+            return false;
+        }
+        inner.current.replace((leader.clone(), epoch_ts));
+        inner.last_leader.replace(leader.clone()).as_ref() != Some(leader)
+    }
+
+    pub fn invalidate(&self) {
+        self.inner.write().unwrap().current.take();
+    }
+
+    pub fn leader(&self) -> Option<Node> {
+        self.inner.read().unwrap().current.as_ref().map(|(leader, _)| leader.clone())
+    }
+
+    pub fn epoch_ts(&self) -> Option<u32> {
+        self.inner.read().unwrap().current.as_ref().map(|(_, ts)| *ts)
+    }
+
+    /// Whether a leader learned during the epoch `epoch_ts` is no longer the current one.
+    pub fn is_stale(&self, epoch_ts: u32) -> bool {
+        self.epoch_ts() != Some(epoch_ts)
+    }
+
+    /// Picks the node a proposal should be directed to: the hinted leader if there is one,
+    /// otherwise `fallback`.
+    pub fn route(&self, fallback: &Node) -> Node {
+        self.leader().unwrap_or_else(|| fallback.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::node;
+
+    #[test]
+    fn a_proposal_is_routed_to_the_new_leader_once_the_hint_is_stale() {
+        let hint = LeaderHint::new();
+        hint.update(&node(3), 0);
+        assert_eq!(hint.route(&node(1)), node(3));
+        let learned_in = hint.epoch_ts().unwrap();
+
+        // an epoch change starts, the new leader is not known yet.
+        hint.invalidate();
+        assert!(hint.is_stale(learned_in));
+        assert_eq!(hint.route(&node(1)), node(1));
+
+        hint.update(&node(2), 5);
+        assert!(hint.is_stale(learned_in));
+        assert_eq!(hint.route(&node(1)), node(2));
+    }
+
+    #[test]
+    fn only_a_new_leader_is_a_change() {
+        let hint = LeaderHint::new();
+        assert!(hint.update(&node(3), 0));
+
+        hint.invalidate();
+        assert!(!hint.update(&node(3), 5));
+        hint.invalidate();
+        assert!(hint.update(&node(2), 8));
+        assert!(!hint.update(&node(3), 6));
+    }
+
+    #[test]
+    fn hints_of_older_epochs_are_ignored() {
+        let hint = LeaderHint::new();
+        hint.update(&node(2), 5);
+        hint.update(&node(3), 3);
+
+        assert_eq!(hint.leader(), Some(node(2)));
+        assert!(!hint.is_stale(5));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::node::Node;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_CAPACITY: usize = 64;
+
+/// A single leadership transition as observed by the eventual leader detector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LeadershipChange {
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u128,
+    pub old_leader: Option<Node>,
+    pub new_leader: Node,
+    /// The suspected processes that caused the change.
+    pub suspected: Vec<Node>,
+}
+
+/// Bounded in-memory record of who was leader when, so that flapping leadership is visible.
+/// Once full, the oldest change is dropped for every new one.
+#[derive(Debug, Clone)]
+pub struct LeadershipHistory {
+    capacity: usize,
+    changes: Arc<Mutex<VecDeque<LeadershipChange>>>,
+}
+
+impl Default for LeadershipHistory {
+    fn default() -> Self {
+        LeadershipHistory::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl LeadershipHistory {
+    pub fn new() -> Self {
+        LeadershipHistory::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        LeadershipHistory {
+            capacity,
+            changes: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub fn record(&self, old_leader: Option<&Node>, new_leader: &Node, suspected: &[Node]) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let change = LeadershipChange {
+            timestamp_ms,
+            old_leader: old_leader.cloned(),
+            new_leader: new_leader.clone(),
+            suspected: suspected.to_vec(),
+        };
+
+        let mut changes = self.changes.lock().unwrap();
+        if self.capacity == 0 {
+            return;
+        }
+        while changes.len() >= self.capacity {
+            changes.pop_front();
+        }
+        changes.push_back(change);
+    }
+
+    /// The recorded changes, oldest first.
+    pub fn changes(&self) -> Vec<LeadershipChange> {
+        self.changes.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn last(&self) -> Option<LeadershipChange> {
+        self.changes.lock().unwrap().back().cloned()
+    }
+}
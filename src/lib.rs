@@ -0,0 +1,48 @@
+//! Distributed consensus algorithm implementation.
+//!
+//! The consensus stack is built out of event handlers that communicate through an `EventQueue`.
+//! `Engine` wires together the handlers needed by a single process, and `Server` runs it
+//! along with the listener receiving the messages of the other processes.
+pub mod app;
+pub mod beb;
+pub mod client;
+pub mod clock;
+pub mod config;
+pub mod ec;
+pub mod eld;
+mod engine;
+pub mod ep;
+pub mod epfd;
+pub mod event;
+pub mod frame;
+pub mod health;
+pub mod history;
+pub mod hint;
+pub mod metadata;
+pub mod metrics;
+pub mod node;
+pub mod pl;
+pub mod protos;
+pub mod quorum;
+pub mod seqc;
+pub mod server;
+pub mod sink;
+pub mod status;
+pub mod storage;
+pub mod sys;
+#[cfg(test)]
+mod testing;
+pub mod timeline;
+pub mod tls;
+pub mod trace;
+pub mod transport;
+pub mod uc;
+pub mod value;
+
+pub use engine::Engine;
+pub use event::{EventQueue, ValueType};
+pub use hint::LeaderHint;
+pub use node::{Node, NodeInfo, NodeRole};
+pub use server::{RunningNode, Server, Shutdown};
+pub use sys::System;
+pub use value::{Batch, ConsensusValue};
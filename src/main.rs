@@ -1,35 +1,33 @@
-mod app;
-mod beb;
-mod ec;
-mod eld;
-mod ep;
-mod epfd;
-mod event;
-mod node;
-mod pl;
-mod protos;
-mod sys;
-mod uc;
 use clap::{App, Arg};
+use distributed_consensus::config::{exclude_hub, read_config, validate_config};
+use distributed_consensus::epfd;
+use distributed_consensus::frame::MAX_FRAME_BYTES;
+use distributed_consensus::health::{serve_health, DEFAULT_HEALTH_WINDOW};
+use distributed_consensus::metrics::serve_metrics;
+use distributed_consensus::sink::{DecisionSink, FileSink, WebhookSink};
+use distributed_consensus::node::{find_rank_collision, highest_ranked, total_votes};
+use distributed_consensus::quorum::{QuorumPolicy, Quorums};
+use distributed_consensus::tls::TlsConfig;
+use distributed_consensus::transport::DEFAULT_SEND_TIMEOUT;
+use distributed_consensus::uc::DEFAULT_DECISION_DEADLINE;
+use distributed_consensus::{Node, NodeInfo, Server};
 use env_logger::{Builder, Target};
-use event::{EventData, EventQueue, InternalMessage};
-use log::{error, info, trace};
-use node::Node;
-use node::NodeInfo;
-use protos::message::Message;
-use serde_json;
+use log::{error, info};
 use std::error::Error;
-use std::fs;
-use std::io::prelude::*;
-use std::net::SocketAddr;
-use std::net::TcpListener;
-use std::path::Path;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut builder = Builder::from_default_env();
     builder.target(Target::Stdout);
     builder.init();
 
+    let default_epfd_delta = epfd::DEFAULT_DELTA.to_string();
+    let default_epfd_startup_grace = epfd::DEFAULT_STARTUP_GRACE.to_string();
+    let default_decision_deadline = DEFAULT_DECISION_DEADLINE.to_string();
+    let default_send_timeout = DEFAULT_SEND_TIMEOUT.as_millis().to_string();
+    let default_max_frame_bytes = MAX_FRAME_BYTES.to_string();
     let matches = App::new("Distributed Consensus")
         .version("1.0")
         .author("Florin T. <tamasflorin@live.com>")
@@ -43,7 +41,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             Arg::with_name("config")
                 .short("c")
                 .long("config")
-                .help("The node configuration file.")
+                .help("The node configuration file, in JSON, TOML or YAML format.")
                 .takes_value(true)
                 .required(true),
         )
@@ -51,96 +49,339 @@ fn main() -> Result<(), Box<dyn Error>> {
             Arg::with_name("hub")
                 .short("hb")
                 .long("hub")
-                .help("The hub configuration file.")
+                .help("The hub configuration file, in JSON, TOML or YAML format. Without a hub, the nodes only get the proposals of clients and keep their decisions to themselves.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state-dir")
+                .long("state-dir")
+                .help("The directory where the consensus state is persisted, it has to outlive the node for it to recover after a crash.")
                 .takes_value(true)
-                .required(true),
+                .required_unless_one(&["check", "print-topology"]),
+        )
+        .arg(
+            Arg::with_name("epfd-delta")
+                .long("epfd-delta")
+                .help("The initial heartbeat timeout of the failure detector, in milliseconds.")
+                .takes_value(true)
+                .default_value(&default_epfd_delta),
+        )
+        .arg(
+            Arg::with_name("epfd-startup-grace")
+                .long("epfd-startup-grace")
+                .help("How long the failure detector waits before its first heartbeat round, in milliseconds, giving the other nodes time to start.")
+                .takes_value(true)
+                .default_value(&default_epfd_startup_grace),
+        )
+        .arg(
+            Arg::with_name("decision-deadline")
+                .long("decision-deadline")
+                .help("How long a proposal may go without any epoch progress before it is reported as timed out, in milliseconds.")
+                .takes_value(true)
+                .default_value(&default_decision_deadline),
+        )
+        .arg(
+            Arg::with_name("send-timeout")
+                .long("send-timeout")
+                .help("How long connecting to another node, and then sending it a message, may take, in milliseconds.")
+                .takes_value(true)
+                .default_value(&default_send_timeout),
+        )
+        .arg(
+            Arg::with_name("max-frame-bytes")
+                .long("max-frame-bytes")
+                .help("Drops the connections announcing a message of more than the given number of bytes, without reading it.")
+                .takes_value(true)
+                .default_value(&default_max_frame_bytes),
+        )
+        .arg(
+            Arg::with_name("compress-above")
+                .long("compress-above")
+                .help("Compresses the messages to the other nodes which take at least the given number of bytes. The nodes receiving them have to support compression, the hub never gets compressed messages.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("workers")
+                .long("workers")
+                .help("How many threads handle the events of the node. The events of a system are always handled in order, by the same thread.")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("quorum")
+                .long("quorum")
+                .help("How many of the voters make a quorum: majority, all, or a number of voters. Every node has to use the same.")
+                .default_value("majority")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("read-quorum")
+                .long("read-quorum")
+                .help("Overrides --quorum for the STATEs collected by the leader of an epoch. Together with the write quorum, it has to add up to more than the voters.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("write-quorum")
+                .long("write-quorum")
+                .help("Overrides --quorum for the ACCEPTs collected by the leader of an epoch. Together with the read quorum, it has to add up to more than the voters.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .help("The address to listen on, e.g. 0.0.0.0:1330. Takes precedence over the address of the node in the configuration file, which is still the one advertised to the other processes.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-addr")
+                .long("metrics-addr")
+                .help("Serves the metrics of the node in the Prometheus text format on the given address, e.g. 127.0.0.1:9100.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("health-addr")
+                .long("health-addr")
+                .help("Answers HTTP liveness probes on the given address, e.g. 127.0.0.1:9101: 200 while the node handles its events, 503 once it is wedged.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .env("CONSENSUS_TRACE")
+                .help("Records every event the node handles to the given JSONL file, so that the run can be replayed.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .help("Secures the connections with TLS, presenting the given PEM certificate of the node. Requires --tls-key and --tls-ca.")
+                .takes_value(true)
+                .requires_all(&["tls-key", "tls-ca"]),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .help("The PEM private key of the certificate given by --tls-cert.")
+                .takes_value(true)
+                .requires("tls-cert"),
+        )
+        .arg(
+            Arg::with_name("tls-ca")
+                .long("tls-ca")
+                .help("The PEM certificate of the authority the certificates of all the nodes are signed by. Nodes presenting any other certificate are rejected.")
+                .takes_value(true)
+                .requires("tls-cert"),
+        )
+        .arg(
+            Arg::with_name("decision-log")
+                .long("decision-log")
+                .help("Appends every decided value to the given file.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Only checks the node and hub configurations, printing the nodes and the initial leader, without starting the node."),
+        )
+        .arg(
+            Arg::with_name("print-topology")
+                .long("print-topology")
+                .help("Prints the resolved view of the cluster as JSON, the address every node resolves to included, without starting the node."),
+        )
+        .arg(
+            Arg::with_name("decision-webhook")
+                .long("decision-webhook")
+                .help("POSTs every decided value to the given http:// URL.")
+                .takes_value(true),
         )
         .get_matches();
 
     let file_name = matches.value_of("config").unwrap();
-    let hub_file = matches.value_of("hub").unwrap();
     let my_id = matches.value_of("id").unwrap().parse::<u16>()?;
-    let mut nodes = read_config(&file_name)?;
-    let hub_nodes = read_config(&hub_file)?;
-    let hub = hub_nodes.get(0).unwrap().clone();
-    nodes.extend(hub_nodes.clone());
-
-    let current_node = nodes.iter().find(|node| node.id == my_id).unwrap().clone();
-    let node_info = std::sync::Arc::new(node::NodeInfo {
-        current_node,
-        hub,
-        nodes,
+    let epfd_delta = matches.value_of("epfd-delta").unwrap().parse::<i64>()?;
+    if epfd_delta <= 0 {
+        return Err("The heartbeat timeout of the failure detector has to be positive.".into());
+    }
+    let epfd_startup_grace = matches
+        .value_of("epfd-startup-grace")
+        .unwrap()
+        .parse::<i64>()?;
+    let decision_deadline = matches
+        .value_of("decision-deadline")
+        .unwrap()
+        .parse::<i64>()?;
+    let send_timeout = matches.value_of("send-timeout").unwrap().parse::<u64>()?;
+    if send_timeout == 0 {
+        return Err("The send timeout has to be positive.".into());
+    }
+    let max_frame_bytes = matches.value_of("max-frame-bytes").unwrap().parse::<usize>()?;
+    let compression_threshold = match matches.value_of("compress-above") {
+        Some(threshold) => Some(threshold.parse::<usize>()?),
+        None => None,
+    };
+    let event_workers = matches.value_of("workers").unwrap().parse::<usize>()?;
+    if event_workers == 0 {
+        return Err("At least one worker has to handle the events.".into());
+    }
+    let quorum = matches.value_of("quorum").unwrap().parse::<QuorumPolicy>()?;
+    let mut quorums = Quorums::uniform(quorum);
+    if let Some(read) = matches.value_of("read-quorum") {
+        quorums.read = read.parse()?;
+    }
+    if let Some(write) = matches.value_of("write-quorum") {
+        quorums.write = write.parse()?;
+    }
+    let listen_address = match matches.value_of("listen") {
+        Some(address) => Some(address.parse::<SocketAddr>()?),
+        None => None,
+    };
+    let metrics_address = match matches.value_of("metrics-addr") {
+        Some(address) => Some(address.parse::<SocketAddr>()?),
+        None => None,
+    };
+    let health_address = match matches.value_of("health-addr") {
+        Some(address) => Some(address.parse::<SocketAddr>()?),
+        None => None,
+    };
+    let trace = matches.value_of("trace").map(PathBuf::from);
+    let tls = match matches.value_of("tls-cert") {
+        Some(cert) => Some(TlsConfig::from_pem_files(
+            cert,
+            matches.value_of("tls-key").unwrap(),
+            matches.value_of("tls-ca").unwrap(),
+        )?),
+        None => None,
+    };
+    let mut sinks: Vec<Box<dyn DecisionSink>> = Vec::new();
+    if let Some(path) = matches.value_of("decision-log") {
+        sinks.push(Box::new(FileSink::new(path)));
+    }
+    if let Some(url) = matches.value_of("decision-webhook") {
+        sinks.push(Box::new(WebhookSink::new(url)?));
+    }
+    let mut nodes = read_config(file_name)?;
+    validate_config(&nodes, my_id)?;
+    if let Some((node, other)) = find_rank_collision(&nodes) {
+        return Err(format!("Nodes {} and {} share the rank {}.", node, other, node.rank).into());
+    }
+    let hub = match matches.value_of("hub") {
+        Some(hub_file) => {
+            let hub = read_config(hub_file)?
+                .into_iter()
+                .next()
+                .ok_or("The hub configuration does not list any node.")?;
+            exclude_hub(&mut nodes, &hub)?;
+            Some(hub)
+        }
+        None => None,
+    };
+    let current_node = nodes
+        .iter()
+        .find(|node| node.id == my_id)
+        .ok_or_else(|| format!("Node {} is the hub.", my_id))?
+        .clone();
+    quorums.validate(total_votes(&nodes))?;
+    let node_info = std::sync::Arc::new(NodeInfo {
+        epfd_delta: chrono::Duration::milliseconds(epfd_delta),
+        epfd_startup_grace: chrono::Duration::milliseconds(epfd_startup_grace),
+        decision_deadline: chrono::Duration::milliseconds(decision_deadline),
+        compression_threshold,
+        quorums,
+        ..NodeInfo::new(current_node, hub, nodes)
     });
+    if matches.is_present("check") {
+        print_summary(&node_info);
+        return Ok(());
+    }
+    if matches.is_present("print-topology") {
+        println!("{}", serde_json::to_string_pretty(&topology(&node_info))?);
+        return Ok(());
+    }
 
-    run(node_info)
+    let state_dir = PathBuf::from(matches.value_of("state-dir").unwrap());
+    let mut server = Server::new(state_dir)
+        .with_sinks(sinks)
+        .with_send_timeout(Duration::from_millis(send_timeout))
+        .with_workers(event_workers)
+        .with_max_frame_bytes(max_frame_bytes);
+    if let Some(listen_address) = listen_address {
+        server = server.with_listen_address(listen_address);
+    }
+    if let Some(trace) = trace {
+        server = server.with_trace(trace);
+    }
+    if let Some(tls) = tls {
+        server = server.with_tls(tls);
+    }
+    run(server, node_info, metrics_address, health_address)
 }
 
-fn read_config<P: AsRef<Path>>(path: &P) -> Result<Vec<Node>, Box<dyn Error>> {
-    let mut file = fs::File::open(path.as_ref())?;
-    let mut contents = String::new();
-    let _ = file.read_to_string(&mut contents)?;
-    let nodes: Vec<Node> = serde_json::from_str(&contents)?;
-    Ok(nodes)
+/// What `--check` found in a valid configuration.
+fn print_summary(node_info: &NodeInfo) {
+    println!("The configuration is valid, {} nodes:", node_info.nodes.len());
+    for node in &node_info.nodes {
+        println!("  {} (rank {}, {:?}, weight {})", node, node.rank, node.role, node.weight);
+    }
+    match &node_info.hub {
+        Some(hub) => println!("Hub: {}", hub),
+        None => println!("No hub"),
+    }
+    if let Some(leader) = highest_ranked(&node_info.nodes) {
+        println!("Initial leader: {}", leader);
+    }
 }
 
-fn run(node_info: std::sync::Arc<NodeInfo>) -> Result<(), Box<dyn Error>> {
-    info!("Listening on Node: {}", node_info.current_node);
-
-    let event_queue = std::sync::Arc::new(EventQueue::create_and_run());
-    let pl = pl::PerfectLink::new(event_queue.clone(), node_info.clone());
-    let app = app::App::new(
-        node_info.current_node.clone(),
-        node_info.hub.clone(),
-        event_queue.clone(),
-    );
-    let app_system_id = "app_system_id";
-    event_queue.register_handler(Box::new(app));
-    event_queue.register_handler(Box::new(pl));
-    event_queue.push(EventData::Internal(
-        app_system_id.to_owned(),
-        InternalMessage::AppInit,
-    ));
-    let listen_result = listen_for_clients(event_queue.clone(), node_info.clone());
-    if listen_result.is_err() {
-        error!("{:?}", listen_result.err());
-    }
-    Ok(())
+/// What `--print-topology` prints: the process, the initial leader, the hub and every node
+/// along with the address it resolves to, or why it does not.
+fn topology(node_info: &NodeInfo) -> serde_json::Value {
+    let describe = |node: &Node| {
+        let (address, error) = match node.resolve() {
+            Ok(address) => (Some(address.to_string()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        serde_json::json!({
+            "id": node.id,
+            "name": node.name,
+            "host": node.host,
+            "port": node.port,
+            "rank": node.rank,
+            "role": node.role,
+            "weight": node.weight,
+            "address": address,
+            "error": error,
+        })
+    };
+    serde_json::json!({
+        "id": node_info.current_node.id,
+        "initial_leader": highest_ranked(&node_info.nodes).map(|leader| leader.id),
+        "hub": node_info.hub.as_ref().map(describe),
+        "nodes": node_info.nodes.iter().map(describe).collect::<Vec<_>>(),
+    })
 }
 
-fn listen_for_clients(
-    event_queue: std::sync::Arc<EventQueue>,
+fn run(
+    server: Server,
     node_info: std::sync::Arc<NodeInfo>,
+    metrics_address: Option<SocketAddr>,
+    health_address: Option<SocketAddr>,
 ) -> Result<(), Box<dyn Error>> {
-    let address: SocketAddr = node_info.current_node.clone().into();
-    let listener = TcpListener::bind(address)?;
-    loop {
-        match listener.accept() {
-            Ok((mut stream, client)) => {
-                trace!("Client connected: {}", client);
-                let mut recv_bytes = Vec::new();
-                let read_result = stream.read_to_end(&mut recv_bytes);
-                if let Ok(_) = read_result {
-                    let proto_buffer = &recv_bytes[4..];
-                    let message: Result<Message, protobuf::ProtobufError> =
-                        protobuf::parse_from_bytes(proto_buffer);
-
-                    match message {
-                        Ok(recv_msg) => {
-                            let system_id: String = recv_msg.get_systemId().into();
-                            let message = EventData::External(system_id, recv_msg);
-                            event_queue.push(message);
-                        }
-                        Err(e) => {
-                            error!("Failed to parse message with error: {}", e);
-                        }
-                    };
-                } else {
-                    error!("Unable to read message bytes.");
-                }
-            }
-            Err(e) => {
-                return Err(Box::new(e));
-            }
-        }
+    let node = server.start(node_info)?;
+    if let Some(metrics_address) = metrics_address {
+        serve_metrics(TcpListener::bind(metrics_address)?, node.engine().metrics())?;
+        info!("Serving metrics on {}", metrics_address);
+    }
+    if let Some(health_address) = health_address {
+        let listener = TcpListener::bind(health_address)?;
+        serve_health(listener, &node.engine().event_queue(), DEFAULT_HEALTH_WINDOW)?;
+        info!("Answering health probes on {}", health_address);
     }
+    let shutdown = node.shutdown_handle();
+    ctrlc::set_handler(move || {
+        info!("Shutting down.");
+        shutdown.trigger();
+    })?;
+    if let Err(e) = node.wait() {
+        error!("{:?}", e);
+    }
+    Ok(())
 }
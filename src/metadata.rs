@@ -0,0 +1,106 @@
+use crate::hint::LeaderHint;
+use crate::node::{Node, NodeId};
+use log::warn;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Upper bound for the encoded size, keys and values included, of the metadata carried
+/// by a single heartbeat.
+pub const MAX_METADATA_BYTES: usize = 512;
+
+/// Key under which the local leader hint is published, as JSON encoded (leader, epoch_ts).
+pub const LEADER_HINT_KEY: &str = "leader";
+
+pub type Metadata = HashMap<String, Vec<u8>>;
+
+fn encoded_size(metadata: &Metadata) -> usize {
+    metadata
+        .iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum()
+}
+
+/// Small pieces of cluster metadata piggybacked on the failure detector heartbeats, so
+/// that they are spread without extra message rounds.
+/// Abstractions publish entries with `set`, the latest metadata received from every peer
+/// can be read with `peer`.
+#[derive(Debug, Clone, Default)]
+pub struct HeartbeatMetadata {
+    local: Arc<RwLock<Metadata>>,
+    peers: Arc<RwLock<HashMap<NodeId, Metadata>>>,
+    leader_hint: Option<LeaderHint>,
+}
+
+impl HeartbeatMetadata {
+    pub fn new() -> Self {
+        HeartbeatMetadata::default()
+    }
+
+    /// Also publishes `leader_hint` with every heartbeat.
+    pub fn with_leader_hint(leader_hint: LeaderHint) -> Self {
+        HeartbeatMetadata {
+            leader_hint: Some(leader_hint),
+            ..HeartbeatMetadata::default()
+        }
+    }
+
+    /// Publishes an entry, returns false if it would exceed `MAX_METADATA_BYTES`.
+    pub fn set(&self, key: &str, value: Vec<u8>) -> bool {
+        let mut local = self.local.write().unwrap();
+        let previous = local.insert(key.to_owned(), value);
+        if encoded_size(&local) > MAX_METADATA_BYTES {
+            match previous {
+                Some(previous) => local.insert(key.to_owned(), previous),
+                None => local.remove(key),
+            };
+            return false;
+        }
+        true
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.local.write().unwrap().remove(key);
+    }
+
+    /// The metadata to attach to an outgoing heartbeat.
+    pub fn outgoing(&self) -> Metadata {
+        let mut metadata = self.local.read().unwrap().clone();
+        let leader_hint = self
+            .leader_hint
+            .as_ref()
+            .and_then(|hint| hint.leader().zip(hint.epoch_ts()));
+        if let Some(leader_hint) = leader_hint {
+            if let Ok(encoded) = serde_json::to_vec(&leader_hint) {
+                metadata.insert(LEADER_HINT_KEY.to_owned(), encoded);
+                if encoded_size(&metadata) > MAX_METADATA_BYTES {
+                    metadata.remove(LEADER_HINT_KEY);
+                }
+            }
+        }
+        metadata
+    }
+
+    /// Records the metadata received from `from`, oversized metadata is dropped.
+    pub fn received(&self, from: &Node, metadata: &Metadata) {
+        if encoded_size(metadata) > MAX_METADATA_BYTES {
+            warn!("Dropping oversized heartbeat metadata from {}", from);
+            return;
+        }
+        self.peers
+            .write()
+            .unwrap()
+            .insert(from.id, metadata.clone());
+    }
+
+    /// The latest metadata received from the process `id`.
+    pub fn peer(&self, id: NodeId) -> Option<Metadata> {
+        self.peers.read().unwrap().get(&id).cloned()
+    }
+
+    /// The leader, and the epoch it leads, that the process `id` last told us about.
+    pub fn peer_leader_hint(&self, id: NodeId) -> Option<(Node, u32)> {
+        let peers = self.peers.read().unwrap();
+        let encoded = peers.get(&id)?.get(LEADER_HINT_KEY)?;
+        serde_json::from_slice(encoded).ok()
+    }
+}
@@ -0,0 +1,255 @@
+use log::{error, trace};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// The upper bounds, in seconds, of the buckets of the decision latency histogram.
+const LATENCY_BUCKETS: [f64; 8] = [0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// Counters and gauges of a process, updated by the abstractions as they go and exported
+/// in the Prometheus text format. The event queue of the process owns them, so that every
+/// abstraction can get to them.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queue_depth: AtomicUsize,
+    decisions: AtomicU64,
+    epoch_changes: AtomicU64,
+    suspected: AtomicUsize,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    /// How many decisions took at most the bound of each of `LATENCY_BUCKETS`.
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// The number of events waiting in the event queue.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// The number of values decided, across all the systems.
+    pub fn decisions(&self) -> u64 {
+        self.decisions.load(Ordering::Relaxed)
+    }
+
+    /// The number of epochs started, across all the systems.
+    pub fn epoch_changes(&self) -> u64 {
+        self.epoch_changes.load(Ordering::Relaxed)
+    }
+
+    /// The number of processes currently suspected, summed over all the systems.
+    pub fn suspected(&self) -> usize {
+        self.suspected.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received.load(Ordering::Relaxed)
+    }
+
+    /// The number of decisions whose latency was observed.
+    pub fn decision_latency_count(&self) -> u64 {
+        self.latency_count.load(Ordering::Relaxed)
+    }
+
+    /// The latencies of the observed decisions, summed.
+    pub fn decision_latency_sum(&self) -> Duration {
+        Duration::from_micros(self.latency_sum_micros.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_decision(&self) {
+        self.decisions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_epoch_change(&self) {
+        self.epoch_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_suspect(&self) {
+        self.suspected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_restore(&self) {
+        // a restore always follows a suspicion, the gauge can't go below zero.
+        let _ = self
+            .suspected
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    pub(crate) fn record_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Observes the time a system took from its proposal to its decision.
+    pub(crate) fn record_decision_latency(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Every metric, in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let metrics: [(&str, &str, &str, u64); 6] = [
+            (
+                "consensus_event_queue_depth",
+                "gauge",
+                "Events waiting in the event queue.",
+                self.queue_depth() as u64,
+            ),
+            (
+                "consensus_decisions_total",
+                "counter",
+                "Values decided, across all the systems.",
+                self.decisions(),
+            ),
+            (
+                "consensus_epoch_changes_total",
+                "counter",
+                "Epochs started, across all the systems.",
+                self.epoch_changes(),
+            ),
+            (
+                "consensus_suspected_nodes",
+                "gauge",
+                "Processes currently suspected by the failure detector.",
+                self.suspected() as u64,
+            ),
+            (
+                "consensus_messages_sent_total",
+                "counter",
+                "Messages sent by the perfect link.",
+                self.messages_sent(),
+            ),
+            (
+                "consensus_messages_received_total",
+                "counter",
+                "Messages delivered by the perfect link.",
+                self.messages_received(),
+            ),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics.iter() {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            let _ = writeln!(text, "{} {}", name, value);
+        }
+
+        let name = "consensus_decision_latency_seconds";
+        let _ = writeln!(text, "# HELP {} Time from the proposal to the decision of a system.", name);
+        let _ = writeln!(text, "# TYPE {} histogram", name);
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            let count = bucket.load(Ordering::Relaxed);
+            let _ = writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let count = self.decision_latency_count();
+        let _ = writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(text, "{}_sum {}", name, self.decision_latency_sum().as_secs_f64());
+        let _ = writeln!(text, "{}_count {}", name, count);
+        text
+    }
+}
+
+/// Answers every HTTP request on `listener` with `metrics`, whatever the path, from a thread
+/// of its own which lives as long as the process.
+pub fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) -> io::Result<()> {
+    thread::Builder::new()
+        .name("metrics".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| answer_scrape(stream, &metrics));
+                if let Err(e) = result {
+                    error!("Unable to serve the metrics: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn answer_scrape(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    // the request itself doesn't matter, it only has to be read before answering it.
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request)?;
+    trace!(
+        "Metrics scraped with {}",
+        String::from_utf8_lossy(&request[..read]).lines().next().unwrap_or_default()
+    );
+
+    let body = metrics.to_prometheus();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_metric_is_exported() {
+        let metrics = Metrics::new();
+        metrics.record_decision();
+        metrics.record_decision();
+        metrics.record_suspect();
+        metrics.set_queue_depth(7);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE consensus_decisions_total counter\n"));
+        assert!(text.contains("\nconsensus_decisions_total 2\n"));
+        assert!(text.contains("\nconsensus_suspected_nodes 1\n"));
+        assert!(text.contains("\nconsensus_event_queue_depth 7\n"));
+        assert!(text.contains("\nconsensus_messages_sent_total 0\n"));
+    }
+
+    #[test]
+    fn a_latency_is_counted_in_every_bucket_it_fits_in() {
+        let metrics = Metrics::new();
+        metrics.record_decision_latency(Duration::from_millis(70));
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains("\nconsensus_decision_latency_seconds_bucket{le=\"0.05\"} 0\n"));
+        assert!(text.contains("\nconsensus_decision_latency_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("\nconsensus_decision_latency_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("\nconsensus_decision_latency_seconds_sum 0.07\n"));
+        assert!(text.contains("\nconsensus_decision_latency_seconds_count 1\n"));
+    }
+
+    #[test]
+    fn the_suspected_gauge_never_goes_below_zero() {
+        let metrics = Metrics::new();
+        metrics.record_suspect();
+        metrics.record_restore();
+        metrics.record_restore();
+        assert_eq!(metrics.suspected(), 0);
+    }
+}
@@ -1,10 +1,28 @@
+use crate::epfd::{DEFAULT_DELTA, DEFAULT_STARTUP_GRACE};
+use crate::event::{delivered_by, EventData};
 use crate::protos::message;
+use crate::quorum::Quorums;
+use crate::uc::DEFAULT_DECISION_DEADLINE;
 use message::ProcessId;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
 
 pub type NodeId = u16;
 
+/// Whether a process takes part in consensus or only learns its decisions.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// Votes, counts in the quorums and may be elected leader.
+    #[default]
+    Voter,
+    /// Gets the decisions, but never votes, broadcasts or leads.
+    Learner,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Eq)]
 pub struct Node {
     pub owner: String,
@@ -13,6 +31,15 @@ pub struct Node {
     pub port: u16,
     pub id: NodeId,
     pub rank: u16,
+    #[serde(default)]
+    pub role: NodeRole,
+    /// How many votes the node casts in the quorums, 1 unless it is more reliable than the others.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
 }
 
 impl Node {
@@ -24,21 +51,65 @@ impl Node {
             port,
             id,
             rank,
+            role: NodeRole::Voter,
+            weight: default_weight(),
         }
     }
+
+    pub fn with_role(mut self, role: NodeRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn is_learner(&self) -> bool {
+        self.role == NodeRole::Learner
+    }
 }
 
-impl From<Node> for SocketAddr {
-    fn from(node: Node) -> Self {
-        SocketAddr::from(&node)
+impl Node {
+    /// Whether both nodes listen on the same host and port.
+    pub fn same_address(&self, other: &Node) -> bool {
+        self.host == other.host && self.port == other.port
+    }
+
+    /// The address the node listens on, its host being looked up if it isn't an IP address.
+    pub fn resolve(&self) -> io::Result<SocketAddr> {
+        (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("The host {} of node {} has no address.", self.host, self.id),
+                )
+            })
     }
 }
 
-impl From<&Node> for SocketAddr {
-    fn from(node: &Node) -> Self {
-        let address = format!("{}:{}", node.host, node.port);
-        let address: SocketAddr = address.parse().expect("Unable to parse socket address");
-        address
+impl TryFrom<Node> for SocketAddr {
+    type Error = io::Error;
+
+    fn try_from(node: Node) -> io::Result<Self> {
+        SocketAddr::try_from(&node)
+    }
+}
+
+/// Only takes IP addresses as hosts, see `Node::resolve` for host names.
+impl TryFrom<&Node> for SocketAddr {
+    type Error = io::Error;
+
+    fn try_from(node: &Node) -> io::Result<Self> {
+        format!("{}:{}", node.host, node.port).parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The host {} of node {} isn't an IP address: {}", node.host, node.id, e),
+            )
+        })
     }
 }
 
@@ -55,6 +126,9 @@ impl From<&Node> for message::ProcessId {
         proc_id.set_host(node.host.clone());
         proc_id.set_index(node.id as i32);
         proc_id.set_port(node.port as i32);
+        proc_id.set_rank(node.rank as i32);
+        proc_id.set_learner(node.is_learner());
+        proc_id.set_weight(node.weight);
         proc_id
     }
 }
@@ -74,16 +148,30 @@ impl From<&Node> for message::EldTrust {
     }
 }
 
+/// The rank on the wire takes precedence. A process id without any, as the hub may send
+/// them, ranks the process by its index, which is how the configurations rank them, so that
+/// leader election over such processes picks the same leader as over the configured ones.
+/// Likewise, a process id without a weight, as the hub sends them, casts a single vote.
 impl From<&ProcessId> for Node {
     fn from(process_id: &ProcessId) -> Self {
+        let rank = match process_id.get_rank() {
+            0 => process_id.get_index(),
+            rank => rank,
+        };
         Node::new(
             process_id.get_owner().to_owned(),
             format!("{}-{}", process_id.get_owner(), process_id.get_index()),
             process_id.get_host().to_owned(),
             process_id.get_port() as u16,
             process_id.get_index() as u16,
-            process_id.get_rank() as u16,
+            rank as u16,
         )
+        .with_role(if process_id.get_learner() {
+            NodeRole::Learner
+        } else {
+            NodeRole::Voter
+        })
+        .with_weight(process_id.get_weight().max(1))
     }
 }
 
@@ -104,7 +192,7 @@ impl PartialEq<Node> for Node {
 
 impl PartialOrd<Node> for Node {
     fn partial_cmp(&self, other: &Node) -> Option<std::cmp::Ordering> {
-        self.id.partial_cmp(&other.id)
+        Some(self.cmp(other))
     }
 }
 
@@ -114,9 +202,275 @@ impl Ord for Node {
     }
 }
 
+impl NodeInfo {
+    /// The view of `current_node` of a system made of `nodes`, with the default heartbeat
+    /// timeouts and decision deadline, no compression and majority quorums.
+    pub fn new(current_node: Node, hub: Option<Node>, nodes: Vec<Node>) -> Self {
+        let nodes = without_hub(hub.as_ref(), &nodes);
+        let (by_id, by_addr) = index(&nodes);
+        NodeInfo {
+            current_node,
+            hub,
+            nodes,
+            by_id,
+            by_addr,
+            epfd_delta: chrono::Duration::milliseconds(DEFAULT_DELTA),
+            epfd_startup_grace: chrono::Duration::milliseconds(DEFAULT_STARTUP_GRACE),
+            compression_threshold: None,
+            quorums: Quorums::default(),
+            decision_deadline: chrono::Duration::milliseconds(DEFAULT_DECISION_DEADLINE),
+            report_fan_out: false,
+        }
+    }
+
+    /// The processes taking part in consensus, the ones heartbeated and counted in the
+    /// quorums. The hub is never one of them.
+    pub fn participants(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Whether `node` takes part in consensus. A straggler of a system made of other processes,
+    /// or the hub, is not.
+    pub fn is_member(&self, node: &Node) -> bool {
+        self.by_id.get(&node.id) == Some(node)
+    }
+
+    /// Whether the abstractions of the system may handle `event_data`: the messages of senders
+    /// outside of the system must not feed their state into it.
+    pub fn accepts(&self, event_data: &EventData) -> bool {
+        delivered_by(event_data).is_none_or(|from| self.is_member(from))
+    }
+
+    /// Whether `node` is a member that votes, the role being the one it has in the system
+    /// rather than the one a message claims.
+    pub fn is_voter(&self, node: &Node) -> bool {
+        self.by_id
+            .get(&node.id)
+            .is_some_and(|member| member == node && !member.is_learner())
+    }
+
+    /// The member or the hub listening on `host` and `port`.
+    pub fn node_at(&self, host: &str, port: u16) -> Option<&Node> {
+        self.by_addr
+            .get(&(host.to_owned(), port))
+            .and_then(|id| self.by_id.get(id))
+            .or_else(|| self.hub.as_ref().filter(|hub| hub.host == host && hub.port == port))
+    }
+
+    /// The members counted in the quorums.
+    pub fn voters(&self) -> impl Iterator<Item = &Node> {
+        self.participants().iter().filter(|node| !node.is_learner())
+    }
+
+    /// The weight of every voter, see `QuorumCounter::weighted`.
+    pub fn weights(&self) -> HashMap<NodeId, u32> {
+        self.voters().map(|node| (node.id, node.weight)).collect()
+    }
+
+    /// The view of the same process once the system is reconfigured to be made of `nodes`.
+    pub fn with_members(&self, nodes: &[Node]) -> Self {
+        let nodes = without_hub(self.hub.as_ref(), nodes);
+        let (by_id, by_addr) = index(&nodes);
+        NodeInfo {
+            nodes,
+            by_id,
+            by_addr,
+            ..self.clone()
+        }
+    }
+
+    /// Every process messages can be exchanged with: the consensus members and the hub.
+    pub fn known_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().chain(self.hub.iter())
+    }
+
+    /// Whether `node` is the hub, never the case without one.
+    pub fn is_hub(&self, node: &Node) -> bool {
+        is_hub(self.hub.as_ref(), node)
+    }
+}
+
+/// The votes cast by the voters among `nodes`, which the quorums are made of.
+pub fn total_votes<'a, I: IntoIterator<Item = &'a Node>>(nodes: I) -> usize {
+    nodes
+        .into_iter()
+        .filter(|node| !node.is_learner())
+        .map(|node| node.weight as usize)
+        .sum()
+}
+
+/// Whether `node` is `hub`, never the case without one. The hub is told apart by its address,
+/// a process merely sharing its id is another one.
+pub fn is_hub(hub: Option<&Node>, node: &Node) -> bool {
+    hub.is_some_and(|hub| node.same_address(hub))
+}
+
+/// `nodes` but the hub, see `is_hub`.
+pub fn without_hub(hub: Option<&Node>, nodes: &[Node]) -> Vec<Node> {
+    nodes.iter().filter(|node| !is_hub(hub, node)).cloned().collect()
+}
+
+/// `nodes` by their id, and the ids by the address of the nodes.
+fn index(nodes: &[Node]) -> (HashMap<NodeId, Node>, HashMap<(String, u16), NodeId>) {
+    let by_id = nodes.iter().map(|node| (node.id, node.clone())).collect();
+    let by_addr = nodes
+        .iter()
+        .map(|node| ((node.host.clone(), node.port), node.id))
+        .collect();
+    (by_id, by_addr)
+}
+
+/// The process leader election picks among `candidates`: the one with the highest rank,
+/// ties on rank being broken by id so that every process picks the same leader.
+/// Learners are never picked.
+pub fn highest_ranked<'a, I: IntoIterator<Item = &'a Node>>(candidates: I) -> Option<&'a Node> {
+    candidates
+        .into_iter()
+        .filter(|node| !node.is_learner())
+        .max_by(|&x, &y| x.rank.cmp(&y.rank).then(x.id.cmp(&y.id)))
+}
+
+/// Leader election picks the process with the highest rank, which is only deterministic
+/// if no two processes share a rank. Returns the first two processes that do.
+pub fn find_rank_collision(nodes: &[Node]) -> Option<(&Node, &Node)> {
+    nodes.iter().enumerate().find_map(|(index, node)| {
+        nodes[index + 1..]
+            .iter()
+            .find(|other| other.rank == node.rank)
+            .map(|other| (node, other))
+    })
+}
+
+/// `nodes` are the processes taking part in consensus, the hub is only an external
+/// coordinator and never one of them. Without a hub, the processes propose on their own.
+/// Built by `new`, which indexes the nodes, possibly along with the other fields, and leaves
+/// the hub out of them should it be listed.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct NodeInfo {
     pub current_node: Node,
-    pub hub: Node,
+    pub hub: Option<Node>,
     pub nodes: Vec<Node>,
+    /// `nodes` by their id, `nodes` being the source of truth.
+    pub by_id: HashMap<NodeId, Node>,
+    /// The ids of `nodes` by the host and port they listen on.
+    pub by_addr: HashMap<(String, u16), NodeId>,
+    /// The initial heartbeat timeout of the failure detector.
+    pub epfd_delta: chrono::Duration,
+    /// How long the failure detector waits before its first heartbeat round, giving the
+    /// other processes time to start listening.
+    pub epfd_startup_grace: chrono::Duration,
+    /// The messages to the other processes taking at least this many bytes are compressed
+    /// by the link, none are if `None`. The hub always gets them as they are.
+    pub compression_threshold: Option<usize>,
+    /// How many of the votes make the read and the write quorums of epoch consensus.
+    pub quorums: Quorums,
+    /// How long a proposal may go without any epoch being started or decided before it is
+    /// reported as timed out.
+    pub decision_deadline: chrono::Duration,
+    /// Whether best-effort broadcast reports the peers each of its messages reached, see
+    /// `InternalMessage::BebBroadcastComplete`. It only sends them otherwise.
+    pub report_fan_out: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::InternalMessage;
+    use crate::testing::{hub, node};
+
+    #[test]
+    fn the_hub_is_never_a_participant() {
+        let mut same_id = hub();
+        same_id.port = 9000;
+        let nodes = vec![node(1), hub(), same_id.clone()];
+        let node_info = NodeInfo::new(node(1), Some(hub()), nodes);
+        assert_eq!(node_info.participants(), &[node(1), same_id.clone()][..]);
+        assert!(node_info.is_hub(&hub()));
+        assert!(!node_info.is_hub(&same_id));
+
+        let reconfigured = node_info.with_members(&[node(2), hub()]);
+        assert_eq!(reconfigured.participants(), &[node(2)][..]);
+    }
+
+    #[test]
+    fn the_indexes_follow_the_nodes() {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let node_info = NodeInfo::new(node(1), Some(hub()), nodes);
+        let reconfigured = node_info.with_members(&[node(2), node(4)]);
+
+        for node_info in &[&node_info, &reconfigured] {
+            assert_eq!(node_info.by_id.len(), node_info.nodes.len());
+            assert_eq!(node_info.by_addr.len(), node_info.nodes.len());
+            for member in &node_info.nodes {
+                assert_eq!(node_info.by_id.get(&member.id), Some(member));
+                assert_eq!(node_info.node_at(&member.host, member.port), Some(member));
+            }
+            assert_eq!(node_info.node_at(&hub().host, hub().port), Some(&hub()));
+        }
+        assert!(!reconfigured.is_member(&node(1)));
+        assert!(reconfigured.is_member(&node(4)));
+    }
+
+    #[test]
+    fn only_the_members_deliver_into_the_system() {
+        let node_info = NodeInfo::new(node(1), Some(hub()), vec![node(1), node(2)]);
+        let delivered = |from: Node| {
+            let msg = message::Message::new();
+            EventData::Internal("sys-1".to_owned(), InternalMessage::PlDeliver(from, msg))
+        };
+
+        assert!(node_info.accepts(&delivered(node(2))));
+        assert!(!node_info.accepts(&delivered(node(3))));
+        assert!(!node_info.accepts(&delivered(hub())));
+        let timeout = EventData::Internal("sys-1".to_owned(), InternalMessage::EpfdTimeout);
+        assert!(node_info.accepts(&timeout));
+    }
+
+    #[test]
+    fn a_process_id_without_a_rank_is_ranked_by_its_index() {
+        let nodes = vec![node(1), node(2), node(3)];
+        let from_the_wire: Vec<Node> = nodes
+            .iter()
+            .map(|node| {
+                let mut process_id = ProcessId::from(node);
+                process_id.clear_rank();
+                Node::from(&process_id)
+            })
+            .collect();
+        assert_eq!(from_the_wire.iter().map(|node| node.rank).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(highest_ranked(&from_the_wire), highest_ranked(&nodes));
+
+        // an explicit rank is kept.
+        let mut process_id = ProcessId::from(&node(1));
+        process_id.set_rank(7);
+        assert_eq!(Node::from(&process_id).rank, 7);
+    }
+
+    #[test]
+    fn nodes_sharing_a_rank_collide() {
+        let mut nodes: Vec<Node> = (1..=3).map(node).collect();
+        assert_eq!(find_rank_collision(&nodes), None);
+
+        nodes[2].rank = 1;
+        let (node, other) = find_rank_collision(&nodes).unwrap();
+        assert_eq!((node.id, other.id), (1, 3));
+    }
+
+    #[test]
+    fn an_invalid_host_is_an_error() {
+        let mut invalid = node(1);
+        invalid.host = "not a host".to_owned();
+        assert!(SocketAddr::try_from(&invalid).is_err());
+        assert!(invalid.resolve().is_err());
+    }
+
+    #[test]
+    fn a_host_name_is_resolved() {
+        let mut named = node(1);
+        named.host = "localhost".to_owned();
+        assert!(SocketAddr::try_from(&named).is_err());
+        let address = named.resolve().unwrap();
+        assert!(address.ip().is_loopback());
+        assert_eq!(address.port(), named.port);
+    }
 }
@@ -1,83 +1,502 @@
 use crate::event::*;
-use crate::node::{Node, NodeInfo};
+use crate::metrics::Metrics;
+use crate::node::{Node, NodeId, NodeInfo};
 use crate::protos::message;
-use log::{trace, error, info};
-use protobuf::Message;
-use std::error::Error;
-use std::io::Write;
-use std::net::{SocketAddr, TcpStream};
-use std::sync::Arc;
+use crate::transport::{encode, encode_versioned, Transport};
+use std::convert::TryFrom;
+use log::{debug, trace, error, info, warn};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use timer::{Guard, Timer};
 use uuid::Uuid;
 
+/// How many of the most recently delivered frames are remembered in order to drop their duplicates.
+pub const DEFAULT_DEDUP_CAPACITY: usize = 10_000;
+/// How long a frame goes without being acknowledged before it is sent again.
+pub const DEFAULT_RETRANSMIT_INTERVAL: Duration = Duration::from_millis(500);
+/// The longest interval the frames are sent again at, a longer one is cut down to it.
+pub const MAX_RETRANSMIT_INTERVAL: Duration = Duration::from_secs(60);
+/// How many frames sent to a process wait for its acknowledgement, the oldest are given up on.
+const MAX_UNACKED: usize = 1024;
+/// How many frames wait to be sent to a process before the oldest of them are dropped.
+const SEND_QUEUE_CAPACITY: usize = 1024;
+/// How many of the last closed systems are remembered, so that their stragglers are not tracked.
+const CLOSED_SYSTEMS_KEPT: usize = 1024;
+
+/// The most recently seen keys, e.g. `(sender, messageUuid)` pairs, the oldest key being
+/// forgotten first once `capacity` of them are remembered.
+#[derive(Debug)]
+struct SeenMessages<K> {
+    capacity: usize,
+    order: VecDeque<K>,
+    seen: HashSet<K>,
+}
+
+impl<K: Eq + Hash + Clone> SeenMessages<K> {
+    fn new(capacity: usize) -> Self {
+        SeenMessages {
+            capacity,
+            order: VecDeque::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.seen.contains(key)
+    }
+
+    /// Remembers the key, returns `false` if it was already remembered.
+    fn insert(&mut self, key: K) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if self.seen.contains(&key) {
+            return false;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.seen.insert(key);
+        true
+    }
+}
+
+/// Hands the messages over to the transport from threads of their own, one per receiver, so
+/// that neither the event queue nor the messages to the other processes wait on a slow one.
+/// The frames a sender could not send are reported to the link as `PlSendFailed`. The threads
+/// exit once the link is dropped.
+struct Senders {
+    transport: Arc<dyn Transport>,
+    event_queue: Weak<EventQueue>,
+    metrics: Arc<Metrics>,
+    outboxes: HashMap<(String, u16), Arc<Outbox>>,
+}
+
+impl Senders {
+    fn new(transport: Arc<dyn Transport>, event_queue: &Arc<EventQueue>) -> Self {
+        Senders {
+            transport,
+            event_queue: Arc::downgrade(event_queue),
+            metrics: event_queue.metrics(),
+            outboxes: HashMap::new(),
+        }
+    }
+
+    fn send(&mut self, dest: &Node, bytes: Vec<u8>) -> io::Result<()> {
+        let outbox = match self.outboxes.entry((dest.host.clone(), dest.port)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let outbox = spawn_sender(
+                    self.transport.clone(),
+                    self.event_queue.clone(),
+                    self.metrics.clone(),
+                    dest,
+                )?;
+                entry.insert(outbox)
+            }
+        };
+        if !outbox.push(bytes) {
+            warn!("{} does not keep up, dropping the oldest frame queued for it", dest);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Senders {
+    fn drop(&mut self) {
+        for outbox in self.outboxes.values() {
+            outbox.close();
+        }
+    }
+}
+
+/// The frames waiting to be sent to a process, at most `SEND_QUEUE_CAPACITY` of them.
+#[derive(Default)]
+struct Outbox {
+    state: Mutex<OutboxState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    frames: VecDeque<Vec<u8>>,
+    closed: bool,
+}
+
+impl Outbox {
+    /// Queues the frame, returns `false` if the oldest frame was dropped to make room for it.
+    fn push(&self, bytes: Vec<u8>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let dropped = state.frames.len() >= SEND_QUEUE_CAPACITY && state.frames.pop_front().is_some();
+        state.frames.push_back(bytes);
+        self.ready.notify_one();
+        !dropped
+    }
+
+    /// Waits for the next frame, `None` once the outbox is closed.
+    fn pop(&self) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(bytes) = state.frames.pop_front() {
+                return Some(bytes);
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+fn spawn_sender(
+    transport: Arc<dyn Transport>,
+    event_queue: Weak<EventQueue>,
+    metrics: Arc<Metrics>,
+    dest: &Node,
+) -> io::Result<Arc<Outbox>> {
+    let outbox = Arc::new(Outbox::default());
+    let frames = outbox.clone();
+    let dest = dest.clone();
+    thread::Builder::new()
+        .name(format!("pl-sender-{}", dest.id))
+        .spawn(move || {
+            while let Some(bytes) = frames.pop() {
+                match transport.send(&dest, &bytes) {
+                    Ok(()) => metrics.record_sent(),
+                    Err(e) => {
+                        trace!("Unable to send a message to {}: {}", dest, e);
+                        if let Some(event_queue) = event_queue.upgrade() {
+                            let failed = InternalMessage::PlSendFailed(dest.clone());
+                            event_queue.push(EventData::Internal(String::new(), failed));
+                        }
+                    }
+                }
+            }
+        })?;
+    Ok(outbox)
+}
+
+/// A frame sent to `dest` that was not acknowledged yet.
+struct Unacked {
+    dest: Node,
+    frame_uuid: String,
+    bytes: Vec<u8>,
+    sent_at: Instant,
+    system_id: String,
+    /// The uuid of the message in the frame, rather than of the frame.
+    message_uuid: String,
+}
+
 pub struct PerfectLink {
     event_queue: Arc<EventQueue>,
     node_info: Arc<NodeInfo>,
+    senders: Senders,
+    seen: SeenMessages<(NodeId, String)>,
+    /// The `(abstractionId, messageUuid)` of the messages delivered, by system, until the
+    /// system decides.
+    delivered: HashMap<String, SeenMessages<(String, String)>>,
+    /// The last systems which decided or were closed, the messages still delivered to them
+    /// are dropped by the application and need no `delivered` entry.
+    closed: SeenMessages<String>,
+    /// The frames sent to every process, oldest first, until it acknowledges them. At most
+    /// `MAX_UNACKED` are kept per process, the oldest being given up on first.
+    unacked: HashMap<NodeId, VecDeque<Unacked>>,
+    /// The `(system_id, process)` pairs suspected, their frames are not sent again meanwhile.
+    suspected: HashSet<(String, NodeId)>,
+    /// The processes a frame could not be sent to, since they last sent one.
+    unreachable: HashSet<NodeId>,
+    retransmit_interval: Duration,
+    retransmit_guard: Option<Guard>,
+    timer: Mutex<Timer>,
 }
 
 impl PerfectLink {
-    pub fn new(event_queue: Arc<EventQueue>, node_info: Arc<NodeInfo>) -> Self {
+    pub fn new(
+        event_queue: Arc<EventQueue>,
+        node_info: Arc<NodeInfo>,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
+        let senders = Senders::new(transport, &event_queue);
         PerfectLink {
             event_queue,
             node_info,
+            senders,
+            seen: SeenMessages::new(DEFAULT_DEDUP_CAPACITY),
+            delivered: HashMap::new(),
+            closed: SeenMessages::new(CLOSED_SYSTEMS_KEPT),
+            unacked: HashMap::new(),
+            suspected: HashSet::new(),
+            unreachable: HashSet::new(),
+            retransmit_interval: DEFAULT_RETRANSMIT_INTERVAL,
+            retransmit_guard: None,
+            timer: Mutex::new(Timer::new()),
         }
     }
 
-    fn send(
-        &self,
-        _: &Node,
-        dest: &Node,
-        message: &message::Message,
-    ) -> Result<(), Box<dyn Error>> {
-        let address_to: SocketAddr = dest.into();
-        let mut stream = TcpStream::connect(address_to)?;
-       
-        let bytes = message.write_to_bytes().unwrap();
-        let length = bytes.len() as i32;
-        let mut msg_as_bytes = length.to_be_bytes().to_vec();
-        msg_as_bytes.extend(bytes);
-        
-        let _ = stream
-            .write(&msg_as_bytes[..])
-            .expect("The message should be sent successsfully.");
+    /// Remembers the `capacity` most recently delivered frames, and messages of every system,
+    /// rather than `DEFAULT_DEDUP_CAPACITY`. A duplicate arriving after its original was
+    /// forgotten is delivered again.
+    pub fn with_dedup_capacity(mut self, capacity: usize) -> Self {
+        self.seen = SeenMessages::new(capacity);
+        self
+    }
 
-        Ok(())
+    /// Sends the frames that go unacknowledged every `interval` rather than every
+    /// `DEFAULT_RETRANSMIT_INTERVAL`, at most every `MAX_RETRANSMIT_INTERVAL` though.
+    pub fn with_retransmit_interval(mut self, interval: Duration) -> Self {
+        self.retransmit_interval = interval.min(MAX_RETRANSMIT_INTERVAL);
+        self
+    }
+
+    /// Only queues the message, it is sent later on by the sender of `dest`. The frames with a
+    /// uuid are sent again until `dest` acknowledges them, but the ones to the hub, which never does.
+    fn send(&mut self, _: &Node, dest: &Node, message: &message::Message) -> io::Result<()> {
+        // the hub only understands the frames without a version.
+        if self.node_info.is_hub(dest) {
+            return self.senders.send(dest, encode(message)?);
+        }
+        let bytes = encode_versioned(message, self.node_info.compression_threshold)?;
+        if !message.get_messageUuid().is_empty() {
+            self.start_retransmitting();
+            let unacked = Unacked {
+                dest: dest.clone(),
+                frame_uuid: message.get_messageUuid().to_owned(),
+                bytes: bytes.clone(),
+                sent_at: Instant::now(),
+                system_id: message.get_systemId().to_owned(),
+                message_uuid: message
+                    .get_networkMessage()
+                    .get_message()
+                    .get_messageUuid()
+                    .to_owned(),
+            };
+            let unacked_to_dest = self.unacked.entry(dest.id).or_default();
+            if unacked_to_dest.len() >= MAX_UNACKED {
+                if let Some(oldest) = unacked_to_dest.pop_front() {
+                    debug!(
+                        "Too many frames to {} go unacknowledged, giving up on {}",
+                        dest, oldest.frame_uuid
+                    );
+                }
+            }
+            unacked_to_dest.push_back(unacked);
+        }
+        self.senders.send(dest, bytes)
+    }
+
+    fn start_retransmitting(&mut self) {
+        if self.retransmit_guard.is_some() {
+            return;
+        }
+        // the queue owns the link, the timer must not keep it alive.
+        let event_queue = Arc::downgrade(&self.event_queue);
+        let interval = chrono::Duration::from_std(self.retransmit_interval)
+            .expect("The retransmit interval is at most MAX_RETRANSMIT_INTERVAL.");
+        self.retransmit_guard = Some(self.timer.lock().unwrap().schedule_repeating(
+            interval,
+            move || {
+                if let Some(event_queue) = event_queue.upgrade() {
+                    let event_data = EventData::Internal(String::new(), InternalMessage::PlRetransmit);
+                    event_queue.push(event_data);
+                }
+            },
+        ));
+    }
+
+    /// Sends again the frames that went unacknowledged for a whole interval, but the ones of
+    /// the systems suspecting their destination.
+    fn retransmit(&mut self) {
+        let now = Instant::now();
+        for unacked in self.unacked.values_mut().flatten() {
+            let suspected = (unacked.system_id.clone(), unacked.dest.id);
+            if self.suspected.contains(&suspected)
+                || now.duration_since(unacked.sent_at) < self.retransmit_interval
+            {
+                continue;
+            }
+            trace!("Retransmitting {} to {}", unacked.frame_uuid, unacked.dest);
+            unacked.sent_at = now;
+            if let Err(e) = self.senders.send(&unacked.dest, unacked.bytes.clone()) {
+                trace!("Unable to send a message to {}: {}", unacked.dest, e);
+            }
+        }
+    }
+
+    /// A process suspected in a system may never acknowledge the frames of that system, they
+    /// are no longer sent again until it is restored.
+    fn on_suspect(&mut self, system_id: &str, dest: &Node) {
+        self.suspected.insert((system_id.to_owned(), dest.id));
+    }
+
+    /// The frames of the system to the restored process are sent again right away.
+    fn on_restore(&mut self, system_id: &str, dest: &Node) {
+        if !self.suspected.remove(&(system_id.to_owned(), dest.id)) {
+            return;
+        }
+        let now = Instant::now();
+        let unacked = self.unacked.get_mut(&dest.id).into_iter().flatten();
+        for unacked in unacked.filter(|unacked| unacked.system_id == system_id) {
+            unacked.sent_at = now;
+            if let Err(e) = self.senders.send(&unacked.dest, unacked.bytes.clone()) {
+                trace!("Unable to send a message to {}: {}", unacked.dest, e);
+            }
+        }
+    }
+
+    /// A closed system is done with, its frames are no longer sent again.
+    fn on_system_closed(&mut self, system_id: &str) {
+        self.forget_delivered(system_id);
+        for unacked in self.unacked.values_mut() {
+            unacked.retain(|unacked| unacked.system_id != system_id);
+        }
+        self.unacked.retain(|_, unacked| !unacked.is_empty());
+        self.suspected.retain(|(suspected_in, _)| suspected_in != system_id);
+    }
+
+    /// Warns once that `dest` cannot be reached, until a frame arrives from it again. The frames
+    /// with a uuid are sent again meanwhile, the others are lost.
+    fn on_send_failed(&mut self, dest: &Node) {
+        if self.unreachable.insert(dest.id) {
+            warn!("Unable to send the messages to {}", dest);
+        }
+    }
+
+    /// Tells `to` that the frame `msg` arrived, the acknowledgement has no uuid of its own so
+    /// that it is neither acknowledged nor sent again in turn.
+    fn acknowledge(&mut self, to: &Node, msg: &message::Message) {
+        let mut ack = message::Message::new();
+        ack.set_field_type(message::Message_Type::PL_ACK);
+        ack.set_messageUuid(msg.get_messageUuid().to_owned());
+        ack.set_systemId(msg.get_systemId().to_owned());
+        let from = self.node_info.current_node.clone();
+        let frame = frame(&from, &ack, String::new());
+        if let Err(e) = self.send(&from, to, &frame) {
+            trace!("Unable to acknowledge {} to {}: {}", msg.get_messageUuid(), to, e);
+        }
+    }
+
+    /// The messages of a system which is done with are no longer told apart.
+    fn forget_delivered(&mut self, system_id: &str) {
+        self.delivered.remove(system_id);
+        self.closed.insert(system_id.to_owned());
+    }
+
+    /// Remembers the message delivered in the system `system_id`, returns `false` if it was
+    /// already delivered. Re-processing an ACCEPT or a STATE would count it twice.
+    fn first_delivery(&mut self, system_id: &str, msg: &message::Message) -> bool {
+        let uuid = msg.get_messageUuid();
+        if uuid.is_empty() || self.closed.contains(&system_id.to_owned()) {
+            return true;
+        }
+        let capacity = self.seen.capacity;
+        self.delivered
+            .entry(system_id.to_owned())
+            .or_insert_with(|| SeenMessages::new(capacity))
+            .insert((msg.get_abstractionId().to_owned(), uuid.to_owned()))
     }
 
-    fn deliver(&self, msg: &message::Message) {
+    fn deliver(&mut self, msg: &message::Message) {
         let network_message = msg.get_networkMessage();
-        let sender: Option<&Node> = self.node_info.nodes.iter().find(|&node| {
-            node.port as i32 == network_message.get_senderListeningPort()
-                && node.host == network_message.get_senderHost()
-        });
-        if !sender.is_some() {
+        let port = network_message.get_senderListeningPort();
+        let sender = u16::try_from(port)
+            .ok()
+            .and_then(|port| self.node_info.node_at(network_message.get_senderHost(), port));
+        if let Some(sender) = sender {
+            let sender = sender.clone();
+            if self.unreachable.remove(&sender.id) {
+                info!("{} can be reached again", sender);
+            }
+            let inner = network_message.get_message();
+            if inner.get_field_type() == message::Message_Type::PL_ACK {
+                trace!("{} acknowledged {}", sender, inner.get_messageUuid());
+                // the upper layers only know about the message, not about the frame.
+                let unacked_to_sender = self.unacked.get_mut(&sender.id);
+                let acknowledged = unacked_to_sender.and_then(|unacked| {
+                    let index = unacked
+                        .iter()
+                        .position(|unacked| unacked.frame_uuid == inner.get_messageUuid())?;
+                    unacked.remove(index)
+                });
+                if let Some(unacked) = acknowledged {
+                    if !unacked.message_uuid.is_empty() {
+                        let acknowledged =
+                            InternalMessage::PlAcknowledged(sender, unacked.message_uuid);
+                        self.event_queue
+                            .push(EventData::Internal(unacked.system_id, acknowledged));
+                    }
+                }
+                return;
+            }
+            // the duplicates are acknowledged as well, the acknowledgement of the original may be lost.
+            let uuid = msg.get_messageUuid();
+            if !uuid.is_empty() && !self.node_info.is_hub(&sender) {
+                self.acknowledge(&sender, msg);
+            }
+            // every frame gets a uuid of its own, a frame seen twice was duplicated on the way.
+            if !uuid.is_empty() && !self.seen.insert((sender.id, uuid.to_owned())) {
+                trace!("Dropping the duplicate {} from {}", uuid, sender);
+                return;
+            }
+            // a message replayed in a frame of its own, e.g. re-wrapped on the way, is only
+            // told apart by the message itself.
+            if !self.first_delivery(msg.get_systemId(), inner) {
+                trace!("Dropping the replayed {} from {}", inner.get_messageUuid(), sender);
+                return;
+            }
+            self.event_queue.metrics().record_received();
+            let mut actual_message = network_message.get_message().clone();
+            actual_message.set_systemId(msg.get_systemId().to_owned());
+
+            // the link only unwraps the message, it is up to the upper layers to interpret it.
+            let internal_message = InternalMessage::PlDeliver(sender, actual_message);
+            let event_data = EventData::Internal(msg.get_systemId().to_owned(), internal_message);
+            self.event_queue.push(event_data);
+        } else {
             error!(
                 "PerfectLink received message from unknown node {:?}. Ignoring message.",
                 network_message
             );
-        } else {
-            let sender = sender.unwrap().clone();
-            let mut actual_message = network_message.get_message().clone();
-            actual_message.set_systemId(msg.get_systemId().to_owned());
-
-            if let message::Message {
-                field_type: message::Message_Type::APP_PROPOSE,
-                ..
-            } = actual_message
-            {
-                let internal_message = InternalMessage::AppPropose(sender, actual_message);
-                let event_data =
-                    EventData::Internal(msg.get_systemId().to_owned(), internal_message);
-                self.event_queue.push(event_data);
-            } else {
-                let internal_message = InternalMessage::PlDeliver(sender, actual_message);
-                let event_data =
-                    EventData::Internal(msg.get_systemId().to_owned(), internal_message);
-                self.event_queue.push(event_data);
-            }
         }
     }
 }
 
+/// `data` sent by `from` as a frame of the link.
+fn frame(from: &Node, data: &message::Message, uuid: String) -> message::Message {
+    let mut network_message = message::NetworkMessage::new();
+    if let message::Message{field_type: message::Message_Type::BEB_BROADCAST, ..} = data {
+        let beb_message = data.get_bebBroadcast();
+        let actual_message = beb_message.get_message();
+        network_message.set_message(actual_message.clone());
+    } else {
+        network_message.set_message(data.clone());
+    }
+
+    network_message.set_senderHost(from.host.clone());
+    network_message.set_senderListeningPort(from.port as i32);
+
+    let mut external_msg = message::Message::new();
+    external_msg.set_field_type(message::Message_Type::NETWORK_MESSAGE);
+    external_msg.set_networkMessage(network_message);
+
+    external_msg.set_messageUuid(uuid);
+    external_msg.set_systemId(data.get_systemId().to_owned());
+    external_msg.set_abstractionId(data.get_abstractionId().to_owned());
+    external_msg
+}
+
 impl EventHandler for PerfectLink {
     fn should_handle_event(&self, _: &EventData) -> bool {
         true
@@ -94,36 +513,403 @@ impl EventHandler for PerfectLink {
                 } = msg
                 {
                     self.deliver(msg);
-                } else {
+                } else if msg.get_field_type() != message::Message_Type::CLIENT_PROPOSE {
+                    // the proposals of clients are taken care of by the application.
                     error!("PerectLink received unexpected message type");
                 }
             },
-            EventData::Internal(_, msg) => {
-                if let InternalMessage::PlSend(from, dest, data) = msg {
-                    let mut network_message = message::NetworkMessage::new();
-                    if let message::Message{field_type: message::Message_Type::BEB_BROADCAST, ..} = data {
-                        let beb_message = data.get_bebBroadcast();
-                        let actual_message = beb_message.get_message();
-                        network_message.set_message(actual_message.clone());
-                    } else {
-                        network_message.set_message(data.clone());
+            EventData::Internal(system_id, msg) => match msg {
+                InternalMessage::PlSend(from, dest, data) => {
+                    let external_msg = frame(from, data, Uuid::new_v4().to_string());
+                    trace!("Sending message {:?}", external_msg.clone());
+                    if let Err(e) = self.send(from, dest, &external_msg) {
+                        trace!("Unable to send a message to {}: {}", dest, e);
                     }
+                }
+                InternalMessage::PlRetransmit => self.retransmit(),
+                InternalMessage::PlSendFailed(dest) => self.on_send_failed(dest),
+                InternalMessage::EpfdSuspect(node) => self.on_suspect(system_id, node),
+                InternalMessage::EpfdRestore(node) => self.on_restore(system_id, node),
+                InternalMessage::SystemClosed => self.on_system_closed(system_id),
+                // the system is done with, whatever it is still sent is dropped by the application.
+                InternalMessage::UcDecide(..) => self.forget_delivered(system_id),
+                _ => (),
+            },
+        };
+    }
+}
 
-                    network_message.set_senderHost(from.host.clone());
-                    network_message.set_senderListeningPort(from.port as i32);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::message::Value;
+    use crate::testing::{hub, node, node_info, wait_until, Recorder};
+    use crate::transport::{decode, InMemoryTransport, TcpTransport};
+    use std::sync::atomic::{AtomicBool, Ordering};
 
-                    let mut external_msg = message::Message::new();
-                    external_msg.set_field_type(message::Message_Type::NETWORK_MESSAGE);
-                    external_msg.set_networkMessage(network_message);
+    const SYSTEM_ID: &str = "sys-1";
 
-                    let uuid = Uuid::new_v4();
-                    external_msg.set_messageUuid(uuid.to_string());
-                    external_msg.set_systemId(data.get_systemId().to_owned());
-                    external_msg.set_abstractionId(data.get_abstractionId().to_owned());
-                    trace!("Sending message {:?}", external_msg.clone());
-                    let _ = self.send(from, dest, &external_msg);
-                }
+    /// The link of node 1, in a system of the nodes 1 to 3 coordinated by a hub.
+    fn link() -> (PerfectLink, Recorder) {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let node_info = Arc::new(node_info(1, &nodes));
+        let pl = PerfectLink::new(event_queue, node_info, Arc::new(TcpTransport::new()));
+        (pl, recorder)
+    }
+
+    /// `inner` as received from `from`, in a frame of its own.
+    fn received(from: &Node, inner: &message::Message) -> EventData {
+        let mut network_message = message::NetworkMessage::new();
+        network_message.set_message(inner.clone());
+        network_message.set_senderHost(from.host.clone());
+        network_message.set_senderListeningPort(from.port as i32);
+
+        let mut msg = message::Message::new();
+        msg.set_field_type(message::Message_Type::NETWORK_MESSAGE);
+        msg.set_networkMessage(network_message);
+        msg.set_messageUuid(Uuid::new_v4().to_string());
+        msg.set_systemId(SYSTEM_ID.to_owned());
+        msg.set_abstractionId(inner.get_abstractionId().to_owned());
+        EventData::External(SYSTEM_ID.to_owned(), msg)
+    }
+
+    fn delivered(recorder: &Recorder) -> Vec<(Node, message::Message)> {
+        recorder
+            .events()
+            .into_iter()
+            .filter_map(|event_data| match event_data {
+                EventData::Internal(_, InternalMessage::PlDeliver(from, msg)) => Some((from, msg)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_proposal_of_the_hub_is_delivered_as_is() {
+        let (mut pl, recorder) = link();
+        let mut propose = message::Message::new();
+        propose.set_field_type(message::Message_Type::APP_PROPOSE);
+        propose.set_messageUuid(Uuid::new_v4().to_string());
+        propose.set_abstractionId("app".to_owned());
+
+        pl.handle(&received(&hub(), &propose));
+
+        wait_until(|| !delivered(&recorder).is_empty());
+        let delivered = delivered(&recorder);
+        assert_eq!(delivered.len(), 1);
+        let (from, msg) = &delivered[0];
+        assert_eq!(from, &hub());
+        assert_eq!(msg.get_field_type(), message::Message_Type::APP_PROPOSE);
+        assert_eq!(msg.get_systemId(), SYSTEM_ID);
+    }
+
+    fn accept(abstraction_id: &str) -> message::Message {
+        let mut accept = message::Message::new();
+        accept.set_field_type(message::Message_Type::EP_ACCEPT_);
+        accept.set_messageUuid(Uuid::new_v4().to_string());
+        accept.set_abstractionId(abstraction_id.to_owned());
+        accept
+    }
+
+    #[test]
+    fn a_duplicated_frame_is_delivered_once() {
+        let (mut pl, recorder) = link();
+        let duplicated = received(&node(2), &accept("ep1"));
+
+        pl.handle(&duplicated);
+        pl.handle(&duplicated);
+        // events are handled in order, once the next frame is delivered the duplicate would be too.
+        pl.handle(&received(&node(2), &accept("ep2")));
+
+        wait_until(|| delivered(&recorder).len() == 2);
+        let abstraction_ids: Vec<_> = delivered(&recorder)
+            .iter()
+            .map(|(_, msg)| msg.get_abstractionId().to_owned())
+            .collect();
+        assert_eq!(abstraction_ids, vec!["ep1", "ep2"]);
+    }
+
+    #[test]
+    fn a_replayed_accept_is_delivered_once_per_system() {
+        let (mut pl, recorder) = link();
+        let replayed = accept("ep1");
+
+        // every replay comes in a frame of its own, with a uuid of its own.
+        pl.handle(&received(&node(2), &replayed));
+        pl.handle(&received(&node(2), &replayed));
+        pl.handle(&received(&node(2), &accept("ep1")));
+        wait_until(|| delivered(&recorder).len() == 2);
+
+        // once the system decides, its messages are forgotten.
+        pl.handle(&EventData::Internal(
+            SYSTEM_ID.to_owned(),
+            InternalMessage::UcDecide(Value::new()),
+        ));
+        pl.handle(&received(&node(2), &replayed));
+        wait_until(|| delivered(&recorder).len() == 3);
+        let uuids: Vec<_> = delivered(&recorder)
+            .iter()
+            .map(|(_, msg)| msg.get_messageUuid().to_owned())
+            .collect();
+        assert_eq!(uuids[0], replayed.get_messageUuid());
+        assert_ne!(uuids[1], replayed.get_messageUuid());
+        assert_eq!(uuids[2], replayed.get_messageUuid());
+        assert!(pl.delivered.is_empty());
+    }
+
+    #[test]
+    fn the_messages_of_a_closed_system_are_no_longer_remembered() {
+        let (mut pl, recorder) = link();
+        pl.handle(&received(&node(2), &accept("ep1")));
+        wait_until(|| delivered(&recorder).len() == 1);
+        assert!(pl.delivered.contains_key(SYSTEM_ID));
+
+        pl.handle(&EventData::Internal(SYSTEM_ID.to_owned(), InternalMessage::SystemClosed));
+        assert!(pl.delivered.is_empty());
+        pl.handle(&received(&node(2), &accept("ep2")));
+        wait_until(|| delivered(&recorder).len() == 2);
+        assert!(pl.delivered.is_empty());
+    }
+
+    #[test]
+    fn the_oldest_frames_are_forgotten_first() {
+        let mut seen = SeenMessages::new(2);
+        assert!(seen.insert((2, "a")));
+        assert!(seen.insert((3, "a")));
+        assert!(!seen.insert((2, "a")));
+
+        assert!(seen.insert((2, "b")));
+        assert!(seen.insert((2, "a")));
+        assert!(!seen.insert((2, "b")));
+    }
+
+    /// Takes `delay` to send a message to the process `slow`, records the receivers of them all.
+    struct SlowTransport {
+        slow: NodeId,
+        delay: Duration,
+        receivers: Mutex<Vec<NodeId>>,
+    }
+
+    impl Transport for SlowTransport {
+        fn send(&self, to: &Node, _: &[u8]) -> io::Result<()> {
+            if to.id == self.slow {
+                thread::sleep(self.delay);
             }
+            self.receivers.lock().unwrap().push(to.id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_slow_process_only_holds_back_the_messages_sent_to_it() {
+        let delay = Duration::from_secs(1);
+        let transport = Arc::new(SlowTransport {
+            slow: 3,
+            delay,
+            receivers: Mutex::new(Vec::new()),
+        });
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut pl = PerfectLink::new(
+            Arc::new(EventQueue::new_inert()),
+            Arc::new(node_info(1, &nodes)),
+            transport.clone(),
+        );
+        let send = |to: u16| {
+            let pl_send = InternalMessage::PlSend(node(1), node(to), accept("ep1"));
+            EventData::Internal(SYSTEM_ID.to_owned(), pl_send)
         };
+
+        let started = Instant::now();
+        pl.handle(&send(3));
+        pl.handle(&send(3));
+        pl.handle(&send(2));
+        wait_until(|| transport.receivers.lock().unwrap().contains(&2));
+        assert!(started.elapsed() < delay);
+    }
+
+    #[test]
+    fn the_oldest_frames_queued_for_a_process_are_dropped_first() {
+        let outbox = Outbox::default();
+        for i in 0..=SEND_QUEUE_CAPACITY {
+            assert_eq!(outbox.push(vec![i as u8]), i < SEND_QUEUE_CAPACITY);
+        }
+        assert_eq!(outbox.pop(), Some(vec![1]));
+
+        outbox.close();
+        assert_eq!(outbox.pop(), None);
+    }
+
+    /// Refuses every frame.
+    struct BrokenTransport;
+
+    impl Transport for BrokenTransport {
+        fn send(&self, _: &Node, _: &[u8]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+        }
+    }
+
+    #[test]
+    fn a_frame_that_cannot_be_sent_is_reported_to_the_link() {
+        let nodes: Vec<Node> = (1..=2).map(node).collect();
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let recorder = Recorder::default();
+        let pl = PerfectLink::new(event_queue.clone(), Arc::new(node_info(1, &nodes)), Arc::new(BrokenTransport));
+        event_queue.register_handler(Box::new(pl));
+        event_queue.register_handler(Box::new(recorder.clone()));
+
+        event_queue.push(send_to_2());
+        wait_until(|| {
+            recorder.events().iter().any(|event_data| matches!(
+                event_data,
+                EventData::Internal(_, InternalMessage::PlSendFailed(dest)) if dest == &node(2)
+            ))
+        });
+    }
+
+    /// Loses the frames to the process `lossy`, all of them or only the first one, and records
+    /// the uuids of the frames to it.
+    struct LossyTransport {
+        inner: InMemoryTransport,
+        lossy: NodeId,
+        lose_all: bool,
+        lost_one: AtomicBool,
+        frames: Mutex<Vec<String>>,
+    }
+
+    impl LossyTransport {
+        fn new(lossy: NodeId, lose_all: bool) -> Self {
+            LossyTransport {
+                inner: InMemoryTransport::new(),
+                lossy,
+                lose_all,
+                lost_one: AtomicBool::new(false),
+                frames: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn frames(&self) -> Vec<String> {
+            self.frames.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for LossyTransport {
+        fn send(&self, to: &Node, bytes: &[u8]) -> io::Result<()> {
+            if to.id == self.lossy {
+                let uuid = decode(bytes)?.get_messageUuid().to_owned();
+                self.frames.lock().unwrap().push(uuid);
+                if self.lose_all || !self.lost_one.swap(true, Ordering::SeqCst) {
+                    return Ok(());
+                }
+            }
+            self.inner.send(to, bytes)
+        }
+    }
+
+    const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Runs the link of the process `id`, of the nodes 1 and 2, on a queue of its own.
+    fn running_link(id: NodeId, transport: &Arc<LossyTransport>) -> (Arc<EventQueue>, Recorder) {
+        let nodes: Vec<Node> = (1..=2).map(node).collect();
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        transport.inner.register(id, &event_queue);
+        let recorder = Recorder::default();
+        let pl = PerfectLink::new(event_queue.clone(), Arc::new(node_info(id, &nodes)), transport.clone())
+            .with_retransmit_interval(RETRANSMIT_INTERVAL);
+        event_queue.register_handler(Box::new(pl));
+        event_queue.register_handler(Box::new(recorder.clone()));
+        (event_queue, recorder)
+    }
+
+    fn send_to_2() -> EventData {
+        let mut accept = accept("ep1");
+        accept.set_systemId(SYSTEM_ID.to_owned());
+        let pl_send = InternalMessage::PlSend(node(1), node(2), accept);
+        EventData::Internal(SYSTEM_ID.to_owned(), pl_send)
+    }
+
+    #[test]
+    fn a_lost_frame_is_sent_again_until_it_is_acknowledged() {
+        let transport = Arc::new(LossyTransport::new(2, false));
+        let (sender_queue, sender) = running_link(1, &transport);
+        let (_receiver_queue, receiver) = running_link(2, &transport);
+
+        sender_queue.push(send_to_2());
+        wait_until(|| !delivered(&receiver).is_empty());
+
+        // the first frame was lost, the one delivered is the same frame sent again.
+        let frames = transport.frames();
+        assert!(frames.len() >= 2);
+        assert!(frames.iter().all(|uuid| uuid == &frames[0]));
+        assert_eq!(delivered(&receiver)[0].1.get_abstractionId(), "ep1");
+
+        // once acknowledged, the frame is no longer sent.
+        thread::sleep(RETRANSMIT_INTERVAL * 4);
+        let sent = transport.frames().len();
+        thread::sleep(RETRANSMIT_INTERVAL * 4);
+        assert_eq!(transport.frames().len(), sent);
+        assert_eq!(delivered(&receiver).len(), 1);
+
+        // the acknowledgement is handed over with the uuid of the message rather than the frame's.
+        let uuid = delivered(&receiver)[0].1.get_messageUuid().to_owned();
+        assert!(sender.events().iter().any(|event_data| matches!(
+            event_data,
+            EventData::Internal(_, InternalMessage::PlAcknowledged(to, acknowledged))
+                if to == &node(2) && acknowledged == &uuid
+        )));
+    }
+
+    /// Waits for the frames to stop, returns how many were sent by then.
+    fn frames_once_stopped(transport: &LossyTransport) -> usize {
+        thread::sleep(RETRANSMIT_INTERVAL * 4);
+        let sent = transport.frames().len();
+        thread::sleep(RETRANSMIT_INTERVAL * 4);
+        assert_eq!(transport.frames().len(), sent);
+        sent
+    }
+
+    #[test]
+    fn the_frames_to_a_suspected_process_wait_for_it_to_be_restored() {
+        let transport = Arc::new(LossyTransport::new(2, true));
+        let (sender_queue, _) = running_link(1, &transport);
+
+        sender_queue.push(send_to_2());
+        wait_until(|| transport.frames().len() >= 2);
+        let suspect = InternalMessage::EpfdSuspect(node(2));
+        sender_queue.push(EventData::Internal(SYSTEM_ID.to_owned(), suspect));
+        let sent = frames_once_stopped(&transport);
+
+        // the suspicion of another system leaves them be, the restoration sends them right away.
+        let restore = InternalMessage::EpfdRestore(node(2));
+        sender_queue.push(EventData::Internal("sys-2".to_owned(), restore.clone()));
+        sender_queue.push(EventData::Internal(SYSTEM_ID.to_owned(), restore));
+        wait_until(|| transport.frames().len() >= sent + 2);
+    }
+
+    #[test]
+    fn the_frames_of_a_closed_system_are_no_longer_sent() {
+        let transport = Arc::new(LossyTransport::new(2, true));
+        let (sender_queue, _) = running_link(1, &transport);
+
+        sender_queue.push(send_to_2());
+        wait_until(|| transport.frames().len() >= 2);
+        sender_queue.push(EventData::Internal(SYSTEM_ID.to_owned(), InternalMessage::SystemClosed));
+        frames_once_stopped(&transport);
+    }
+
+    #[test]
+    fn only_so_many_frames_to_a_process_wait_for_its_acknowledgement() {
+        let nodes: Vec<Node> = (1..=2).map(node).collect();
+        let mut pl = PerfectLink::new(
+            Arc::new(EventQueue::new_inert()),
+            Arc::new(node_info(1, &nodes)),
+            Arc::new(BrokenTransport),
+        );
+        for _ in 0..=MAX_UNACKED {
+            pl.handle(&send_to_2());
+        }
+        assert_eq!(pl.unacked[&2].len(), MAX_UNACKED);
     }
 }
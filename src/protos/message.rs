@@ -34,6 +34,8 @@ pub struct ProcessId {
     pub owner: ::std::string::String,
     pub index: i32,
     pub rank: i32,
+    pub learner: bool,
+    pub weight: u32,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -146,6 +148,36 @@ impl ProcessId {
     pub fn set_rank(&mut self, v: i32) {
         self.rank = v;
     }
+
+    // bool learner = 6;
+
+
+    pub fn get_learner(&self) -> bool {
+        self.learner
+    }
+    pub fn clear_learner(&mut self) {
+        self.learner = false;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_learner(&mut self, v: bool) {
+        self.learner = v;
+    }
+
+    // uint32 weight = 7;
+
+
+    pub fn get_weight(&self) -> u32 {
+        self.weight
+    }
+    pub fn clear_weight(&mut self) {
+        self.weight = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_weight(&mut self, v: u32) {
+        self.weight = v;
+    }
 }
 
 impl ::protobuf::Message for ProcessId {
@@ -184,6 +216,20 @@ impl ::protobuf::Message for ProcessId {
                     let tmp = is.read_int32()?;
                     self.rank = tmp;
                 },
+                6 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_bool()?;
+                    self.learner = tmp;
+                },
+                7 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint32()?;
+                    self.weight = tmp;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -211,6 +257,12 @@ impl ::protobuf::Message for ProcessId {
         if self.rank != 0 {
             my_size += ::protobuf::rt::value_size(5, self.rank, ::protobuf::wire_format::WireTypeVarint);
         }
+        if self.learner != false {
+            my_size += 2;
+        }
+        if self.weight != 0 {
+            my_size += ::protobuf::rt::value_size(7, self.weight, ::protobuf::wire_format::WireTypeVarint);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -232,6 +284,12 @@ impl ::protobuf::Message for ProcessId {
         if self.rank != 0 {
             os.write_int32(5, self.rank)?;
         }
+        if self.learner != false {
+            os.write_bool(6, self.learner)?;
+        }
+        if self.weight != 0 {
+            os.write_uint32(7, self.weight)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -296,6 +354,16 @@ impl ::protobuf::Message for ProcessId {
                     |m: &ProcessId| { &m.rank },
                     |m: &mut ProcessId| { &mut m.rank },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBool>(
+                    "learner",
+                    |m: &ProcessId| { &m.learner },
+                    |m: &mut ProcessId| { &mut m.learner },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint32>(
+                    "weight",
+                    |m: &ProcessId| { &m.weight },
+                    |m: &mut ProcessId| { &mut m.weight },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<ProcessId>(
                     "ProcessId",
                     fields,
@@ -320,6 +388,8 @@ impl ::protobuf::Clear for ProcessId {
         self.owner.clear();
         self.index = 0;
         self.rank = 0;
+        self.learner = false;
+        self.weight = 0;
         self.unknown_fields.clear();
     }
 }
@@ -341,6 +411,7 @@ pub struct Value {
     // message fields
     pub defined: bool,
     pub v: i32,
+    pub data: ::std::vec::Vec<u8>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -386,6 +457,32 @@ impl Value {
     pub fn set_v(&mut self, v: i32) {
         self.v = v;
     }
+
+    // bytes data = 3;
+
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn clear_data(&mut self) {
+        self.data.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_data(&mut self, v: ::std::vec::Vec<u8>) {
+        self.data = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_data(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.data
+    }
+
+    // Take field
+    pub fn take_data(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.data, ::std::vec::Vec::new())
+    }
 }
 
 impl ::protobuf::Message for Value {
@@ -411,6 +508,9 @@ impl ::protobuf::Message for Value {
                     let tmp = is.read_int32()?;
                     self.v = tmp;
                 },
+                3 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.data)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -429,6 +529,9 @@ impl ::protobuf::Message for Value {
         if self.v != 0 {
             my_size += ::protobuf::rt::value_size(2, self.v, ::protobuf::wire_format::WireTypeVarint);
         }
+        if !self.data.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(3, &self.data);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -441,6 +544,9 @@ impl ::protobuf::Message for Value {
         if self.v != 0 {
             os.write_int32(2, self.v)?;
         }
+        if !self.data.is_empty() {
+            os.write_bytes(3, &self.data)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -490,6 +596,11 @@ impl ::protobuf::Message for Value {
                     |m: &Value| { &m.v },
                     |m: &mut Value| { &mut m.v },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "data",
+                    |m: &Value| { &m.data },
+                    |m: &mut Value| { &mut m.data },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<Value>(
                     "Value",
                     fields,
@@ -511,6 +622,7 @@ impl ::protobuf::Clear for Value {
     fn clear(&mut self) {
         self.defined = false;
         self.v = 0;
+        self.data.clear();
         self.unknown_fields.clear();
     }
 }
@@ -1130,6 +1242,403 @@ impl ::protobuf::reflect::ProtobufValue for AppDecide {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+pub struct ClientPropose {
+    // message fields
+    pub value: ::protobuf::SingularPtrField<Value>,
+    pub processes: ::protobuf::RepeatedField<ProcessId>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a ClientPropose {
+    fn default() -> &'a ClientPropose {
+        <ClientPropose as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl ClientPropose {
+    pub fn new() -> ClientPropose {
+        ::std::default::Default::default()
+    }
+
+    // .main.Value value = 1;
+
+
+    pub fn get_value(&self) -> &Value {
+        self.value.as_ref().unwrap_or_else(|| Value::default_instance())
+    }
+    pub fn clear_value(&mut self) {
+        self.value.clear();
+    }
+
+    pub fn has_value(&self) -> bool {
+        self.value.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_value(&mut self, v: Value) {
+        self.value = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_value(&mut self) -> &mut Value {
+        if self.value.is_none() {
+            self.value.set_default();
+        }
+        self.value.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_value(&mut self) -> Value {
+        self.value.take().unwrap_or_else(|| Value::new())
+    }
+
+    // repeated .main.ProcessId processes = 2;
+
+
+    pub fn get_processes(&self) -> &[ProcessId] {
+        &self.processes
+    }
+    pub fn clear_processes(&mut self) {
+        self.processes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_processes(&mut self, v: ::protobuf::RepeatedField<ProcessId>) {
+        self.processes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_processes(&mut self) -> &mut ::protobuf::RepeatedField<ProcessId> {
+        &mut self.processes
+    }
+
+    // Take field
+    pub fn take_processes(&mut self) -> ::protobuf::RepeatedField<ProcessId> {
+        ::std::mem::replace(&mut self.processes, ::protobuf::RepeatedField::new())
+    }
+}
+
+impl ::protobuf::Message for ClientPropose {
+    fn is_initialized(&self) -> bool {
+        for v in &self.value {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        for v in &self.processes {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.value)?;
+                },
+                2 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.processes)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if let Some(ref v) = self.value.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        for value in &self.processes {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if let Some(ref v) = self.value.as_ref() {
+            os.write_tag(1, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        for v in &self.processes {
+            os.write_tag(2, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> ClientPropose {
+        ClientPropose::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy::INIT;
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Value>>(
+                    "value",
+                    |m: &ClientPropose| { &m.value },
+                    |m: &mut ClientPropose| { &mut m.value },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<ProcessId>>(
+                    "processes",
+                    |m: &ClientPropose| { &m.processes },
+                    |m: &mut ClientPropose| { &mut m.processes },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<ClientPropose>(
+                    "ClientPropose",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static ClientPropose {
+        static mut instance: ::protobuf::lazy::Lazy<ClientPropose> = ::protobuf::lazy::Lazy::INIT;
+        unsafe {
+            instance.get(ClientPropose::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for ClientPropose {
+    fn clear(&mut self) {
+        self.value.clear();
+        self.processes.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for ClientPropose {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for ClientPropose {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Reconfigure {
+    // message fields
+    pub processes: ::protobuf::RepeatedField<ProcessId>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a Reconfigure {
+    fn default() -> &'a Reconfigure {
+        <Reconfigure as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl Reconfigure {
+    pub fn new() -> Reconfigure {
+        ::std::default::Default::default()
+    }
+
+    // repeated .main.ProcessId processes = 1;
+
+
+    pub fn get_processes(&self) -> &[ProcessId] {
+        &self.processes
+    }
+    pub fn clear_processes(&mut self) {
+        self.processes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_processes(&mut self, v: ::protobuf::RepeatedField<ProcessId>) {
+        self.processes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_processes(&mut self) -> &mut ::protobuf::RepeatedField<ProcessId> {
+        &mut self.processes
+    }
+
+    // Take field
+    pub fn take_processes(&mut self) -> ::protobuf::RepeatedField<ProcessId> {
+        ::std::mem::replace(&mut self.processes, ::protobuf::RepeatedField::new())
+    }
+}
+
+impl ::protobuf::Message for Reconfigure {
+    fn is_initialized(&self) -> bool {
+        for v in &self.processes {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.processes)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        for value in &self.processes {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        for v in &self.processes {
+            os.write_tag(1, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Reconfigure {
+        Reconfigure::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy::INIT;
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<ProcessId>>(
+                    "processes",
+                    |m: &Reconfigure| { &m.processes },
+                    |m: &mut Reconfigure| { &mut m.processes },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<Reconfigure>(
+                    "Reconfigure",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static Reconfigure {
+        static mut instance: ::protobuf::lazy::Lazy<Reconfigure> = ::protobuf::lazy::Lazy::INIT;
+        unsafe {
+            instance.get(Reconfigure::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for Reconfigure {
+    fn clear(&mut self) {
+        self.processes.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Reconfigure {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Reconfigure {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
+    }
+}
+
 #[derive(PartialEq,Clone,Default)]
 pub struct UcPropose {
     // message fields
@@ -4376,6 +4885,8 @@ impl ::protobuf::reflect::ProtobufValue for EpfdTimeout {
 
 #[derive(PartialEq,Clone,Default)]
 pub struct EpfdHeartbeatRequest_ {
+    // message fields
+    pub metadata: ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -4391,6 +4902,31 @@ impl EpfdHeartbeatRequest_ {
     pub fn new() -> EpfdHeartbeatRequest_ {
         ::std::default::Default::default()
     }
+
+    // repeated .main.EpfdHeartbeatRequest_.MetadataEntry metadata = 1;
+
+
+    pub fn get_metadata(&self) -> &::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>> {
+        &self.metadata
+    }
+    pub fn clear_metadata(&mut self) {
+        self.metadata.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_metadata(&mut self, v: ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>>) {
+        self.metadata = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_metadata(&mut self) -> &mut ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>> {
+        &mut self.metadata
+    }
+
+    // Take field
+    pub fn take_metadata(&mut self) -> ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>> {
+        ::std::mem::replace(&mut self.metadata, ::std::collections::HashMap::new())
+    }
 }
 
 impl ::protobuf::Message for EpfdHeartbeatRequest_ {
@@ -4402,6 +4938,9 @@ impl ::protobuf::Message for EpfdHeartbeatRequest_ {
         while !is.eof()? {
             let (field_number, wire_type) = is.read_tag_unpack()?;
             match field_number {
+                1 => {
+                    ::protobuf::rt::read_map_into::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(wire_type, is, &mut self.metadata)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -4414,12 +4953,14 @@ impl ::protobuf::Message for EpfdHeartbeatRequest_ {
     #[allow(unused_variables)]
     fn compute_size(&self) -> u32 {
         let mut my_size = 0;
+        my_size += ::protobuf::rt::compute_map_size::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(1, &self.metadata);
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
     }
 
     fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        ::protobuf::rt::write_map_with_cached_sizes::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(1, &self.metadata, os)?;
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -4458,7 +4999,12 @@ impl ::protobuf::Message for EpfdHeartbeatRequest_ {
         static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy::INIT;
         unsafe {
             descriptor.get(|| {
-                let fields = ::std::vec::Vec::new();
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_map_accessor::<_, ::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(
+                    "metadata",
+                    |m: &EpfdHeartbeatRequest_| { &m.metadata },
+                    |m: &mut EpfdHeartbeatRequest_| { &mut m.metadata },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<EpfdHeartbeatRequest_>(
                     "EpfdHeartbeatRequest_",
                     fields,
@@ -4478,6 +5024,7 @@ impl ::protobuf::Message for EpfdHeartbeatRequest_ {
 
 impl ::protobuf::Clear for EpfdHeartbeatRequest_ {
     fn clear(&mut self) {
+        self.metadata.clear();
         self.unknown_fields.clear();
     }
 }
@@ -4496,6 +5043,8 @@ impl ::protobuf::reflect::ProtobufValue for EpfdHeartbeatRequest_ {
 
 #[derive(PartialEq,Clone,Default)]
 pub struct EpfdHeartbeatReply_ {
+    // message fields
+    pub metadata: ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -4511,6 +5060,31 @@ impl EpfdHeartbeatReply_ {
     pub fn new() -> EpfdHeartbeatReply_ {
         ::std::default::Default::default()
     }
+
+    // repeated .main.EpfdHeartbeatReply_.MetadataEntry metadata = 1;
+
+
+    pub fn get_metadata(&self) -> &::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>> {
+        &self.metadata
+    }
+    pub fn clear_metadata(&mut self) {
+        self.metadata.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_metadata(&mut self, v: ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>>) {
+        self.metadata = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_metadata(&mut self) -> &mut ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>> {
+        &mut self.metadata
+    }
+
+    // Take field
+    pub fn take_metadata(&mut self) -> ::std::collections::HashMap<::std::string::String, ::std::vec::Vec<u8>> {
+        ::std::mem::replace(&mut self.metadata, ::std::collections::HashMap::new())
+    }
 }
 
 impl ::protobuf::Message for EpfdHeartbeatReply_ {
@@ -4522,6 +5096,9 @@ impl ::protobuf::Message for EpfdHeartbeatReply_ {
         while !is.eof()? {
             let (field_number, wire_type) = is.read_tag_unpack()?;
             match field_number {
+                1 => {
+                    ::protobuf::rt::read_map_into::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(wire_type, is, &mut self.metadata)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -4534,12 +5111,14 @@ impl ::protobuf::Message for EpfdHeartbeatReply_ {
     #[allow(unused_variables)]
     fn compute_size(&self) -> u32 {
         let mut my_size = 0;
+        my_size += ::protobuf::rt::compute_map_size::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(1, &self.metadata);
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
     }
 
     fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        ::protobuf::rt::write_map_with_cached_sizes::<::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(1, &self.metadata, os)?;
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -4578,7 +5157,12 @@ impl ::protobuf::Message for EpfdHeartbeatReply_ {
         static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy::INIT;
         unsafe {
             descriptor.get(|| {
-                let fields = ::std::vec::Vec::new();
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_map_accessor::<_, ::protobuf::types::ProtobufTypeString, ::protobuf::types::ProtobufTypeBytes>(
+                    "metadata",
+                    |m: &EpfdHeartbeatReply_| { &m.metadata },
+                    |m: &mut EpfdHeartbeatReply_| { &mut m.metadata },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<EpfdHeartbeatReply_>(
                     "EpfdHeartbeatReply_",
                     fields,
@@ -4598,6 +5182,7 @@ impl ::protobuf::Message for EpfdHeartbeatReply_ {
 
 impl ::protobuf::Clear for EpfdHeartbeatReply_ {
     fn clear(&mut self) {
+        self.metadata.clear();
         self.unknown_fields.clear();
     }
 }
@@ -5706,6 +6291,7 @@ pub struct Message {
     pub appRegistration: ::protobuf::SingularPtrField<AppRegistration>,
     pub appPropose: ::protobuf::SingularPtrField<AppPropose>,
     pub appDecide: ::protobuf::SingularPtrField<AppDecide>,
+    pub clientPropose: ::protobuf::SingularPtrField<ClientPropose>,
     pub ucDecide: ::protobuf::SingularPtrField<UcDecide>,
     pub ucPropose: ::protobuf::SingularPtrField<UcPropose>,
     pub epAbort: ::protobuf::SingularPtrField<EpAbort>,
@@ -5731,6 +6317,7 @@ pub struct Message {
     pub epfdRestore: ::protobuf::SingularPtrField<EpfdRestore>,
     pub plDeliver: ::protobuf::SingularPtrField<PlDeliver>,
     pub plSend: ::protobuf::SingularPtrField<PlSend>,
+    pub reconfigure: ::protobuf::SingularPtrField<Reconfigure>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -5972,6 +6559,39 @@ impl Message {
         self.appDecide.take().unwrap_or_else(|| AppDecide::new())
     }
 
+    // .main.ClientPropose clientPropose = 9;
+
+
+    pub fn get_clientPropose(&self) -> &ClientPropose {
+        self.clientPropose.as_ref().unwrap_or_else(|| ClientPropose::default_instance())
+    }
+    pub fn clear_clientPropose(&mut self) {
+        self.clientPropose.clear();
+    }
+
+    pub fn has_clientPropose(&self) -> bool {
+        self.clientPropose.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_clientPropose(&mut self, v: ClientPropose) {
+        self.clientPropose = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_clientPropose(&mut self) -> &mut ClientPropose {
+        if self.clientPropose.is_none() {
+            self.clientPropose.set_default();
+        }
+        self.clientPropose.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_clientPropose(&mut self) -> ClientPropose {
+        self.clientPropose.take().unwrap_or_else(|| ClientPropose::new())
+    }
+
     // .main.UcDecide ucDecide = 10;
 
 
@@ -6796,6 +7416,39 @@ impl Message {
     pub fn take_plSend(&mut self) -> PlSend {
         self.plSend.take().unwrap_or_else(|| PlSend::new())
     }
+
+    // .main.Reconfigure reconfigure = 80;
+
+
+    pub fn get_reconfigure(&self) -> &Reconfigure {
+        self.reconfigure.as_ref().unwrap_or_else(|| Reconfigure::default_instance())
+    }
+    pub fn clear_reconfigure(&mut self) {
+        self.reconfigure.clear();
+    }
+
+    pub fn has_reconfigure(&self) -> bool {
+        self.reconfigure.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_reconfigure(&mut self, v: Reconfigure) {
+        self.reconfigure = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_reconfigure(&mut self) -> &mut Reconfigure {
+        if self.reconfigure.is_none() {
+            self.reconfigure.set_default();
+        }
+        self.reconfigure.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_reconfigure(&mut self) -> Reconfigure {
+        self.reconfigure.take().unwrap_or_else(|| Reconfigure::new())
+    }
 }
 
 impl ::protobuf::Message for Message {
@@ -6820,6 +7473,11 @@ impl ::protobuf::Message for Message {
                 return false;
             }
         };
+        for v in &self.clientPropose {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         for v in &self.ucDecide {
             if !v.is_initialized() {
                 return false;
@@ -6945,6 +7603,11 @@ impl ::protobuf::Message for Message {
                 return false;
             }
         };
+        for v in &self.reconfigure {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -6976,6 +7639,9 @@ impl ::protobuf::Message for Message {
                 8 => {
                     ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.appDecide)?;
                 },
+                9 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.clientPropose)?;
+                },
                 10 => {
                     ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.ucDecide)?;
                 },
@@ -7051,6 +7717,9 @@ impl ::protobuf::Message for Message {
                 71 => {
                     ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.plSend)?;
                 },
+                80 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.reconfigure)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -7091,6 +7760,10 @@ impl ::protobuf::Message for Message {
             let len = v.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         }
+        if let Some(ref v) = self.clientPropose.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         if let Some(ref v) = self.ucDecide.as_ref() {
             let len = v.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
@@ -7191,6 +7864,10 @@ impl ::protobuf::Message for Message {
             let len = v.compute_size();
             my_size += 2 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         }
+        if let Some(ref v) = self.reconfigure.as_ref() {
+            let len = v.compute_size();
+            my_size += 2 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -7229,6 +7906,11 @@ impl ::protobuf::Message for Message {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         }
+        if let Some(ref v) = self.clientPropose.as_ref() {
+            os.write_tag(9, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         if let Some(ref v) = self.ucDecide.as_ref() {
             os.write_tag(10, ::protobuf::wire_format::WireTypeLengthDelimited)?;
             os.write_raw_varint32(v.get_cached_size())?;
@@ -7354,6 +8036,11 @@ impl ::protobuf::Message for Message {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         }
+        if let Some(ref v) = self.reconfigure.as_ref() {
+            os.write_tag(80, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -7433,6 +8120,11 @@ impl ::protobuf::Message for Message {
                     |m: &Message| { &m.appDecide },
                     |m: &mut Message| { &mut m.appDecide },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<ClientPropose>>(
+                    "clientPropose",
+                    |m: &Message| { &m.clientPropose },
+                    |m: &mut Message| { &mut m.clientPropose },
+                ));
                 fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<UcDecide>>(
                     "ucDecide",
                     |m: &Message| { &m.ucDecide },
@@ -7558,6 +8250,11 @@ impl ::protobuf::Message for Message {
                     |m: &Message| { &m.plSend },
                     |m: &mut Message| { &mut m.plSend },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Reconfigure>>(
+                    "reconfigure",
+                    |m: &Message| { &m.reconfigure },
+                    |m: &mut Message| { &mut m.reconfigure },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<Message>(
                     "Message",
                     fields,
@@ -7585,6 +8282,7 @@ impl ::protobuf::Clear for Message {
         self.appRegistration.clear();
         self.appPropose.clear();
         self.appDecide.clear();
+        self.clientPropose.clear();
         self.ucDecide.clear();
         self.ucPropose.clear();
         self.epAbort.clear();
@@ -7610,6 +8308,7 @@ impl ::protobuf::Clear for Message {
         self.epfdRestore.clear();
         self.plDeliver.clear();
         self.plSend.clear();
+        self.reconfigure.clear();
         self.unknown_fields.clear();
     }
 }
@@ -7632,6 +8331,7 @@ pub enum Message_Type {
     APP_REGISTRATION = 5,
     APP_PROPOSE = 6,
     APP_DECIDE = 7,
+    CLIENT_PROPOSE = 8,
     UC_DECIDE = 10,
     UC_PROPOSE = 11,
     EP_ABORT = 20,
@@ -7657,6 +8357,8 @@ pub enum Message_Type {
     EPFD_RESTORE = 64,
     PL_DELIVER = 70,
     PL_SEND = 71,
+    PL_ACK = 72,
+    RECONFIGURE = 80,
 }
 
 impl ::protobuf::ProtobufEnum for Message_Type {
@@ -7670,6 +8372,7 @@ impl ::protobuf::ProtobufEnum for Message_Type {
             5 => ::std::option::Option::Some(Message_Type::APP_REGISTRATION),
             6 => ::std::option::Option::Some(Message_Type::APP_PROPOSE),
             7 => ::std::option::Option::Some(Message_Type::APP_DECIDE),
+            8 => ::std::option::Option::Some(Message_Type::CLIENT_PROPOSE),
             10 => ::std::option::Option::Some(Message_Type::UC_DECIDE),
             11 => ::std::option::Option::Some(Message_Type::UC_PROPOSE),
             20 => ::std::option::Option::Some(Message_Type::EP_ABORT),
@@ -7695,6 +8398,8 @@ impl ::protobuf::ProtobufEnum for Message_Type {
             64 => ::std::option::Option::Some(Message_Type::EPFD_RESTORE),
             70 => ::std::option::Option::Some(Message_Type::PL_DELIVER),
             71 => ::std::option::Option::Some(Message_Type::PL_SEND),
+            72 => ::std::option::Option::Some(Message_Type::PL_ACK),
+            80 => ::std::option::Option::Some(Message_Type::RECONFIGURE),
             _ => ::std::option::Option::None
         }
     }
@@ -7705,6 +8410,7 @@ impl ::protobuf::ProtobufEnum for Message_Type {
             Message_Type::APP_REGISTRATION,
             Message_Type::APP_PROPOSE,
             Message_Type::APP_DECIDE,
+            Message_Type::CLIENT_PROPOSE,
             Message_Type::UC_DECIDE,
             Message_Type::UC_PROPOSE,
             Message_Type::EP_ABORT,
@@ -7730,6 +8436,8 @@ impl ::protobuf::ProtobufEnum for Message_Type {
             Message_Type::EPFD_RESTORE,
             Message_Type::PL_DELIVER,
             Message_Type::PL_SEND,
+            Message_Type::PL_ACK,
+            Message_Type::RECONFIGURE,
         ];
         values
     }
@@ -7760,66 +8468,79 @@ impl ::protobuf::reflect::ProtobufValue for Message_Type {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\rmessage.proto\x12\x04main\"s\n\tProcessId\x12\x12\n\x04host\x18\x01\
-    \x20\x01(\tR\x04host\x12\x12\n\x04port\x18\x02\x20\x01(\x05R\x04port\x12\
-    \x14\n\x05owner\x18\x03\x20\x01(\tR\x05owner\x12\x14\n\x05index\x18\x04\
-    \x20\x01(\x05R\x05index\x12\x12\n\x04rank\x18\x05\x20\x01(\x05R\x04rank\
-    \"/\n\x05Value\x12\x18\n\x07defined\x18\x01\x20\x01(\x08R\x07defined\x12\
-    \x0c\n\x01v\x18\x02\x20\x01(\x05R\x01v\"=\n\x0fAppRegistration\x12\x14\n\
-    \x05owner\x18\x01\x20\x01(\tR\x05owner\x12\x14\n\x05index\x18\x02\x20\
-    \x01(\x05R\x05index\"^\n\nAppPropose\x12!\n\x05value\x18\x01\x20\x01(\
-    \x0b2\x0b.main.ValueR\x05value\x12-\n\tprocesses\x18\x02\x20\x03(\x0b2\
-    \x0f.main.ProcessIdR\tprocesses\".\n\tAppDecide\x12!\n\x05value\x18\x01\
-    \x20\x01(\x0b2\x0b.main.ValueR\x05value\".\n\tUcPropose\x12!\n\x05value\
-    \x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\"-\n\x08UcDecide\x12!\n\
-    \x05value\x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\"\t\n\x07EpAbor\
-    t\"h\n\tEpAborted\x12\x10\n\x03ets\x18\x01\x20\x01(\x05R\x03ets\x12&\n\
-    \x0evalueTimestamp\x18\x02\x20\x01(\x05R\x0evalueTimestamp\x12!\n\x05val\
-    ue\x18\x03\x20\x01(\x0b2\x0b.main.ValueR\x05value\".\n\tEpPropose\x12!\n\
-    \x05value\x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\"?\n\x08EpDecid\
-    e\x12\x10\n\x03ets\x18\x01\x20\x01(\x05R\x03ets\x12!\n\x05value\x18\x02\
-    \x20\x01(\x0b2\x0b.main.ValueR\x05value\"\t\n\x07EpRead_\"U\n\x08EpState\
-    _\x12&\n\x0evalueTimestamp\x18\x01\x20\x01(\x05R\x0evalueTimestamp\x12!\
-    \n\x05value\x18\x02\x20\x01(\x0b2\x0b.main.ValueR\x05value\"-\n\x08EpWri\
-    te_\x12!\n\x05value\x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\"\x0b\
-    \n\tEpAccept_\"/\n\nEpDecided_\x12!\n\x05value\x18\x01\x20\x01(\x0b2\x0b\
-    .main.ValueR\x05value\"\t\n\x07EcNack_\"a\n\x0cEcStartEpoch\x12\"\n\x0cn\
-    ewTimestamp\x18\x01\x20\x01(\x05R\x0cnewTimestamp\x12-\n\tnewLeader\x18\
-    \x02\x20\x01(\x0b2\x0f.main.ProcessIdR\tnewLeader\"+\n\x0bEcNewEpoch_\
-    \x12\x1c\n\ttimestamp\x18\x01\x20\x01(\x05R\ttimestamp\"7\n\x0cBebBroadc\
-    ast\x12'\n\x07message\x18\x01\x20\x01(\x0b2\r.main.MessageR\x07message\"\
-    ^\n\nBebDeliver\x12'\n\x07message\x18\x01\x20\x01(\x0b2\r.main.MessageR\
-    \x07message\x12'\n\x06sender\x18\x02\x20\x01(\x0b2\x0f.main.ProcessIdR\
-    \x06sender\"\x0c\n\nEldTimeout\"5\n\x08EldTrust\x12)\n\x07process\x18\
-    \x01\x20\x01(\x0b2\x0f.main.ProcessIdR\x07process\"\r\n\x0bEpfdTimeout\"\
-    \x17\n\x15EpfdHeartbeatRequest_\"\x15\n\x13EpfdHeartbeatReply_\"8\n\x0bE\
-    pfdSuspect\x12)\n\x07process\x18\x01\x20\x01(\x0b2\x0f.main.ProcessIdR\
-    \x07process\"8\n\x0bEpfdRestore\x12)\n\x07process\x18\x01\x20\x01(\x0b2\
-    \x0f.main.ProcessIdR\x07process\"d\n\x06PlSend\x121\n\x0bdestination\x18\
-    \x01\x20\x01(\x0b2\x0f.main.ProcessIdR\x0bdestination\x12'\n\x07message\
-    \x18\x02\x20\x01(\x0b2\r.main.MessageR\x07message\"]\n\tPlDeliver\x12'\n\
-    \x06sender\x18\x01\x20\x01(\x0b2\x0f.main.ProcessIdR\x06sender\x12'\n\
-    \x07message\x18\x02\x20\x01(\x0b2\r.main.MessageR\x07message\"\x8b\x01\n\
-    \x0eNetworkMessage\x12\x1e\n\nsenderHost\x18\x01\x20\x01(\tR\nsenderHost\
-    \x120\n\x13senderListeningPort\x18\x02\x20\x01(\x05R\x13senderListeningP\
-    ort\x12'\n\x07message\x18\x03\x20\x01(\x0b2\r.main.MessageR\x07message\"\
-    \xce\x10\n\x07Message\x12&\n\x04type\x18\x01\x20\x01(\x0e2\x12.main.Mess\
-    age.TypeR\x04type\x12\x20\n\x0bmessageUuid\x18\x02\x20\x01(\tR\x0bmessag\
-    eUuid\x12$\n\rabstractionId\x18\x03\x20\x01(\tR\rabstractionId\x12\x1a\n\
-    \x08systemId\x18\x04\x20\x01(\tR\x08systemId\x12<\n\x0enetworkMessage\
-    \x18\x05\x20\x01(\x0b2\x14.main.NetworkMessageR\x0enetworkMessage\x12?\n\
-    \x0fappRegistration\x18\x06\x20\x01(\x0b2\x15.main.AppRegistrationR\x0fa\
-    ppRegistration\x120\n\nappPropose\x18\x07\x20\x01(\x0b2\x10.main.AppProp\
-    oseR\nappPropose\x12-\n\tappDecide\x18\x08\x20\x01(\x0b2\x0f.main.AppDec\
-    ideR\tappDecide\x12*\n\x08ucDecide\x18\n\x20\x01(\x0b2\x0e.main.UcDecide\
-    R\x08ucDecide\x12-\n\tucPropose\x18\x0b\x20\x01(\x0b2\x0f.main.UcPropose\
-    R\tucPropose\x12'\n\x07epAbort\x18\x14\x20\x01(\x0b2\r.main.EpAbortR\x07\
-    epAbort\x12-\n\tepAborted\x18\x15\x20\x01(\x0b2\x0f.main.EpAbortedR\tepA\
-    borted\x12,\n\tepAccept_\x18\x16\x20\x01(\x0b2\x0f.main.EpAccept_R\x08ep\
-    Accept\x12*\n\x08epDecide\x18\x17\x20\x01(\x0b2\x0e.main.EpDecideR\x08ep\
-    Decide\x12/\n\nepDecided_\x18\x18\x20\x01(\x0b2\x10.main.EpDecided_R\tep\
-    Decided\x12-\n\tepPropose\x18\x19\x20\x01(\x0b2\x0f.main.EpProposeR\tepP\
-    ropose\x12&\n\x07epRead_\x18\x1a\x20\x01(\x0b2\r.main.EpRead_R\x06epRead\
+    \n\rmessage.proto\x12\x04main\"\xa5\x01\n\tProcessId\x12\x12\n\x04host\
+    \x18\x01\x20\x01(\tR\x04host\x12\x12\n\x04port\x18\x02\x20\x01(\x05R\x04\
+    port\x12\x14\n\x05owner\x18\x03\x20\x01(\tR\x05owner\x12\x14\n\x05index\
+    \x18\x04\x20\x01(\x05R\x05index\x12\x12\n\x04rank\x18\x05\x20\x01(\x05R\
+    \x04rank\x12\x18\n\x07learner\x18\x06\x20\x01(\x08R\x07learner\x12\x16\n\
+    \x06weight\x18\x07\x20\x01(\rR\x06weight\"C\n\x05Value\x12\x18\n\x07defi\
+    ned\x18\x01\x20\x01(\x08R\x07defined\x12\x0c\n\x01v\x18\x02\x20\x01(\x05\
+    R\x01v\x12\x12\n\x04data\x18\x03\x20\x01(\x0cR\x04data\"=\n\x0fAppRegist\
+    ration\x12\x14\n\x05owner\x18\x01\x20\x01(\tR\x05owner\x12\x14\n\x05inde\
+    x\x18\x02\x20\x01(\x05R\x05index\"^\n\nAppPropose\x12!\n\x05value\x18\
+    \x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\x12-\n\tprocesses\x18\x02\
+    \x20\x03(\x0b2\x0f.main.ProcessIdR\tprocesses\".\n\tAppDecide\x12!\n\x05\
+    value\x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\"a\n\rClientPropose\
+    \x12!\n\x05value\x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\x12-\n\t\
+    processes\x18\x02\x20\x03(\x0b2\x0f.main.ProcessIdR\tprocesses\"<\n\x0bR\
+    econfigure\x12-\n\tprocesses\x18\x01\x20\x03(\x0b2\x0f.main.ProcessIdR\t\
+    processes\".\n\tUcPropose\x12!\n\x05value\x18\x01\x20\x01(\x0b2\x0b.main\
+    .ValueR\x05value\"-\n\x08UcDecide\x12!\n\x05value\x18\x01\x20\x01(\x0b2\
+    \x0b.main.ValueR\x05value\"\t\n\x07EpAbort\"h\n\tEpAborted\x12\x10\n\x03\
+    ets\x18\x01\x20\x01(\x05R\x03ets\x12&\n\x0evalueTimestamp\x18\x02\x20\
+    \x01(\x05R\x0evalueTimestamp\x12!\n\x05value\x18\x03\x20\x01(\x0b2\x0b.m\
+    ain.ValueR\x05value\".\n\tEpPropose\x12!\n\x05value\x18\x01\x20\x01(\x0b\
+    2\x0b.main.ValueR\x05value\"?\n\x08EpDecide\x12\x10\n\x03ets\x18\x01\x20\
+    \x01(\x05R\x03ets\x12!\n\x05value\x18\x02\x20\x01(\x0b2\x0b.main.ValueR\
+    \x05value\"\t\n\x07EpRead_\"U\n\x08EpState_\x12&\n\x0evalueTimestamp\x18\
+    \x01\x20\x01(\x05R\x0evalueTimestamp\x12!\n\x05value\x18\x02\x20\x01(\
+    \x0b2\x0b.main.ValueR\x05value\"-\n\x08EpWrite_\x12!\n\x05value\x18\x01\
+    \x20\x01(\x0b2\x0b.main.ValueR\x05value\"\x0b\n\tEpAccept_\"/\n\nEpDecid\
+    ed_\x12!\n\x05value\x18\x01\x20\x01(\x0b2\x0b.main.ValueR\x05value\"\t\n\
+    \x07EcNack_\"a\n\x0cEcStartEpoch\x12\"\n\x0cnewTimestamp\x18\x01\x20\x01\
+    (\x05R\x0cnewTimestamp\x12-\n\tnewLeader\x18\x02\x20\x01(\x0b2\x0f.main.\
+    ProcessIdR\tnewLeader\"+\n\x0bEcNewEpoch_\x12\x1c\n\ttimestamp\x18\x01\
+    \x20\x01(\x05R\ttimestamp\"7\n\x0cBebBroadcast\x12'\n\x07message\x18\x01\
+    \x20\x01(\x0b2\r.main.MessageR\x07message\"^\n\nBebDeliver\x12'\n\x07mes\
+    sage\x18\x01\x20\x01(\x0b2\r.main.MessageR\x07message\x12'\n\x06sender\
+    \x18\x02\x20\x01(\x0b2\x0f.main.ProcessIdR\x06sender\"\x0c\n\nEldTimeout\
+    \"5\n\x08EldTrust\x12)\n\x07process\x18\x01\x20\x01(\x0b2\x0f.main.Proce\
+    ssIdR\x07process\"\r\n\x0bEpfdTimeout\"\x9b\x01\n\x15EpfdHeartbeatReques\
+    t_\x12E\n\x08metadata\x18\x01\x20\x03(\x0b2).main.EpfdHeartbeatRequest_.\
+    MetadataEntryR\x08metadata\x1a;\n\rMetadataEntry\x12\x10\n\x03key\x18\
+    \x01\x20\x01(\tR\x03key\x12\x14\n\x05value\x18\x02\x20\x01(\x0cR\x05valu\
+    e:\x028\x01\"\x97\x01\n\x13EpfdHeartbeatReply_\x12C\n\x08metadata\x18\
+    \x01\x20\x03(\x0b2'.main.EpfdHeartbeatReply_.MetadataEntryR\x08metadata\
+    \x1a;\n\rMetadataEntry\x12\x10\n\x03key\x18\x01\x20\x01(\tR\x03key\x12\
+    \x14\n\x05value\x18\x02\x20\x01(\x0cR\x05value:\x028\x01\"8\n\x0bEpfdSus\
+    pect\x12)\n\x07process\x18\x01\x20\x01(\x0b2\x0f.main.ProcessIdR\x07proc\
+    ess\"8\n\x0bEpfdRestore\x12)\n\x07process\x18\x01\x20\x01(\x0b2\x0f.main\
+    .ProcessIdR\x07process\"d\n\x06PlSend\x121\n\x0bdestination\x18\x01\x20\
+    \x01(\x0b2\x0f.main.ProcessIdR\x0bdestination\x12'\n\x07message\x18\x02\
+    \x20\x01(\x0b2\r.main.MessageR\x07message\"]\n\tPlDeliver\x12'\n\x06send\
+    er\x18\x01\x20\x01(\x0b2\x0f.main.ProcessIdR\x06sender\x12'\n\x07message\
+    \x18\x02\x20\x01(\x0b2\r.main.MessageR\x07message\"\x8b\x01\n\x0eNetwork\
+    Message\x12\x1e\n\nsenderHost\x18\x01\x20\x01(\tR\nsenderHost\x120\n\x13\
+    senderListeningPort\x18\x02\x20\x01(\x05R\x13senderListeningPort\x12'\n\
+    \x07message\x18\x03\x20\x01(\x0b2\r.main.MessageR\x07message\"\xef\x11\n\
+    \x07Message\x12&\n\x04type\x18\x01\x20\x01(\x0e2\x12.main.Message.TypeR\
+    \x04type\x12\x20\n\x0bmessageUuid\x18\x02\x20\x01(\tR\x0bmessageUuid\x12\
+    $\n\rabstractionId\x18\x03\x20\x01(\tR\rabstractionId\x12\x1a\n\x08syste\
+    mId\x18\x04\x20\x01(\tR\x08systemId\x12<\n\x0enetworkMessage\x18\x05\x20\
+    \x01(\x0b2\x14.main.NetworkMessageR\x0enetworkMessage\x12?\n\x0fappRegis\
+    tration\x18\x06\x20\x01(\x0b2\x15.main.AppRegistrationR\x0fappRegistrati\
+    on\x120\n\nappPropose\x18\x07\x20\x01(\x0b2\x10.main.AppProposeR\nappPro\
+    pose\x12-\n\tappDecide\x18\x08\x20\x01(\x0b2\x0f.main.AppDecideR\tappDec\
+    ide\x129\n\rclientPropose\x18\t\x20\x01(\x0b2\x13.main.ClientProposeR\rc\
+    lientPropose\x12*\n\x08ucDecide\x18\n\x20\x01(\x0b2\x0e.main.UcDecideR\
+    \x08ucDecide\x12-\n\tucPropose\x18\x0b\x20\x01(\x0b2\x0f.main.UcProposeR\
+    \tucPropose\x12'\n\x07epAbort\x18\x14\x20\x01(\x0b2\r.main.EpAbortR\x07e\
+    pAbort\x12-\n\tepAborted\x18\x15\x20\x01(\x0b2\x0f.main.EpAbortedR\tepAb\
+    orted\x12,\n\tepAccept_\x18\x16\x20\x01(\x0b2\x0f.main.EpAccept_R\x08epA\
+    ccept\x12*\n\x08epDecide\x18\x17\x20\x01(\x0b2\x0e.main.EpDecideR\x08epD\
+    ecide\x12/\n\nepDecided_\x18\x18\x20\x01(\x0b2\x10.main.EpDecided_R\tepD\
+    ecided\x12-\n\tepPropose\x18\x19\x20\x01(\x0b2\x0f.main.EpProposeR\tepPr\
+    opose\x12&\n\x07epRead_\x18\x1a\x20\x01(\x0b2\r.main.EpRead_R\x06epRead\
     \x12)\n\x08epState_\x18\x1b\x20\x01(\x0b2\x0e.main.EpState_R\x07epState\
     \x12)\n\x08epWrite_\x18\x1c\x20\x01(\x0b2\x0e.main.EpWrite_R\x07epWrite\
     \x12&\n\x07ecNack_\x18\x1f\x20\x01(\x0b2\r.main.EcNack_R\x06ecNack\x122\
@@ -7837,20 +8558,22 @@ static file_descriptor_proto_data: &'static [u8] = b"\
     ectR\x0bepfdSuspect\x123\n\x0bepfdRestore\x18@\x20\x01(\x0b2\x11.main.Ep\
     fdRestoreR\x0bepfdRestore\x12-\n\tplDeliver\x18F\x20\x01(\x0b2\x0f.main.\
     PlDeliverR\tplDeliver\x12$\n\x06plSend\x18G\x20\x01(\x0b2\x0c.main.PlSen\
-    dR\x06plSend\"\xfd\x03\n\x04Type\x12\x13\n\x0fNETWORK_MESSAGE\x10\0\x12\
-    \x14\n\x10APP_REGISTRATION\x10\x05\x12\x0f\n\x0bAPP_PROPOSE\x10\x06\x12\
-    \x0e\n\nAPP_DECIDE\x10\x07\x12\r\n\tUC_DECIDE\x10\n\x12\x0e\n\nUC_PROPOS\
-    E\x10\x0b\x12\x0c\n\x08EP_ABORT\x10\x14\x12\x0e\n\nEP_ABORTED\x10\x15\
-    \x12\x0e\n\nEP_ACCEPT_\x10\x16\x12\r\n\tEP_DECIDE\x10\x17\x12\x0f\n\x0bE\
-    P_DECIDED_\x10\x18\x12\x0e\n\nEP_PROPOSE\x10\x19\x12\x0c\n\x08EP_READ_\
-    \x10\x1a\x12\r\n\tEP_STATE_\x10\x1b\x12\r\n\tEP_WRITE_\x10\x1c\x12\x0c\n\
-    \x08EC_NACK_\x10\x1e\x12\x11\n\rEC_NEW_EPOCH_\x10\x1f\x12\x12\n\x0eEC_ST\
-    ART_EPOCH\x10\x20\x12\x11\n\rBEB_BROADCAST\x10(\x12\x0f\n\x0bBEB_DELIVER\
-    \x10)\x12\x0f\n\x0bELD_TIMEOUT\x102\x12\r\n\tELD_TRUST\x103\x12\x10\n\
-    \x0cEPFD_TIMEOUT\x10<\x12\x1a\n\x16EPFD_HEARTBEAT_REQUEST\x10=\x12\x18\n\
-    \x14EPFD_HEARTBEAT_REPLY\x10>\x12\x10\n\x0cEPFD_SUSPECT\x10?\x12\x10\n\
-    \x0cEPFD_RESTORE\x10@\x12\x0e\n\nPL_DELIVER\x10F\x12\x0b\n\x07PL_SEND\
-    \x10Gb\x06proto3\
+    dR\x06plSend\x123\n\x0breconfigure\x18P\x20\x01(\x0b2\x11.main.Reconfigu\
+    reR\x0breconfigure\"\xae\x04\n\x04Type\x12\x13\n\x0fNETWORK_MESSAGE\x10\
+    \0\x12\x14\n\x10APP_REGISTRATION\x10\x05\x12\x0f\n\x0bAPP_PROPOSE\x10\
+    \x06\x12\x0e\n\nAPP_DECIDE\x10\x07\x12\x12\n\x0eCLIENT_PROPOSE\x10\x08\
+    \x12\r\n\tUC_DECIDE\x10\n\x12\x0e\n\nUC_PROPOSE\x10\x0b\x12\x0c\n\x08EP_\
+    ABORT\x10\x14\x12\x0e\n\nEP_ABORTED\x10\x15\x12\x0e\n\nEP_ACCEPT_\x10\
+    \x16\x12\r\n\tEP_DECIDE\x10\x17\x12\x0f\n\x0bEP_DECIDED_\x10\x18\x12\x0e\
+    \n\nEP_PROPOSE\x10\x19\x12\x0c\n\x08EP_READ_\x10\x1a\x12\r\n\tEP_STATE_\
+    \x10\x1b\x12\r\n\tEP_WRITE_\x10\x1c\x12\x0c\n\x08EC_NACK_\x10\x1e\x12\
+    \x11\n\rEC_NEW_EPOCH_\x10\x1f\x12\x12\n\x0eEC_START_EPOCH\x10\x20\x12\
+    \x11\n\rBEB_BROADCAST\x10(\x12\x0f\n\x0bBEB_DELIVER\x10)\x12\x0f\n\x0bEL\
+    D_TIMEOUT\x102\x12\r\n\tELD_TRUST\x103\x12\x10\n\x0cEPFD_TIMEOUT\x10<\
+    \x12\x1a\n\x16EPFD_HEARTBEAT_REQUEST\x10=\x12\x18\n\x14EPFD_HEARTBEAT_RE\
+    PLY\x10>\x12\x10\n\x0cEPFD_SUSPECT\x10?\x12\x10\n\x0cEPFD_RESTORE\x10@\
+    \x12\x0e\n\nPL_DELIVER\x10F\x12\x0b\n\x07PL_SEND\x10G\x12\n\n\x06PL_ACK\
+    \x10H\x12\x0f\n\x0bRECONFIGURE\x10Pb\x06proto3\
 ";
 
 static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy::INIT;
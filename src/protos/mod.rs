@@ -1 +1,11 @@
+// The generated code predates several newer rustc lints, which older toolchains don't know,
+// and the APIs of the later protobuf 2.x releases, which deprecate the ones it uses.
+#[allow(
+    deprecated,
+    unknown_lints,
+    renamed_and_removed_lints,
+    unused_parens,
+    static_mut_refs,
+    mismatched_lifetime_syntaxes
+)]
 pub mod message;
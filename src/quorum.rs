@@ -0,0 +1,274 @@
+use crate::config::ConfigError;
+use crate::node::NodeId;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// How many of the N votes of a system make a quorum, every voter casting as many votes as
+/// its weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuorumPolicy {
+    /// The smallest strict majority, N/2 + 1.
+    #[default]
+    Majority,
+    /// Every voter, a single crashed voter then blocks the decision.
+    All,
+    /// At least this many votes.
+    AtLeast(usize),
+}
+
+impl QuorumPolicy {
+    /// The number of votes making a quorum among `n` of them.
+    pub fn threshold(&self, n: usize) -> usize {
+        match self {
+            QuorumPolicy::Majority => n / 2 + 1,
+            QuorumPolicy::All => n,
+            QuorumPolicy::AtLeast(k) => *k,
+        }
+    }
+
+    /// Checks that a quorum of the `n` votes can be reached, and that any two quorums share
+    /// a voter: an epoch only learns about the value decided in a previous one through it.
+    pub fn validate(&self, n: usize) -> Result<(), ConfigError> {
+        let threshold = self.threshold(n);
+        if threshold == 0 || threshold > n || threshold * 2 <= n {
+            return Err(ConfigError::InvalidQuorum(*self, n));
+        }
+        Ok(())
+    }
+}
+
+/// The quorums of the two phases of epoch consensus: the leader collects the STATEs of a read
+/// quorum, then the ACCEPTs of a write quorum. Every read quorum has to share a voter with every
+/// write quorum, for an epoch to learn about the value written by a previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quorums {
+    pub read: QuorumPolicy,
+    pub write: QuorumPolicy,
+}
+
+impl Quorums {
+    /// Both phases with the quorums of `policy`.
+    pub fn uniform(policy: QuorumPolicy) -> Self {
+        Quorums {
+            read: policy,
+            write: policy,
+        }
+    }
+
+    /// Checks that both quorums can be reached among the `n` votes, and that a read and a
+    /// write quorum always share a voter, i.e. that they add up to more than `n`.
+    pub fn validate(&self, n: usize) -> Result<(), ConfigError> {
+        if self.read == self.write {
+            return self.read.validate(n);
+        }
+        let (read, write) = (self.read.threshold(n), self.write.threshold(n));
+        if read == 0 || write == 0 || read > n || write > n || read + write <= n {
+            return Err(ConfigError::UnsafeQuorums(*self, n));
+        }
+        Ok(())
+    }
+}
+
+impl From<QuorumPolicy> for Quorums {
+    fn from(policy: QuorumPolicy) -> Self {
+        Quorums::uniform(policy)
+    }
+}
+
+impl fmt::Display for QuorumPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuorumPolicy::Majority => write!(f, "majority"),
+            QuorumPolicy::All => write!(f, "all"),
+            QuorumPolicy::AtLeast(k) => write!(f, "{}", k),
+        }
+    }
+}
+
+/// Parses `majority`, `all` or a number of voters.
+impl FromStr for QuorumPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "majority" => Ok(QuorumPolicy::Majority),
+            "all" => Ok(QuorumPolicy::All),
+            k => k.parse().map(QuorumPolicy::AtLeast).map_err(|_| {
+                format!("The quorum {} is neither majority, all, nor a number of voters.", s)
+            }),
+        }
+    }
+}
+
+/// Counts the votes of the distinct processes that contributed to a round, e.g. the processes
+/// that sent their STATE or ACCEPT. A process contributing several times is only counted once,
+/// so duplicates can never make the count reach the threshold early.
+#[derive(Debug, Clone)]
+pub struct QuorumCounter {
+    threshold: usize,
+    senders: BTreeSet<NodeId>,
+    /// The weights of the processes, the ones not listed casting a single vote.
+    weights: HashMap<NodeId, u32>,
+    votes: usize,
+}
+
+impl QuorumCounter {
+    pub fn new(threshold: usize) -> Self {
+        Self::weighted(threshold, HashMap::new())
+    }
+
+    /// Counts as many votes for every process as its weight in `weights`.
+    pub fn weighted(threshold: usize, weights: HashMap<NodeId, u32>) -> Self {
+        QuorumCounter {
+            threshold,
+            senders: BTreeSet::new(),
+            weights,
+            votes: 0,
+        }
+    }
+
+    /// The smallest strict majority of `n` processes, i.e. N/2 + 1.
+    pub fn majority(n: usize) -> Self {
+        Self::new(QuorumPolicy::Majority.threshold(n))
+    }
+
+    /// Counts the contribution of `sender`. Returns `true` only for the contribution that makes
+    /// the count reach the threshold, so the quorum is reported exactly once per round.
+    pub fn add(&mut self, sender: NodeId) -> bool {
+        if !self.senders.insert(sender) {
+            return false;
+        }
+        let before = self.votes;
+        self.votes += self.weights.get(&sender).map_or(1, |&weight| weight as usize);
+        before < self.threshold && self.votes >= self.threshold
+    }
+
+    /// The number of distinct processes that contributed so far.
+    pub fn len(&self) -> usize {
+        self.senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    /// Forgets every contribution, starting the next round.
+    pub fn reset(&mut self) {
+        self.senders.clear();
+        self.votes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn senders_are_counted_once() {
+        let mut counter = QuorumCounter::new(3);
+        for _ in 0..10 {
+            counter.add(1);
+        }
+        counter.add(2);
+        assert_eq!(counter.len(), 2);
+    }
+
+    #[test]
+    fn the_threshold_is_reported_once() {
+        let mut counter = QuorumCounter::majority(4);
+        assert!(!counter.add(1));
+        assert!(!counter.add(2));
+        assert!(counter.add(3));
+        assert!(!counter.add(3));
+        assert!(!counter.add(4));
+    }
+
+    #[test]
+    fn every_policy_has_its_threshold() {
+        let thresholds = |n| {
+            [QuorumPolicy::Majority, QuorumPolicy::All, QuorumPolicy::AtLeast(3)]
+                .iter()
+                .map(|policy| policy.threshold(n))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(thresholds(3), vec![2, 3, 3]);
+        assert_eq!(thresholds(4), vec![3, 4, 3]);
+    }
+
+    #[test]
+    fn a_policy_is_only_valid_if_its_quorums_can_be_reached_and_intersect() {
+        for n in 3..=4 {
+            assert_eq!(QuorumPolicy::Majority.validate(n), Ok(()));
+            assert_eq!(QuorumPolicy::All.validate(n), Ok(()));
+            assert_eq!(QuorumPolicy::AtLeast(3).validate(n), Ok(()));
+            assert!(QuorumPolicy::AtLeast(5).validate(n).is_err());
+        }
+        // two disjoint pairs of voters among 4 could decide different values.
+        assert_eq!(
+            QuorumPolicy::AtLeast(2).validate(4),
+            Err(ConfigError::InvalidQuorum(QuorumPolicy::AtLeast(2), 4))
+        );
+        assert!(QuorumPolicy::AtLeast(0).validate(0).is_err());
+    }
+
+    #[test]
+    fn a_read_and_a_write_quorum_have_to_intersect() {
+        let asymmetric = Quorums {
+            read: QuorumPolicy::AtLeast(1),
+            write: QuorumPolicy::All,
+        };
+        assert_eq!(asymmetric.validate(3), Ok(()));
+
+        let unsafe_pair = Quorums {
+            read: QuorumPolicy::AtLeast(1),
+            write: QuorumPolicy::AtLeast(2),
+        };
+        assert_eq!(unsafe_pair.validate(3), Err(ConfigError::UnsafeQuorums(unsafe_pair, 3)));
+        let unreachable = Quorums {
+            read: QuorumPolicy::AtLeast(4),
+            write: QuorumPolicy::Majority,
+        };
+        assert!(unreachable.validate(3).is_err());
+        assert_eq!(
+            Quorums::uniform(QuorumPolicy::AtLeast(2)).validate(4),
+            Err(ConfigError::InvalidQuorum(QuorumPolicy::AtLeast(2), 4))
+        );
+    }
+
+    #[test]
+    fn a_policy_is_parsed_from_its_name() {
+        for policy in &[QuorumPolicy::Majority, QuorumPolicy::All, QuorumPolicy::AtLeast(3)] {
+            assert_eq!(policy.to_string().parse(), Ok(*policy));
+        }
+        assert!("most".parse::<QuorumPolicy>().is_err());
+    }
+
+    #[test]
+    fn a_heavy_voter_and_a_light_one_make_a_quorum() {
+        let weights: HashMap<NodeId, u32> = vec![(1, 3), (2, 1), (3, 1)].into_iter().collect();
+        let threshold = QuorumPolicy::Majority.threshold(5);
+        assert_eq!(threshold, 3);
+
+        let mut counter = QuorumCounter::weighted(threshold, weights.clone());
+        assert!(!counter.add(2));
+        assert!(!counter.add(3));
+        assert!(counter.add(1));
+
+        let mut counter = QuorumCounter::weighted(threshold, weights);
+        assert!(counter.add(1));
+        assert!(!counter.add(2));
+    }
+
+    #[test]
+    fn a_reset_starts_the_next_round() {
+        let mut counter = QuorumCounter::new(2);
+        counter.add(1);
+        assert!(counter.add(2));
+
+        counter.reset();
+        assert!(counter.is_empty());
+        assert!(!counter.add(2));
+        assert!(counter.add(1));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::event::*;
+use crate::node::NodeInfo;
+use crate::protos::message::Value;
+use crate::sys::{local_epoch_change_storage, local_state_storage, System};
+use log::{debug, trace};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Returns the system id used by the uniform consensus instance deciding `slot` of a sequence.
+/// Every slot runs in its own system so that messages of different instances never cross-talk.
+pub fn slot_system_id(system_id: &str, slot: usize) -> String {
+    format!("{}/{}", system_id, slot)
+}
+
+/// Sequence (multi-shot) consensus: decides a log of values, one uniform consensus
+/// instance per slot. A slot is only started once the previous one has decided, so
+/// decisions are indicated in slot order.
+/// Events:
+/// Request: ⟨ seqc, Propose | v ⟩: Queues value v to be proposed in the next free slot.
+/// Indication: ⟨ seqc, Decide | i, v ⟩: Outputs the value v decided for slot i.
+pub struct SequenceConsensus {
+    node_info: Arc<NodeInfo>,
+    event_queue: Arc<EventQueue>,
+    system_id: String,
+    slot: usize,
+    slot_system: System,
+    /// The values still to be proposed, in the order they were requested.
+    pending: VecDeque<Value>,
+    /// The value this process proposed, along with the slot it was proposed in.
+    in_flight: Option<(usize, Value)>,
+    state_dir: PathBuf,
+}
+
+impl SequenceConsensus {
+    pub fn new(
+        node_info: Arc<NodeInfo>,
+        event_queue: Arc<EventQueue>,
+        system_id: String,
+        state_dir: PathBuf,
+    ) -> Self {
+        let slot_system = Self::create_slot(&node_info, &event_queue, &system_id, 0, &state_dir);
+        SequenceConsensus {
+            node_info,
+            event_queue,
+            system_id,
+            slot: 0,
+            slot_system,
+            pending: VecDeque::new(),
+            in_flight: None,
+            state_dir,
+        }
+    }
+
+    fn create_slot(
+        node_info: &Arc<NodeInfo>,
+        event_queue: &Arc<EventQueue>,
+        system_id: &str,
+        slot: usize,
+        state_dir: &Path,
+    ) -> System {
+        let slot_system_id = slot_system_id(system_id, slot);
+        let storage = local_state_storage(state_dir, &slot_system_id);
+        let ec_storage = local_epoch_change_storage(state_dir, &slot_system_id);
+        System::new::<ValueType>(
+            slot_system_id,
+            node_info.clone(),
+            event_queue.clone(),
+            storage,
+            ec_storage,
+        )
+    }
+
+    /// upon event ⟨ seqc, Propose | v ⟩ do
+    fn seq_propose(&mut self, value: &Value) {
+        self.pending.push_back(value.clone());
+        self.propose_next();
+    }
+
+    fn propose_next(&mut self) {
+        if self.in_flight.is_some() {
+            return;
+        }
+        if let Some(value) = self.pending.pop_front() {
+            let proposal = InternalMessage::UcPropose(value.clone());
+            let event_data = EventData::Internal(self.slot_system.system_id.clone(), proposal);
+            self.in_flight = Some((self.slot, value));
+            self.event_queue.push(event_data);
+        }
+    }
+
+    /// upon event ⟨ uc.i, Decide | v ⟩ such that i = slot do
+    fn uc_decide(&mut self, value: &Value) {
+        debug!("Slot {} of {} decided.", self.slot, self.system_id);
+        let decide_message = InternalMessage::SeqDecide(self.slot, value.clone());
+        let event_data = EventData::Internal(self.system_id.clone(), decide_message);
+        self.event_queue.push(event_data);
+
+        // our own proposal might have lost the slot, in which case it is retried in the next one.
+        // An equal value decided in the slot it was proposed in is indistinguishable from it.
+        if let Some((slot, proposed)) = self.in_flight.take() {
+            if slot == self.slot && &proposed != value {
+                self.pending.push_front(proposed);
+            }
+        }
+
+        // the slot is decided, its abstractions have nothing left to do.
+        self.slot_system.close(&self.event_queue);
+        self.slot += 1;
+        self.slot_system = Self::create_slot(
+            &self.node_info,
+            &self.event_queue,
+            &self.system_id,
+            self.slot,
+            &self.state_dir,
+        );
+        self.propose_next();
+    }
+}
+
+impl EventHandler for SequenceConsensus {
+    fn should_handle_event(&self, event_data: &EventData) -> bool {
+        if let EventData::Internal(system_id, _) = event_data {
+            system_id == &self.system_id || system_id == &self.slot_system.system_id
+        } else {
+            false
+        }
+    }
+
+    fn handle(&mut self, event_data: &EventData) {
+        trace!("Handler summoned with event {:?}", event_data);
+
+        if let EventData::Internal(system_id, msg) = event_data {
+            match msg {
+                InternalMessage::SeqPropose(value) if system_id == &self.system_id => {
+                    self.seq_propose(value)
+                }
+                InternalMessage::UcDecide(value) if system_id == &self.slot_system.system_id => {
+                    self.uc_decide(value)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pl::PerfectLink;
+    use crate::testing::{node, node_info, state_dir, wait_until, Recorder};
+    use crate::transport::InMemoryTransport;
+    use crate::value::ConsensusValue;
+
+    fn proposed_value(recorder: &Recorder, slot: usize) -> Option<Value> {
+        let slot_system = slot_system_id("log", slot);
+        recorder
+            .events()
+            .into_iter()
+            .find_map(|event_data| match event_data {
+                EventData::Internal(system_id, InternalMessage::UcPropose(value))
+                    if system_id == slot_system =>
+                {
+                    Some(value)
+                }
+                _ => None,
+            })
+    }
+
+    fn decided_slots(recorder: &Recorder) -> Vec<(usize, ValueType)> {
+        recorder
+            .events()
+            .into_iter()
+            .filter_map(|event_data| match event_data {
+                EventData::Internal(_, InternalMessage::SeqDecide(slot, value)) => {
+                    Some((slot, ValueType::from_value(&value)?))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_values_are_decided_in_slot_order() {
+        let node_info = Arc::new(node_info(1, &[node(1)]));
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let seqc = SequenceConsensus::new(
+            node_info,
+            event_queue.clone(),
+            "log".to_owned(),
+            state_dir("seqc"),
+        );
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(seqc));
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let handler_count = event_queue.handler_count();
+
+        for value in &[7, 3, 5] {
+            let proposal = InternalMessage::SeqPropose(value.to_value());
+            event_queue.push(EventData::Internal("log".to_owned(), proposal));
+        }
+        // each slot decides the value proposed in it, as its uniform consensus would.
+        for slot in 0..3 {
+            wait_until(|| proposed_value(&recorder, slot).is_some());
+            let value = proposed_value(&recorder, slot).unwrap();
+            let decision = InternalMessage::UcDecide(value);
+            event_queue.push(EventData::Internal(slot_system_id("log", slot), decision));
+        }
+        wait_until(|| decided_slots(&recorder).len() == 3);
+
+        assert_eq!(decided_slots(&recorder), vec![(0, 7), (1, 3), (2, 5)]);
+        // only the system of the next slot is left running.
+        wait_until(|| event_queue.handler_count() == handler_count);
+    }
+
+    #[test]
+    fn a_proposal_losing_its_slot_is_proposed_in_the_next_one() {
+        let node_info = Arc::new(node_info(1, &[node(1)]));
+        let event_queue = Arc::new(EventQueue::new_inert());
+        let mut seqc = SequenceConsensus::new(
+            node_info,
+            event_queue.clone(),
+            "log".to_owned(),
+            state_dir("seqc-lost-slot"),
+        );
+
+        seqc.handle(&EventData::Internal(
+            "log".to_owned(),
+            InternalMessage::SeqPropose(7.to_value()),
+        ));
+        // another process wins the slot with a value of its own.
+        seqc.handle(&EventData::Internal(
+            slot_system_id("log", 0),
+            InternalMessage::UcDecide(8.to_value()),
+        ));
+
+        assert_eq!(seqc.in_flight, Some((1, 7.to_value())));
+        assert!(seqc.pending.is_empty());
+    }
+
+    #[test]
+    fn back_to_back_proposals_are_both_decided_in_order() {
+        let node_info = Arc::new(node_info(1, &[node(1)]));
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        // the leader sends its own STATE and ACCEPT over the link.
+        let transport = Arc::new(InMemoryTransport::new());
+        transport.register(1, &event_queue);
+        let link = PerfectLink::new(event_queue.clone(), node_info.clone(), transport);
+        let seqc = SequenceConsensus::new(
+            node_info,
+            event_queue.clone(),
+            "log".to_owned(),
+            state_dir("back_to_back_proposals"),
+        );
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(link));
+        event_queue.register_handler(Box::new(seqc));
+        event_queue.register_handler(Box::new(recorder.clone()));
+
+        // the second proposal waits for the first one to be decided by its uniform consensus.
+        for value in &[4, 6] {
+            let proposal = InternalMessage::SeqPropose(value.to_value());
+            event_queue.push(EventData::Internal("log".to_owned(), proposal));
+        }
+        wait_until(|| decided_slots(&recorder).len() == 2);
+
+        assert_eq!(decided_slots(&recorder), vec![(0, 4), (1, 6)]);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::engine::Engine;
+use crate::event::{EventData, EventQueue};
+use crate::frame::{read_frame_limited, MAX_FRAME_BYTES};
+use crate::node::{Node, NodeInfo};
+use crate::protos::message::Message;
+use crate::sink::DecisionSink;
+use crate::tls::TlsConfig;
+use crate::transport::{TcpTransport, DEFAULT_SEND_TIMEOUT};
+use crate::value::ConsensusValue;
+use log::{error, info, trace};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Runs a whole process: the consensus engine along with the listener handing it the
+/// messages of the other processes and of the hub.
+pub struct Server {
+    state_dir: PathBuf,
+    listen_address: Option<SocketAddr>,
+    sinks: Vec<Box<dyn DecisionSink>>,
+    trace: Option<PathBuf>,
+    send_timeout: Duration,
+    workers: usize,
+    tls: Option<TlsConfig>,
+    max_frame_bytes: usize,
+}
+
+impl Server {
+    /// `state_dir` is where the consensus state is persisted in order to survive crashes.
+    pub fn new<P: AsRef<Path>>(state_dir: P) -> Self {
+        Server {
+            state_dir: state_dir.as_ref().to_path_buf(),
+            listen_address: None,
+            sinks: Vec::new(),
+            trace: None,
+            send_timeout: DEFAULT_SEND_TIMEOUT,
+            workers: 1,
+            tls: None,
+            max_frame_bytes: MAX_FRAME_BYTES,
+        }
+    }
+
+    /// Binds to `listen_address` rather than to the address of the current node, which
+    /// remains the one advertised to the other processes.
+    pub fn with_listen_address(mut self, listen_address: SocketAddr) -> Self {
+        self.listen_address = Some(listen_address);
+        self
+    }
+
+    /// Every decision is additionally exported to the given `sinks`.
+    pub fn with_sinks(mut self, sinks: Vec<Box<dyn DecisionSink>>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// Records every event the engine handles to the file at `trace`, see `EventQueue::trace_to`.
+    pub fn with_trace<P: AsRef<Path>>(mut self, trace: P) -> Self {
+        self.trace = Some(trace.as_ref().to_path_buf());
+        self
+    }
+
+    /// Gives up on sending a message to another process after `send_timeout`,
+    /// see `TcpTransport::with_timeout`.
+    pub fn with_send_timeout(mut self, send_timeout: Duration) -> Self {
+        self.send_timeout = send_timeout;
+        self
+    }
+
+    /// Handles the events on `workers` threads rather than on a single one, see
+    /// `Engine::with_workers`.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Secures every connection, both ways, with `tls`. The other processes, and the hub,
+    /// have to use TLS as well.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Drops the connections announcing a message larger than `max_frame_bytes` rather than
+    /// `MAX_FRAME_BYTES`, without reading it.
+    pub fn with_max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.max_frame_bytes = max_frame_bytes;
+        self
+    }
+
+    /// Binds the listener, then starts the engine and registers the process with the hub.
+    pub fn start(self, node_info: Arc<NodeInfo>) -> io::Result<RunningNode> {
+        let listener = bind_listener(&node_info, self.listen_address)?;
+        let local_address = listener.local_addr()?;
+        info!("Listening on Node: {}", node_info.current_node);
+
+        let mut transport = TcpTransport::new().with_timeout(self.send_timeout);
+        if let Some(tls) = &self.tls {
+            transport = transport.with_tls(tls.clone());
+        }
+        let engine = Engine::with_workers(
+            node_info,
+            self.state_dir,
+            self.sinks,
+            Arc::new(transport),
+            self.workers,
+        )?;
+        if let Some(trace) = &self.trace {
+            engine.event_queue().trace_to(trace)?;
+            info!("Tracing the events to {}", trace.display());
+        }
+        engine.start();
+
+        let shutdown = Shutdown {
+            stopped: Arc::new(AtomicBool::new(false)),
+            local_address,
+        };
+        let listener = {
+            let event_queue = engine.event_queue();
+            let stopped = shutdown.stopped.clone();
+            let tls = self.tls;
+            let max_frame_bytes = self.max_frame_bytes;
+            thread::Builder::new()
+                .name("listener".to_owned())
+                .spawn(move || {
+                    let tls = tls.as_ref();
+                    listen_for_clients(&event_queue, &listener, &stopped, tls, max_frame_bytes)
+                })?
+        };
+
+        Ok(RunningNode {
+            engine,
+            shutdown,
+            listener: Some(listener),
+        })
+    }
+}
+
+/// Stops the listener of a running node, e.g. from a signal handler. Clones stop the same node.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    stopped: Arc<AtomicBool>,
+    local_address: SocketAddr,
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // `accept` only returns once a client shows up, so we show up ourselves.
+        let _ = TcpStream::connect(reachable(self.local_address));
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// A started process, see `Server::start`.
+pub struct RunningNode {
+    engine: Engine,
+    shutdown: Shutdown,
+    listener: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl RunningNode {
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// The address the listener is actually bound to.
+    pub fn local_address(&self) -> SocketAddr {
+        self.shutdown.local_address
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// See `Engine::propose`.
+    pub fn propose<V: ConsensusValue>(&self, system_id: &str, value: &V, processes: &[Node]) {
+        self.engine.propose(system_id, value, processes);
+    }
+
+    /// Blocks until the listener stops, which only happens on a failure or once shut down,
+    /// then shuts the event queue down after it handled the outstanding events.
+    pub fn wait(mut self) -> io::Result<()> {
+        let result = match self.listener.take() {
+            Some(listener) => listener
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("The listener thread panicked."))),
+            None => Ok(()),
+        };
+        self.engine.event_queue().shutdown();
+        result
+    }
+
+    /// Stops the listener and then the event queue.
+    pub fn shutdown(self) -> io::Result<()> {
+        self.shutdown.trigger();
+        self.wait()
+    }
+}
+
+/// The address to connect to in order to reach a listener bound to `address`.
+fn reachable(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port())
+        }
+        _ => address,
+    }
+}
+
+/// Binds to `listen_address` if given, otherwise to the address of the current node in
+/// the configuration. Either way, the configured address is the one advertised to the others.
+pub fn bind_listener(
+    node_info: &NodeInfo,
+    listen_address: Option<SocketAddr>,
+) -> io::Result<TcpListener> {
+    let address = match listen_address {
+        Some(listen_address) => listen_address,
+        None => node_info.current_node.resolve()?,
+    };
+    TcpListener::bind(address)
+}
+
+/// The message `stream` sends, a connection carrying a single one. `None` if it closes the
+/// connection without sending any. The connection is dropped along with `stream`, e.g. when
+/// the message is larger than `max_frame_bytes`.
+fn read_message(
+    mut stream: TcpStream,
+    tls: Option<&TlsConfig>,
+    max_frame_bytes: usize,
+) -> io::Result<Option<Message>> {
+    match tls {
+        Some(tls) => read_frame_limited(&mut tls.accept(stream)?, max_frame_bytes),
+        None => read_frame_limited(&mut stream, max_frame_bytes),
+    }
+}
+
+fn listen_for_clients(
+    event_queue: &EventQueue,
+    listener: &TcpListener,
+    stopped: &AtomicBool,
+    tls: Option<&TlsConfig>,
+    max_frame_bytes: usize,
+) -> io::Result<()> {
+    loop {
+        let (stream, client) = listener.accept()?;
+        if stopped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        trace!("Client connected: {}", client);
+        match read_message(stream, tls, max_frame_bytes) {
+            Ok(Some(recv_msg)) => {
+                let system_id: String = recv_msg.get_systemId().into();
+                let message = EventData::External(system_id, recv_msg);
+                event_queue.push(message);
+            }
+            Ok(None) => trace!("{} sent no message", client),
+            Err(e) => {
+                error!("Dropping the message of {}: {}", client, e);
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::write_frame;
+    use crate::testing::{node, node_info, wait_until, Recorder};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn the_listen_address_overrides_the_configured_one() {
+        // the configured port is taken, so binding to it would fail.
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut current = node(1);
+        current.port = taken.local_addr().unwrap().port();
+        let node_info = node_info(1, &[current]);
+        let listen_address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let listener = bind_listener(&node_info, Some(listen_address)).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), listen_address);
+        assert!(bind_listener(&node_info, None).is_err());
+    }
+
+    #[test]
+    fn a_connection_announcing_a_huge_message_is_dropped() {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let listening = {
+            let (event_queue, stopped) = (event_queue.clone(), stopped.clone());
+            thread::spawn(move || listen_for_clients(&event_queue, &listener, &stopped, None, 1024))
+        };
+
+        // the listener closes the connection rather than waiting for the 2 GiB announced.
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(&i32::MAX.to_be_bytes()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // a timeout would mean the connection was kept open.
+        match stream.read(&mut [0; 1]) {
+            Ok(read) => assert_eq!(read, 0),
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+        }
+
+        // it keeps on listening to the next ones.
+        let mut message = Message::new();
+        message.set_systemId("sys-1".to_owned());
+        let mut stream = TcpStream::connect(address).unwrap();
+        write_frame(&mut stream, &message).unwrap();
+        drop(stream);
+        wait_until(|| !recorder.events().is_empty());
+        assert_eq!(recorder.events().len(), 1);
+
+        stopped.store(true, Ordering::SeqCst);
+        TcpStream::connect(address).unwrap();
+        listening.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use crate::storage::LogStorage;
+use log::{error, warn};
+use serde::Serialize;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+const WEBHOOK_ATTEMPTS: u32 = 5;
+/// How many decisions may wait for the webhook before the next ones are dropped.
+const WEBHOOK_QUEUE: usize = 64;
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_millis(200);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A decision as exported to the external sinks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Decision {
+    pub system_id: String,
+    pub value: serde_json::Value,
+}
+
+/// An external system every decision of the application is pushed to.
+pub trait DecisionSink: Send {
+    fn publish(&mut self, decision: &Decision) -> io::Result<()>;
+}
+
+/// Appends every decision to a file, one JSON object per line.
+pub struct FileSink {
+    log: LogStorage,
+}
+
+impl FileSink {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        FileSink {
+            log: LogStorage::new(path),
+        }
+    }
+}
+
+impl DecisionSink for FileSink {
+    fn publish(&mut self, decision: &Decision) -> io::Result<()> {
+        self.log.append(decision)
+    }
+}
+
+/// POSTs every decision as JSON to a plain `http://` URL. Deliveries happen one after the
+/// other on a worker thread and are retried a few times, so a slow or unavailable endpoint
+/// never holds up the consensus. Once `WEBHOOK_QUEUE` decisions wait for it, the next ones
+/// are dropped rather than queued.
+pub struct WebhookSink {
+    host: String,
+    path: String,
+    queue: SyncSender<Vec<u8>>,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> io::Result<Self> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Only http:// webhooks are supported: {}", url),
+            )
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Missing webhook host: {}", url),
+            ));
+        }
+        let address = if authority.contains(':') {
+            authority.to_owned()
+        } else {
+            format!("{}:80", authority)
+        };
+
+        // the worker stops once the sink, and with it the sending end of the queue, is dropped.
+        let (queue, requests) = mpsc::sync_channel(WEBHOOK_QUEUE);
+        thread::Builder::new()
+            .name("decision-webhook".to_owned())
+            .spawn(move || WebhookSink::deliver_all(&address, requests))?;
+
+        Ok(WebhookSink {
+            host: authority.to_owned(),
+            path: path.to_owned(),
+            queue,
+        })
+    }
+
+    fn deliver_all(address: &str, requests: Receiver<Vec<u8>>) {
+        for request in requests {
+            let delivered = (1..=WEBHOOK_ATTEMPTS).any(|attempt| {
+                match WebhookSink::post(address, &request) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Webhook delivery attempt {} failed: {}", attempt, e);
+                        thread::sleep(WEBHOOK_RETRY_DELAY * attempt);
+                        false
+                    }
+                }
+            });
+            if !delivered {
+                error!("Giving up on delivering a decision to {}", address);
+            }
+        }
+    }
+
+    fn post(address: &str, request: &[u8]) -> io::Result<()> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.write_all(request)?;
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response)?;
+        let status = response.split_whitespace().nth(1).unwrap_or_default();
+        if status.starts_with('2') {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "Webhook answered with status {:?}",
+                status
+            )))
+        }
+    }
+}
+
+impl DecisionSink for WebhookSink {
+    fn publish(&mut self, decision: &Decision) -> io::Result<()> {
+        let body = serde_json::to_string(decision)?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        );
+
+        match self.queue.try_send(request.into_bytes()) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!(
+                    "The webhook is {} decisions behind, dropping the decision of {}",
+                    WEBHOOK_QUEUE, decision.system_id
+                ),
+            )),
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "The webhook worker is gone.",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::state_dir;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn decision(system_id: &str, value: i32) -> Decision {
+        Decision {
+            system_id: system_id.to_owned(),
+            value: serde_json::json!(value),
+        }
+    }
+
+    /// Reads a single HTTP request off `stream`, returning its body.
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "The request ended early.");
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length: usize = text[..end]
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .and_then(|length| length.trim().parse().ok())
+                    .unwrap_or_default();
+                if text.len() >= end + 4 + length {
+                    return text[end + 4..end + 4 + length].to_owned();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_file_sink_appends_every_decision() {
+        let path = state_dir("sink-file").join("decisions.log");
+        let mut sink = FileSink::new(&path);
+        sink.publish(&decision("sys-1", 4)).unwrap();
+        sink.publish(&decision("sys-2", 2)).unwrap();
+
+        let appended: Vec<serde_json::Value> = LogStorage::new(&path).read_all().unwrap();
+        assert_eq!(
+            appended,
+            vec![
+                serde_json::json!({"system_id": "sys-1", "value": 4}),
+                serde_json::json!({"system_id": "sys-2", "value": 2}),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_webhook_sink_posts_the_decision() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (bodies, received) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let body = read_request(&mut stream);
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            bodies.send(body).unwrap();
+        });
+
+        let mut sink = WebhookSink::new(&format!("http://{}/decisions", address)).unwrap();
+        sink.publish(&decision("sys-1", 42)).unwrap();
+
+        let body = received.recv_timeout(Duration::from_secs(10)).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, serde_json::json!({"system_id": "sys-1", "value": 42}));
+    }
+
+    #[test]
+    fn the_decisions_a_stuck_webhook_cannot_keep_up_with_are_dropped() {
+        // the connections are never accepted, let alone answered.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut sink = WebhookSink::new(&format!("http://{}/decisions", address)).unwrap();
+
+        // the worker holds on to at most one of them, the others fill the queue.
+        let published: Vec<_> = (0..WEBHOOK_QUEUE + 2)
+            .map(|value| sink.publish(&decision("sys-1", value as i32)))
+            .collect();
+        let dropped = published.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(dropped.kind(), io::ErrorKind::WouldBlock);
+    }
+}
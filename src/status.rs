@@ -0,0 +1,34 @@
+use crate::node::Node;
+use std::sync::{Arc, Mutex};
+
+/// Live view of the failure and leader detectors of a system, updated by the handlers
+/// so that other threads can poll it.
+#[derive(Debug, Clone, Default)]
+pub struct SystemStatus {
+    leader: Arc<Mutex<Option<Node>>>,
+    suspected: Arc<Mutex<Vec<Node>>>,
+}
+
+impl SystemStatus {
+    pub fn new() -> Self {
+        SystemStatus::default()
+    }
+
+    /// The leader the eventual leader detector currently trusts.
+    pub fn current_leader(&self) -> Option<Node> {
+        self.leader.lock().unwrap().clone()
+    }
+
+    /// The processes the failure detector currently suspects.
+    pub fn suspected(&self) -> Vec<Node> {
+        self.suspected.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_leader(&self, leader: &Node) {
+        self.leader.lock().unwrap().replace(leader.clone());
+    }
+
+    pub(crate) fn set_suspected(&self, suspected: &[Node]) {
+        *self.suspected.lock().unwrap() = suspected.to_vec();
+    }
+}
@@ -0,0 +1,199 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Durable storage for a single value, used by the abstractions that must survive a crash.
+pub trait Storage<T>: Send + Sync {
+    fn write(&self, value: &T) -> io::Result<()>;
+
+    /// Fails with `io::ErrorKind::NotFound` if nothing has been written yet.
+    fn read(&self) -> io::Result<T>;
+}
+
+/// Stores the value as JSON inside of a file. Writes go to a sibling `.tmp` file which is
+/// then renamed over the target, so a crash never leaves a torn file behind.
+pub struct LocalStorage {
+    path: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        LocalStorage {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".tmp");
+        self.path.with_file_name(file_name)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Storage<T> for LocalStorage {
+    fn write(&self, value: &T) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string(value)?;
+        let temp_path = self.temp_path();
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &self.path)
+    }
+
+    fn read(&self) -> io::Result<T> {
+        let mut file = fs::File::open(&self.path)?;
+        let mut contents = String::new();
+        let _ = file.read_to_string(&mut contents)?;
+        let value = serde_json::from_str(&contents)?;
+        Ok(value)
+    }
+}
+
+/// Keeps the value as JSON in memory. Clones share the same value, so it survives handlers
+/// being recreated within the same process, but not the process itself.
+pub struct MemoryStorage<T> {
+    contents: Arc<Mutex<Option<String>>>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> MemoryStorage<T> {
+    pub fn new() -> Self {
+        MemoryStorage {
+            contents: Arc::new(Mutex::new(None)),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for MemoryStorage<T> {
+    fn default() -> Self {
+        MemoryStorage::new()
+    }
+}
+
+impl<T> Clone for MemoryStorage<T> {
+    fn clone(&self) -> Self {
+        MemoryStorage {
+            contents: Arc::clone(&self.contents),
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Storage<T> for MemoryStorage<T> {
+    fn write(&self, value: &T) -> io::Result<()> {
+        let contents = serde_json::to_string(value)?;
+        self.contents.lock().unwrap().replace(contents);
+        Ok(())
+    }
+
+    fn read(&self) -> io::Result<T> {
+        let contents = self.contents.lock().unwrap();
+        match contents.as_ref() {
+            Some(contents) => Ok(serde_json::from_str(contents)?),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "nothing has been written yet",
+            )),
+        }
+    }
+}
+
+/// Append-only storage keeping one JSON encoded entry per line of a file.
+pub struct LogStorage {
+    path: PathBuf,
+}
+
+impl LogStorage {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        LogStorage {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn append<T: Serialize>(&self, entry: &T) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// All the entries appended so far, oldest first. A torn last line, left behind by a
+    /// crash in the middle of an append, is skipped.
+    pub fn read_all<T: DeserializeOwned>(&self) -> io::Result<Vec<T>> {
+        let mut contents = String::new();
+        match fs::File::open(&self.path) {
+            Ok(mut file) => {
+                let _ = file.read_to_string(&mut contents)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        }
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ep::EpochConsensusState;
+    use crate::testing::state_dir;
+
+    #[test]
+    fn the_memory_storage_returns_what_was_written_last() {
+        let storage = MemoryStorage::new();
+        storage.write(&"first".to_owned()).unwrap();
+        storage.write(&"second".to_owned()).unwrap();
+        assert_eq!(storage.read().unwrap(), "second");
+
+        let storage = MemoryStorage::new();
+        let state = EpochConsensusState::new(4, Some(2));
+        storage.write(&state).unwrap();
+        assert_eq!(storage.read().unwrap(), state);
+    }
+
+    #[test]
+    fn the_memory_storage_is_shared_by_its_clones() {
+        let storage = MemoryStorage::<i32>::new();
+        let error = storage.read().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        storage.clone().write(&3).unwrap();
+        assert_eq!(storage.read().unwrap(), 3);
+    }
+
+    #[test]
+    fn a_torn_temp_file_leaves_the_committed_value_readable() {
+        let path = state_dir("storage-torn").join("state.json");
+        let storage = LocalStorage::new(&path);
+        storage.write(&7).unwrap();
+
+        // a crash in the middle of the next write leaves the temp file half written.
+        fs::write(storage.temp_path(), b"{\"tor").unwrap();
+
+        let value: i32 = storage.read().unwrap();
+        assert_eq!(value, 7);
+
+        storage.write(&8).unwrap();
+        let value: i32 = storage.read().unwrap();
+        assert_eq!(value, 8);
+    }
+}
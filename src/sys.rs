@@ -1,62 +1,244 @@
 use crate::beb::BestEffortBroadcast;
-use crate::ec::EpochChange;
+use crate::clock::{Clock, SystemClock};
+use crate::ec::{EpochChange, EpochChangeStorage};
 use crate::eld::EventualLeaderDetector;
-use crate::ep::{EpochConsensus, EpochConsensusState};
+use crate::ep::{EpochConsensus, EpochConsensusState, StateStorage};
 use crate::epfd::EvenutallyPerfectFailureDetector;
-use crate::event::EventQueue;
-use crate::event::ValueType;
+use crate::event::{EventData, EventQueue, HandlerId, InternalMessage};
+use crate::history::LeadershipHistory;
+use crate::hint::LeaderHint;
+use crate::metadata::HeartbeatMetadata;
 use crate::node::NodeInfo;
+use crate::status::SystemStatus;
+use crate::storage::LocalStorage;
 use crate::uc::UniformConsensus;
-use std::sync::Arc;
+use crate::value::ConsensusValue;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The file backed storage used for the epoch consensus state of `system_id`.
+pub fn local_state_storage<V: ConsensusValue>(state_dir: &Path, system_id: &str) -> StateStorage<V> {
+    Arc::new(LocalStorage::new(storage_path(state_dir, system_id, "ep")))
+}
+
+/// The file backed storage used for the epoch change timestamps of `system_id`.
+pub fn local_epoch_change_storage(state_dir: &Path, system_id: &str) -> EpochChangeStorage {
+    Arc::new(LocalStorage::new(storage_path(state_dir, system_id, "ec")))
+}
+
+/// Removes the files the state of `system_id` is persisted to, once it has nothing left to
+/// recover. The files which were never written are not an error.
+pub fn remove_local_storage(state_dir: &Path, system_id: &str) -> io::Result<()> {
+    for abstraction in &["ep", "ec"] {
+        match fs::remove_file(storage_path(state_dir, system_id, abstraction)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn storage_path(state_dir: &Path, system_id: &str, abstraction: &str) -> PathBuf {
+    state_dir.join(format!("{}.{}.json", system_id.replace('/', "-"), abstraction))
+}
+
+/// The views of the running systems of a process, by system id.
+pub type SystemViews = Arc<Mutex<HashMap<String, SystemView>>>;
+
+/// What other threads can read of a running system, without going through the event queue.
+#[derive(Debug, Clone)]
+pub struct SystemView {
+    pub leader_hint: LeaderHint,
+    pub leadership_history: LeadershipHistory,
+}
 
 pub struct System {
     pub system_id: String,
+    pub leader_hint: LeaderHint,
+    pub leadership_history: LeadershipHistory,
+    pub metadata: HeartbeatMetadata,
+    pub status: SystemStatus,
+    /// What the process knows of the members of the system, as of its last reconfiguration.
+    pub node_info: Arc<NodeInfo>,
+    /// The handlers of the abstractions, but for the epoch consensus instance.
+    handler_ids: Vec<HandlerId>,
+    /// The running epoch consensus instance, replaced by uniform consensus at every epoch.
+    epoch_handler: Arc<Mutex<Option<HandlerId>>>,
 }
 
 impl System {
-    pub fn new(
+    pub fn new<V: ConsensusValue>(
         system_id: String,
         node_info: Arc<NodeInfo>,
         event_queue: Arc<EventQueue>,
-        _: ValueType,
+        storage: StateStorage<V>,
+        ec_storage: EpochChangeStorage,
     ) -> Self {
+        let status = SystemStatus::new();
+        let leader_hint = LeaderHint::new();
+        let metadata = HeartbeatMetadata::with_leader_hint(leader_hint.clone());
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock::new());
         let mut epfd = EvenutallyPerfectFailureDetector::new(
             node_info.clone(),
             event_queue.clone(),
             system_id.clone(),
+            metadata.clone(),
+            status.clone(),
+            clock.clone(),
+        );
+        let leadership_history = LeadershipHistory::new();
+        let mut eld = EventualLeaderDetector::new(
+            node_info.clone(),
+            event_queue.clone(),
+            system_id.clone(),
+            leadership_history.clone(),
+            status.clone(),
         );
-        let mut eld =
-            EventualLeaderDetector::new(node_info.clone(), event_queue.clone(), system_id.clone());
         let beb =
             BestEffortBroadcast::new(node_info.clone(), event_queue.clone(), system_id.clone());
-        let ec = EpochChange::new(node_info.clone(), event_queue.clone(), system_id.clone());
-        let ep = EpochConsensus::new(
+        let ec = EpochChange::new(
             node_info.clone(),
             event_queue.clone(),
-            EpochConsensusState::new(0, 0),
-            ec.trusted.clone(),
-            0,
             system_id.clone(),
-            0,
+            ec_storage,
         );
-        let uc = UniformConsensus::new(
-            event_queue.clone(),
+        let ep = EpochConsensus::<V>::new(
             node_info.clone(),
+            event_queue.clone(),
+            EpochConsensusState::new(0, None),
+            storage.clone(),
             ec.trusted.clone(),
+            0,
             system_id.clone(),
         );
 
         epfd.init();
         eld.init();
+
+        let initial_leader = ec.trusted.clone();
+        leader_hint.update(&initial_leader, 0);
+        status.set_leader(&initial_leader);
+        let mut handler_ids = vec![
+            event_queue.register_handler(Box::new(epfd)),
+            event_queue.register_handler(Box::new(eld)),
+            event_queue.register_handler(Box::new(beb)),
+            event_queue.register_handler(Box::new(ec)),
+        ];
+        let epoch_id = event_queue.register_handler_with(|id| Box::new(ep.with_handler_id(id)));
+        let epoch_handler = Arc::new(Mutex::new(Some(epoch_id)));
+
+        let uc = UniformConsensus::<V>::new(
+            event_queue.clone(),
+            node_info.clone(),
+            initial_leader,
+            system_id.clone(),
+            leader_hint.clone(),
+            storage,
+            clock,
+        )
+        .with_epoch_handler(epoch_handler.clone());
         uc.init();
+        handler_ids.push(event_queue.register_handler(Box::new(uc)));
+
+        System {
+            system_id,
+            leader_hint,
+            leadership_history,
+            metadata,
+            status,
+            node_info,
+            handler_ids,
+            epoch_handler,
+        }
+    }
+
+    pub fn view(&self) -> SystemView {
+        SystemView {
+            leader_hint: self.leader_hint.clone(),
+            leadership_history: self.leadership_history.clone(),
+        }
+    }
+
+    /// Deregisters the handlers of every abstraction of the system, which then stops taking
+    /// part in consensus, and signals `SystemClosed` to the handlers shared by the systems,
+    /// e.g. the perfect link. Closing a closed system has no further effect.
+    pub fn close(&self, event_queue: &EventQueue) {
+        for &id in &self.handler_ids {
+            event_queue.deregister_handler(id);
+        }
+        if let Some(id) = self.epoch_handler.lock().unwrap().take() {
+            event_queue.deregister_handler(id);
+        }
+        let closed = EventData::Internal(self.system_id.clone(), InternalMessage::SystemClosed);
+        event_queue.push(closed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{EventData, InternalMessage, ValueType};
+    use crate::storage::MemoryStorage;
+    use crate::event::EventHandler;
+    use crate::testing::{node, node_info, wait_until};
+
+    #[test]
+    fn the_aborted_epochs_leave_no_handler_behind() {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let nodes = vec![node(1)];
+        let system = System::new::<ValueType>(
+            "sys-1".to_owned(),
+            Arc::new(node_info(1, &nodes)),
+            event_queue.clone(),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(MemoryStorage::new()),
+        );
+        let handler_count = event_queue.handler_count();
 
-        event_queue.register_handler(Box::new(epfd));
-        event_queue.register_handler(Box::new(eld));
-        event_queue.register_handler(Box::new(beb));
-        event_queue.register_handler(Box::new(ec));
-        event_queue.register_handler(Box::new(ep));
-        event_queue.register_handler(Box::new(uc));
+        for ts in 1..=5 {
+            let start_epoch = InternalMessage::EcStartEpoch(node(1), ts);
+            event_queue.push(EventData::Internal("sys-1".to_owned(), start_epoch));
+            wait_until(|| system.leader_hint.epoch_ts() == Some(ts));
+        }
+        // the aborted instances deregister themselves while handling their abort.
+        wait_until(|| event_queue.handler_count() == handler_count);
+    }
+
+    #[test]
+    fn the_initial_leader_is_the_one_leader_election_picks() {
+        // listed out of order, node 2 wins the tie on rank against node 1.
+        let mut nodes = vec![node(3), node(1), node(2)];
+        nodes[0].rank = 1;
+        nodes[1].rank = 7;
+        nodes[2].rank = 7;
+        let node_info = Arc::new(node_info(3, &nodes));
+        let system = System::new::<ValueType>(
+            "sys-1".to_owned(),
+            node_info.clone(),
+            Arc::new(EventQueue::new_inert()),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(MemoryStorage::new()),
+        );
+
+        let status = SystemStatus::new();
+        let mut eld = EventualLeaderDetector::new(
+            node_info,
+            Arc::new(EventQueue::new_inert()),
+            "sys-1".to_owned(),
+            LeadershipHistory::new(),
+            status.clone(),
+        );
+        // the leader detector only picks a leader once something changes.
+        eld.handle(&EventData::Internal(
+            "sys-1".to_owned(),
+            InternalMessage::EpfdSuspect(node(3)),
+        ));
 
-        System { system_id }
+        assert_eq!(status.current_leader(), Some(node(2)));
+        assert_eq!(system.status.current_leader(), status.current_leader());
+        assert_eq!(system.leader_hint.leader(), status.current_leader());
     }
 }
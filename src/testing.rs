@@ -0,0 +1,86 @@
+//! Helpers shared by the unit tests of the abstractions.
+use crate::event::{EventData, EventHandler, EventQueue, InternalMessage};
+use crate::node::{Node, NodeInfo};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The node `id`, of rank `id`, listening on a port of its own.
+pub fn node(id: u16) -> Node {
+    let name = format!("node{}", id);
+    Node::new("test".to_owned(), name, "127.0.0.1".to_owned(), 5000 + id, id, id)
+}
+
+/// The hub coordinating the systems of the tests.
+pub fn hub() -> Node {
+    node(100)
+}
+
+/// The view of the member `current` of a system made of `nodes`, coordinated by `hub()`.
+pub fn node_info(current: u16, nodes: &[Node]) -> NodeInfo {
+    let current_node = nodes
+        .iter()
+        .find(|node| node.id == current)
+        .cloned()
+        .expect("The current node must be one of the nodes.");
+    NodeInfo::new(current_node, Some(hub()), nodes.to_vec())
+}
+
+/// An empty directory for the persisted state of the test `name`, the state of a previous
+/// run must not leak into this one.
+pub fn state_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join("distributed_consensus_unit_tests")
+        .join(format!("{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Waits for `condition` to hold, for at most 10 seconds.
+pub fn wait_until<F: Fn() -> bool>(condition: F) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(
+            Instant::now() < deadline,
+            "Timed out waiting for the condition."
+        );
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// The internal events pushed to the inert `event_queue` since it was last drained.
+pub fn pushed(event_queue: &EventQueue) -> Vec<InternalMessage> {
+    let recorder = Recorder::default();
+    event_queue.drain_to(&mut recorder.clone());
+    recorder
+        .events()
+        .into_iter()
+        .filter_map(|event_data| match event_data {
+            EventData::Internal(_, event) => Some(event),
+            EventData::External(..) => None,
+        })
+        .collect()
+}
+
+/// Records every event it is handed, the recorded events being shared by its clones.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    events: Arc<Mutex<Vec<EventData>>>,
+}
+
+impl Recorder {
+    pub fn events(&self) -> Vec<EventData> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl EventHandler for Recorder {
+    fn should_handle_event(&self, _: &EventData) -> bool {
+        true
+    }
+
+    fn handle(&mut self, event_data: &EventData) {
+        self.events.lock().unwrap().push(event_data.clone());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::event::*;
+use crate::node::{Node, NodeInfo};
+use crate::protos::message::{Message, Message_Type};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Delivered,
+}
+
+/// A message that was sent or delivered by the local process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u128,
+    pub direction: Direction,
+    pub system_id: String,
+    pub abstraction_id: String,
+    pub message_type: Message_Type,
+    /// The receiver of a sent message, or the sender of a delivered one.
+    pub peer: Node,
+}
+
+/// The ordered flow of messages sent and delivered by the local process, so that a single
+/// consensus run can be followed from propose to decide. Only the most recent entries,
+/// across all the systems, are kept.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<TimelineEntry>>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Timeline::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Timeline {
+            capacity,
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    fn record(&self, direction: Direction, system_id: &str, peer: &Node, msg: &Message) {
+        // broadcasts travel wrapped, the timeline shows what is actually being broadcast.
+        let msg = if msg.has_bebBroadcast() {
+            msg.get_bebBroadcast().get_message()
+        } else {
+            msg
+        };
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let entry = TimelineEntry {
+            timestamp_ms,
+            direction,
+            system_id: system_id.to_owned(),
+            abstraction_id: msg.get_abstractionId().to_owned(),
+            message_type: msg.get_field_type(),
+            peer: peer.clone(),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if self.capacity == 0 {
+            return;
+        }
+        while entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The messages of `system_id`, in the order they were sent or delivered.
+    pub fn timeline(&self, system_id: &str) -> Vec<TimelineEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.system_id == system_id)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Records the link level traffic, together with the broadcasts delivered locally
+/// without going through the link, into a `Timeline`.
+pub struct TimelineRecorder {
+    node_info: Arc<NodeInfo>,
+    timeline: Timeline,
+}
+
+impl TimelineRecorder {
+    pub fn new(node_info: Arc<NodeInfo>, timeline: Timeline) -> Self {
+        TimelineRecorder {
+            node_info,
+            timeline,
+        }
+    }
+}
+
+impl EventHandler for TimelineRecorder {
+    fn should_handle_event(&self, event_data: &EventData) -> bool {
+        matches!(
+            event_data,
+            EventData::Internal(_, InternalMessage::PlSend(..))
+                | EventData::Internal(_, InternalMessage::PlDeliver(..))
+                | EventData::Internal(_, InternalMessage::BebDeliver(..))
+        )
+    }
+
+    fn handle(&mut self, event_data: &EventData) {
+        if let EventData::Internal(system_id, msg) = event_data {
+            match msg {
+                InternalMessage::PlSend(_, to, msg) => {
+                    self.timeline.record(Direction::Sent, system_id, to, msg)
+                }
+                InternalMessage::PlDeliver(from, msg) => {
+                    self.timeline
+                        .record(Direction::Delivered, system_id, from, msg)
+                }
+                InternalMessage::BebDeliver(from, msg) if from == &self.node_info.current_node => {
+                    self.timeline
+                        .record(Direction::Delivered, system_id, from, msg)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::sys::System;
+    use crate::testing::{node, node_info, wait_until, Recorder};
+    use crate::value::ConsensusValue;
+
+    /// Hands the messages a process sends to itself straight back to it, in place of a link.
+    struct Loopback(Arc<EventQueue>);
+
+    impl EventHandler for Loopback {
+        fn should_handle_event(&self, event_data: &EventData) -> bool {
+            matches!(
+                event_data,
+                EventData::Internal(_, InternalMessage::PlSend(from, to, _)) if from == to
+            )
+        }
+
+        fn handle(&mut self, event_data: &EventData) {
+            if let EventData::Internal(system_id, InternalMessage::PlSend(from, _, msg)) =
+                event_data
+            {
+                let deliver = InternalMessage::PlDeliver(from.clone(), msg.clone());
+                self.0.push(EventData::Internal(system_id.clone(), deliver));
+            }
+        }
+    }
+
+    #[test]
+    fn the_timeline_follows_a_run_from_read_to_decided() {
+        let node_info = Arc::new(node_info(1, &[node(1)]));
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let timeline = Timeline::new();
+        let recorder = Recorder::default();
+        let _system = System::new::<ValueType>(
+            "sys-1".to_owned(),
+            node_info.clone(),
+            event_queue.clone(),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(MemoryStorage::new()),
+        );
+        let timeline_recorder = TimelineRecorder::new(node_info, timeline.clone());
+        event_queue.register_handler(Box::new(timeline_recorder));
+        event_queue.register_handler(Box::new(Loopback(event_queue.clone())));
+        event_queue.register_handler(Box::new(recorder.clone()));
+
+        let proposal = InternalMessage::UcPropose(42.to_value());
+        event_queue.push(EventData::Internal("sys-1".to_owned(), proposal));
+        wait_until(|| {
+            recorder.events().iter().any(|event_data| {
+                matches!(event_data, EventData::Internal(_, InternalMessage::UcDecide(_)))
+            })
+        });
+
+        let mut flow: Vec<Message_Type> = Vec::new();
+        for entry in timeline.timeline("sys-1") {
+            if entry.abstraction_id.starts_with("ep") && !flow.contains(&entry.message_type) {
+                flow.push(entry.message_type);
+            }
+        }
+        assert_eq!(
+            flow,
+            vec![
+                Message_Type::EP_READ_,
+                Message_Type::EP_STATE_,
+                Message_Type::EP_WRITE_,
+                Message_Type::EP_ACCEPT_,
+                Message_Type::EP_DECIDED_,
+            ]
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use crate::node::Node;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use std::convert::TryFrom;
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Mutually authenticated TLS between the processes: every process presents its own certificate,
+/// both when connecting and when accepted, and only accepts the certificates signed by the CA.
+/// A process is reached at its host, which its certificate has to be issued for.
+#[derive(Clone)]
+pub struct TlsConfig {
+    client: Arc<ClientConfig>,
+    server: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// `cert` and `key` are the PEM files of the current process, `ca` the PEM file of the
+    /// authority the certificates of all the processes are signed by.
+    pub fn from_pem_files<P: AsRef<Path>>(cert: P, key: P, ca: P) -> io::Result<Self> {
+        let chain = CertificateDer::pem_file_iter(&cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| pem_error(cert.as_ref(), e))?;
+        let key = PrivateKeyDer::from_pem_file(&key).map_err(|e| pem_error(key.as_ref(), e))?;
+        let mut roots = RootCertStore::empty();
+        for ca_cert in CertificateDer::pem_file_iter(&ca).map_err(|e| pem_error(ca.as_ref(), e))? {
+            let ca_cert = ca_cert.map_err(|e| pem_error(ca.as_ref(), e))?;
+            roots.add(ca_cert).map_err(tls_error)?;
+        }
+        let roots = Arc::new(roots);
+
+        let verifier = WebPkiClientVerifier::builder(roots.clone())
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut server = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(chain.clone(), key.clone_key())
+            .map_err(tls_error)?;
+        // a connection carries a single message, the client closes it without reading any
+        // ticket, which would reset the connection before the message is read.
+        server.send_tls13_tickets = 0;
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(chain, key)
+            .map_err(tls_error)?;
+
+        Ok(TlsConfig {
+            client: Arc::new(client),
+            server: Arc::new(server),
+        })
+    }
+
+    /// Secures the connection made to `to`. The handshake happens along with the first write.
+    pub fn connect(
+        &self,
+        to: &Node,
+        stream: TcpStream,
+    ) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let server_name = ServerName::try_from(to.host.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = ClientConnection::new(self.client.clone(), server_name).map_err(tls_error)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+
+    /// Secures a connection accepted by the listener. The handshake happens along with the first read.
+    pub fn accept(&self, stream: TcpStream) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+        let connection = ServerConnection::new(self.server.clone()).map_err(tls_error)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+fn pem_error(path: &Path, e: rustls::pki_types::pem::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unable to read {}: {}", path.display(), e),
+    )
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
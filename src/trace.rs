@@ -0,0 +1,135 @@
+use crate::event::EventData;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A line of a trace file: an event as it was dispatched to the handlers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Starts at 0 and grows by one with every dispatched event.
+    pub sequence: u64,
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+    pub event: EventData,
+}
+
+/// Writes the events dispatched by an event queue to a JSONL file, one `TraceEntry` per line,
+/// see `EventQueue::trace_to`.
+pub struct Tracer {
+    file: LineWriter<File>,
+    sequence: u64,
+}
+
+impl Tracer {
+    /// Truncates the file at `path` if there is one already.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Tracer {
+            file: LineWriter::new(File::create(path)?),
+            sequence: 0,
+        })
+    }
+
+    pub fn record(&mut self, event_data: &EventData) -> io::Result<()> {
+        let entry = TraceEntry {
+            sequence: self.sequence,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            event: event_data.clone(),
+        };
+        let line = serde_json::to_string(&entry)?;
+        self.sequence += 1;
+        writeln!(self.file, "{}", line)
+    }
+}
+
+/// The events recorded in the trace file at `path`, in the order they were dispatched.
+pub fn replay<P: AsRef<Path>>(path: P) -> io::Result<Vec<EventData>> {
+    let mut events = Vec::new();
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: TraceEntry = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {} of the trace is not a valid entry: {}", index + 1, e),
+            )
+        })?;
+        events.push(entry.event);
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::InternalMessage;
+    use crate::protos::message::{Message, Message_Type};
+    use crate::testing::{node, state_dir};
+
+    #[test]
+    fn the_recorded_events_are_replayed_in_order() {
+        let path = state_dir("the_recorded_events_are_replayed_in_order").join("trace.jsonl");
+        let mut msg = Message::new();
+        msg.set_field_type(Message_Type::EP_READ_);
+        msg.set_abstractionId("ep3".to_owned());
+
+        let mut tracer = Tracer::create(&path).unwrap();
+        tracer
+            .record(&EventData::Internal("sys-1".to_owned(), InternalMessage::AppInit))
+            .unwrap();
+        tracer
+            .record(&EventData::Internal(
+                "sys-1".to_owned(),
+                InternalMessage::PlDeliver(node(2), msg.clone()),
+            ))
+            .unwrap();
+        tracer
+            .record(&EventData::External("sys-2".to_owned(), msg.clone()))
+            .unwrap();
+
+        let sequences: Vec<_> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<TraceEntry>(line).unwrap().sequence)
+            .collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+
+        let events = replay(&path).unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[0],
+            EventData::Internal(system_id, InternalMessage::AppInit) if system_id == "sys-1"
+        ));
+        match &events[1] {
+            EventData::Internal(_, InternalMessage::PlDeliver(from, replayed)) => {
+                assert_eq!(from.port, node(2).port);
+                assert_eq!(replayed, &msg);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert!(matches!(
+            &events[2],
+            EventData::External(system_id, replayed) if system_id == "sys-2" && replayed == &msg
+        ));
+    }
+
+    #[test]
+    fn a_corrupted_trace_is_rejected() {
+        let path = state_dir("a_corrupted_trace_is_rejected").join("trace.jsonl");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{\"sequence\": 0}\n").unwrap();
+
+        let error = replay(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,617 @@
+use crate::event::{EventData, EventQueue};
+use crate::frame::{read_frame, write_frame, write_payload};
+use crate::node::{Node, NodeId};
+use crate::protos::message::Message;
+use crate::tls::TlsConfig;
+use log::trace;
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Carries the encoded messages of the perfect link to the other processes.
+pub trait Transport: Send + Sync {
+    fn send(&self, to: &Node, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// Frames `message` the way the hub expects it, see `frame::write_frame`.
+pub fn encode(message: &Message) -> io::Result<Vec<u8>> {
+    let mut framed = Vec::new();
+    write_frame(&mut framed, message)?;
+    Ok(framed)
+}
+
+/// The version of the frames the processes exchange, frames of any other version are dropped.
+/// It has to change along with the messages, so that processes running different versions
+/// can tell they can't understand each other.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Follows the length of a frame of the processes, which the hub frames don't have. A serialized
+/// message never starts with a zero byte, which would be the tag of the field 0, so frames
+/// without it are told apart.
+const HEADER: u8 = 0;
+
+/// The flag following the protocol version, telling how the message was encoded.
+const RAW: u8 = 0;
+const COMPRESSED: u8 = 1;
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Frames `message` for another process: its length, as a big endian `i32`, followed by the
+/// `HEADER`, the `PROTOCOL_VERSION`, a flag and the message. Messages taking at least
+/// `compression_threshold` bytes are compressed. Only `decode` understands these: the hub doesn't.
+pub fn encode_versioned(message: &Message, compression_threshold: Option<usize>) -> io::Result<Vec<u8>> {
+    let bytes = protobuf::Message::write_to_bytes(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let (flag, bytes) = match compression_threshold {
+        Some(threshold) if bytes.len() >= threshold => {
+            (COMPRESSED, zstd::bulk::compress(&bytes, COMPRESSION_LEVEL)?)
+        }
+        _ => (RAW, bytes),
+    };
+    let mut payload = vec![HEADER, PROTOCOL_VERSION, flag];
+    payload.extend(bytes);
+    let mut framed = Vec::new();
+    write_payload(&mut framed, &payload)?;
+    Ok(framed)
+}
+
+/// The message framed by `encode` or by `encode_versioned`.
+pub fn decode(mut bytes: &[u8]) -> io::Result<Message> {
+    read_frame(&mut bytes)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The message is missing its length.",
+        )
+    })
+}
+
+/// The message following the length of a frame, with or without a version. A compressed message
+/// is rejected once it decompresses to more than `max_bytes`, the rest of it is not decompressed.
+pub(crate) fn decode_payload(bytes: &[u8], max_bytes: usize) -> io::Result<Message> {
+    let decompressed;
+    let bytes = match bytes {
+        [HEADER, PROTOCOL_VERSION, RAW, message @ ..] => message,
+        [HEADER, PROTOCOL_VERSION, COMPRESSED, compressed @ ..] => {
+            decompressed = decompress(compressed, max_bytes)?;
+            &decompressed[..]
+        }
+        [HEADER, PROTOCOL_VERSION, flag, ..] => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown encoding {} of the message.", flag),
+            ))
+        }
+        [HEADER, version, ..] => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The message is of version {} of the protocol, only version {} is understood.",
+                    version, PROTOCOL_VERSION
+                ),
+            ))
+        }
+        [HEADER] => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The message is missing its protocol version.",
+            ))
+        }
+        message => message,
+    };
+    let mut message = Message::new();
+    protobuf::Message::merge_from_bytes(&mut message, bytes)
+        .and_then(|()| protobuf::Message::check_initialized(&message))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(message)
+}
+
+fn decompress(compressed: &[u8], max_bytes: usize) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    zstd::stream::read::Decoder::new(compressed)?
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > max_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The message decompresses to more than the {} bytes allowed.", max_bytes),
+        ));
+    }
+    Ok(decompressed)
+}
+
+/// How long connecting to a process, and then writing a message to it, may take by default.
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Looks up the addresses a process listens on.
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, node: &Node) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Looks the host of the process up again for every message, so that a process which moved
+/// to another address behind the same name is still reached.
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, node: &Node) -> io::Result<Vec<SocketAddr>> {
+        Ok((node.host.as_str(), node.port).to_socket_addrs()?.collect())
+    }
+}
+
+/// Opens a connection per message to the address the receiver listens on.
+pub struct TcpTransport {
+    timeout: Duration,
+    tls: Option<TlsConfig>,
+    resolver: Arc<dyn Resolver>,
+}
+
+impl TcpTransport {
+    pub fn new() -> Self {
+        TcpTransport::default()
+    }
+
+    /// Gives up on connecting, and then on writing, after `timeout` rather than after
+    /// `DEFAULT_SEND_TIMEOUT`. Messages are sent from the event queue, an unreachable
+    /// process would otherwise stall the whole node.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends over TLS rather than in plain text, the receivers have to listen with TLS as well.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Looks the receivers up with `resolver` rather than with the system resolver.
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Connects to the first of the addresses of `to` that accepts the connection.
+    fn connect(&self, to: &Node) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The host {} of node {} has no address.", to.host, to.id),
+        );
+        for address in self.resolver.resolve(to)? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    trace!("Unable to connect to {} at {}: {}", to.id, address, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl Default for TcpTransport {
+    fn default() -> Self {
+        TcpTransport {
+            timeout: DEFAULT_SEND_TIMEOUT,
+            tls: None,
+            resolver: Arc::new(SystemResolver),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&self, to: &Node, bytes: &[u8]) -> io::Result<()> {
+        let mut stream = self.connect(to)?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        match &self.tls {
+            Some(tls) => {
+                // the handshake reads the answers of the receiver.
+                stream.set_read_timeout(Some(self.timeout))?;
+                let mut stream = tls.connect(to, stream)?;
+                stream.write_all(bytes)?;
+                stream.conn.send_close_notify();
+                stream.flush()
+            }
+            None => stream.write_all(bytes),
+        }
+    }
+}
+
+/// Hands the messages straight to the event queue of the receiver, so that several processes
+/// can run within a single one without binding any port. The queues are only weakly held,
+/// a process that is gone simply stops receiving.
+#[derive(Default)]
+pub struct InMemoryTransport {
+    queues: Mutex<HashMap<NodeId, Weak<EventQueue>>>,
+}
+
+impl InMemoryTransport {
+    pub fn new() -> Self {
+        InMemoryTransport::default()
+    }
+
+    /// Delivers the messages sent to the process `id` to `event_queue`.
+    pub fn register(&self, id: NodeId, event_queue: &Arc<EventQueue>) {
+        let mut queues = self.queues.lock().unwrap();
+        queues.insert(id, Arc::downgrade(event_queue));
+    }
+}
+
+impl Transport for InMemoryTransport {
+    fn send(&self, to: &Node, bytes: &[u8]) -> io::Result<()> {
+        let event_queue = self
+            .queues
+            .lock()
+            .unwrap()
+            .get(&to.id)
+            .and_then(|event_queue| event_queue.upgrade());
+        match event_queue {
+            Some(event_queue) => {
+                let message = decode(bytes)?;
+                let system_id = message.get_systemId().to_owned();
+                event_queue.push(EventData::External(system_id, message));
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("No process {} to deliver to.", to.id),
+            )),
+        }
+    }
+}
+
+/// Wraps another transport to lose and delay messages, and to cut processes off from one
+/// another, so that the protocol can be exercised under the failures it is meant to tolerate.
+/// The sender of a message is told from the address it carries, just like the link does.
+pub struct FaultyTransport {
+    inner: Arc<dyn Transport>,
+    nodes: Vec<Node>,
+    drop_rate: f64,
+    max_delay: Duration,
+    partitions: Mutex<Vec<(Vec<NodeId>, Vec<NodeId>)>>,
+}
+
+impl FaultyTransport {
+    /// Hands every message of the processes `nodes` over to `inner`, until configured otherwise.
+    pub fn new(inner: Arc<dyn Transport>, nodes: &[Node]) -> Self {
+        FaultyTransport {
+            inner,
+            nodes: nodes.to_vec(),
+            drop_rate: 0.0,
+            max_delay: Duration::from_secs(0),
+            partitions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Loses every message with a probability of `drop_rate`, between 0 and 1.
+    pub fn with_drop_rate(mut self, drop_rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&drop_rate),
+            "The drop rate must be between 0 and 1."
+        );
+        self.drop_rate = drop_rate;
+        self
+    }
+
+    /// Delays every message by a random duration of up to `max_delay`.
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Loses the messages between the processes of `a` and those of `b`, both ways, until healed.
+    pub fn partition(&self, a: &[NodeId], b: &[NodeId]) {
+        let mut partitions = self.partitions.lock().unwrap();
+        partitions.push((a.to_vec(), b.to_vec()));
+    }
+
+    /// Lifts every partition.
+    pub fn heal(&self) {
+        self.partitions.lock().unwrap().clear();
+    }
+
+    fn is_partitioned(&self, from: NodeId, to: NodeId) -> bool {
+        let partitions = self.partitions.lock().unwrap();
+        partitions.iter().any(|(a, b)| {
+            (a.contains(&from) && b.contains(&to)) || (b.contains(&from) && a.contains(&to))
+        })
+    }
+
+    fn sender(&self, bytes: &[u8]) -> Option<NodeId> {
+        let message = decode(bytes).ok()?;
+        let network_message = message.get_networkMessage();
+        self.nodes
+            .iter()
+            .find(|node| {
+                node.port as i32 == network_message.get_senderListeningPort()
+                    && node.host == network_message.get_senderHost()
+            })
+            .map(|node| node.id)
+    }
+}
+
+impl Transport for FaultyTransport {
+    /// A lost message is not reported, the network doesn't tell the sender either.
+    fn send(&self, to: &Node, bytes: &[u8]) -> io::Result<()> {
+        let mut rng = rand::thread_rng();
+        let partitioned = self
+            .sender(bytes)
+            .is_some_and(|from| self.is_partitioned(from, to.id));
+        if partitioned || rng.gen_bool(self.drop_rate) {
+            trace!("Losing a message to {}", to);
+            return Ok(());
+        }
+
+        let max_delay = self.max_delay.as_micros() as u64;
+        if max_delay == 0 {
+            return self.inner.send(to, bytes);
+        }
+        let delay = Duration::from_micros(rng.gen_range(0, max_delay + 1));
+        let inner = Arc::clone(&self.inner);
+        let to = to.clone();
+        let bytes = bytes.to_vec();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            if let Err(e) = inner.send(&to, &bytes) {
+                trace!("Unable to send a delayed message to {}: {}", to, e);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::message::{Message_Type, NetworkMessage};
+    use crate::testing::{node, wait_until, Recorder};
+
+    /// Records the receivers of the messages it is handed.
+    #[derive(Default)]
+    struct Receivers(Mutex<Vec<NodeId>>);
+
+    impl Receivers {
+        fn get(&self) -> Vec<NodeId> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for Receivers {
+        fn send(&self, to: &Node, _: &[u8]) -> io::Result<()> {
+            self.0.lock().unwrap().push(to.id);
+            Ok(())
+        }
+    }
+
+    /// A message sent by the process `from`, as framed by the link.
+    fn framed_from(from: NodeId) -> Vec<u8> {
+        let sender = node(from);
+        let mut network_message = NetworkMessage::new();
+        network_message.set_senderHost(sender.host.clone());
+        network_message.set_senderListeningPort(sender.port as i32);
+        let mut message = Message::new();
+        message.set_field_type(Message_Type::NETWORK_MESSAGE);
+        message.set_networkMessage(network_message);
+        encode(&message).unwrap()
+    }
+
+    fn faulty_transport(receivers: &Arc<Receivers>) -> FaultyTransport {
+        let nodes: Vec<_> = (1..=3).map(node).collect();
+        let inner: Arc<dyn Transport> = receivers.clone();
+        FaultyTransport::new(inner, &nodes)
+    }
+
+    #[test]
+    fn a_message_survives_being_encoded() {
+        let mut message = Message::new();
+        message.set_field_type(Message_Type::APP_PROPOSE);
+        message.set_systemId("sys-1".to_owned());
+
+        let decoded = decode(&encode(&message).unwrap()).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn the_in_memory_transport_delivers_to_the_registered_queue() {
+        let transport = InMemoryTransport::new();
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(recorder.clone()));
+        transport.register(2, &event_queue);
+
+        let mut message = Message::new();
+        message.set_systemId("sys-1".to_owned());
+        let bytes = encode(&message).unwrap();
+        transport.send(&node(2), &bytes).unwrap();
+        assert!(transport.send(&node(3), &bytes).is_err());
+
+        wait_until(|| recorder.events().len() == 1);
+        match &recorder.events()[0] {
+            EventData::External(system_id, received) => {
+                assert_eq!(system_id, "sys-1");
+                assert_eq!(received, &message);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_partition_cuts_both_ways_until_healed() {
+        let receivers = Arc::new(Receivers::default());
+        let transport = faulty_transport(&receivers);
+        transport.partition(&[1], &[2, 3]);
+
+        transport.send(&node(2), &framed_from(1)).unwrap();
+        transport.send(&node(1), &framed_from(3)).unwrap();
+        transport.send(&node(3), &framed_from(2)).unwrap();
+        assert_eq!(receivers.get(), vec![3]);
+
+        transport.heal();
+        transport.send(&node(2), &framed_from(1)).unwrap();
+        assert_eq!(receivers.get(), vec![3, 2]);
+    }
+
+    #[test]
+    fn messages_are_lost_at_the_drop_rate() {
+        let receivers = Arc::new(Receivers::default());
+        let transport = faulty_transport(&receivers).with_drop_rate(1.0);
+        for _ in 0..10 {
+            transport.send(&node(2), &framed_from(1)).unwrap();
+        }
+        assert!(receivers.get().is_empty());
+    }
+
+    #[test]
+    fn delayed_messages_are_sent_eventually() {
+        let receivers = Arc::new(Receivers::default());
+        let transport = faulty_transport(&receivers).with_max_delay(Duration::from_millis(20));
+        for _ in 0..10 {
+            transport.send(&node(2), &framed_from(1)).unwrap();
+        }
+        wait_until(|| receivers.get().len() == 10);
+    }
+
+    #[test]
+    fn a_send_to_an_unreachable_process_gives_up_in_time() {
+        // an address of the documentation range, which nobody answers on.
+        let mut blackhole = node(2);
+        blackhole.host = "192.0.2.1".to_owned();
+        let transport = TcpTransport::new().with_timeout(Duration::from_millis(100));
+
+        let started = std::time::Instant::now();
+        assert!(transport.send(&blackhole, &framed_from(1)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    /// Answers each lookup with the next of its lists of addresses.
+    struct ChangingResolver(Mutex<Vec<Vec<SocketAddr>>>);
+
+    impl Resolver for ChangingResolver {
+        fn resolve(&self, _: &Node) -> io::Result<Vec<SocketAddr>> {
+            Ok(self.0.lock().unwrap().remove(0))
+        }
+    }
+
+    fn listener() -> (std::net::TcpListener, SocketAddr) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        (listener, address)
+    }
+
+    #[test]
+    fn every_send_looks_the_receiver_up_again() {
+        let (old, old_address) = listener();
+        let (new, new_address) = listener();
+        let resolver = ChangingResolver(Mutex::new(vec![vec![old_address], vec![new_address]]));
+        let transport = TcpTransport::new().with_resolver(Arc::new(resolver));
+
+        transport.send(&node(2), &framed_from(1)).unwrap();
+        transport.send(&node(2), &framed_from(1)).unwrap();
+
+        old.set_nonblocking(true).unwrap();
+        new.set_nonblocking(true).unwrap();
+        assert!(old.accept().is_ok());
+        assert!(old.accept().is_err());
+        assert!(new.accept().is_ok());
+    }
+
+    #[test]
+    fn the_next_address_is_tried_when_one_refuses_the_connection() {
+        let (_, closed_address) = listener();
+        let (open, open_address) = listener();
+        let resolver = ChangingResolver(Mutex::new(vec![vec![closed_address, open_address]]));
+        let transport = TcpTransport::new().with_resolver(Arc::new(resolver));
+
+        transport.send(&node(2), &framed_from(1)).unwrap();
+
+        open.set_nonblocking(true).unwrap();
+        assert!(open.accept().is_ok());
+    }
+
+    /// A message wrapped by the link, taking a little over `size` bytes.
+    fn wrapped_message(size: usize) -> Message {
+        let mut inner = Message::new();
+        inner.set_field_type(Message_Type::EP_WRITE_);
+        inner.set_abstractionId(format!("ep{}", "3".repeat(size)));
+        let mut network_message = NetworkMessage::new();
+        network_message.set_message(inner);
+        let mut message = Message::new();
+        message.set_field_type(Message_Type::NETWORK_MESSAGE);
+        message.set_networkMessage(network_message);
+        message
+    }
+
+    #[test]
+    fn a_compressed_message_is_decoded_as_it_was() {
+        let message = wrapped_message(10_000);
+        let framed = encode_versioned(&message, Some(1024)).unwrap();
+        assert_eq!(framed[4..7], [HEADER, PROTOCOL_VERSION, COMPRESSED]);
+        assert!(framed.len() < encode(&message).unwrap().len());
+        let decoded = decode(&framed).unwrap();
+        assert_eq!(encode(&decoded).unwrap(), encode(&message).unwrap());
+
+        // below the threshold, the message is framed as is.
+        let message = wrapped_message(10);
+        let framed = encode_versioned(&message, Some(1024)).unwrap();
+        assert_eq!(framed[4..7], [HEADER, PROTOCOL_VERSION, RAW]);
+        assert_eq!(decode(&framed).unwrap(), message);
+    }
+
+    #[test]
+    fn a_frame_of_another_version_is_rejected() {
+        let message = wrapped_message(10);
+        let mut framed = encode_versioned(&message, None).unwrap();
+        framed[5] = PROTOCOL_VERSION + 1;
+        let error = decode(&framed).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("version"));
+
+        // a truncated header is rejected just the same.
+        assert!(decode(&framed[..5]).is_err());
+        // the frames of the hub have no header at all.
+        assert_eq!(decode(&encode(&message).unwrap()).unwrap(), message);
+    }
+
+    #[test]
+    fn a_message_decompressing_to_more_than_allowed_is_rejected() {
+        let message = wrapped_message(1000);
+        let bytes = protobuf::Message::write_to_bytes(&message).unwrap();
+        let mut payload = vec![HEADER, PROTOCOL_VERSION, COMPRESSED];
+        payload.extend(zstd::bulk::compress(&bytes, COMPRESSION_LEVEL).unwrap());
+
+        assert_eq!(decode_payload(&payload, bytes.len()).unwrap(), message);
+        let error = decode_payload(&payload, bytes.len() - 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn no_frame_makes_the_decoder_panic() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        // the regression corpus of the fuzz target, see fuzz/corpus/decode.
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[0, 0, 0]).is_err());
+
+        let frames = [
+            encode(&wrapped_message(10)).unwrap(),
+            encode_versioned(&wrapped_message(10), None).unwrap(),
+            encode_versioned(&wrapped_message(1000), Some(0)).unwrap(),
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut frame = frames[rng.gen_range(0, frames.len())].clone();
+            frame.truncate(rng.gen_range(0, frame.len() + 1));
+            for _ in 0..rng.gen_range(0, 4) {
+                if !frame.is_empty() {
+                    let at = rng.gen_range(0, frame.len());
+                    frame[at] = rng.gen();
+                }
+            }
+            let _ = decode(&frame);
+        }
+    }
+}
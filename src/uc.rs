@@ -1,9 +1,20 @@
+use crate::clock::{Clock, Guard};
 use crate::ep;
-use crate::ep::EpochConsensusState;
+use crate::ep::{EpochConsensusState, StateStorage};
 use crate::event::*;
+use crate::hint::LeaderHint;
 use crate::node::{Node, NodeInfo};
-use log::{trace};
-use std::sync::Arc;
+use crate::protos::message::Value;
+use crate::value::ConsensusValue;
+use log::{debug, trace, warn};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// How long a proposal may go without progress by default, in milliseconds.
+pub const DEFAULT_DECISION_DEADLINE: i64 = 30000;
+/// How many broadcasts of epochs which haven't started yet are kept, the next ones are dropped.
+const MAX_EARLY: usize = 256;
 
 pub struct UniformConsensusState {
     pub epoch_timestamp: u32,
@@ -19,45 +30,116 @@ impl UniformConsensusState {
     }
 }
 
-pub struct UniformConsensus {
+pub struct UniformConsensus<V> {
     event_queue: Arc<EventQueue>,
     node_info: Arc<NodeInfo>,
-    value: Option<ValueType>,
+    value: Option<V>,
+    /// The values proposed while `value` was undecided, handed back once the instance decides.
+    queued: VecDeque<V>,
     proposed: bool,
     decided: bool,
     state: UniformConsensusState,
     new_state: UniformConsensusState,
     system_id: String,
-    ep_index: usize,
+    leader_hint: LeaderHint,
+    storage: StateStorage<V>,
+    /// Broadcasts of epochs which haven't started here yet, delivered once they do.
+    early: Vec<EventData>,
+    /// When the value was proposed here, the decision latency is measured from then on.
+    proposed_at: Option<Instant>,
+    /// The handler of the running epoch consensus instance, shared with the system.
+    epoch_handler: Arc<Mutex<Option<HandlerId>>>,
+    clock: Arc<dyn Clock>,
+    /// Reports the proposal as timed out unless some progress is made first.
+    deadline_guard: Option<Guard>,
 }
 
-impl UniformConsensus {
+impl<V: ConsensusValue> UniformConsensus<V> {
     pub fn new(
         event_queue: Arc<EventQueue>,
         node_info: Arc<NodeInfo>,
         initial_leader: Node,
         system_id: String,
+        leader_hint: LeaderHint,
+        storage: StateStorage<V>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         UniformConsensus {
             event_queue,
             node_info,
             value: None,
+            queued: VecDeque::new(),
             proposed: false,
             decided: false,
             state: UniformConsensusState::new(0, Some(initial_leader)),
             new_state: UniformConsensusState::new(0, None),
             system_id,
-            ep_index: 0,
+            leader_hint,
+            storage,
+            early: Vec::new(),
+            proposed_at: None,
+            epoch_handler: Arc::new(Mutex::new(None)),
+            clock,
+            deadline_guard: None,
         }
     }
 
+    /// Keeps the id of the handler of every epoch consensus instance started in `epoch_handler`.
+    pub fn with_epoch_handler(mut self, epoch_handler: Arc<Mutex<Option<HandlerId>>>) -> Self {
+        self.epoch_handler = epoch_handler;
+        self
+    }
+
     /// upon event ⟨ uc, Init ⟩ do
     pub fn init(&self) {}
 
+    /// (Re)starts the deadline of the proposal, if there is one still to decide. Once it passes,
+    /// `UcProposeTimedOut` is signalled, e.g. so that the proposer gets to hear about it.
+    fn watch_progress(&mut self) {
+        if self.decided || self.value.is_none() {
+            return;
+        }
+        // the queue owns the instance, the clock must not keep it alive.
+        let event_queue = Arc::downgrade(&self.event_queue);
+        let system_id = self.system_id.clone();
+        let guard = self.clock.schedule(
+            self.node_info.decision_deadline,
+            Box::new(move || {
+                if let Some(event_queue) = event_queue.upgrade() {
+                    let timed_out = InternalMessage::UcProposeTimedOut;
+                    event_queue.push(EventData::Internal(system_id, timed_out));
+                }
+            }),
+        );
+        self.deadline_guard = Some(guard);
+    }
+
     /// upon event ⟨ uc, Propose | v ⟩ do
-    fn uc_propose(&mut self, value: ValueType) {
+    /// An instance decides a single value, a value proposed while another one is undecided is
+    /// queued, and handed back as `UcUndecided` as the instance decides, for the application to
+    /// propose it again in the follow-up of the system.
+    fn uc_propose(&mut self, value: &Value) {
         // val := v;
-        self.value.replace(value);
+        if let Some(value) = V::from_value(value) {
+            if self.decided {
+                warn!("System {} already decided, dropping the proposal of {:?}", self.system_id, value);
+                return;
+            }
+            match &self.value {
+                Some(previous) if *previous == value => return,
+                Some(previous) => {
+                    debug!(
+                        "The proposal of {:?} waits for the undecided proposal of {:?} in system {}",
+                        value, previous, self.system_id
+                    );
+                    self.queued.push_back(value);
+                    return;
+                }
+                None => self.value = Some(value),
+            }
+            self.proposed_at.get_or_insert_with(Instant::now);
+            self.watch_progress();
+        }
     }
 
     /// upon event ⟨ ec, StartEpoch | newts', newl' ⟩ do
@@ -65,6 +147,11 @@ impl UniformConsensus {
         // (newts, newl) := (newts', newl');
         self.new_state.epoch_timestamp = timestamp;
         self.new_state.leader.replace(leader.clone());
+        self.event_queue.metrics().record_epoch_change();
+
+        // the leader is about to change, whatever we knew about it is no longer reliable.
+        self.leader_hint.invalidate();
+        self.watch_progress();
 
         // trigger ⟨ ep.ets, Abort ⟩;
         let ets = self.state.epoch_timestamp;
@@ -74,7 +161,7 @@ impl UniformConsensus {
     }
 
     /// upon event ⟨ ep.ts, Aborted | state ⟩ such that ts = ets do
-    fn ep_aborted(&mut self, epoch_ts: u32, ts: u32, value: ValueType) {
+    fn ep_aborted(&mut self, epoch_ts: u32, value_timestamp: u32, value: &Value) {
         if self.state.epoch_timestamp == epoch_ts {
             // (ets, l) := (newts, newl);
             self.state.epoch_timestamp = self.new_state.epoch_timestamp;
@@ -84,25 +171,64 @@ impl UniformConsensus {
             self.proposed = false;
 
             // Initialize a new instance ep.ets of epoch consensus with timestamp ets, leader l, and state state;
-            let state = EpochConsensusState::new(ts, value);
+            // the aborted (valts, val) pair, not the epoch it was aborted in.
+            let state = EpochConsensusState::new(value_timestamp, V::from_value(value));
             let leader = self
                 .state
                 .leader
                 .clone()
                 .expect("We should have a leader at this point.");
-            
-            self.ep_index += 1;
-            let ep = ep::EpochConsensus::new(
+            let leader_changed = self.leader_hint.update(&leader, self.state.epoch_timestamp);
+            self.watch_progress();
+            if leader_changed {
+                let changed = InternalMessage::LeaderChanged(leader.clone());
+                self.event_queue.push(EventData::Internal(self.system_id.clone(), changed));
+            }
+
+            let ep = ep::EpochConsensus::<V>::new(
                 self.node_info.clone(),
                 self.event_queue.clone(),
                 state,
+                self.storage.clone(),
                 leader,
                 self.state.epoch_timestamp,
                 self.system_id.clone(),
-                self.ep_index,
             );
-            self.event_queue
-                .register_handler( Box::new(ep));
+            // the previous instance has already deregistered itself when it aborted.
+            let id = self
+                .event_queue
+                .register_handler_with(|id| Box::new(ep.with_handler_id(id)));
+            self.epoch_handler.lock().unwrap().replace(id);
+
+            // pushed after the registration, the instance is there by the time they are handled.
+            let ets = self.state.epoch_timestamp;
+            for event_data in std::mem::take(&mut self.early) {
+                if let EventData::Internal(_, InternalMessage::BebDeliver(_, msg)) = &event_data {
+                    match ep::epoch_of(msg) {
+                        Some(ts) if ts == ets => self.event_queue.push(event_data),
+                        Some(ts) if ts > ets => self.early.push(event_data),
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    /// The leader of an epoch broadcasts as soon as it starts it, the others might get
+    /// the broadcast before they do. It would be lost without an instance to handle it.
+    fn keep_if_early(&mut self, event_data: &EventData) {
+        if let EventData::Internal(_, InternalMessage::BebDeliver(_, msg)) = event_data {
+            if ep::epoch_of(msg).is_none_or(|ts| ts <= self.state.epoch_timestamp) {
+                return;
+            }
+            if self.early.len() >= MAX_EARLY {
+                debug!(
+                    "System {} holds too many early broadcasts, dropping {:?}",
+                    self.system_id, msg
+                );
+                return;
+            }
+            self.early.push(event_data.clone());
         }
     }
 
@@ -113,27 +239,50 @@ impl UniformConsensus {
             .leader
             .as_ref()
             .expect("We should have a leader at this point.");
-        if leader == &self.node_info.current_node && self.value.is_some() {
+        if leader != &self.node_info.current_node || self.proposed {
+            return;
+        }
+        if let Some(value) = &self.value {
             self.proposed = true;
             let propose_message =
-                InternalMessage::EpPropose(self.state.epoch_timestamp, self.value.unwrap());
+                InternalMessage::EpPropose(self.state.epoch_timestamp, value.to_value());
             let event_data = EventData::Internal(self.system_id.clone(), propose_message);
             self.event_queue.push(event_data);
         }
     }
 
     /// upon event ⟨ ep.ts, Decide | v ⟩ such that ts = ets do
-    fn ep_decide(&mut self, ts: u32, value: ValueType) {
+    fn ep_decide(&mut self, ts: u32, value: &Value) {
         if !self.decided && self.state.epoch_timestamp == ts {
             self.decided = true;
-            let decide_message = InternalMessage::UcDecide(value);
+            self.deadline_guard = None;
+            if let Some(proposed_at) = self.proposed_at {
+                let latency = proposed_at.elapsed();
+                debug!("System {} decided {:?} after its proposal", self.system_id, latency);
+                self.event_queue.metrics().record_decision_latency(latency);
+            }
+            // handed back first, the application still runs the system when it gets them.
+            self.hand_back_undecided(value);
+            let decide_message = InternalMessage::UcDecide(value.clone());
             let event_data = EventData::Internal(self.system_id.clone(), decide_message);
             self.event_queue.push(event_data);
         }
     }
+
+    /// The proposals made here other than the decided one, in the order they were made, are
+    /// left for the proposer to make again in the next instance.
+    fn hand_back_undecided(&mut self, decided: &Value) {
+        let decided = V::from_value(decided);
+        let proposals = self.value.take().into_iter().chain(self.queued.drain(..));
+        for proposal in proposals.filter(|proposal| Some(proposal) != decided.as_ref()) {
+            warn!("The proposal of {:?} was not decided in system {}", proposal, self.system_id);
+            let undecided = InternalMessage::UcUndecided(proposal.to_value());
+            self.event_queue.push(EventData::Internal(self.system_id.clone(), undecided));
+        }
+    }
 }
 
-impl EventHandler for UniformConsensus {
+impl<V: ConsensusValue> EventHandler for UniformConsensus<V> {
     fn should_handle_event(&self, event_data: &EventData) -> bool {
         if let EventData::Internal(system_id, _) = event_data {
             system_id == &self.system_id   
@@ -148,7 +297,7 @@ impl EventHandler for UniformConsensus {
         if let EventData::Internal(_, msg) = event_data {
             match msg {
                 InternalMessage::UcPropose(value) => {
-                    self.uc_propose(value.clone());
+                    self.uc_propose(value);
                     // we need to call this here since this is the point where the value changes
                     self.change_proposed();
                 }
@@ -158,15 +307,299 @@ impl EventHandler for UniformConsensus {
                     // we need to call this here since this is the point where the value changes
                     self.change_proposed();
                 }
-                InternalMessage::EpAborted(e_ts, ts, value) => {
-                    self.ep_aborted(*e_ts, *ts, *value);
+                InternalMessage::EpAborted(e_ts, value_ts, value) => {
+                    self.ep_aborted(*e_ts, *value_ts, value);
 
                     // we need to call this here since this is where the current leader might change.
                     self.change_proposed();
                 }
-                InternalMessage::EpDecide(ts, value) => self.ep_decide(*ts, *value),
+                InternalMessage::EpDecide(ts, value) => self.ep_decide(*ts, value),
+                InternalMessage::BebDeliver(..) => self.keep_if_early(event_data),
+                // the running epoch keeps its members, the next one is started with the new ones.
+                InternalMessage::Reconfigure(nodes) => {
+                    self.node_info = Arc::new(self.node_info.with_members(nodes))
+                }
                 _ => (),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::message::{Message, Message_Type};
+    use crate::storage::MemoryStorage;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::testing::{node, node_info, pushed, wait_until, Recorder};
+
+    const SYSTEM_ID: &str = "sys-1";
+
+    /// The uniform consensus of node 2 in a system of the nodes 1 to 3, initially led by 1.
+    fn uniform_consensus() -> (UniformConsensus<i32>, Arc<EventQueue>) {
+        let event_queue = Arc::new(EventQueue::new_inert());
+        (uniform_consensus_on(event_queue.clone()), event_queue)
+    }
+
+    fn uniform_consensus_on(event_queue: Arc<EventQueue>) -> UniformConsensus<i32> {
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        UniformConsensus::new(
+            event_queue,
+            Arc::new(node_info(2, &nodes)),
+            node(1),
+            SYSTEM_ID.to_owned(),
+            hint_of(node(1)),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(SystemClock::new()),
+        )
+    }
+
+    /// The hint of a system initially led by `leader`, as the system starts it.
+    fn hint_of(leader: Node) -> LeaderHint {
+        let leader_hint = LeaderHint::new();
+        leader_hint.update(&leader, 0);
+        leader_hint
+    }
+
+    fn read(ts: u32) -> Message {
+        let mut msg = Message::new();
+        msg.set_field_type(Message_Type::EP_READ_);
+        msg.set_abstractionId(format!("ep{}", ts));
+        msg.set_systemId(SYSTEM_ID.to_owned());
+        msg
+    }
+
+    fn deliver(uc: &mut UniformConsensus<i32>, event: InternalMessage) {
+        uc.handle(&EventData::Internal(SYSTEM_ID.to_owned(), event));
+    }
+
+    fn undecided(events: &[InternalMessage]) -> Vec<i32> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                InternalMessage::UcUndecided(value) => i32::from_value(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn decided(events: &[InternalMessage]) -> Vec<i32> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                InternalMessage::UcDecide(value) => i32::from_value(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_proposal_made_before_the_previous_one_decided_is_queued() {
+        let (mut uc, event_queue) = uniform_consensus();
+        for value in &[4, 6, 4, 8] {
+            deliver(&mut uc, InternalMessage::UcPropose(value.to_value()));
+        }
+        // the first proposal is the one proposed once this process leads.
+        assert_eq!(uc.value, Some(4));
+        assert_eq!(uc.queued, vec![6, 8]);
+
+        // the queued proposals are handed back in order, before the decision.
+        deliver(&mut uc, InternalMessage::EpDecide(0, 4.to_value()));
+        let events = pushed(&event_queue);
+        assert_eq!(undecided(&events), vec![6, 8]);
+        assert!(matches!(events.last(), Some(InternalMessage::UcDecide(..))));
+        assert!(uc.value.is_none() && uc.queued.is_empty());
+
+        // the application proposes them again to the next instance, until all of them decided.
+        let mut decisions = decided(&events);
+        let mut proposals = undecided(&events);
+        while let Some(&first) = proposals.first() {
+            let (mut next, event_queue) = uniform_consensus();
+            for value in &proposals {
+                deliver(&mut next, InternalMessage::UcPropose(value.to_value()));
+            }
+            deliver(&mut next, InternalMessage::EpDecide(0, first.to_value()));
+            let events = pushed(&event_queue);
+            decisions.extend(decided(&events));
+            proposals = undecided(&events);
+        }
+        assert_eq!(decisions, vec![4, 6, 8]);
+    }
+
+    #[test]
+    fn the_leader_proposes_to_its_epoch_once() {
+        let event_queue = Arc::new(EventQueue::new_inert());
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut uc = UniformConsensus::<i32>::new(
+            event_queue.clone(),
+            Arc::new(node_info(1, &nodes)),
+            node(1),
+            SYSTEM_ID.to_owned(),
+            hint_of(node(1)),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(SystemClock::new()),
+        );
+        for value in &[4, 6] {
+            deliver(&mut uc, InternalMessage::UcPropose(value.to_value()));
+        }
+
+        let proposals = pushed(&event_queue)
+            .into_iter()
+            .filter(|event| matches!(event, InternalMessage::EpPropose(..)))
+            .count();
+        assert_eq!(proposals, 1);
+    }
+
+    #[test]
+    fn a_read_of_an_epoch_not_started_yet_is_delivered_once_it_is() {
+        let (mut uc, event_queue) = uniform_consensus();
+        deliver(&mut uc, InternalMessage::BebDeliver(node(3), read(0)));
+        deliver(&mut uc, InternalMessage::BebDeliver(node(3), read(5)));
+        deliver(&mut uc, InternalMessage::BebDeliver(node(3), read(8)));
+        deliver(&mut uc, InternalMessage::EcStartEpoch(node(3), 5));
+        pushed(&event_queue);
+
+        deliver(&mut uc, InternalMessage::EpAborted(0, 0, Value::new()));
+        let redelivered: Vec<_> = pushed(&event_queue)
+            .into_iter()
+            .filter_map(|event| match event {
+                InternalMessage::BebDeliver(_, msg) => ep::epoch_of(&msg),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(redelivered, vec![5]);
+        // the broadcast of a later epoch waits for it.
+        assert_eq!(uc.early.len(), 1);
+    }
+
+    #[test]
+    fn only_so_many_early_broadcasts_are_kept() {
+        let (mut uc, _) = uniform_consensus();
+        for ts in 1..=MAX_EARLY as u32 + 1 {
+            deliver(&mut uc, InternalMessage::BebDeliver(node(3), read(ts)));
+        }
+        assert_eq!(uc.early.len(), MAX_EARLY);
+    }
+
+    #[test]
+    fn the_latency_from_the_proposal_to_the_decision_is_recorded() {
+        let (mut uc, event_queue) = uniform_consensus();
+        deliver(&mut uc, InternalMessage::UcPropose(7.to_value()));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        deliver(&mut uc, InternalMessage::EpDecide(0, 7.to_value()));
+
+        let metrics = event_queue.metrics();
+        assert_eq!(metrics.decision_latency_count(), 1);
+        assert!(metrics.decision_latency_sum() >= std::time::Duration::from_millis(5));
+    }
+
+    #[test]
+    fn the_next_epoch_takes_over_the_aborted_state() {
+        let event_queue = Arc::new(EventQueue::create_and_run().unwrap());
+        let recorder = Recorder::default();
+        event_queue.register_handler(Box::new(recorder.clone()));
+        let mut uc = uniform_consensus_on(event_queue.clone());
+
+        deliver(&mut uc, InternalMessage::EcStartEpoch(node(3), 5));
+        deliver(&mut uc, InternalMessage::EpAborted(0, 4, 9.to_value()));
+        // the new instance answers the READ of its leader with the state it was started with.
+        let read = InternalMessage::BebDeliver(node(3), read(5));
+        event_queue.push(EventData::Internal(SYSTEM_ID.to_owned(), read));
+
+        let states = || -> Vec<Message> {
+            recorder
+                .events()
+                .into_iter()
+                .filter_map(|event_data| match event_data {
+                    EventData::Internal(_, InternalMessage::PlSend(_, to, msg))
+                        if to == node(3) && msg.get_field_type() == Message_Type::EP_STATE_ =>
+                    {
+                        Some(msg)
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        wait_until(|| !states().is_empty());
+        let state = states()[0].get_epState_().clone();
+        assert_eq!(states()[0].get_abstractionId(), "ep5");
+        assert_eq!(state.get_valueTimestamp(), 4);
+        assert_eq!(i32::from_value(state.get_value()), Some(9));
+    }
+
+    fn leader_changes(events: &[InternalMessage]) -> Vec<Node> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                InternalMessage::LeaderChanged(leader) => Some(leader.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_leader_change_is_signalled_once() {
+        let (mut uc, event_queue) = uniform_consensus();
+        deliver(&mut uc, InternalMessage::EcStartEpoch(node(3), 5));
+        deliver(&mut uc, InternalMessage::EpAborted(0, 0, Value::new()));
+        assert_eq!(leader_changes(&pushed(&event_queue)), vec![node(3)]);
+
+        // another epoch of the same leader changes nothing.
+        deliver(&mut uc, InternalMessage::EcStartEpoch(node(3), 8));
+        deliver(&mut uc, InternalMessage::EpAborted(5, 0, Value::new()));
+        assert!(leader_changes(&pushed(&event_queue)).is_empty());
+    }
+
+    /// The uniform consensus of node 2, initially led by 1, proposals timing out after 20ms
+    /// of `clock`.
+    fn hasty_uniform_consensus(
+        clock: Arc<ManualClock>,
+    ) -> (UniformConsensus<i32>, Arc<EventQueue>) {
+        let event_queue = Arc::new(EventQueue::new_inert());
+        let nodes: Vec<Node> = (1..=3).map(node).collect();
+        let mut node_info = node_info(2, &nodes);
+        node_info.decision_deadline = chrono::Duration::milliseconds(20);
+        let uc = UniformConsensus::new(
+            event_queue.clone(),
+            Arc::new(node_info),
+            node(1),
+            SYSTEM_ID.to_owned(),
+            LeaderHint::new(),
+            Arc::new(MemoryStorage::new()),
+            clock,
+        );
+        (uc, event_queue)
+    }
+
+    fn timeouts(events: &[InternalMessage]) -> usize {
+        events
+            .iter()
+            .filter(|event| matches!(event, InternalMessage::UcProposeTimedOut))
+            .count()
+    }
+
+    #[test]
+    fn a_proposal_cut_off_from_the_leader_times_out() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut uc, event_queue) = hasty_uniform_consensus(clock.clone());
+        // nothing is delivered back, as if node 1 were partitioned away.
+        deliver(&mut uc, InternalMessage::UcPropose(7.to_value()));
+        clock.advance(chrono::Duration::milliseconds(19));
+        assert_eq!(timeouts(&pushed(&event_queue)), 0);
+        clock.advance(chrono::Duration::milliseconds(1));
+        assert_eq!(timeouts(&pushed(&event_queue)), 1);
+
+        clock.advance(chrono::Duration::milliseconds(60));
+        assert_eq!(timeouts(&pushed(&event_queue)), 0);
+    }
+
+    #[test]
+    fn a_decided_proposal_does_not_time_out() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut uc, event_queue) = hasty_uniform_consensus(clock.clone());
+        deliver(&mut uc, InternalMessage::UcPropose(7.to_value()));
+        deliver(&mut uc, InternalMessage::EpDecide(0, 7.to_value()));
+        clock.advance(chrono::Duration::milliseconds(60));
+        assert_eq!(timeouts(&pushed(&event_queue)), 0);
+    }
+}
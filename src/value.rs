@@ -0,0 +1,82 @@
+use crate::protos::message;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// A value the consensus stack can agree on.
+/// Values travel on the wire inside the protobuf `Value` message: by default they are
+/// serialized as JSON into its `data` field, while integers keep using the `v` field
+/// so that they stay compatible with the hub.
+pub trait ConsensusValue:
+    Clone + Eq + Ord + Debug + Serialize + DeserializeOwned + Send + 'static
+{
+    fn to_value(&self) -> message::Value {
+        let mut value = message::Value::new();
+        value.set_defined(true);
+        value.set_data(serde_json::to_vec(self).expect("Consensus values must be serializable."));
+        value
+    }
+
+    /// Returns `None` for the undefined value, or if the value cannot be decoded.
+    fn from_value(value: &message::Value) -> Option<Self> {
+        if value.get_defined() {
+            serde_json::from_slice(value.get_data()).ok()
+        } else {
+            None
+        }
+    }
+}
+
+impl ConsensusValue for i32 {
+    fn to_value(&self) -> message::Value {
+        let mut value = message::Value::new();
+        value.set_defined(true);
+        value.set_v(*self);
+        value
+    }
+
+    fn from_value(value: &message::Value) -> Option<Self> {
+        if value.get_defined() {
+            Some(value.get_v())
+        } else {
+            None
+        }
+    }
+}
+
+impl ConsensusValue for String {}
+
+impl ConsensusValue for Vec<u8> {}
+
+/// A set of key-value updates that is agreed on, and applied, as a whole in a single
+/// consensus instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Batch {
+    pub entries: BTreeMap<String, String>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Batch::default()
+    }
+
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.entries.insert(key.into(), value.into());
+    }
+
+    /// Applies every update of the batch to `store`.
+    pub fn apply_to(&self, store: &mut BTreeMap<String, String>) {
+        store.extend(self.entries.clone());
+    }
+}
+
+impl ConsensusValue for Batch {}
+
+/// Encodes a possibly undefined value.
+pub fn to_maybe_value<V: ConsensusValue>(value: Option<&V>) -> message::Value {
+    match value {
+        Some(value) => value.to_value(),
+        None => message::Value::new(),
+    }
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use distributed_consensus::transport::InMemoryTransport;
+use distributed_consensus::{Engine, Node};
+use std::sync::Arc;
+
+/// The value proposed by the proposer of a run, the others proposing their id.
+const PROPOSAL: i32 = 42;
+
+/// Runs a system of `n` processes sharing an in-memory transport, `proposer` proposing
+/// `PROPOSAL`, the process at `missing`, if any, never being started. A process only takes
+/// part in a system once proposed to, so the others join it with a proposal of their own,
+/// just as the hub proposes to every process. Returns the values the processes decided.
+fn decide(name: &str, n: u16, proposer: usize, missing: Option<usize>) -> Vec<serde_json::Value> {
+    let nodes: Vec<Node> = (1..=n).map(common::node).collect();
+    let transport = Arc::new(InMemoryTransport::new());
+    let started: Vec<(usize, Engine, common::RecordingSink)> = (0..nodes.len())
+        .filter(|&index| Some(index) != missing)
+        .map(|index| {
+            let node = &nodes[index];
+            let sink = common::RecordingSink::default();
+            let engine = Engine::with_transport(
+                common::node_info(node, &nodes),
+                common::state_dir(name, node.id),
+                vec![Box::new(sink.clone())],
+                transport.clone(),
+            )
+            .unwrap();
+            transport.register(node.id, &engine.event_queue());
+            (index, engine, sink)
+        })
+        .collect();
+
+    // the highest ranked process leads the initial epoch, it proposes last so that the others
+    // already run the system when it broadcasts, see `common::propose_everywhere`.
+    for (index, engine, _) in &started {
+        let value = if *index == proposer {
+            PROPOSAL
+        } else {
+            i32::from(nodes[*index].id)
+        };
+        engine.propose("sys-1", &value, &nodes);
+        if *index != nodes.len() - 1 {
+            common::wait_until(|| engine.is_running("sys-1"));
+        }
+    }
+
+    let sinks: Vec<_> = started.iter().map(|(_, _, sink)| sink.clone()).collect();
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == started.len());
+    common::decided(&sinks, "sys-1")
+}
+
+/// Every process decides the same value, one of the proposed ones.
+fn assert_agreement(decided: &[serde_json::Value], n: u16) {
+    assert!(decided.iter().all(|value| value == &decided[0]));
+    let proposed: Vec<_> = (1..=i32::from(n))
+        .chain(Some(PROPOSAL))
+        .map(|value| serde_json::json!(value))
+        .collect();
+    assert!(proposed.contains(&decided[0]));
+}
+
+#[test]
+fn three_processes_agree_whoever_proposes() {
+    for proposer in 0..3 {
+        let decided = decide(&format!("cluster-3-{}", proposer), 3, proposer, None);
+        assert_eq!(decided.len(), 3);
+        assert_agreement(&decided, 3);
+    }
+}
+
+#[test]
+fn five_processes_agree_whoever_proposes() {
+    for proposer in 0..5 {
+        let decided = decide(&format!("cluster-5-{}", proposer), 5, proposer, None);
+        assert_eq!(decided.len(), 5);
+        assert_agreement(&decided, 5);
+    }
+}
+
+#[test]
+fn the_leader_decides_its_own_proposal() {
+    let decided = decide("cluster-leader", 3, 2, None);
+    assert!(decided.iter().all(|value| value == &serde_json::json!(PROPOSAL)));
+}
+
+#[test]
+fn a_majority_decides_without_the_process_never_started() {
+    // the initial leader is the one never started, it has to be suspected first.
+    let decided = decide("cluster-majority", 3, 0, Some(2));
+    assert_eq!(decided.len(), 2);
+    assert_agreement(&decided, 3);
+}
+
+#[test]
+fn the_proposal_of_a_process_taking_over_the_lead_is_decided() {
+    // node 2 was not leading when it proposed, it leads once the never started node 3 is suspected.
+    let decided = decide("cluster-takeover", 3, 1, Some(2));
+    assert_eq!(decided, vec![serde_json::json!(PROPOSAL); 2]);
+}
+
+#[test]
+fn the_leader_of_a_running_system_is_hinted() {
+    let nodes: Vec<Node> = (1..=3).map(common::node).collect();
+    let engine = Engine::with_transport(
+        common::node_info(&nodes[0], &nodes),
+        common::state_dir("cluster-hint", 1),
+        Vec::new(),
+        Arc::new(InMemoryTransport::new()),
+    )
+    .unwrap();
+    assert_eq!(engine.leader_hint("sys-1"), None);
+
+    // alone, the process never decides, the system keeps running.
+    engine.propose("sys-1", &PROPOSAL, &nodes);
+    common::wait_until(|| engine.leader_hint("sys-1") == Some(common::node(3)));
+}
+
+#[test]
+fn the_leadership_changes_of_a_running_system_are_recorded() {
+    let nodes: Vec<Node> = (1..=3).map(common::node).collect();
+    let engine = Engine::with_transport(
+        common::node_info(&nodes[0], &nodes),
+        common::state_dir("cluster-history", 1),
+        Vec::new(),
+        Arc::new(InMemoryTransport::new()),
+    )
+    .unwrap();
+    engine.propose("sys-1", &PROPOSAL, &nodes);
+
+    // the others never answer, the process ends up suspecting them and trusting itself.
+    common::wait_until(|| {
+        let changes = engine.leadership_history("sys-1");
+        changes.last().is_some_and(|change| change.new_leader == common::node(1))
+    });
+}
+
+#[test]
+fn a_failure_detector_without_a_timeout_is_refused() {
+    let nodes = vec![common::node(1)];
+    let mut node_info = (*common::node_info(&nodes[0], &nodes)).clone();
+    node_info.epfd_delta = chrono::Duration::zero();
+    let engine = Engine::with_transport(
+        Arc::new(node_info),
+        common::state_dir("cluster-zero-delta", 1),
+        Vec::new(),
+        Arc::new(InMemoryTransport::new()),
+    );
+    assert_eq!(engine.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn an_engine_without_any_worker_is_refused() {
+    let nodes = vec![common::node(1)];
+    let engine = Engine::with_workers(
+        common::node_info(&nodes[0], &nodes),
+        common::state_dir("cluster-no-workers", 1),
+        Vec::new(),
+        Arc::new(InMemoryTransport::new()),
+        0,
+    );
+    assert_eq!(engine.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn a_proposal_without_a_quorum_is_reported_as_timed_out() {
+    let nodes: Vec<Node> = (1..=3).map(common::node).collect();
+    let mut node_info = (*common::node_info(&nodes[0], &nodes)).clone();
+    node_info.decision_deadline = chrono::Duration::milliseconds(50);
+    let engine = Engine::with_transport(
+        Arc::new(node_info),
+        common::state_dir("cluster-timeout", 1),
+        Vec::new(),
+        Arc::new(InMemoryTransport::new()),
+    )
+    .unwrap();
+    let timeouts = engine.timeouts();
+    engine.propose("sys-1", &PROPOSAL, &nodes);
+
+    // alone, the process never gathers a quorum.
+    let timeout = timeouts.recv_timeout(std::time::Duration::from_secs(10));
+    assert_eq!(timeout, Ok(("sys-1".to_owned(), chrono::Duration::milliseconds(50))));
+    assert!(engine.is_running("sys-1"));
+}
@@ -0,0 +1,210 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use distributed_consensus::node::NodeId;
+use distributed_consensus::sink::{Decision, DecisionSink};
+use distributed_consensus::transport::{InMemoryTransport, Transport};
+use distributed_consensus::{Engine, Node, NodeInfo};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The node `id`, of rank `id`.
+pub fn node(id: u16) -> Node {
+    let name = format!("node{}", id);
+    Node::new("test".to_owned(), name, "127.0.0.1".to_owned(), 6000 + id, id, id)
+}
+
+/// The view of `current` of a cluster made of `nodes`, coordinated by the hub `node(100)`.
+pub fn node_info(current: &Node, nodes: &[Node]) -> Arc<NodeInfo> {
+    Arc::new(NodeInfo::new(current.clone(), Some(node(100)), nodes.to_vec()))
+}
+
+/// An empty directory for the state of the node `id` in the test `name`, the persisted
+/// state of a previous run must not leak into this one.
+pub fn state_dir(name: &str, id: u16) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join("distributed_consensus_tests")
+        .join(format!("{}-{}", name, std::process::id()))
+        .join(id.to_string());
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Records the decisions it is handed, the recorded decisions being shared by its clones.
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    decisions: Arc<Mutex<Vec<Decision>>>,
+}
+
+impl RecordingSink {
+    pub fn decisions(&self) -> Vec<Decision> {
+        self.decisions.lock().unwrap().clone()
+    }
+}
+
+impl DecisionSink for RecordingSink {
+    fn publish(&mut self, decision: &Decision) -> io::Result<()> {
+        self.decisions.lock().unwrap().push(decision.clone());
+        Ok(())
+    }
+}
+
+/// Waits for `condition` to hold, for at most 10 seconds.
+pub fn wait_until<F: Fn() -> bool>(condition: F) {
+    assert!(
+        wait_for(condition, Duration::from_secs(10)),
+        "Timed out waiting for the condition."
+    );
+}
+
+/// Waits for `condition` to hold, for at most `timeout`. Returns whether it held.
+pub fn wait_for<F: Fn() -> bool>(condition: F, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    true
+}
+
+/// The engines of `nodes`, along with the sinks recording their decisions, reaching each other
+/// through `transport` which must eventually hand the messages over to `in_memory`.
+pub fn in_memory_cluster(
+    name: &str,
+    nodes: &[Node],
+    in_memory: &InMemoryTransport,
+    transport: Arc<dyn Transport>,
+) -> (Vec<Engine>, Vec<RecordingSink>) {
+    let sinks: Vec<_> = nodes.iter().map(|_| RecordingSink::default()).collect();
+    let engines = nodes
+        .iter()
+        .zip(&sinks)
+        .map(|(node, sink)| {
+            let engine = Engine::with_transport(
+                node_info(node, nodes),
+                state_dir(name, node.id),
+                vec![Box::new(sink.clone())],
+                transport.clone(),
+            )
+            .unwrap();
+            in_memory.register(node.id, &engine.event_queue());
+            engine
+        })
+        .collect();
+    (engines, sinks)
+}
+
+/// Has every engine propose its value in `system_id`, made of `nodes`.
+pub fn propose_everywhere(engines: &[Engine], system_id: &str, values: &[i32], nodes: &[Node]) {
+    // the highest ranked node leads the initial epoch and messages for systems that were not
+    // started yet are dropped, so the others start the system before it broadcasts its READ.
+    // None of them can decide before it proposes, their systems keep running until then.
+    let mut proposals: Vec<_> = nodes.iter().zip(engines.iter().zip(values)).collect();
+    proposals.sort_by(|(x, _), (y, _)| x.rank.cmp(&y.rank).then(x.id.cmp(&y.id)));
+    let leader = proposals.pop();
+    for (_, (engine, value)) in proposals {
+        engine.propose(system_id, value, nodes);
+        wait_until(|| engine.is_running(system_id));
+    }
+    if let Some((_, (engine, value))) = leader {
+        engine.propose(system_id, value, nodes);
+    }
+}
+
+/// The values decided in `system_id`, by the processes that decided.
+pub fn decided(sinks: &[RecordingSink], system_id: &str) -> Vec<serde_json::Value> {
+    sinks
+        .iter()
+        .flat_map(|sink| sink.decisions())
+        .filter(|decision| decision.system_id == system_id)
+        .map(|decision| decision.value)
+        .collect()
+}
+
+/// Holds the messages to every process back until `window` of them are pending, then sends
+/// them in an order shuffled by an RNG seeded with `seed`, so that a reordering reproduces.
+/// The messages held for longer than `FLUSH_INTERVAL`, or when the transport is dropped, are
+/// sent shuffled as well, so that none of them is held back forever.
+pub struct ReorderingTransport {
+    held: Arc<HeldMessages>,
+}
+
+/// How often the messages held by a `ReorderingTransport` are sent whatever their number.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A process, along with the messages held for it.
+type Held = (Node, Vec<Vec<u8>>);
+
+struct HeldMessages {
+    inner: Arc<dyn Transport>,
+    window: usize,
+    rng: Mutex<StdRng>,
+    pending: Mutex<HashMap<NodeId, Held>>,
+}
+
+impl HeldMessages {
+    fn send_shuffled(&self, to: &Node, mut batch: Vec<Vec<u8>>) -> io::Result<()> {
+        batch.shuffle(&mut *self.rng.lock().unwrap());
+        for bytes in batch {
+            self.inner.send(to, &bytes)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {
+        let held: Vec<_> = self.pending.lock().unwrap().drain().map(|(_, held)| held).collect();
+        for (to, batch) in held {
+            let _ = self.send_shuffled(&to, batch);
+        }
+    }
+}
+
+impl ReorderingTransport {
+    pub fn new(inner: Arc<dyn Transport>, window: usize, seed: u64) -> Self {
+        let held = Arc::new(HeldMessages {
+            inner,
+            window,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            pending: Mutex::new(HashMap::new()),
+        });
+        let flushed = Arc::downgrade(&held);
+        thread::spawn(move || loop {
+            thread::sleep(FLUSH_INTERVAL);
+            match flushed.upgrade() {
+                Some(held) => held.flush(),
+                None => return,
+            }
+        });
+        ReorderingTransport { held }
+    }
+}
+
+impl Drop for ReorderingTransport {
+    fn drop(&mut self) {
+        self.held.flush();
+    }
+}
+
+impl Transport for ReorderingTransport {
+    fn send(&self, to: &Node, bytes: &[u8]) -> io::Result<()> {
+        let batch = {
+            let mut pending = self.held.pending.lock().unwrap();
+            let (_, held) = pending.entry(to.id).or_insert_with(|| (to.clone(), Vec::new()));
+            held.push(bytes.to_vec());
+            if held.len() < self.held.window {
+                return Ok(());
+            }
+            std::mem::take(held)
+        };
+        self.held.send_shuffled(to, batch)
+    }
+}
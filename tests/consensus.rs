@@ -0,0 +1,372 @@
+mod common;
+
+use distributed_consensus::app::App;
+use distributed_consensus::client;
+use distributed_consensus::epfd::{DEFAULT_DELTA, DEFAULT_STARTUP_GRACE};
+use distributed_consensus::metrics::serve_metrics;
+use distributed_consensus::protos::message::Message_Type;
+use distributed_consensus::timeline::Direction;
+use distributed_consensus::tls::TlsConfig;
+use distributed_consensus::transport::{FaultyTransport, InMemoryTransport};
+use distributed_consensus::{Engine, EventQueue, NodeRole, Server};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Runs the node binary with `--check` on a node configuration made of `nodes`.
+fn check(name: &str, nodes: &str) -> std::process::Output {
+    run_node(name, nodes, "--check")
+}
+
+/// Runs the node binary with `flag` on a node configuration made of `nodes`.
+fn run_node(name: &str, nodes: &str, flag: &str) -> std::process::Output {
+    let dir = common::state_dir(name, 0);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("nodes.json");
+    std::fs::write(&config, nodes).unwrap();
+    std::process::Command::new(env!("CARGO_BIN_EXE_distributed_consensus"))
+        .args(["1", "--config", config.to_str().unwrap(), flag])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn the_configuration_is_checked_without_starting_the_node() {
+    let valid = check(
+        "check-valid",
+        r#"[
+            {"owner": "test", "name": "node1", "host": "127.0.0.1", "port": 5001, "id": 1, "rank": 1},
+            {"owner": "test", "name": "node2", "host": "127.0.0.1", "port": 5002, "id": 2, "rank": 2}
+        ]"#,
+    );
+    assert!(valid.status.success());
+    assert!(String::from_utf8_lossy(&valid.stdout).contains("Initial leader"));
+
+    let duplicate_ids = check(
+        "check-invalid",
+        r#"[
+            {"owner": "test", "name": "node1", "host": "127.0.0.1", "port": 5001, "id": 1, "rank": 1},
+            {"owner": "test", "name": "node2", "host": "127.0.0.1", "port": 5002, "id": 1, "rank": 2}
+        ]"#,
+    );
+    assert!(!duplicate_ids.status.success());
+}
+
+#[test]
+fn the_topology_lists_every_node_and_the_leader() {
+    let output = run_node(
+        "topology",
+        r#"[
+            {"owner": "test", "name": "node1", "host": "127.0.0.1", "port": 5001, "id": 1, "rank": 1},
+            {"owner": "test", "name": "node2", "host": "127.0.0.1", "port": 5002, "id": 2, "rank": 3},
+            {"owner": "test", "name": "node3", "host": "no-such-host.invalid", "port": 5003, "id": 3, "rank": 2}
+        ]"#,
+        "--print-topology",
+    );
+    assert!(output.status.success());
+    let topology: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(topology["id"], 1);
+    assert_eq!(topology["initial_leader"], 2);
+    assert!(topology["hub"].is_null());
+
+    let nodes = topology["nodes"].as_array().unwrap();
+    let ids: Vec<_> = nodes.iter().map(|node| node["id"].as_u64().unwrap()).collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(nodes[1]["address"], "127.0.0.1:5002");
+    assert_eq!(nodes[1]["rank"], 3);
+    // a host that does not resolve shows up as such, rather than failing the whole view.
+    assert!(nodes[2]["address"].is_null());
+    assert!(nodes[2]["error"].is_string());
+}
+
+#[test]
+fn an_engine_runs_its_queue_for_the_node_it_was_given() {
+    let node = common::node(1);
+    let nodes = vec![node.clone()];
+    let state_dir = common::state_dir("engine", node.id);
+    let engine = Engine::new(common::node_info(&node, &nodes), state_dir, Vec::new()).unwrap();
+
+    assert!(engine.event_queue().is_running());
+    assert_eq!(engine.node_info().current_node, node);
+}
+
+#[test]
+fn a_node_started_as_a_library_decides_its_own_proposal() {
+    let node = common::node(2);
+    let nodes = vec![node.clone()];
+    let sink = common::RecordingSink::default();
+    let running = Server::new(common::state_dir("server", node.id))
+        .with_sinks(vec![Box::new(sink.clone())])
+        .start(common::node_info(&node, &nodes))
+        .unwrap();
+    assert_eq!(running.local_address(), SocketAddr::try_from(&node).unwrap());
+
+    running.propose("sys-1", &42, &nodes);
+    common::wait_until(|| !sink.decisions().is_empty());
+    let decision = &sink.decisions()[0];
+    assert_eq!(decision.system_id, "sys-1");
+    assert_eq!(decision.value, serde_json::json!(42));
+
+    let event_queue = running.engine().event_queue();
+    running.shutdown().unwrap();
+    assert!(!event_queue.is_running());
+}
+
+#[test]
+fn a_client_proposes_without_the_hub() {
+    let node = common::node(5);
+    let nodes = vec![node.clone()];
+    let sink = common::RecordingSink::default();
+    let running = Server::new(common::state_dir("client", node.id))
+        .with_sinks(vec![Box::new(sink.clone())])
+        .start(common::node_info(&node, &nodes))
+        .unwrap();
+
+    client::propose(running.local_address(), "client-1", &42, &nodes).unwrap();
+    common::wait_until(|| !sink.decisions().is_empty());
+    let decision = &sink.decisions()[0];
+    assert_eq!(decision.system_id, "client-1");
+    assert_eq!(decision.value, serde_json::json!(42));
+    running.shutdown().unwrap();
+}
+
+/// The certificate shared by the nodes of the tests, issued for 127.0.0.1 by the test CA.
+fn tls() -> TlsConfig {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("tls");
+    TlsConfig::from_pem_files(dir.join("node.pem"), dir.join("node.key"), dir.join("ca.pem"))
+        .unwrap()
+}
+
+#[test]
+fn two_nodes_agree_over_tls() {
+    let nodes: Vec<_> = (7..=8).map(common::node).collect();
+    let sinks: Vec<_> = nodes.iter().map(|_| common::RecordingSink::default()).collect();
+    let running: Vec<_> = nodes
+        .iter()
+        .zip(&sinks)
+        .map(|(node, sink)| {
+            Server::new(common::state_dir("tls", node.id))
+                .with_sinks(vec![Box::new(sink.clone())])
+                .with_tls(tls())
+                .start(common::node_info(node, &nodes))
+                .unwrap()
+        })
+        .collect();
+
+    // node 8 leads the initial epoch, it proposes once node 7 started the system.
+    for (running, value) in running.iter().zip(&[7, 8]) {
+        running.propose("sys-1", value, &nodes);
+        thread::sleep(Duration::from_millis(10));
+    }
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == 2);
+    let decided = common::decided(&sinks, "sys-1");
+    assert_eq!(decided[0], decided[1]);
+
+    // a message sent in plain text isn't listened to.
+    client::propose(running[0].local_address(), "plain", &1, &nodes[..1]).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert!(common::decided(&sinks, "plain").is_empty());
+
+    for running in running {
+        running.shutdown().unwrap();
+    }
+}
+
+/// The value of `name` among the metrics served on `address`.
+fn scrape(address: SocketAddr, name: &str) -> u64 {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{} ", name)))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("No metric {} in {}", name, response))
+}
+
+#[test]
+fn the_decision_counter_is_scraped_after_a_decision() {
+    let nodes = vec![common::node(1)];
+    let transport = Arc::new(InMemoryTransport::new());
+    let (engines, sinks) =
+        common::in_memory_cluster("metrics", &nodes, &transport, transport.clone());
+    let (engine, sink) = (&engines[0], &sinks[0]);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    serve_metrics(listener, engine.metrics()).unwrap();
+    assert_eq!(scrape(address, "consensus_decisions_total"), 0);
+
+    engine.propose("sys-1", &42, &nodes);
+    common::wait_until(|| !sink.decisions().is_empty());
+    assert_eq!(scrape(address, "consensus_decisions_total"), 1);
+}
+
+#[test]
+fn the_decisions_of_every_system_are_received_by_the_embedder() {
+    let nodes = vec![common::node(1)];
+    let transport = Arc::new(InMemoryTransport::new());
+    let (engines, _) = common::in_memory_cluster("decisions", &nodes, &transport, transport.clone());
+    let decisions = engines[0].decisions();
+
+    engines[0].propose("sys-1", &4, &nodes);
+    engines[0].propose("sys-2", &6, &nodes);
+
+    let mut received: Vec<_> = (0..2)
+        .map(|_| decisions.recv_timeout(Duration::from_secs(10)).unwrap())
+        .collect();
+    received.sort();
+    assert_eq!(received, vec![("sys-1".to_owned(), 4), ("sys-2".to_owned(), 6)]);
+}
+
+#[test]
+fn a_proposal_can_be_waited_for() {
+    let nodes = vec![common::node(1)];
+    let transport = Arc::new(InMemoryTransport::new());
+    let (engines, _) = common::in_memory_cluster("propose-and-wait", &nodes, &transport, transport.clone());
+
+    let decided = engines[0].propose_and_wait("sys-1", 42, &nodes, Duration::from_secs(10));
+    assert_eq!(decided.unwrap(), 42);
+
+    // the leader of this system, node 2, is not running.
+    let unreachable = vec![common::node(1), common::node(2)];
+    let timeout = Duration::from_millis(200);
+    let error = engines[0].propose_and_wait("sys-2", 7, &unreachable, timeout).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn three_nodes_sharing_an_in_memory_transport_agree() {
+    let nodes: Vec<_> = (1..=3).map(common::node).collect();
+    let transport = Arc::new(InMemoryTransport::new());
+    let (engines, sinks) =
+        common::in_memory_cluster("in-memory", &nodes, &transport, transport.clone());
+
+    common::propose_everywhere(&engines, "sys-1", &[7, 8, 9], &nodes);
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == 3);
+    let decided = common::decided(&sinks, "sys-1");
+    assert!(decided.iter().all(|value| value == &decided[0]));
+}
+
+#[test]
+fn a_learner_gets_the_decision_of_the_voters() {
+    let mut nodes: Vec<_> = (1..=4).map(common::node).collect();
+    // ranked lowest, it starts the system before the leader broadcasts.
+    nodes[3].role = NodeRole::Learner;
+    nodes[3].rank = 0;
+    let transport = Arc::new(InMemoryTransport::new());
+    let (engines, sinks) =
+        common::in_memory_cluster("learner", &nodes, &transport, transport.clone());
+
+    common::propose_everywhere(&engines, "sys-1", &[7, 8, 9, 10], &nodes);
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == 4);
+    let decided = common::decided(&sinks, "sys-1");
+    assert!(decided.iter().all(|value| value == &decided[0]));
+    assert_ne!(decided[0], serde_json::json!(10));
+}
+
+#[test]
+fn a_replayed_trace_reaches_the_same_decision() {
+    let nodes: Vec<_> = (1..=3).map(common::node).collect();
+    let transport = Arc::new(InMemoryTransport::new());
+    let (engines, sinks) =
+        common::in_memory_cluster("traced", &nodes, &transport, transport.clone());
+    let trace = common::state_dir("traced", 0).join("trace.jsonl");
+    engines[0].event_queue().trace_to(&trace).unwrap();
+
+    common::propose_everywhere(&engines, "sys-1", &[7, 8, 9], &nodes);
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == 3);
+    // nothing gets traced anymore once the queue is shut down.
+    engines[0].event_queue().shutdown();
+
+    let event_queue = Arc::new(EventQueue::create_from_trace(&trace).unwrap());
+    let replayed = common::RecordingSink::default();
+    let app = App::new(
+        nodes[0].clone(),
+        Some(common::node(100)),
+        event_queue.clone(),
+        common::state_dir("replayed", 1),
+        chrono::Duration::milliseconds(DEFAULT_DELTA),
+        chrono::Duration::milliseconds(DEFAULT_STARTUP_GRACE),
+        vec![Box::new(replayed.clone())],
+    );
+    event_queue.register_handler(Box::new(app));
+    assert!(event_queue.replay() > 0);
+
+    assert_eq!(
+        common::decided(&[replayed], "sys-1"),
+        common::decided(&sinks[..1], "sys-1")
+    );
+}
+
+#[test]
+fn a_partitioned_leader_is_replaced_and_the_decision_survives_healing() {
+    let nodes: Vec<_> = (1..=3).map(common::node).collect();
+    let in_memory = Arc::new(InMemoryTransport::new());
+    let transport = Arc::new(FaultyTransport::new(in_memory.clone(), &nodes));
+    let (engines, sinks) =
+        common::in_memory_cluster("partition", &nodes, &in_memory, transport.clone());
+
+    // node 3 leads the initial epoch, the others have to elect node 2 to decide without it.
+    transport.partition(&[3], &[1, 2]);
+    common::propose_everywhere(&engines, "sys-1", &[7, 8, 9], &nodes);
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == 2);
+    let new_epochs_from_2 = engines[0]
+        .timeline("sys-1")
+        .into_iter()
+        .filter(|entry| {
+            entry.direction == Direction::Delivered
+                && entry.message_type == Message_Type::EC_NEW_EPOCH_
+                && entry.peer.id == 2
+        })
+        .count();
+    assert!(new_epochs_from_2 > 0);
+    assert!(sinks[2].decisions().is_empty());
+
+    // liveness resumes once healed, without ever deciding a second value.
+    transport.heal();
+    common::propose_everywhere(&engines, "sys-2", &[1, 2, 3], &nodes);
+    common::wait_until(|| common::decided(&sinks, "sys-2").len() == 3);
+    let decided = common::decided(&sinks, "sys-1");
+    assert!(decided.iter().all(|value| value == &decided[0]));
+}
+
+/// The values decided in a system of three processes whose messages are reordered by `seed`.
+/// The held messages are eventually sent, every process decides.
+fn decided_under_reordering(seed: u64) -> Vec<serde_json::Value> {
+    let nodes: Vec<_> = (1..=3).map(common::node).collect();
+    let in_memory = Arc::new(InMemoryTransport::new());
+    let transport = Arc::new(common::ReorderingTransport::new(in_memory.clone(), 4, seed));
+    let name = format!("reordering-{}", seed);
+    let (engines, sinks) = common::in_memory_cluster(&name, &nodes, &in_memory, transport);
+
+    common::propose_everywhere(&engines, "sys-1", &[7, 8, 9], &nodes);
+    common::wait_until(|| common::decided(&sinks, "sys-1").len() == 3);
+    common::decided(&sinks, "sys-1")
+}
+
+#[test]
+fn reordered_messages_never_lead_to_two_decisions() {
+    // `REORDERING_SEED` replays a single reordering.
+    let seeds: Vec<u64> = match std::env::var("REORDERING_SEED") {
+        Ok(seed) => vec![seed.parse().expect("REORDERING_SEED must be a number.")],
+        Err(_) => (0..3).collect(),
+    };
+    for seed in seeds {
+        let decided = decided_under_reordering(seed);
+        assert_eq!(decided.len(), 3, "Seed {} decided {:?}", seed, decided);
+        assert!(
+            decided.iter().all(|value| value == &decided[0]),
+            "Seed {} decided {:?}",
+            seed,
+            decided
+        );
+    }
+}